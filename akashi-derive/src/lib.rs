@@ -0,0 +1,279 @@
+//! Derive macros for [Akashi](https://docs.rs/akashi).
+//!
+//! These are re-exported from the `akashi` crate, so you shouldn't need
+//! to depend on this crate directly. See `akashi::ecs::Entity` and
+//! `akashi::ecs::Component` for documentation and examples.
+
+extern crate proc_macro;
+
+use proc_macro::TokenStream;
+use proc_macro2::{Span, TokenStream as TokenStream2};
+use quote::quote;
+use syn::parse::{Parse, ParseStream};
+use syn::punctuated::Punctuated;
+use syn::{
+    parse_macro_input, parse_quote, Data, DeriveInput, Error, Fields, Ident, Result, Token, Type,
+};
+
+/// The fields an `Entity` implementation needs, along with the names
+/// that are used for them when they aren't marked with an attribute.
+const ENTITY_FIELDS: [(&str, &[&str]); 5] = [
+    ("id", &["id"]),
+    ("component_manager", &["component_manager"]),
+    ("components_attached", &["components_attached"]),
+    (
+        "preloaded_components",
+        &["preloaded_components", "component_preloads"],
+    ),
+    ("changes", &["changes"]),
+];
+
+/// Derives `akashi::ecs::Entity` for a struct.
+///
+/// The struct needs fields holding the entity's ID, component manager,
+/// set of attached components, preloaded components, and change set. By
+/// default these are found by name (`id`, `component_manager`,
+/// `components_attached`, `component_preloads` or `preloaded_components`,
+/// and `changes`); fields with other names can be marked with
+/// `#[entity(id)]`, `#[entity(component_manager)]`, and so on.
+///
+/// Any other fields are set to their default values when the entity is
+/// created by `Entity::new`.
+///
+/// Marking the struct itself with `#[entity(clone)]` also implements
+/// `Clone`, which most storage backends need. Clones start out with no
+/// preloaded components; every other field is cloned.
+#[proc_macro_derive(Entity, attributes(entity))]
+pub fn derive_entity(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand_entity(input)
+        .unwrap_or_else(|e| e.to_compile_error())
+        .into()
+}
+
+/// Derives `akashi::ecs::Component` for a type.
+///
+/// `#[component(entity = Card)]` implements `Component<Card>`; the
+/// attribute can list several entities, as in
+/// `#[component(entity = Card, entity = Player)]`. Without the attribute,
+/// the type can be attached to any entity.
+#[proc_macro_derive(Component, attributes(component))]
+pub fn derive_component(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand_component(input)
+        .unwrap_or_else(|e| e.to_compile_error())
+        .into()
+}
+
+fn expand_entity(input: DeriveInput) -> Result<TokenStream2> {
+    let fields = match &input.data {
+        Data::Struct(data) => match &data.fields {
+            Fields::Named(fields) => &fields.named,
+            _ => {
+                return Err(Error::new_spanned(
+                    &input.ident,
+                    "Entity can only be derived for structs with named fields",
+                ))
+            }
+        },
+        _ => {
+            return Err(Error::new_spanned(
+                &input.ident,
+                "Entity can only be derived for structs",
+            ))
+        }
+    };
+
+    let mut derive_clone = false;
+    for attr in input.attrs.iter().filter(|a| a.path.is_ident("entity")) {
+        let option: Ident = attr.parse_args()?;
+        if option == "clone" {
+            derive_clone = true;
+        } else {
+            return Err(Error::new_spanned(&option, "unknown entity option"));
+        }
+    }
+
+    // Find the field for each role, preferring attributes over names.
+    let mut roles: Vec<Option<Ident>> = vec![None; ENTITY_FIELDS.len()];
+    for field in fields.iter() {
+        let ident = field.ident.clone().unwrap();
+
+        for attr in field.attrs.iter().filter(|a| a.path.is_ident("entity")) {
+            let role: Ident = attr.parse_args()?;
+            let idx = ENTITY_FIELDS
+                .iter()
+                .position(|(name, _)| role == name)
+                .ok_or_else(|| Error::new_spanned(&role, "unknown entity field"))?;
+
+            if roles[idx].is_some() {
+                return Err(Error::new_spanned(
+                    &role,
+                    format!("more than one field is marked #[entity({})]", role),
+                ));
+            }
+
+            roles[idx] = Some(ident.clone());
+        }
+    }
+
+    for (idx, (name, defaults)) in ENTITY_FIELDS.iter().enumerate() {
+        if roles[idx].is_none() {
+            roles[idx] = fields
+                .iter()
+                .filter_map(|field| field.ident.as_ref())
+                .find(|ident| defaults.iter().any(|default| *ident == default))
+                .cloned();
+        }
+
+        if roles[idx].is_none() {
+            return Err(Error::new(
+                Span::call_site(),
+                format!(
+                    "missing field for the entity's {0} (name it `{1}`, or mark it with #[entity({0})])",
+                    name, defaults[0]
+                ),
+            ));
+        }
+    }
+
+    let roles: Vec<Ident> = roles.into_iter().map(Option::unwrap).collect();
+    let (id, cm, attached, preloads, changes) =
+        (&roles[0], &roles[1], &roles[2], &roles[3], &roles[4]);
+
+    let others: Vec<&Ident> = fields
+        .iter()
+        .filter_map(|field| field.ident.as_ref())
+        .filter(|ident| !roles.contains(ident))
+        .collect();
+
+    let name = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+
+    let clone_impl = if derive_clone {
+        let cloned = roles
+            .iter()
+            .filter(|ident| *ident != preloads)
+            .chain(others.iter().cloned());
+
+        quote! {
+            impl #impl_generics ::std::clone::Clone for #name #ty_generics #where_clause {
+                fn clone(&self) -> Self {
+                    Self {
+                        #preloads: ::akashi::__private::DashMap::new(),
+                        #(#cloned: ::std::clone::Clone::clone(&self.#cloned),)*
+                    }
+                }
+            }
+        }
+    } else {
+        TokenStream2::new()
+    };
+
+    Ok(quote! {
+        #clone_impl
+
+        impl #impl_generics ::akashi::ecs::Entity for #name #ty_generics #where_clause {
+            fn new(
+                id: ::akashi::Snowflake,
+                component_manager: ::std::sync::Arc<::akashi::ecs::ComponentManager<Self>>,
+                components_attached: ::std::collections::HashSet<::std::any::TypeId>,
+            ) -> Self {
+                Self {
+                    #id: id,
+                    #cm: component_manager,
+                    #attached: components_attached,
+                    #preloads: ::akashi::__private::DashMap::new(),
+                    #changes: ::akashi::ecs::ChangeSet::new(),
+                    #(#others: ::std::default::Default::default(),)*
+                }
+            }
+
+            fn id(&self) -> ::akashi::Snowflake {
+                self.#id
+            }
+
+            fn component_manager(&self) -> &::akashi::ecs::ComponentManager<Self> {
+                &self.#cm
+            }
+
+            fn components_attached(&self) -> &::std::collections::HashSet<::std::any::TypeId> {
+                &self.#attached
+            }
+
+            fn components_attached_mut(
+                &mut self,
+            ) -> &mut ::std::collections::HashSet<::std::any::TypeId> {
+                &mut self.#attached
+            }
+
+            fn preloaded_components(
+                &self,
+            ) -> &::akashi::__private::DashMap<
+                ::std::any::TypeId,
+                ::std::boxed::Box<dyn ::akashi::ecs::Component<Self> + Send + Sync + 'static>,
+            > {
+                &self.#preloads
+            }
+
+            fn changes(&self) -> &::akashi::ecs::ChangeSet {
+                &self.#changes
+            }
+
+            fn changes_mut(&mut self) -> &mut ::akashi::ecs::ChangeSet {
+                &mut self.#changes
+            }
+        }
+    })
+}
+
+/// A single `entity = Type` argument to `#[component(...)]`.
+struct ComponentArg {
+    entity: Type,
+}
+
+impl Parse for ComponentArg {
+    fn parse(input: ParseStream) -> Result<Self> {
+        let key: Ident = input.parse()?;
+        if key != "entity" {
+            return Err(Error::new_spanned(&key, "expected `entity = ...`"));
+        }
+
+        input.parse::<Token![=]>()?;
+        Ok(ComponentArg {
+            entity: input.parse()?,
+        })
+    }
+}
+
+fn expand_component(input: DeriveInput) -> Result<TokenStream2> {
+    let mut entities: Vec<Type> = Vec::new();
+    for attr in input.attrs.iter().filter(|a| a.path.is_ident("component")) {
+        let args = attr.parse_args_with(Punctuated::<ComponentArg, Token![,]>::parse_terminated)?;
+        entities.extend(args.into_iter().map(|arg| arg.entity));
+    }
+
+    let name = &input.ident;
+    let (_, ty_generics, _) = input.generics.split_for_impl();
+
+    if entities.is_empty() {
+        let mut generics = input.generics.clone();
+        generics
+            .params
+            .push(parse_quote!(__AkashiEntity: ::akashi::ecs::Entity));
+        let (impl_generics, _, where_clause) = generics.split_for_impl();
+
+        return Ok(quote! {
+            impl #impl_generics ::akashi::ecs::Component<__AkashiEntity>
+                for #name #ty_generics #where_clause {}
+        });
+    }
+
+    let (impl_generics, _, where_clause) = input.generics.split_for_impl();
+    Ok(quote! {
+        #(
+            impl #impl_generics ::akashi::ecs::Component<#entities>
+                for #name #ty_generics #where_clause {}
+        )*
+    })
+}
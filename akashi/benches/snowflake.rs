@@ -0,0 +1,136 @@
+extern crate num_cpus;
+extern crate rayon;
+
+use akashi::clock::Clock;
+use akashi::snowflake::SequenceOverrun;
+use akashi::SnowflakeGenerator;
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use rayon::prelude::*;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+const N_ELEMS: u64 = 64 * 1024;
+
+/// A clock that jumps forward instead of sleeping, without taking a lock.
+///
+/// Generating more IDs than the sequence numbers for a millisecond allow
+/// would otherwise mostly measure time spent asleep.
+#[derive(Debug)]
+struct VirtualClock {
+    nanos: AtomicU64,
+}
+
+impl VirtualClock {
+    fn new() -> VirtualClock {
+        let now = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap();
+
+        VirtualClock {
+            nanos: AtomicU64::new(now.as_nanos() as u64),
+        }
+    }
+}
+
+impl Clock for VirtualClock {
+    fn now(&self) -> SystemTime {
+        SystemTime::UNIX_EPOCH + Duration::from_nanos(self.nanos.load(Ordering::Relaxed))
+    }
+
+    fn sleep(&self, duration: Duration) {
+        self.nanos
+            .fetch_add(duration.as_nanos() as u64, Ordering::Relaxed);
+    }
+}
+
+fn with_pool<F: FnOnce() + Send>(threads: usize, f: F) {
+    rayon::ThreadPoolBuilder::new()
+        .num_threads(threads)
+        .build()
+        .unwrap()
+        .install(f);
+}
+
+pub fn generate_test(c: &mut Criterion) {
+    let mut group = c.benchmark_group("snowflake_generate");
+    group.throughput(Throughput::Elements(N_ELEMS));
+
+    let max = num_cpus::get();
+    for threads in 1..=max {
+        group.bench_with_input(
+            BenchmarkId::from_parameter(threads),
+            &threads,
+            |b, &threads| {
+                let snowflake_gen =
+                    SnowflakeGenerator::new(0, 0).with_clock(Arc::new(VirtualClock::new()));
+
+                with_pool(threads, || {
+                    b.iter(|| {
+                        (0..N_ELEMS)
+                            .into_par_iter()
+                            .map(|_x| snowflake_gen.generate())
+                            .max()
+                    });
+                });
+            },
+        );
+    }
+}
+
+pub fn try_generate_test(c: &mut Criterion) {
+    let mut group = c.benchmark_group("snowflake_try_generate");
+    group.throughput(Throughput::Elements(N_ELEMS));
+
+    let max = num_cpus::get();
+    for threads in 1..=max {
+        let generated = AtomicU64::new(0);
+        let overruns = AtomicU64::new(0);
+
+        group.bench_with_input(
+            BenchmarkId::from_parameter(threads),
+            &threads,
+            |b, &threads| {
+                let snowflake_gen = SnowflakeGenerator::new(0, 0);
+
+                with_pool(threads, || {
+                    b.iter(|| {
+                        generated.fetch_add(N_ELEMS, Ordering::Relaxed);
+                        (0..N_ELEMS)
+                            .into_par_iter()
+                            .map(|_x| snowflake_gen.try_generate())
+                            .filter_map(|res| match res {
+                                Ok(id) => Some(id),
+                                Err(e) => {
+                                    assert!(e.downcast_ref::<SequenceOverrun>().is_some());
+                                    overruns.fetch_add(1, Ordering::Relaxed);
+                                    None
+                                }
+                            })
+                            .max()
+                    });
+                });
+            },
+        );
+
+        // Overrun calls return straight away, so they make the timings
+        // look better than they are; report how many there were.
+        let generated = generated.load(Ordering::Relaxed);
+        let overruns = overruns.load(Ordering::Relaxed);
+        println!(
+            "snowflake_try_generate/{}: {} of {} calls overran ({:.1}%)",
+            threads,
+            overruns,
+            generated,
+            100.0 * overruns as f64 / generated.max(1) as f64
+        );
+    }
+}
+
+criterion_group! {
+    name = benches;
+    config = Criterion::default().measurement_time(Duration::from_secs(20));
+    targets = generate_test, try_generate_test
+}
+
+criterion_main!(benches);
@@ -50,7 +50,7 @@ pub fn store_test(c: &mut Criterion) {
             BenchmarkId::from_parameter(threads),
             &threads,
             |b, &threads| {
-                let mut snowflake_gen = SnowflakeGenerator::new(0, 0);
+                let snowflake_gen = SnowflakeGenerator::new(0, 0);
                 let cm: Arc<ComponentManager<Card>> = Arc::new(ComponentManager::new());
 
                 let pool = rayon::ThreadPoolBuilder::new()
@@ -62,7 +62,7 @@ pub fn store_test(c: &mut Criterion) {
                     b.iter_batched(
                         || {
                             let cards: Vec<Card> = (0..N_ELEMS)
-                                .map(|_x| Card::generate(&mut snowflake_gen, cm.clone()))
+                                .map(|_x| Card::generate(&snowflake_gen, cm.clone()))
                                 .collect();
 
                             let backend = NullBackend {};
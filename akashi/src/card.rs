@@ -6,18 +6,20 @@ use std::sync::Arc;
 
 use dashmap::DashMap;
 
-use crate::ecs::{Component, ComponentManager, Entity};
+use crate::ecs::{ChangeSet, Component, ComponentManager, Entity};
 use crate::snowflake::{Snowflake, SnowflakeGenerator};
 
 /// Represents a tradable card.
 ///
 /// Strictly speaking, this is just a bare-bones [`Entity`].  
+#[derive(Entity)]
+#[entity(clone)]
 pub struct Card {
     id: Snowflake,
     component_manager: Arc<ComponentManager<Card>>,
     components_attached: HashSet<TypeId>,
     component_preloads: DashMap<TypeId, Box<dyn Component<Card> + Send + Sync + 'static>>,
-    dirty: bool,
+    changes: ChangeSet,
 }
 
 impl Card {
@@ -32,13 +34,13 @@ impl Card {
             component_manager,
             components_attached,
             component_preloads: DashMap::new(),
-            dirty: false,
+            changes: ChangeSet::new(),
         }
     }
 
     /// Create an 'empty' `Card` instance with a random ID.
     pub fn generate(
-        snowflake_gen: &mut SnowflakeGenerator,
+        snowflake_gen: &SnowflakeGenerator,
         component_manager: Arc<ComponentManager<Card>>,
     ) -> Card {
         Card {
@@ -46,7 +48,7 @@ impl Card {
             component_manager,
             components_attached: HashSet::new(),
             component_preloads: DashMap::new(),
-            dirty: false,
+            changes: ChangeSet::new(),
         }
     }
 
@@ -61,60 +63,12 @@ impl Card {
     }
 }
 
-impl Clone for Card {
-    fn clone(&self) -> Self {
-        Self {
-            id: self.id,
-            dirty: self.dirty,
-            component_manager: self.component_manager.clone(),
-            components_attached: self.components_attached.clone(),
-            component_preloads: DashMap::new(),
-        }
-    }
-}
-
 impl PartialEq for Card {
     fn eq(&self, other: &Self) -> bool {
         (self.id == other.id) && Arc::ptr_eq(&self.component_manager, &other.component_manager)
     }
 }
 
-impl Entity for Card {
-    fn new(id: Snowflake, cm: Arc<ComponentManager<Card>>, components: HashSet<TypeId>) -> Card {
-        Card::new(id, cm, components)
-    }
-
-    fn id(&self) -> Snowflake {
-        self.id()
-    }
-
-    fn component_manager(&self) -> &ComponentManager<Card> {
-        &self.component_manager
-    }
-
-    fn components_attached(&self) -> &HashSet<TypeId> {
-        &self.components_attached
-    }
-
-    fn components_attached_mut(&mut self) -> &mut HashSet<TypeId> {
-        &mut self.components_attached
-    }
-
-    fn preloaded_components(
-        &self,
-    ) -> &DashMap<TypeId, Box<dyn Component<Self> + Send + Sync + 'static>> {
-        &self.component_preloads
-    }
-
-    fn dirty(&self) -> bool {
-        self.dirty
-    }
-
-    fn dirty_mut(&mut self) -> &mut bool {
-        &mut self.dirty
-    }
-}
-
 mod tests {
     #[allow(unused_imports)]
     use super::*;
@@ -124,11 +78,11 @@ mod tests {
 
     #[test]
     fn test_card_generate() {
-        let mut snowflake_gen = SnowflakeGenerator::new(0, 0);
+        let snowflake_gen = SnowflakeGenerator::new(0, 0);
         let cm = Arc::new(ComponentManager::new());
 
-        let card1 = Card::generate(&mut snowflake_gen, cm.clone());
-        let card2 = Card::generate(&mut snowflake_gen, cm);
+        let card1 = Card::generate(&snowflake_gen, cm.clone());
+        let card2 = Card::generate(&snowflake_gen, cm);
 
         assert_ne!(card1.id(), card2.id());
     }
@@ -0,0 +1,91 @@
+//! Sources of the current time.
+//!
+//! Anything in Akashi that needs to know what time it is (such as
+//! [`SnowflakeGenerator`](crate::SnowflakeGenerator) and
+//! [`Ledger`](crate::ledger::Ledger)) gets it from a [`Clock`]. By
+//! default this is the [`SystemClock`], but a [`ManualClock`] can be
+//! swapped in to run tests in virtual time.
+
+use std::fmt;
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+use parking_lot::Mutex;
+
+/// A source of the current time.
+pub trait Clock: fmt::Debug + Send + Sync {
+    /// Gets the current time.
+    fn now(&self) -> SystemTime;
+
+    /// Waits until at least `duration` has passed on this clock.
+    ///
+    /// By default, this puts the current thread to sleep.
+    fn sleep(&self, duration: Duration) {
+        thread::sleep(duration);
+    }
+}
+
+/// A [`Clock`] that reads the system time.
+#[derive(Debug, Copy, Clone, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> SystemTime {
+        SystemTime::now()
+    }
+}
+
+/// A [`Clock`] that only moves when told to.
+///
+/// Sleeping on a `ManualClock` advances it instead of blocking, so code
+/// that waits for time to pass will run immediately.
+///
+/// # Example
+///
+/// ```
+/// use akashi::clock::{Clock, ManualClock};
+/// use std::time::{Duration, SystemTime};
+///
+/// let start = SystemTime::UNIX_EPOCH + Duration::from_secs(1_600_000_000);
+/// let clock = ManualClock::new(start);
+/// assert_eq!(clock.now(), start);
+///
+/// clock.advance(Duration::from_secs(5));
+/// clock.sleep(Duration::from_secs(1));
+/// assert_eq!(clock.now(), start + Duration::from_secs(6));
+/// ```
+#[derive(Debug)]
+pub struct ManualClock {
+    now: Mutex<SystemTime>,
+}
+
+impl ManualClock {
+    /// Creates a new `ManualClock` set to the given time.
+    pub fn new(now: SystemTime) -> ManualClock {
+        ManualClock {
+            now: Mutex::new(now),
+        }
+    }
+
+    /// Sets the current time.
+    ///
+    /// This can be used to move the clock backwards.
+    pub fn set(&self, now: SystemTime) {
+        *self.now.lock() = now;
+    }
+
+    /// Moves the clock forwards.
+    pub fn advance(&self, duration: Duration) {
+        *self.now.lock() += duration;
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> SystemTime {
+        *self.now.lock()
+    }
+
+    fn sleep(&self, duration: Duration) {
+        self.advance(duration);
+    }
+}
@@ -2,7 +2,9 @@
 
 pub mod card_type;
 pub mod inventory;
+pub mod regen;
 pub mod resource;
+pub mod wallet;
 
 #[doc(inline)]
 pub use card_type::{AttachedCardType, CardType, CardTypeLayer};
@@ -10,10 +12,18 @@ pub use card_type::{AttachedCardType, CardType, CardTypeLayer};
 #[doc(inline)]
 pub use inventory::{Inventory, InventoryBackendWrapper};
 
+#[doc(inline)]
+pub use regen::{Overflow, RegenResource};
+
 #[doc(inline)]
 pub use resource::Resource;
 
 pub use resource::{InvalidAddition, InvalidSet, InvalidSoftCapAdjustment, InvalidSubtraction};
 
+#[doc(inline)]
+pub use wallet::{Wallet, WalletBackendWrapper};
+
+pub use wallet::{InsufficientFunds, UnknownCurrency};
+
 // pub mod card_text;
 // pub use card_text::CardText;
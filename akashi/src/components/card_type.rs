@@ -2,7 +2,7 @@
 
 use crate::card::Card;
 use crate::ecs::entity_store::{ReadReference, StoreHandle, WriteReference};
-use crate::ecs::{Component, ComponentAdapter, ComponentManager, Entity, EntityManager};
+use crate::ecs::{ChangeSet, Component, ComponentAdapter, ComponentManager, Entity, EntityManager};
 use crate::snowflake::{Snowflake, SnowflakeGenerator};
 use crate::util::Result;
 
@@ -16,12 +16,15 @@ use std::sync::Arc;
 ///
 /// For instance, this [`Entity`](Entity) can be used to group together card data
 /// common to a specific character or other card variety.
+#[derive(Entity)]
+#[entity(clone)]
 pub struct CardType {
+    #[entity(id)]
     type_id: Snowflake,
     component_manager: Arc<ComponentManager<CardType>>,
     components_attached: HashSet<TypeId>,
     component_preloads: DashMap<TypeId, Box<dyn Component<Self> + Send + Sync + 'static>>,
-    dirty: bool,
+    changes: ChangeSet,
 }
 
 impl CardType {
@@ -36,14 +39,14 @@ impl CardType {
             component_manager,
             components_attached,
             component_preloads: DashMap::new(),
-            dirty: false,
+            changes: ChangeSet::new(),
         }
     }
 
     /// Creates an empty `CardType` instance, with a randomized ID and
     /// no attached [`Components`](Component).
     pub fn generate(
-        snowflake_gen: &mut SnowflakeGenerator,
+        snowflake_gen: &SnowflakeGenerator,
         component_manager: Arc<ComponentManager<CardType>>,
     ) -> CardType {
         CardType {
@@ -51,7 +54,7 @@ impl CardType {
             component_manager,
             components_attached: HashSet::new(),
             component_preloads: DashMap::new(),
-            dirty: false,
+            changes: ChangeSet::new(),
         }
     }
 
@@ -60,54 +63,6 @@ impl CardType {
     }
 }
 
-impl Entity for CardType {
-    fn new(id: Snowflake, cm: Arc<ComponentManager<Self>>, components: HashSet<TypeId>) -> Self {
-        CardType::new(id, cm, components)
-    }
-
-    fn id(&self) -> Snowflake {
-        self.type_id
-    }
-
-    fn component_manager(&self) -> &ComponentManager<CardType> {
-        &self.component_manager
-    }
-
-    fn components_attached(&self) -> &HashSet<TypeId> {
-        &self.components_attached
-    }
-
-    fn components_attached_mut(&mut self) -> &mut HashSet<TypeId> {
-        &mut self.components_attached
-    }
-
-    fn preloaded_components(
-        &self,
-    ) -> &DashMap<TypeId, Box<dyn Component<Self> + Send + Sync + 'static>> {
-        &self.component_preloads
-    }
-
-    fn dirty(&self) -> bool {
-        self.dirty
-    }
-
-    fn dirty_mut(&mut self) -> &mut bool {
-        &mut self.dirty
-    }
-}
-
-impl Clone for CardType {
-    fn clone(&self) -> Self {
-        Self {
-            type_id: self.type_id,
-            dirty: self.dirty,
-            component_manager: self.component_manager.clone(),
-            components_attached: self.components_attached.clone(),
-            component_preloads: DashMap::new(),
-        }
-    }
-}
-
 /// A [`Component`] representing a particular [`CardType`]
 /// entity that is associated with a [`Card`].
 #[derive(Clone)]
@@ -218,7 +173,7 @@ mod tests {
 
     #[test]
     fn test_store_type() {
-        let mut fixtures = Fixtures::new();
+        let fixtures = Fixtures::new();
         let type_id = fixtures.snowflake_gen.generate();
         let card_id: Snowflake;
 
@@ -245,7 +200,7 @@ mod tests {
 
     #[test]
     fn test_attached_card_type_load() {
-        let mut fixtures = Fixtures::new();
+        let fixtures = Fixtures::new();
 
         // Create and store a new Card Type with attached MockTypeData.
         let mut card_type: CardType = fixtures
@@ -184,7 +184,7 @@ mod tests {
 
     #[test]
     fn test_inv() {
-        let mut snowflake_gen = SnowflakeGenerator::new(0, 0);
+        let snowflake_gen = SnowflakeGenerator::new(0, 0);
         let mut ent_mgr = EntityManager::new();
 
         #[derive(Clone)]
@@ -227,7 +227,7 @@ mod tests {
     fn test_inv_wrapper() {
         use crate::Player;
 
-        let mut snowflake_gen = SnowflakeGenerator::new(0, 0);
+        let snowflake_gen = SnowflakeGenerator::new(0, 0);
         let mut ent_mgr = EntityManager::new();
 
         ent_mgr
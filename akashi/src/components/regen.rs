@@ -0,0 +1,398 @@
+//! Time-based regenerating resources (such as stamina) as [`Player`]
+//! components.
+
+use std::time::{Duration, SystemTime};
+
+use serde::{Deserialize, Serialize};
+
+use super::resource::{InvalidAddition, InvalidSubtraction, Resource};
+use crate::ecs::Component;
+use crate::player::Player;
+
+/// Controls what happens when a [`RegenResource`] is added to past its
+/// regeneration cap.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Overflow {
+    /// Additions can take the value past the regeneration cap (up to the
+    /// underlying [`Resource`]'s own upper bound, if any). Regeneration
+    /// pauses until the value drops back below the cap.
+    Allow,
+
+    /// Additions can't take the value past the regeneration cap.
+    Cap,
+}
+
+/// A [`Resource`] that regenerates over time, up to a cap.
+///
+/// Rather than being updated continuously, a `RegenResource` stores its
+/// value as of a timestamp, and works out how much has been regenerated
+/// since then whenever it's read. Every method that reads or modifies the
+/// value takes the current time as a parameter; in most cases this will
+/// just be `SystemTime::now()`, or [`Clock::now`](crate::clock::Clock::now)
+/// when running in virtual time.
+///
+/// # Example
+///
+/// ```
+/// use std::time::{Duration, SystemTime};
+/// use akashi::components::{Overflow, RegenResource, Resource};
+///
+/// let start = SystemTime::now();
+/// let minute = Duration::from_secs(60);
+///
+/// // 1 stamina every 5 minutes, up to 20.
+/// let mut stamina = RegenResource::new(
+///     Resource::new(20, Some(0), Some(999)),
+///     20,
+///     minute * 5,
+///     start,
+/// );
+///
+/// stamina.checked_sub(10.into(), start).unwrap();
+/// assert_eq!(stamina.val(start + minute * 12), 12);
+/// assert_eq!(stamina.time_until_full(start + minute * 12), Some(minute * 38));
+///
+/// // Items can push stamina past the cap.
+/// stamina.capped_add(50.into(), start + minute * 12);
+/// assert_eq!(stamina.val(start + minute * 12), 62);
+/// assert_eq!(stamina.overflow(), Overflow::Allow);
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RegenResource {
+    resource: Resource,
+    updated: SystemTime,
+    cap: i64,
+    interval: Duration,
+    amount: i64,
+    overflow: Overflow,
+}
+
+impl RegenResource {
+    /// Creates a new `RegenResource` that regenerates by 1 every
+    /// `interval`, up to `cap`.
+    ///
+    /// `now` is the time at which `resource` had its current value.
+    /// By default, additions are allowed to overflow past `cap`.
+    pub fn new(resource: Resource, cap: i64, interval: Duration, now: SystemTime) -> RegenResource {
+        RegenResource {
+            resource,
+            updated: now,
+            cap,
+            interval,
+            amount: 1,
+            overflow: Overflow::Allow,
+        }
+    }
+
+    /// Sets how much this resource regenerates by every interval.
+    pub fn with_amount(mut self, amount: i64) -> RegenResource {
+        self.amount = amount;
+        self
+    }
+
+    /// Sets the overflow behavior for this resource.
+    pub fn with_overflow(mut self, overflow: Overflow) -> RegenResource {
+        self.overflow = overflow;
+        self
+    }
+
+    /// Gets the cap this resource regenerates up to.
+    pub fn cap(&self) -> i64 {
+        self.cap
+    }
+
+    /// Gets how often this resource regenerates.
+    pub fn interval(&self) -> Duration {
+        self.interval
+    }
+
+    /// Gets how much this resource regenerates by every interval.
+    pub fn amount(&self) -> i64 {
+        self.amount
+    }
+
+    /// Gets the overflow behavior for this resource.
+    pub fn overflow(&self) -> Overflow {
+        self.overflow
+    }
+
+    /// Gets the time this resource's stored value was last brought up to
+    /// date.
+    pub fn last_updated(&self) -> SystemTime {
+        self.updated
+    }
+
+    /// Gets the number of whole intervals that have passed between the
+    /// last update and `now`.
+    fn ticks(&self, now: SystemTime) -> u128 {
+        let elapsed = now.duration_since(self.updated).unwrap_or_default();
+        match self.interval.as_nanos() {
+            0 => u128::MAX,
+            interval => elapsed.as_nanos() / interval,
+        }
+    }
+
+    /// Gets the value of this resource at the given time.
+    pub fn val(&self, now: SystemTime) -> i64 {
+        let val = self.resource.val();
+        if val >= self.cap || self.amount <= 0 {
+            return val;
+        }
+
+        let ticks = self.ticks(now).min(i64::MAX as u128) as i64;
+        val.saturating_add(ticks.saturating_mul(self.amount))
+            .min(self.cap)
+    }
+
+    /// Gets a copy of the underlying [`Resource`], with its value as of
+    /// the given time.
+    pub fn resource(&self, now: SystemTime) -> Resource {
+        let mut updated = self.clone();
+        updated.update(now);
+        updated.resource
+    }
+
+    /// Checks whether this resource has regenerated up to its cap at the
+    /// given time.
+    pub fn is_full(&self, now: SystemTime) -> bool {
+        self.val(now) >= self.cap
+    }
+
+    /// Gets how long it will be after `now` until this resource next
+    /// regenerates, or `None` if it's full.
+    pub fn time_until_next(&self, now: SystemTime) -> Option<Duration> {
+        if self.is_full(now) || self.amount <= 0 {
+            return None;
+        }
+
+        let elapsed = now.duration_since(self.updated).unwrap_or_default();
+        let interval = self.interval.as_nanos();
+        let into_interval = elapsed.as_nanos() % interval;
+        Some(nanos_to_duration(interval - into_interval))
+    }
+
+    /// Gets how long it will be after `now` until this resource has
+    /// regenerated up to its cap.
+    ///
+    /// Returns a zero duration if it's already full, and `None` if it
+    /// will never fill up by itself (because it regenerates by a
+    /// non-positive amount) or if the time it fills up at can't be
+    /// represented.
+    pub fn time_until_full(&self, now: SystemTime) -> Option<Duration> {
+        if self.is_full(now) {
+            return Some(Duration::from_secs(0));
+        }
+
+        if self.amount <= 0 {
+            return None;
+        }
+
+        let missing = self.cap.checked_sub(self.resource.val())? as u128;
+        let amount = self.amount as u128;
+        let ticks = missing.div_ceil(amount);
+
+        let nanos = self.interval.as_nanos().checked_mul(ticks)?;
+        let full_at = self.updated.checked_add(nanos_to_duration(nanos))?;
+        Some(full_at.duration_since(now).unwrap_or_default())
+    }
+
+    /// Brings the stored value up to date with the given time.
+    ///
+    /// Partial progress towards the next interval is kept, unless the
+    /// resource is full, in which case regeneration starts again from
+    /// `now` once the value drops below the cap.
+    fn update(&mut self, now: SystemTime) {
+        let val = self.val(now);
+        if val >= self.cap || self.amount <= 0 {
+            self.updated = now;
+        } else {
+            let ticks = self.ticks(now);
+            self.updated += nanos_to_duration(self.interval.as_nanos() * ticks);
+        }
+
+        self.resource.capped_set(val.into());
+    }
+
+    /// Subtracts from this resource's value at the given time.
+    ///
+    /// # Errors
+    ///
+    /// Returns an [`InvalidSubtraction`] error if the new value would be
+    /// below the underlying [`Resource`]'s lower bound.
+    pub fn checked_sub(
+        &mut self,
+        rhs: Resource,
+        now: SystemTime,
+    ) -> Result<(), InvalidSubtraction> {
+        self.update(now);
+        self.resource.checked_sub(rhs)
+    }
+
+    /// Subtracts from this resource's value at the given time, capping the
+    /// new value at the underlying [`Resource`]'s lower bound.
+    pub fn capped_sub(&mut self, rhs: Resource, now: SystemTime) {
+        self.update(now);
+        self.resource.capped_sub(rhs)
+    }
+
+    /// Adds to this resource's value at the given time.
+    ///
+    /// # Errors
+    ///
+    /// Returns an [`InvalidAddition`] error if the new value would be
+    /// above the underlying [`Resource`]'s upper bound, or above the
+    /// regeneration cap when using [`Overflow::Cap`].
+    pub fn checked_add(&mut self, rhs: Resource, now: SystemTime) -> Result<(), InvalidAddition> {
+        self.update(now);
+        match self.overflow {
+            Overflow::Allow => self.resource.checked_add(rhs),
+            Overflow::Cap => {
+                let mut capped = self.capped_resource();
+                capped.checked_add(rhs)?;
+                self.resource.capped_set(capped);
+                Ok(())
+            }
+        }
+    }
+
+    /// Adds to this resource's value at the given time, capping the new
+    /// value at the underlying [`Resource`]'s upper bound (and at the
+    /// regeneration cap when using [`Overflow::Cap`]).
+    pub fn capped_add(&mut self, rhs: Resource, now: SystemTime) {
+        self.update(now);
+        match self.overflow {
+            Overflow::Allow => self.resource.capped_add(rhs),
+            Overflow::Cap => {
+                let mut capped = self.capped_resource();
+                capped.capped_add(rhs);
+                self.resource.capped_set(capped);
+            }
+        }
+    }
+
+    /// Gets a copy of the underlying [`Resource`] with the regeneration
+    /// cap as its upper bound.
+    fn capped_resource(&self) -> Resource {
+        let max = match self.resource.max() {
+            Some(max) => max.min(self.cap),
+            None => self.cap,
+        };
+
+        // Don't lower the value if it's already above the cap.
+        let max = max.max(self.resource.val());
+        Resource::new(self.resource.val(), self.resource.min(), Some(max))
+    }
+}
+
+fn nanos_to_duration(nanos: u128) -> Duration {
+    const NANOS_PER_SEC: u128 = 1_000_000_000;
+
+    let secs = (nanos / NANOS_PER_SEC).min(u64::MAX as u128) as u64;
+    Duration::new(secs, (nanos % NANOS_PER_SEC) as u32)
+}
+
+impl Component<Player> for RegenResource {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MINUTE: Duration = Duration::from_secs(60);
+
+    fn stamina(start: SystemTime) -> RegenResource {
+        RegenResource::new(Resource::new(20, Some(0), Some(999)), 20, MINUTE * 5, start)
+    }
+
+    #[test]
+    fn test_regeneration() {
+        let start = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000);
+        let mut rsc = stamina(start);
+        assert!(rsc.is_full(start));
+        assert_eq!(rsc.time_until_next(start), None);
+        assert_eq!(rsc.time_until_full(start), Some(Duration::from_secs(0)));
+
+        // Regeneration starts from when the resource is spent, not from
+        // when it was last updated.
+        let spend = start + MINUTE * 60;
+        rsc.checked_sub(3.into(), spend).unwrap();
+        assert_eq!(rsc.val(spend), 17);
+        assert_eq!(rsc.val(spend + MINUTE * 4), 17);
+        assert_eq!(rsc.val(spend + MINUTE * 5), 18);
+        assert_eq!(rsc.time_until_next(spend + MINUTE * 7), Some(MINUTE * 3));
+        assert_eq!(rsc.time_until_full(spend + MINUTE * 7), Some(MINUTE * 8));
+        assert_eq!(rsc.val(spend + MINUTE * 1000), 20);
+
+        // Partial progress is kept across updates.
+        rsc.checked_sub(1.into(), spend + MINUTE * 7).unwrap();
+        assert_eq!(rsc.val(spend + MINUTE * 7), 17);
+        assert_eq!(rsc.val(spend + MINUTE * 10), 18);
+        assert_eq!(rsc.resource(spend + MINUTE * 10).val(), 18);
+    }
+
+    #[test]
+    fn test_checked_sub() {
+        let start = SystemTime::UNIX_EPOCH;
+        let mut rsc = stamina(start);
+        rsc.checked_sub(20.into(), start).unwrap();
+
+        assert!(rsc.checked_sub(1.into(), start + MINUTE * 4).is_err());
+        assert_eq!(rsc.val(start + MINUTE * 4), 0);
+
+        // Stamina regenerated in the meantime can be spent.
+        rsc.checked_sub(2.into(), start + MINUTE * 10).unwrap();
+        assert_eq!(rsc.val(start + MINUTE * 10), 0);
+    }
+
+    #[test]
+    fn test_overflow() {
+        let start = SystemTime::UNIX_EPOCH;
+        let mut rsc = stamina(start);
+        rsc.checked_sub(5.into(), start).unwrap();
+
+        rsc.checked_add(30.into(), start).unwrap();
+        assert_eq!(rsc.val(start + MINUTE * 100), 45);
+        assert!(rsc.is_full(start));
+
+        rsc.checked_sub(30.into(), start + MINUTE * 100).unwrap();
+        assert_eq!(rsc.val(start + MINUTE * 105), 16);
+
+        let mut rsc = stamina(start).with_overflow(Overflow::Cap);
+        rsc.checked_sub(5.into(), start).unwrap();
+        assert!(rsc.checked_add(6.into(), start).is_err());
+        assert_eq!(rsc.val(start), 15);
+
+        rsc.capped_add(6.into(), start);
+        assert_eq!(rsc.val(start), 20);
+    }
+
+    #[test]
+    fn test_time_until_full_overflow() {
+        let start = SystemTime::UNIX_EPOCH;
+
+        // The missing amount doesn't fit in an i64.
+        let rsc = RegenResource::new(Resource::new(i64::MIN, None, None), i64::MAX, MINUTE, start);
+        assert_eq!(rsc.time_until_full(start), None);
+
+        // The time it fills up at doesn't fit in a SystemTime.
+        let rsc = RegenResource::new(Resource::new(0, None, None), 10, Duration::MAX, start);
+        assert!(!rsc.is_full(start));
+        assert_eq!(rsc.time_until_full(start), None);
+
+        let rsc = RegenResource::new(Resource::new(0, None, None), i64::MAX, MINUTE, start);
+        assert_eq!(rsc.time_until_full(start), None);
+    }
+
+    #[test]
+    fn test_amount_and_serialization() {
+        let start = SystemTime::UNIX_EPOCH;
+        let mut rsc = stamina(start).with_amount(3);
+        rsc.checked_sub(10.into(), start).unwrap();
+
+        assert_eq!(rsc.val(start + MINUTE * 5), 13);
+        assert_eq!(rsc.time_until_full(start), Some(MINUTE * 20));
+
+        let serialized = serde_json::to_string(&rsc).unwrap();
+        let deserialized: RegenResource = serde_json::from_str(&serialized).unwrap();
+        assert_eq!(rsc, deserialized);
+    }
+}
@@ -1,13 +1,14 @@
 //! A data type for player-held game resource counts.
 
 use failure::Fail;
+use serde::{Deserialize, Serialize};
 
 use crate::ecs::Component;
 use crate::player::Player;
 
 /// Represents an arbitrary numeric player resource, with optional
 /// lower and upper caps.
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub struct Resource {
     val: i64,
     min: Option<i64>,
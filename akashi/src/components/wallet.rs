@@ -0,0 +1,367 @@
+//! Multi-currency wallets as [`Player`] components.
+
+use std::collections::{BTreeMap, HashSet};
+
+use failure::Fail;
+
+use super::resource::Resource;
+use crate::ecs::{Component, ComponentAdapter};
+use crate::player::Player;
+use crate::util::Result;
+
+/// Holds any number of named currencies, each represented by a
+/// [`Resource`] with its own bounds.
+///
+/// Since [`Resource`] itself implements `Component<Player>`, a player can
+/// only have one `Resource` attached at a time. `Wallet` allows a single
+/// player to hold several currencies at once (for example, free and paid
+/// premium currency, stamina, and event tokens).
+///
+/// # Example
+///
+/// ```
+/// use akashi::components::{Resource, Wallet};
+///
+/// let mut wallet = Wallet::new()
+///     .with_currency("free_gems", Resource::new(50, Some(0), None))
+///     .with_currency("paid_gems", Resource::new(100, Some(0), None));
+///
+/// // Free gems are spent before paid ones.
+/// let spent = wallet.spend(&["free_gems", "paid_gems"], 80).unwrap();
+/// assert_eq!(
+///     spent,
+///     vec![(String::from("free_gems"), 50), (String::from("paid_gems"), 30)]
+/// );
+///
+/// assert_eq!(wallet.balance("free_gems"), 0);
+/// assert_eq!(wallet.balance("paid_gems"), 70);
+/// ```
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Wallet {
+    currencies: BTreeMap<String, Resource>,
+}
+
+impl Wallet {
+    /// Creates a new, empty `Wallet`.
+    pub fn new() -> Wallet {
+        Wallet {
+            currencies: BTreeMap::new(),
+        }
+    }
+
+    /// Adds a currency to this wallet, replacing any currency with the
+    /// same name, and returns the wallet.
+    pub fn with_currency(mut self, name: &str, resource: Resource) -> Wallet {
+        self.insert(name, resource);
+        self
+    }
+
+    /// Adds a currency to this wallet, returning the currency it replaced
+    /// (if any).
+    pub fn insert(&mut self, name: &str, resource: Resource) -> Option<Resource> {
+        self.currencies.insert(name.to_owned(), resource)
+    }
+
+    /// Removes a currency from this wallet, returning it if it was
+    /// present.
+    pub fn remove(&mut self, name: &str) -> Option<Resource> {
+        self.currencies.remove(name)
+    }
+
+    /// Gets a currency from this wallet.
+    pub fn get(&self, name: &str) -> Option<&Resource> {
+        self.currencies.get(name)
+    }
+
+    /// Gets a mutable reference to a currency in this wallet.
+    pub fn get_mut(&mut self, name: &str) -> Option<&mut Resource> {
+        self.currencies.get_mut(name)
+    }
+
+    /// Gets the current value of a currency, or 0 if this wallet doesn't
+    /// hold it.
+    pub fn balance(&self, name: &str) -> i64 {
+        self.currencies.get(name).map_or(0, |rsc| rsc.val())
+    }
+
+    /// Iterates over every currency in this wallet, in order of name.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &Resource)> + '_ {
+        self.currencies
+            .iter()
+            .map(|(name, rsc)| (name.as_str(), rsc))
+    }
+
+    /// Adds an amount to a currency in this wallet.
+    ///
+    /// # Errors
+    ///
+    /// Returns an [`UnknownCurrency`] error if this wallet doesn't hold
+    /// the currency, or an
+    /// [`InvalidAddition`](super::InvalidAddition) error if the new value
+    /// would be outside the currency's bounds.
+    pub fn checked_add(&mut self, name: &str, amount: i64) -> Result<()> {
+        self.currencies
+            .get_mut(name)
+            .ok_or_else(|| UnknownCurrency(name.to_owned()))?
+            .checked_add(amount.into())?;
+
+        Ok(())
+    }
+
+    /// Subtracts an amount from a currency in this wallet.
+    ///
+    /// # Errors
+    ///
+    /// Returns an [`UnknownCurrency`] error if this wallet doesn't hold
+    /// the currency, or an
+    /// [`InvalidSubtraction`](super::InvalidSubtraction) error if the new
+    /// value would be outside the currency's bounds.
+    pub fn checked_sub(&mut self, name: &str, amount: i64) -> Result<()> {
+        self.currencies
+            .get_mut(name)
+            .ok_or_else(|| UnknownCurrency(name.to_owned()))?
+            .checked_sub(amount.into())?;
+
+        Ok(())
+    }
+
+    /// Gets the total amount that could be spent from the given
+    /// currencies, without going below any of their lower bounds.
+    ///
+    /// Currencies with no lower bound can be spent without limit, so this
+    /// saturates at `i64::MAX`. Currencies listed more than once are only
+    /// counted once.
+    pub fn available(&self, priority: &[&str]) -> i64 {
+        dedup_priority(priority)
+            .iter()
+            .filter_map(|name| self.currencies.get(*name))
+            .fold(0i64, |total, rsc| total.saturating_add(spendable(rsc)))
+    }
+
+    /// Spends an amount from several currencies, in order of priority.
+    ///
+    /// As much as possible is taken from the first currency in `priority`
+    /// before moving on to the next one, and so on. Currencies that this
+    /// wallet doesn't hold are skipped, as are repeated currencies after
+    /// their first appearance. Returns how much was taken from
+    /// each currency, leaving out currencies that weren't needed.
+    ///
+    /// Nothing is spent if `amount` isn't positive.
+    ///
+    /// # Errors
+    ///
+    /// Returns an [`InsufficientFunds`] error if the currencies don't
+    /// have enough between them, in which case nothing is spent.
+    pub fn spend(&mut self, priority: &[&str], amount: i64) -> Result<Vec<(String, i64)>> {
+        if amount <= 0 {
+            return Ok(Vec::new());
+        }
+
+        let available = self.available(priority);
+        if available < amount {
+            return Err(InsufficientFunds(amount, available).into());
+        }
+
+        let mut remaining = amount;
+        let mut spent = Vec::new();
+        for name in dedup_priority(priority).iter() {
+            if remaining == 0 {
+                break;
+            }
+
+            if let Some(rsc) = self.currencies.get_mut(*name) {
+                let taken = spendable(rsc).min(remaining);
+                if taken > 0 {
+                    rsc.checked_sub(taken.into())?;
+                    spent.push(((*name).to_owned(), taken));
+                    remaining -= taken;
+                }
+            }
+        }
+
+        Ok(spent)
+    }
+}
+
+/// Removes repeated currency names from a priority list, keeping the
+/// first appearance of each.
+fn dedup_priority<'a>(priority: &[&'a str]) -> Vec<&'a str> {
+    let mut seen = HashSet::new();
+    priority
+        .iter()
+        .copied()
+        .filter(|name| seen.insert(*name))
+        .collect()
+}
+
+/// Gets how much can be subtracted from a [`Resource`] without going
+/// below its lower bound.
+fn spendable(rsc: &Resource) -> i64 {
+    match rsc.min() {
+        Some(min) => rsc.val().saturating_sub(min).max(0),
+        None => i64::MAX,
+    }
+}
+
+impl Component<Player> for Wallet {}
+
+impl Component<Player> for BTreeMap<String, Resource> {}
+
+impl From<BTreeMap<String, Resource>> for Wallet {
+    fn from(currencies: BTreeMap<String, Resource>) -> Wallet {
+        Wallet { currencies }
+    }
+}
+
+impl From<Wallet> for BTreeMap<String, Resource> {
+    fn from(wallet: Wallet) -> BTreeMap<String, Resource> {
+        wallet.currencies
+    }
+}
+
+/// Acts as a [`ComponentBackend`](crate::ComponentBackend) for
+/// [`Wallets`](Wallet) by wrapping another
+/// [`ComponentBackend`](crate::ComponentBackend).
+///
+/// The wrapped storage type needs to implement loading and storing maps of
+/// currency names to [`Resources`](Resource) via the
+/// `ComponentBackend<Player, BTreeMap<String, Resource>>` trait.
+pub type WalletBackendWrapper<W> = ComponentAdapter<Player, BTreeMap<String, Resource>, Wallet, W>;
+
+/// Returned when trying to use a currency that a [`Wallet`] doesn't hold.
+#[derive(Fail, Debug)]
+#[fail(display = "Unknown currency: {}", _0)]
+pub struct UnknownCurrency(String);
+
+/// Returned when a [`Wallet`] doesn't hold enough currency to spend an
+/// amount.
+#[derive(Fail, Debug)]
+#[fail(
+    display = "Not enough currency (attempted to spend {}, only {} available)",
+    _0, _1
+)]
+pub struct InsufficientFunds(i64, i64);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::components::{InvalidAddition, InvalidSubtraction};
+    use crate::ecs::{Entity, EntityManager};
+    use crate::file_storage::FileComponentStorage;
+    use crate::local_storage::LocalEntityStorage;
+    use crate::snowflake::Snowflake;
+
+    fn wallet() -> Wallet {
+        Wallet::new()
+            .with_currency("free_gems", Resource::new(50, Some(0), None))
+            .with_currency("paid_gems", Resource::new(100, Some(0), None))
+            .with_currency("stamina", Resource::new(10, Some(0), Some(20)))
+    }
+
+    #[test]
+    fn test_add_sub() {
+        let mut wallet = wallet();
+
+        wallet.checked_add("stamina", 5).unwrap();
+        assert_eq!(wallet.balance("stamina"), 15);
+
+        let err = wallet.checked_add("stamina", 10).unwrap_err();
+        assert!(err.downcast_ref::<InvalidAddition>().is_some());
+        assert_eq!(wallet.balance("stamina"), 15);
+
+        let err = wallet.checked_sub("stamina", 20).unwrap_err();
+        assert!(err.downcast_ref::<InvalidSubtraction>().is_some());
+
+        let err = wallet.checked_add("tokens", 1).unwrap_err();
+        assert!(err.downcast_ref::<UnknownCurrency>().is_some());
+        assert_eq!(wallet.balance("tokens"), 0);
+    }
+
+    #[test]
+    fn test_spend_priority() {
+        let mut wallet = wallet();
+        let priority = ["event_gems", "free_gems", "paid_gems"];
+        assert_eq!(wallet.available(&priority), 150);
+
+        let spent = wallet.spend(&priority, 30).unwrap();
+        assert_eq!(spent, vec![(String::from("free_gems"), 30)]);
+
+        let err = wallet.spend(&priority, 121).unwrap_err();
+        assert!(err.downcast_ref::<InsufficientFunds>().is_some());
+        assert_eq!(wallet.balance("free_gems"), 20);
+        assert_eq!(wallet.balance("paid_gems"), 100);
+
+        let spent = wallet.spend(&priority, 120).unwrap();
+        assert_eq!(
+            spent,
+            vec![
+                (String::from("free_gems"), 20),
+                (String::from("paid_gems"), 100)
+            ]
+        );
+
+        assert_eq!(wallet.spend(&priority, 0).unwrap(), Vec::new());
+    }
+
+    #[test]
+    fn test_spend_duplicate_currency() {
+        let mut wallet = Wallet::new().with_currency("gems", Resource::new(100, Some(0), None));
+        let priority = ["gems", "gems"];
+        assert_eq!(wallet.available(&priority), 100);
+
+        let err = wallet.spend(&priority, 150).unwrap_err();
+        assert!(err.downcast_ref::<InsufficientFunds>().is_some());
+        assert_eq!(wallet.balance("gems"), 100);
+
+        let spent = wallet.spend(&priority, 100).unwrap();
+        assert_eq!(spent, vec![(String::from("gems"), 100)]);
+    }
+
+    #[test]
+    fn test_spend_unbounded() {
+        let mut wallet = Wallet::new()
+            .with_currency("credit", Resource::new(0, None, None))
+            .with_currency("gems", Resource::new(10, Some(0), None));
+
+        assert_eq!(wallet.available(&["gems", "credit"]), i64::MAX);
+
+        let spent = wallet.spend(&["gems", "credit"], 25).unwrap();
+        assert_eq!(
+            spent,
+            vec![(String::from("gems"), 10), (String::from("credit"), 15)]
+        );
+        assert_eq!(wallet.balance("credit"), -15);
+    }
+
+    #[test]
+    fn test_wallet_wrapper() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut manager = EntityManager::new();
+
+        manager
+            .register_entity(LocalEntityStorage::<Player>::new())
+            .unwrap();
+
+        manager
+            .register_component(
+                "Wallet",
+                WalletBackendWrapper::new(
+                    FileComponentStorage::<Player, BTreeMap<String, Resource>>::open(
+                        dir.path(),
+                        "Wallet",
+                    )
+                    .unwrap(),
+                ),
+            )
+            .unwrap();
+
+        let id = Snowflake::from(1u64);
+        let mut player: Player = manager.create(id).unwrap();
+        player.set_component(wallet()).unwrap();
+        manager.store(player).unwrap();
+
+        let handle = manager.load::<Player>(id).unwrap();
+        let loaded: Wallet = handle.get().unwrap().get_component().unwrap().unwrap();
+        assert_eq!(loaded, wallet());
+    }
+}
@@ -1,10 +1,26 @@
 //! Akashi's Entity-Component-System architecture.
 
+pub mod async_store;
+pub mod blocking;
+pub mod cache;
 pub mod component;
 pub mod component_store;
 pub mod entity;
 pub mod entity_manager;
 pub mod entity_store;
+pub mod event;
+pub mod query;
+pub mod system;
+pub mod transaction;
+
+#[doc(inline)]
+pub use async_store::{
+    AsyncComponentBackend, AsyncComponentManager, AsyncEntityBackend, AsyncEntityStore,
+    AsyncReadReference, AsyncStore, AsyncStoreHandle, AsyncWriteReference,
+};
+
+#[doc(inline)]
+pub use cache::CachePolicy;
 
 #[doc(inline)]
 pub use component::{Component, ComponentManager};
@@ -13,16 +29,35 @@ pub use component::{Component, ComponentManager};
 pub use component_store::{ComponentAdapter, ComponentBackend};
 
 #[doc(inline)]
-pub use entity::Entity;
+pub use entity::{ChangeSet, Entity};
+
+pub use akashi_derive::{Component, Entity};
 
 #[doc(inline)]
-pub use entity_store::{EntityBackend, EntityStore, Store, StoreHandle};
+pub use entity_store::{DropPolicy, EntityBackend, EntityStore, KeyIter, Store, StoreHandle};
+
+pub(crate) use entity_store::store_attachment_changes;
 
 #[doc(inline)]
 pub use entity_manager::EntityManager;
 
+#[doc(inline)]
+pub use event::{Event, EventBus, EventFilter, EventKind, SubscriptionId};
+
+#[doc(inline)]
+pub use query::{Query, QueryComponents};
+
+#[doc(inline)]
+pub use system::{Scheduler, System, SystemAccess};
+
+#[doc(inline)]
+pub use transaction::{Transaction, TransactionalBackend};
+
+pub use cache::{FlushError, FlushErrors};
 pub use component_store::DowncastError;
 pub use entity::ClearComponentsError;
+pub use system::RunSystemsError;
+pub use transaction::TransactionError;
 
 #[derive(Fail, Debug)]
 #[fail(display = "No handlers registered for type {}", name)]
@@ -44,9 +79,16 @@ mod tests {
     use crate::local_storage::LocalComponentStorage;
     use crate::snowflake::SnowflakeGenerator;
 
+    use crate::local_storage::LocalEntityStorage;
+    use crate::snowflake::Snowflake;
+
     use std::any;
+    use std::any::TypeId;
+    use std::collections::HashSet;
     use std::fmt;
 
+    use dashmap::DashMap;
+
     use failure::{Error, Fail};
     use std::sync::Arc;
 
@@ -62,6 +104,26 @@ mod tests {
     struct TestComponentC(f64);
     impl Component<Card> for TestComponentC {}
 
+    #[derive(Entity)]
+    #[entity(clone)]
+    struct DerivedEntity {
+        #[entity(id)]
+        key: Snowflake,
+        #[entity(component_manager)]
+        manager: Arc<ComponentManager<DerivedEntity>>,
+        components_attached: HashSet<TypeId>,
+        preloaded_components: DashMap<TypeId, Box<dyn Component<Self> + Send + Sync + 'static>>,
+        changes: ChangeSet,
+        extra: Vec<u64>,
+    }
+
+    #[derive(Component, PartialEq, Debug, Clone)]
+    #[component(entity = DerivedEntity)]
+    struct DerivedComponent(u64);
+
+    #[derive(Component, PartialEq, Debug, Clone)]
+    struct GenericComponent<T: Send + Sync + 'static>(T);
+
     fn new_store<T: Component<Card> + Clone + 'static>() -> LocalComponentStorage<Card, T> {
         LocalComponentStorage::new()
     }
@@ -97,8 +159,8 @@ mod tests {
         cm.register_component("TestComponentA", new_store::<TestComponentA>())
             .unwrap();
 
-        let mut snowflake_gen = SnowflakeGenerator::new(0, 0);
-        let mut card = Card::generate(&mut snowflake_gen, Arc::new(cm));
+        let snowflake_gen = SnowflakeGenerator::new(0, 0);
+        let mut card = Card::generate(&snowflake_gen, Arc::new(cm));
 
         // Check to make sure attempts to use unregistered component types are gracefully handled.
         expect_err::<TypeNotFoundError, Option<TestComponentB>>(card.get_component());
@@ -117,8 +179,8 @@ mod tests {
         cm.register_component("TestComponentB", new_store::<TestComponentB>())
             .unwrap();
 
-        let mut snowflake_gen = SnowflakeGenerator::new(0, 0);
-        let mut card = Card::generate(&mut snowflake_gen, Arc::new(cm));
+        let snowflake_gen = SnowflakeGenerator::new(0, 0);
+        let mut card = Card::generate(&snowflake_gen, Arc::new(cm));
 
         let component_a: Option<TestComponentA> = card.get_component().unwrap();
         let component_b: Option<TestComponentB> = card.get_component().unwrap();
@@ -145,8 +207,8 @@ mod tests {
         cm.register_component("TestComponentA", new_store::<TestComponentA>())
             .unwrap();
 
-        let mut snowflake_gen = SnowflakeGenerator::new(0, 0);
-        let mut card = Card::generate(&mut snowflake_gen, Arc::new(cm));
+        let snowflake_gen = SnowflakeGenerator::new(0, 0);
+        let mut card = Card::generate(&snowflake_gen, Arc::new(cm));
 
         // Component hasn't been added yet.
         assert!(!card.has_component::<TestComponentA>());
@@ -164,8 +226,8 @@ mod tests {
         cm.register_component("TestComponentA", new_store::<TestComponentA>())
             .unwrap();
 
-        let mut snowflake_gen = SnowflakeGenerator::new(0, 0);
-        let mut card = Card::generate(&mut snowflake_gen, Arc::new(cm));
+        let snowflake_gen = SnowflakeGenerator::new(0, 0);
+        let mut card = Card::generate(&snowflake_gen, Arc::new(cm));
 
         // Deletion of nonexistent Components shouldn't fail.
         assert!(!card.has_component::<TestComponentA>());
@@ -179,4 +241,153 @@ mod tests {
         card.delete_component::<TestComponentA>().unwrap();
         assert!(!card.has_component::<TestComponentA>());
     }
+
+    #[test]
+    fn test_change_tracking() {
+        let mut cm = ComponentManager::new();
+        cm.register_component("TestComponentA", new_store::<TestComponentA>())
+            .unwrap();
+        cm.register_component("TestComponentB", new_store::<TestComponentB>())
+            .unwrap();
+
+        let snowflake_gen = SnowflakeGenerator::new(0, 0);
+        let mut card = Card::generate(&snowflake_gen, Arc::new(cm));
+        let type_a = TypeId::of::<TestComponentA>();
+        let type_b = TypeId::of::<TestComponentB>();
+        assert!(!card.dirty());
+
+        card.set_component(TestComponentA(1)).unwrap();
+        card.set_component(TestComponentB(2)).unwrap();
+        assert!(card.changes().was_set(&type_a));
+        assert!(card.changes().was_set(&type_b));
+        assert!(card.changes().attachments_changed());
+
+        let changes = card.take_changes();
+        assert_eq!(changes.set_components().len(), 2);
+        assert!(!card.dirty());
+
+        // Updating an attached component doesn't change attachments.
+        card.set_component(TestComponentA(3)).unwrap();
+        assert!(card.changes().contains(&type_a));
+        assert!(!card.changes().contains(&type_b));
+        assert!(!card.changes().attachments_changed());
+
+        // Deleting a component replaces any earlier update to it.
+        card.delete_component::<TestComponentA>().unwrap();
+        assert!(card.changes().was_deleted(&type_a));
+        assert!(!card.changes().was_set(&type_a));
+        assert!(card.changes().attachments_changed());
+
+        card.changes_mut().clear();
+        assert!(!card.dirty());
+    }
+
+    #[test]
+    fn test_async_entity_manager() {
+        use crate::ecs::blocking::{
+            block_on, BlockingComponentBackend, BlockingEntityBackend, BlockingPool,
+        };
+        use crate::local_storage::LocalEntityStorage;
+
+        fn assert_send<F: Send>(future: F) -> F {
+            future
+        }
+
+        let pool = Arc::new(BlockingPool::new(2).unwrap());
+        let mut manager = EntityManager::new();
+        manager
+            .register_async_entity(BlockingEntityBackend::new(
+                LocalEntityStorage::<Card>::new(),
+                pool.clone(),
+            ))
+            .unwrap();
+        manager
+            .register_async_component(
+                "TestComponentA",
+                BlockingComponentBackend::new(new_store::<TestComponentA>(), pool),
+            )
+            .unwrap();
+
+        // Async-registered types can't be registered again, or used
+        // synchronously.
+        assert!(manager
+            .register_entity(LocalEntityStorage::<Card>::new())
+            .is_err());
+        assert!(manager.load::<Card>(1u64.into()).is_err());
+
+        let components = manager.async_components::<Card>().unwrap();
+
+        block_on(assert_send(async {
+            let mut card: Card = manager.create(1u64.into()).unwrap();
+            components
+                .set_component(&mut card, TestComponentA(5))
+                .await
+                .unwrap();
+            manager.store_async(card).await.unwrap();
+            assert!(manager.exists_async::<Card>(1u64.into()).await.unwrap());
+
+            let card = manager
+                .load_mut_async::<Card>(1u64.into())
+                .await
+                .unwrap()
+                .take()
+                .unwrap();
+            let component: Option<TestComponentA> = components.get_component(&card).await.unwrap();
+            assert_eq!(component, Some(TestComponentA(5)));
+            manager.store_async(card).await.unwrap();
+
+            manager.delete_async::<Card>(1u64.into()).await.unwrap();
+            assert!(!manager.exists_async::<Card>(1u64.into()).await.unwrap());
+            assert!(!components
+                .component_exists::<TestComponentA>(&manager.create(1u64.into()).unwrap())
+                .await
+                .unwrap());
+        }));
+    }
+
+    #[test]
+    fn test_derive() {
+        let mut manager = EntityManager::new();
+        manager
+            .register_entity(LocalEntityStorage::<DerivedEntity>::new())
+            .unwrap();
+        manager
+            .register_component(
+                "DerivedComponent",
+                LocalComponentStorage::<DerivedEntity, DerivedComponent>::new(),
+            )
+            .unwrap();
+        manager
+            .register_component(
+                "GenericComponent",
+                LocalComponentStorage::<DerivedEntity, GenericComponent<String>>::new(),
+            )
+            .unwrap();
+
+        let id: Snowflake = 1u64.into();
+        let mut entity: DerivedEntity = manager.create(id).unwrap();
+        assert_eq!(entity.id(), id);
+        assert_eq!(entity.key, id);
+        assert!(entity.extra.is_empty());
+        assert!(!entity.dirty());
+
+        entity.set_component(DerivedComponent(5)).unwrap();
+        entity
+            .set_component(GenericComponent(String::from("hello")))
+            .unwrap();
+        assert!(entity.dirty());
+        assert_eq!(entity.components_attached().len(), 2);
+        manager.store(entity).unwrap();
+
+        let handle = manager.load::<DerivedEntity>(id).unwrap();
+        let entity = handle.get().unwrap();
+        assert_eq!(
+            entity.get_component::<DerivedComponent>().unwrap(),
+            Some(DerivedComponent(5))
+        );
+        assert_eq!(
+            entity.get_component::<GenericComponent<String>>().unwrap(),
+            Some(GenericComponent(String::from("hello")))
+        );
+    }
 }
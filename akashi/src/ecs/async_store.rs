@@ -0,0 +1,1370 @@
+//! Async counterparts to [`Store`](super::Store) and its backend traits.
+//!
+//! [`AsyncEntityBackend`] and [`AsyncComponentBackend`] mirror
+//! [`EntityBackend`](super::EntityBackend) and
+//! [`ComponentBackend`](super::ComponentBackend), except that their
+//! methods return futures instead of blocking. Existing blocking backends
+//! can be used through the adapters in the [`blocking`](super::blocking)
+//! module.
+//!
+//! An [`AsyncStore`] coordinates access to [`Entities`](Entity) in the
+//! same way that a [`Store`](super::Store) does: each [`Entity`] is only
+//! loaded once, even if several tasks ask for it at the same time, and all
+//! of them share the resulting handle.
+//!
+//! [`Component`] data is accessed through an [`AsyncComponentManager`],
+//! which holds an [`AsyncComponentBackend`] for each registered
+//! [`Component`] type. Each [`AsyncStore`] has one, and uses it to delete
+//! the [`Components`](Component) attached to [`Entities`](Entity) that it
+//! deletes.
+//!
+//! # Handles and locking
+//!
+//! [`AsyncStore::load`] and [`AsyncStore::load_mut`] return
+//! [`AsyncReadReference`]s and [`AsyncWriteReference`]s to handles. These
+//! are locked asynchronously: a task waiting for a handle that's in use
+//! is suspended instead of blocking its thread, so tasks sharing a
+//! single-threaded executor can't deadlock each other by waiting on the
+//! same handle. The references themselves can be held across `.await`s
+//! and sent between threads, so changes can be saved by calling
+//! [`AsyncStoreHandle::store`] on a reference from [`AsyncStore::load_mut`].
+//!
+//! As with a synchronous [`Store`](super::Store), a task shouldn't call
+//! [`AsyncStore::store`] or [`AsyncStore::delete`] for an [`Entity`]
+//! while it's still holding a reference to that [`Entity`]'s handle; it
+//! would wait forever for the reference to be released.
+//!
+//! Unlike [`StoreHandles`](super::StoreHandle), [`AsyncStoreHandles`](AsyncStoreHandle)
+//! can't write back changes when they are dropped, since that would mean
+//! blocking on storage; changes have to be stored explicitly.
+
+use super::event::{Event, EventBus, EventKind};
+use super::{
+    ChangeSet, ClearComponentsError, Component, ComponentManager, Entity, TypeNotFoundError,
+};
+use crate::snowflake::Snowflake;
+use crate::util::Result;
+
+use std::any::{self, Any, TypeId};
+use std::cell::UnsafeCell;
+use std::collections::HashMap;
+use std::fmt;
+use std::future::{self, Future};
+use std::mem;
+use std::ops::{Deref, DerefMut};
+use std::pin::Pin;
+use std::result;
+use std::sync::{Arc, Weak};
+use std::task::{Context, Poll, Waker};
+
+use dashmap::DashMap;
+use failure::format_err;
+use parking_lot::Mutex;
+
+/// A boxed future that can be sent between threads.
+pub type BoxFuture<'a, T> = Pin<Box<dyn Future<Output = T> + Send + 'a>>;
+
+/// An async version of [`EntityBackend`](super::EntityBackend).
+///
+/// Structs that implement this trait can be passed to
+/// [`AsyncStore::new`] or
+/// [`EntityManager::register_async_entity`](super::EntityManager::register_async_entity).
+pub trait AsyncEntityBackend<T: Entity + 'static>: Send + Sync {
+    /// Loads data for an [`Entity`] from storage, if any [`Entity`] with
+    /// the given ID exists.
+    fn load(&self, id: Snowflake, cm: Arc<ComponentManager<T>>)
+        -> BoxFuture<'_, Result<Option<T>>>;
+
+    /// Checks to see if an [`Entity`] with the given ID exists in storage.
+    fn exists(&self, id: Snowflake) -> BoxFuture<'_, Result<bool>>;
+
+    /// Saves data for an [`Entity`] to storage.
+    fn store<'a>(&'a self, id: Snowflake, object: &'a T) -> BoxFuture<'a, Result<()>>;
+
+    /// Deletes data for an [`Entity`] from storage.
+    fn delete(&self, id: Snowflake) -> BoxFuture<'_, Result<()>>;
+
+    /// Retrieve a list of [`Entity`] IDs from storage.
+    fn keys(&self, page: u64, limit: u64) -> BoxFuture<'_, Result<Vec<Snowflake>>>;
+}
+
+/// An async version of [`ComponentBackend`](super::ComponentBackend).
+pub trait AsyncComponentBackend<T, U>: Send + Sync
+where
+    T: Entity + 'static,
+    U: Component<T> + 'static,
+{
+    /// Loads an instance of a [`Component`] from storage.
+    fn load<'a>(&'a self, entity: &'a T) -> BoxFuture<'a, Result<Option<U>>>;
+
+    /// Saves an instance of a [`Component`] to storage.
+    fn store<'a>(&'a self, entity: &'a T, component: U) -> BoxFuture<'a, Result<()>>;
+
+    /// Check to see if there is any stored [`Component`] data associated
+    /// with an `Entity`.
+    fn exists<'a>(&'a self, entity: &'a T) -> BoxFuture<'a, Result<bool>>;
+
+    /// Delete the stored [`Component`] data associated with the given
+    /// `Entity`, if any.
+    fn delete<'a>(&'a self, entity: &'a T) -> BoxFuture<'a, Result<()>>;
+
+    /// Lists the IDs of all [`Entities`](Entity) that have [`Component`]
+    /// data stored in this backend.
+    ///
+    /// As with [`ComponentBackend::keys`](super::ComponentBackend::keys),
+    /// backends that can't list their contents can leave this as the
+    /// default implementation, which returns `Ok(None)`.
+    fn keys(&self) -> BoxFuture<'_, Result<Option<Vec<Snowflake>>>> {
+        Box::pin(future::ready(Ok(None)))
+    }
+}
+
+type AsyncDeleteFn<T> = Box<dyn for<'a> Fn(&'a T) -> BoxFuture<'a, Result<()>> + Send + Sync>;
+
+// The registered backend for a component type, along with a type-erased
+// delete function for clearing out entities.
+struct AsyncComponentTypeData<T: Entity + 'static> {
+    // Holds an `Arc<dyn AsyncComponentBackend<T, U>>`.
+    backend: Box<dyn Any + Send + Sync>,
+    delete: AsyncDeleteFn<T>,
+}
+
+/// Manages async access to [`Component`] data, the way a
+/// [`ComponentManager`] does for blocking backends.
+///
+/// Each [`Component`] type is registered with an
+/// [`AsyncComponentBackend`]. The methods on this manager are async
+/// versions of the [`Entity`] trait's component methods, and keep the
+/// [`Entity`]'s attached components and [`ChangeSet`] up to date in the
+/// same way.
+///
+/// [`AsyncStores`](AsyncStore) use an `AsyncComponentManager` to delete the
+/// components attached to [`Entities`](Entity) that they delete.
+///
+/// # Example
+///
+/// ```
+/// use akashi::{Card, Entity};
+/// use akashi::ecs::{AsyncComponentManager, Component, ComponentManager};
+/// use akashi::ecs::blocking::{block_on, BlockingComponentBackend, BlockingPool};
+/// use akashi::local_storage::LocalComponentStorage;
+/// use std::collections::HashSet;
+/// use std::sync::Arc;
+///
+/// #[derive(Clone, Debug, PartialEq)]
+/// struct Level(u64);
+/// impl Component<Card> for Level {}
+///
+/// let pool = Arc::new(BlockingPool::new(2).unwrap());
+/// let mut components = AsyncComponentManager::new();
+/// components
+///     .register_component(
+///         "Level",
+///         BlockingComponentBackend::new(LocalComponentStorage::<Card, Level>::new(), pool),
+///     )
+///     .unwrap();
+///
+/// let mut card = Card::new(1u64.into(), Arc::new(ComponentManager::new()), HashSet::new());
+///
+/// block_on(async {
+///     components.set_component(&mut card, Level(3)).await.unwrap();
+///     assert!(card.has_component::<Level>());
+///
+///     let level: Option<Level> = components.get_component(&card).await.unwrap();
+///     assert_eq!(level, Some(Level(3)));
+/// });
+/// ```
+pub struct AsyncComponentManager<T: Entity + 'static> {
+    component_types: HashMap<TypeId, AsyncComponentTypeData<T>>,
+    component_names: HashMap<TypeId, String>,
+    events: Arc<EventBus>,
+}
+
+impl<T: Entity + 'static> AsyncComponentManager<T> {
+    pub fn new() -> AsyncComponentManager<T> {
+        AsyncComponentManager::with_events(Arc::new(EventBus::new()))
+    }
+
+    /// Creates a new `AsyncComponentManager` that reports changes to the
+    /// given [`EventBus`].
+    pub fn with_events(events: Arc<EventBus>) -> AsyncComponentManager<T> {
+        AsyncComponentManager {
+            component_types: HashMap::new(),
+            component_names: HashMap::new(),
+            events,
+        }
+    }
+
+    /// Gets the [`EventBus`] that this manager reports changes to.
+    pub fn events(&self) -> &Arc<EventBus> {
+        &self.events
+    }
+
+    fn emit(&self, kind: EventKind, entity: &T, type_id: &TypeId) {
+        self.events.emit(Event::component::<T>(
+            kind,
+            entity.id(),
+            *type_id,
+            self.component_name(type_id),
+        ));
+    }
+
+    /// Registers a backing storage object and unique name for a
+    /// [`Component`] type.
+    pub fn register_component<U, V>(&mut self, name: &str, backend: V) -> Result<()>
+    where
+        U: Component<T> + 'static,
+        V: AsyncComponentBackend<T, U> + 'static,
+    {
+        let type_id = TypeId::of::<U>();
+        if self.component_types.contains_key(&type_id) {
+            return Err(format_err!(
+                "component type already registered: {}",
+                any::type_name::<U>()
+            ));
+        }
+
+        let backend: Arc<dyn AsyncComponentBackend<T, U>> = Arc::new(backend);
+        let deleter = backend.clone();
+        let delete: AsyncDeleteFn<T> = Box::new(move |entity| {
+            let backend = deleter.clone();
+            Box::pin(async move { backend.delete(entity).await })
+        });
+
+        self.component_types.insert(
+            type_id,
+            AsyncComponentTypeData {
+                backend: Box::new(backend),
+                delete,
+            },
+        );
+        self.component_names.insert(type_id, name.to_owned());
+
+        Ok(())
+    }
+
+    /// Check to see if a particular [`Component`] type has a registered
+    /// backend.
+    pub fn is_registered<U: Component<T> + 'static>(&self) -> bool {
+        self.component_types.contains_key(&TypeId::of::<U>())
+    }
+
+    pub fn component_name(&self, type_id: &TypeId) -> Option<&str> {
+        self.component_names.get(type_id).map(|r| r.as_str())
+    }
+
+    fn backend<U: Component<T> + 'static>(&self) -> Result<&Arc<dyn AsyncComponentBackend<T, U>>> {
+        self.component_types
+            .get(&TypeId::of::<U>())
+            .map(|data| {
+                data.backend
+                    .downcast_ref::<Arc<dyn AsyncComponentBackend<T, U>>>()
+                    .expect("failed to downcast AsyncComponentBackend")
+            })
+            .ok_or_else(|| TypeNotFoundError::new(any::type_name::<U>().to_owned()).into())
+    }
+
+    /// Gets a [`Component`] attached to an [`Entity`].
+    ///
+    /// Like [`Entity::get_component`], this returns preloaded
+    /// components without going to storage.
+    pub async fn get_component<U: Component<T> + 'static>(&self, entity: &T) -> Result<Option<U>> {
+        let backend = self.backend::<U>()?;
+
+        if let Some((_type_id, boxed)) = entity.preloaded_components().remove(&TypeId::of::<U>()) {
+            let boxed: Box<dyn Component<T> + 'static> = boxed;
+            if let Ok(downcasted) = boxed.downcast::<U>() {
+                return Ok(Some(*downcasted));
+            }
+        }
+
+        if !entity.has_component::<U>() {
+            return Ok(None);
+        }
+
+        backend.load(entity).await
+    }
+
+    /// Attaches a [`Component`] to an [`Entity`], or updates an
+    /// already-attached [`Component`].
+    pub async fn set_component<U: Component<T> + 'static>(
+        &self,
+        entity: &mut T,
+        component: U,
+    ) -> Result<()> {
+        let type_id = TypeId::of::<U>();
+        self.backend::<U>()?.store(entity, component).await?;
+
+        let attached = entity.components_attached_mut().insert(type_id);
+        entity.changes_mut().record_set(type_id, attached);
+        self.emit(EventKind::ComponentSet, entity, &type_id);
+        Ok(())
+    }
+
+    /// Deletes an attached [`Component`] from an [`Entity`].
+    pub async fn delete_component<U: Component<T> + 'static>(&self, entity: &mut T) -> Result<()> {
+        let type_id = TypeId::of::<U>();
+        let backend = self.backend::<U>()?;
+
+        let detached = entity.components_attached_mut().remove(&type_id);
+        entity.changes_mut().record_delete(type_id, detached);
+        backend.delete(entity).await?;
+        self.emit(EventKind::ComponentDeleted, entity, &type_id);
+        Ok(())
+    }
+
+    /// Check to see if associated [`Component`] data exists for the given
+    /// [`Entity`] and [`Component`] type.
+    pub async fn component_exists<U: Component<T> + 'static>(&self, entity: &T) -> Result<bool> {
+        self.backend::<U>()?.exists(entity).await
+    }
+
+    /// List the IDs of all entities with stored data for a [`Component`]
+    /// type.
+    ///
+    /// Returns `Ok(None)` if the backend for the [`Component`] type
+    /// doesn't support listing its contents.
+    pub async fn component_keys<U: Component<T> + 'static>(
+        &self,
+    ) -> Result<Option<Vec<Snowflake>>> {
+        self.backend::<U>()?.keys().await
+    }
+
+    /// Deletes all [`Components`](Component) attached to an [`Entity`].
+    ///
+    /// # Errors
+    ///
+    /// As with [`Entity::clear_components`], errors from the backends
+    /// are collected into a [`ClearComponentsError`]. Attached
+    /// [`Components`](Component) without a registered backend count as
+    /// errors too.
+    pub async fn clear_components(
+        &self,
+        entity: &mut T,
+    ) -> result::Result<(), ClearComponentsError> {
+        let mut err = ClearComponentsError::new();
+        let attached: Vec<TypeId> = entity.components_attached().iter().copied().collect();
+
+        for type_id in attached.iter() {
+            let res = match self.component_types.get(type_id) {
+                Some(data) => (data.delete)(entity).await,
+                None => Err(TypeNotFoundError::new(format!("{:?}", type_id)).into()),
+            };
+
+            match res {
+                Ok(_) => self.emit(EventKind::ComponentDeleted, entity, type_id),
+                Err(e) => err.push(e),
+            }
+        }
+
+        entity.components_attached_mut().clear();
+        for type_id in attached {
+            entity.changes_mut().record_delete(type_id, true);
+        }
+
+        if err.len() > 0 {
+            Err(err)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+impl<T: Entity + 'static> Default for AsyncComponentManager<T> {
+    fn default() -> AsyncComponentManager<T> {
+        AsyncComponentManager::new()
+    }
+}
+
+impl<T: Entity + 'static> fmt::Debug for AsyncComponentManager<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "AsyncComponentManager<{}> {{ {} types }}",
+            any::type_name::<T>(),
+            self.component_types.len()
+        )
+    }
+}
+
+enum InitState {
+    New,
+    Running(Vec<Waker>),
+    Done,
+}
+
+// An async-aware equivalent of `Once`, used to make sure that each
+// handle is only initialized by one task.
+//
+// If the task initializing a handle fails or is cancelled, the next task
+// waiting on it takes over.
+struct AsyncOnce {
+    state: Mutex<InitState>,
+}
+
+impl AsyncOnce {
+    fn new() -> AsyncOnce {
+        AsyncOnce {
+            state: Mutex::new(InitState::New),
+        }
+    }
+
+    fn is_done(&self) -> bool {
+        matches!(*self.state.lock(), InitState::Done)
+    }
+
+    // Resolves to `Some(guard)` if the caller should run initialization,
+    // or `None` once some other task has finished it.
+    fn begin(&self) -> impl Future<Output = Option<InitGuard<'_>>> + '_ {
+        future::poll_fn(move |cx: &mut Context<'_>| {
+            let mut state = self.state.lock();
+            match &mut *state {
+                InitState::Done => Poll::Ready(None),
+                InitState::Running(wakers) => {
+                    wakers.push(cx.waker().clone());
+                    Poll::Pending
+                }
+                InitState::New => {
+                    *state = InitState::Running(Vec::new());
+                    Poll::Ready(Some(InitGuard {
+                        once: self,
+                        finished: false,
+                    }))
+                }
+            }
+        })
+    }
+}
+
+struct InitGuard<'a> {
+    once: &'a AsyncOnce,
+    finished: bool,
+}
+
+impl InitGuard<'_> {
+    fn finish(mut self) {
+        self.finished = true;
+    }
+}
+
+impl Drop for InitGuard<'_> {
+    fn drop(&mut self) {
+        let next = if self.finished {
+            InitState::Done
+        } else {
+            InitState::New
+        };
+
+        let prev = mem::replace(&mut *self.once.state.lock(), next);
+        if let InitState::Running(wakers) = prev {
+            for waker in wakers {
+                waker.wake();
+            }
+        }
+    }
+}
+
+struct LockState {
+    readers: usize,
+    writer: bool,
+    waiting: Vec<Waker>,
+}
+
+// An async-aware read-write lock, used to guard handles.
+//
+// Tasks waiting on the lock are suspended instead of blocking their
+// thread. Its guards hold a strong reference to the lock rather than
+// borrowing it, so they can be kept across `.await`s and sent between
+// threads. Every waiting task is woken whenever the lock is released, so
+// this isn't fair: a steady stream of readers can hold off a writer.
+struct AsyncRwLock<T> {
+    state: Mutex<LockState>,
+    value: UnsafeCell<T>,
+}
+
+// The lock state makes sure that the value is either shared by readers,
+// or borrowed mutably by a single writer.
+unsafe impl<T: Send> Send for AsyncRwLock<T> {}
+unsafe impl<T: Send + Sync> Sync for AsyncRwLock<T> {}
+
+impl<T> AsyncRwLock<T> {
+    fn new(value: T) -> AsyncRwLock<T> {
+        AsyncRwLock {
+            state: Mutex::new(LockState {
+                readers: 0,
+                writer: false,
+                waiting: Vec::new(),
+            }),
+            value: UnsafeCell::new(value),
+        }
+    }
+
+    fn read(this: Arc<Self>) -> impl Future<Output = AsyncReadReference<T>> {
+        future::poll_fn(move |cx: &mut Context<'_>| {
+            let mut state = this.state.lock();
+            if state.writer {
+                state.waiting.push(cx.waker().clone());
+                Poll::Pending
+            } else {
+                state.readers += 1;
+                Poll::Ready(AsyncReadReference { lock: this.clone() })
+            }
+        })
+    }
+
+    fn write(this: Arc<Self>) -> impl Future<Output = AsyncWriteReference<T>> {
+        future::poll_fn(move |cx: &mut Context<'_>| {
+            let mut state = this.state.lock();
+            if state.writer || state.readers > 0 {
+                state.waiting.push(cx.waker().clone());
+                Poll::Pending
+            } else {
+                state.writer = true;
+                Poll::Ready(AsyncWriteReference { lock: this.clone() })
+            }
+        })
+    }
+
+    fn wake_all(state: &mut LockState) {
+        for waker in state.waiting.drain(..) {
+            waker.wake();
+        }
+    }
+}
+
+/// A read-locked reference to a value, returned by [`AsyncStore::load`].
+///
+/// Unlike a [`ReadReference`](super::entity_store::ReadReference), this
+/// can be held across `.await`s and sent between threads.
+pub struct AsyncReadReference<T> {
+    lock: Arc<AsyncRwLock<T>>,
+}
+
+impl<T> Deref for AsyncReadReference<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        // Safe, since the lock is read-locked while this reference exists.
+        unsafe { &*self.lock.value.get() }
+    }
+}
+
+impl<T> Drop for AsyncReadReference<T> {
+    fn drop(&mut self) {
+        let mut state = self.lock.state.lock();
+        state.readers -= 1;
+        if state.readers == 0 {
+            AsyncRwLock::<T>::wake_all(&mut state);
+        }
+    }
+}
+
+/// A write-locked reference to a value, returned by
+/// [`AsyncStore::load_mut`].
+///
+/// Unlike a [`WriteReference`](super::entity_store::WriteReference), this
+/// can be held across `.await`s and sent between threads.
+pub struct AsyncWriteReference<T> {
+    lock: Arc<AsyncRwLock<T>>,
+}
+
+impl<T> Deref for AsyncWriteReference<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        // Safe, since the lock is write-locked while this reference exists.
+        unsafe { &*self.lock.value.get() }
+    }
+}
+
+impl<T> DerefMut for AsyncWriteReference<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        // Safe, since the lock is write-locked while this reference exists.
+        unsafe { &mut *self.lock.value.get() }
+    }
+}
+
+impl<T> Drop for AsyncWriteReference<T> {
+    fn drop(&mut self) {
+        let mut state = self.lock.state.lock();
+        state.writer = false;
+        AsyncRwLock::<T>::wake_all(&mut state);
+    }
+}
+
+/// A shared handle to an [`Entity`] and its async storage backend.
+///
+/// This works like a [`StoreHandle`](super::StoreHandle), except that its
+/// storage methods are async, and that dirty [`Entities`](Entity) aren't
+/// written back when the handle is dropped.
+pub struct AsyncStoreHandle<T>
+where
+    T: Entity + 'static,
+{
+    backend: Arc<dyn AsyncEntityBackend<T> + 'static>,
+    components: Arc<AsyncComponentManager<T>>,
+    events: Arc<EventBus>,
+    id: Snowflake,
+    object: Option<T>,
+}
+
+impl<T> AsyncStoreHandle<T>
+where
+    T: Entity + 'static,
+{
+    /// Gets a reference to the object within this handle.
+    pub fn get(&self) -> Option<&T> {
+        self.object.as_ref()
+    }
+
+    /// Gets a mutable reference to the object within this handle.
+    pub fn get_mut(&mut self) -> Option<&mut T> {
+        self.object.as_mut()
+    }
+
+    /// Replaces the object within this handle with something else.
+    pub fn replace(&mut self, object: T) -> Option<T> {
+        self.object.replace(object)
+    }
+
+    /// Takes the object out of this handle, leaving it empty.
+    ///
+    /// Unlike [`delete`](AsyncStoreHandle::delete), this doesn't touch
+    /// storage.
+    pub fn take(&mut self) -> Option<T> {
+        self.object.take()
+    }
+
+    /// Gets the ID of the [`Entity`] in this handle.
+    pub fn id(&self) -> Snowflake {
+        self.id
+    }
+
+    /// Checks whether anything is actually contained in this handle.
+    pub fn exists(&self) -> bool {
+        self.object.is_some()
+    }
+
+    /// Gets the [`AsyncComponentManager`] for the store this handle
+    /// belongs to.
+    pub fn components(&self) -> &Arc<AsyncComponentManager<T>> {
+        &self.components
+    }
+
+    /// Gets the changes made to the [`Entity`] in this handle since it
+    /// was loaded or last stored, if there is one.
+    pub fn changes(&self) -> Option<&ChangeSet> {
+        self.object.as_ref().map(|obj| obj.changes())
+    }
+
+    /// Puts whatever is in this handle into storage.
+    ///
+    /// As with [`StoreHandle::store`](super::StoreHandle::store), the
+    /// [`Entity`] isn't marked as clean afterwards.
+    pub async fn store(&self) -> Result<()> {
+        let event = match &self.object {
+            None => {
+                self.backend.delete(self.id).await?;
+                Event::entity::<T>(EventKind::EntityDeleted, self.id)
+            }
+            Some(obj) => {
+                self.backend.store(self.id, obj).await?;
+                Event::entity::<T>(EventKind::EntityStored, self.id)
+                    .with_changes(obj.changes().clone())
+            }
+        };
+
+        self.events.emit(event);
+        Ok(())
+    }
+
+    /// Clears out the data in this handle, then deletes the [`Entity`]
+    /// from storage.
+    ///
+    /// Attached [`Components`](Component) are deleted through the
+    /// store's [`AsyncComponentManager`].
+    pub async fn delete(&mut self) -> Result<()> {
+        if let Some(obj) = &mut self.object {
+            self.components.clear_components(obj).await?;
+        }
+
+        self.object = None;
+        self.backend.delete(self.id).await?;
+        self.events
+            .emit(Event::entity::<T>(EventKind::EntityDeleted, self.id));
+        Ok(())
+    }
+
+    fn set_object(&mut self, object: Option<T>) {
+        self.object = object;
+    }
+}
+
+struct AsyncStoredHandleData<T>
+where
+    T: Entity + 'static,
+{
+    initializer: Arc<AsyncOnce>,
+    handle: Weak<AsyncRwLock<AsyncStoreHandle<T>>>,
+}
+
+// Strong version of AsyncStoredHandleData
+struct AsyncHandleData<T>
+where
+    T: Entity + 'static,
+{
+    initializer: Arc<AsyncOnce>,
+    handle: Arc<AsyncRwLock<AsyncStoreHandle<T>>>,
+}
+
+/// Handles storing [`Entities`](Entity) through an
+/// [`AsyncEntityBackend`], and coordinating access to them across tasks.
+///
+/// See the [module documentation](self) for details on how this differs
+/// from a [`Store`](super::Store).
+///
+/// # Example
+///
+/// ```
+/// use akashi::{Card, Entity};
+/// use akashi::ecs::{AsyncStore, ComponentManager};
+/// use akashi::ecs::blocking::{block_on, BlockingEntityBackend, BlockingPool};
+/// use akashi::local_storage::LocalEntityStorage;
+/// use std::collections::HashSet;
+/// use std::sync::Arc;
+///
+/// let pool = Arc::new(BlockingPool::new(2).unwrap());
+/// let backend = BlockingEntityBackend::new(LocalEntityStorage::new(), pool);
+/// let store = AsyncStore::new(Arc::new(backend));
+/// let cm = Arc::new(ComponentManager::new());
+///
+/// block_on(async {
+///     let card = Card::new(1u64.into(), cm.clone(), HashSet::new());
+///     store.store(card).await.unwrap();
+///
+///     let handle = store.load(1u64.into(), cm.clone()).await.unwrap();
+///     assert!(handle.exists());
+/// });
+/// ```
+pub struct AsyncStore<T, U>
+where
+    T: Entity + 'static,
+    U: AsyncEntityBackend<T> + 'static,
+{
+    backend: Arc<U>,
+    components: Arc<AsyncComponentManager<T>>,
+    events: Arc<EventBus>,
+    refs: DashMap<Snowflake, AsyncStoredHandleData<T>>,
+}
+
+impl<T, U> AsyncStore<T, U>
+where
+    T: Entity + 'static,
+    U: AsyncEntityBackend<T> + 'static,
+{
+    /// Creates a new `AsyncStore` with its own [`EventBus`].
+    pub fn new(backend: Arc<U>) -> AsyncStore<T, U> {
+        AsyncStore::with_events(backend, Arc::new(EventBus::new()))
+    }
+
+    /// Creates a new `AsyncStore` that reports changes to the given
+    /// [`EventBus`].
+    ///
+    /// The store starts out with an empty [`AsyncComponentManager`] that
+    /// reports to the same [`EventBus`].
+    pub fn with_events(backend: Arc<U>, events: Arc<EventBus>) -> AsyncStore<T, U> {
+        AsyncStore {
+            backend,
+            components: Arc::new(AsyncComponentManager::with_events(events.clone())),
+            events,
+            refs: DashMap::new(),
+        }
+    }
+
+    /// Sets the [`AsyncComponentManager`] used to access
+    /// [`Component`] data for this store's [`Entities`](Entity).
+    pub fn with_components(mut self, components: AsyncComponentManager<T>) -> AsyncStore<T, U> {
+        self.components = Arc::new(components);
+        self
+    }
+
+    /// Gets the [`EventBus`] that this store reports changes to.
+    pub fn events(&self) -> &Arc<EventBus> {
+        &self.events
+    }
+
+    /// Gets the [`AsyncComponentManager`] for this store.
+    pub fn components(&self) -> &Arc<AsyncComponentManager<T>> {
+        &self.components
+    }
+
+    /// Retrieves or creates a possibly-uninitialized [`AsyncStoreHandle`]
+    /// from the underlying hashmap.
+    fn get_handle(&self, id: Snowflake) -> AsyncHandleData<T> {
+        let mut entry = self
+            .refs
+            .entry(id)
+            .or_insert_with(|| AsyncStoredHandleData {
+                initializer: Arc::new(AsyncOnce::new()),
+                handle: Weak::new(),
+            });
+
+        if let Some(strong) = entry.handle.upgrade() {
+            AsyncHandleData {
+                initializer: entry.initializer.clone(),
+                handle: strong,
+            }
+        } else {
+            let backend: Arc<dyn AsyncEntityBackend<T> + 'static> = self.backend.clone();
+            let handle = AsyncStoreHandle {
+                backend,
+                components: self.components.clone(),
+                events: self.events.clone(),
+                id,
+                object: None,
+            };
+
+            let initializer = Arc::new(AsyncOnce::new());
+            let strong = Arc::new(AsyncRwLock::new(handle));
+
+            entry.handle = Arc::downgrade(&strong);
+            entry.initializer = initializer.clone();
+
+            AsyncHandleData {
+                initializer,
+                handle: strong,
+            }
+        }
+    }
+
+    // Initializes a handle by loading data from the backend, if no other
+    // task has done so already.
+    async fn initialize_handle(
+        &self,
+        id: Snowflake,
+        cm: Arc<ComponentManager<T>>,
+    ) -> Result<AsyncHandleData<T>> {
+        let handle_data = self.get_handle(id);
+
+        if let Some(guard) = handle_data.initializer.begin().await {
+            let mut data = self.backend.load(id, cm).await?;
+
+            // Freshly-loaded entities haven't been changed yet.
+            if let Some(entity) = &mut data {
+                entity.changes_mut().clear();
+            }
+
+            AsyncRwLock::write(handle_data.handle.clone())
+                .await
+                .set_object(data);
+            guard.finish();
+        }
+
+        Ok(handle_data)
+    }
+
+    /// Gets an immutable reference to the handle for the [`Entity`]
+    /// with the given ID.
+    ///
+    /// Data for the [`Entity`] will be loaded from storage if needed.
+    pub async fn load(
+        &self,
+        id: Snowflake,
+        cm: Arc<ComponentManager<T>>,
+    ) -> Result<AsyncReadReference<AsyncStoreHandle<T>>> {
+        let handle_data = self.initialize_handle(id, cm).await?;
+        Ok(AsyncRwLock::read(handle_data.handle).await)
+    }
+
+    /// Gets a mutable reference to the handle for the [`Entity`] with
+    /// the given ID.
+    ///
+    /// Data for the [`Entity`] will be loaded from storage if needed.
+    pub async fn load_mut(
+        &self,
+        id: Snowflake,
+        cm: Arc<ComponentManager<T>>,
+    ) -> Result<AsyncWriteReference<AsyncStoreHandle<T>>> {
+        let handle_data = self.initialize_handle(id, cm).await?;
+        Ok(AsyncRwLock::write(handle_data.handle).await)
+    }
+
+    /// Puts the given [`Entity`] into storage, overwriting any previously
+    /// stored [`Entity`] data with the same ID.
+    ///
+    /// The [`Entity`] is moved into its handle afterwards, even if
+    /// storing it fails (in which case it's left dirty).
+    pub async fn store(&self, mut object: T) -> Result<()> {
+        let id = object.id();
+        let res = self.backend.store(id, &object).await;
+
+        let event = match &res {
+            Ok(_) => Some(
+                Event::entity::<T>(EventKind::EntityStored, id).with_changes(object.take_changes()),
+            ),
+            Err(_) => None,
+        };
+
+        let handle_data = self.get_handle(id);
+        let init = handle_data.initializer.begin().await;
+        AsyncRwLock::write(handle_data.handle)
+            .await
+            .set_object(Some(object));
+
+        if let Some(guard) = init {
+            guard.finish();
+        }
+
+        if let Some(event) = event {
+            self.events.emit(event);
+        }
+
+        res
+    }
+
+    /// Deletes the [`Entity`] with the given ID from storage.
+    ///
+    /// As with [`Store::delete`](super::Store::delete), the [`Entity`] is
+    /// loaded first, so that attached [`Components`](Component) are
+    /// properly deleted. This goes through the store's
+    /// [`AsyncComponentManager`], so it doesn't block on
+    /// [`Component`] storage either.
+    pub async fn delete(&self, id: Snowflake, cm: Arc<ComponentManager<T>>) -> Result<()> {
+        let handle_data = self.initialize_handle(id, cm).await?;
+
+        {
+            let mut handle = AsyncRwLock::write(handle_data.handle).await;
+            if let Some(obj) = handle.get_mut() {
+                self.components.clear_components(obj).await?;
+            }
+
+            handle.set_object(None);
+        }
+
+        self.backend.delete(id).await?;
+        self.events
+            .emit(Event::entity::<T>(EventKind::EntityDeleted, id));
+        Ok(())
+    }
+
+    /// Checks to see if an [`Entity`] with the given ID exists.
+    pub async fn exists(&self, id: Snowflake) -> Result<bool> {
+        let handle_data = self.get_handle(id);
+
+        if handle_data.initializer.is_done() {
+            Ok(AsyncRwLock::read(handle_data.handle).await.exists())
+        } else {
+            self.backend.exists(id).await
+        }
+    }
+
+    /// Retrieves a list of [`Entity`] IDs from storage.
+    pub async fn keys(&self, page: u64, limit: u64) -> Result<Vec<Snowflake>> {
+        self.backend.keys(page, limit).await
+    }
+}
+
+impl<T, U> fmt::Debug for AsyncStore<T, U>
+where
+    T: Entity + 'static,
+    U: AsyncEntityBackend<T> + 'static,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "AsyncStore<{}, {}> {{ {} keys }}",
+            any::type_name::<T>(),
+            any::type_name::<U>(),
+            self.refs.len()
+        )
+    }
+}
+
+/// An interface for accessing [`AsyncStores`](AsyncStore) without their
+/// backend type parameter, like [`EntityStore`](super::EntityStore).
+pub trait AsyncEntityStore<T>: Send + Sync
+where
+    T: Entity + 'static,
+{
+    fn load(
+        &self,
+        id: Snowflake,
+        cm: Arc<ComponentManager<T>>,
+    ) -> BoxFuture<'_, Result<AsyncReadReference<AsyncStoreHandle<T>>>>;
+
+    fn load_mut(
+        &self,
+        id: Snowflake,
+        cm: Arc<ComponentManager<T>>,
+    ) -> BoxFuture<'_, Result<AsyncWriteReference<AsyncStoreHandle<T>>>>;
+
+    fn store(&self, object: T) -> BoxFuture<'_, Result<()>>;
+    fn delete(&self, id: Snowflake, cm: Arc<ComponentManager<T>>) -> BoxFuture<'_, Result<()>>;
+    fn exists(&self, id: Snowflake) -> BoxFuture<'_, Result<bool>>;
+    fn keys(&self, page: u64, limit: u64) -> BoxFuture<'_, Result<Vec<Snowflake>>>;
+    fn components(&self) -> &Arc<AsyncComponentManager<T>>;
+    fn components_mut(&mut self) -> &mut Arc<AsyncComponentManager<T>>;
+}
+
+impl<T, U> AsyncEntityStore<T> for AsyncStore<T, U>
+where
+    T: Entity + 'static,
+    U: AsyncEntityBackend<T> + 'static,
+{
+    fn load(
+        &self,
+        id: Snowflake,
+        cm: Arc<ComponentManager<T>>,
+    ) -> BoxFuture<'_, Result<AsyncReadReference<AsyncStoreHandle<T>>>> {
+        Box::pin(self.load(id, cm))
+    }
+
+    fn load_mut(
+        &self,
+        id: Snowflake,
+        cm: Arc<ComponentManager<T>>,
+    ) -> BoxFuture<'_, Result<AsyncWriteReference<AsyncStoreHandle<T>>>> {
+        Box::pin(self.load_mut(id, cm))
+    }
+
+    fn store(&self, object: T) -> BoxFuture<'_, Result<()>> {
+        Box::pin(self.store(object))
+    }
+
+    fn delete(&self, id: Snowflake, cm: Arc<ComponentManager<T>>) -> BoxFuture<'_, Result<()>> {
+        Box::pin(self.delete(id, cm))
+    }
+
+    fn exists(&self, id: Snowflake) -> BoxFuture<'_, Result<bool>> {
+        Box::pin(self.exists(id))
+    }
+
+    fn keys(&self, page: u64, limit: u64) -> BoxFuture<'_, Result<Vec<Snowflake>>> {
+        Box::pin(self.keys(page, limit))
+    }
+
+    fn components(&self) -> &Arc<AsyncComponentManager<T>> {
+        self.components()
+    }
+
+    fn components_mut(&mut self) -> &mut Arc<AsyncComponentManager<T>> {
+        &mut self.components
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ecs::blocking::{
+        block_on, BlockingComponentBackend, BlockingEntityBackend, BlockingPool,
+    };
+    use crate::ecs::event::EventFilter;
+    use crate::ecs::EntityBackend;
+    use crate::local_storage::{LocalComponentStorage, LocalEntityStorage};
+    use crate::Card;
+
+    use std::collections::HashSet;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Barrier;
+    use std::task::Wake;
+    use std::thread;
+
+    // Counts loads, and blocks them until released.
+    struct SlowBackend {
+        inner: LocalEntityStorage<Card>,
+        loads: AtomicUsize,
+        barrier: Barrier,
+    }
+
+    impl EntityBackend<Card> for SlowBackend {
+        fn load(&self, id: Snowflake, cm: Arc<ComponentManager<Card>>) -> Result<Option<Card>> {
+            self.loads.fetch_add(1, Ordering::SeqCst);
+            self.barrier.wait();
+            self.inner.load(id, cm)
+        }
+
+        fn exists(&self, id: Snowflake) -> Result<bool> {
+            self.inner.exists(id)
+        }
+
+        fn store(&self, id: Snowflake, object: &Card) -> Result<()> {
+            self.inner.store(id, object)
+        }
+
+        fn delete(&self, id: Snowflake) -> Result<()> {
+            self.inner.delete(id)
+        }
+
+        fn keys(&self, page: u64, limit: u64) -> Result<Vec<Snowflake>> {
+            self.inner.keys(page, limit)
+        }
+    }
+
+    fn assert_send<F: Send>(future: F) -> F {
+        future
+    }
+
+    struct CountingWaker(AtomicUsize);
+
+    impl Wake for CountingWaker {
+        fn wake(self: Arc<Self>) {
+            self.0.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    #[test]
+    fn test_load_store() {
+        let pool = Arc::new(BlockingPool::new(2).unwrap());
+        let backend = BlockingEntityBackend::new(LocalEntityStorage::<Card>::new(), pool);
+        let store = AsyncStore::new(Arc::new(backend));
+        let cm = Arc::new(ComponentManager::new());
+        let id: Snowflake = 1u64.into();
+
+        block_on(assert_send(async {
+            assert!(!store.exists(id).await.unwrap());
+
+            let mut card = Card::new(id, cm.clone(), HashSet::new());
+            card.changes_mut().mark_attachments_changed();
+            store.store(card).await.unwrap();
+
+            assert!(store.exists(id).await.unwrap());
+            assert_eq!(store.keys(0, 10).await.unwrap(), vec![id]);
+
+            {
+                let mut handle = store.load_mut(id, cm.clone()).await.unwrap();
+                assert!(!handle.get().unwrap().dirty());
+
+                handle
+                    .get_mut()
+                    .unwrap()
+                    .changes_mut()
+                    .mark_attachments_changed();
+                handle.store().await.unwrap();
+                assert!(handle.get().unwrap().dirty());
+            }
+
+            store.delete(id, cm.clone()).await.unwrap();
+            assert!(!store.exists(id).await.unwrap());
+        }));
+    }
+
+    #[derive(Clone, Debug, PartialEq)]
+    struct Level(u64);
+    impl Component<Card> for Level {}
+
+    #[derive(Clone, Debug, PartialEq)]
+    struct Unregistered;
+    impl Component<Card> for Unregistered {}
+
+    #[test]
+    fn test_components() {
+        let pool = Arc::new(BlockingPool::new(2).unwrap());
+        let mut components = AsyncComponentManager::new();
+        components
+            .register_component(
+                "Level",
+                BlockingComponentBackend::new(LocalComponentStorage::<Card, Level>::new(), pool),
+            )
+            .unwrap();
+
+        assert!(components.is_registered::<Level>());
+        assert!(!components.is_registered::<Unregistered>());
+
+        let (_sub, events) = components
+            .events()
+            .subscribe_channel(EventFilter::component_name("Level"));
+        let cm = Arc::new(ComponentManager::new());
+        let mut card = Card::new(1u64.into(), cm, HashSet::new());
+
+        block_on(assert_send(async {
+            assert_eq!(
+                components.get_component::<Level>(&card).await.unwrap(),
+                None
+            );
+            assert!(components
+                .get_component::<Unregistered>(&card)
+                .await
+                .unwrap_err()
+                .downcast_ref::<TypeNotFoundError>()
+                .is_some());
+
+            components.set_component(&mut card, Level(3)).await.unwrap();
+            assert!(card.has_component::<Level>());
+            assert!(card.changes().was_set(&TypeId::of::<Level>()));
+            assert!(components.component_exists::<Level>(&card).await.unwrap());
+            assert_eq!(
+                components.get_component::<Level>(&card).await.unwrap(),
+                Some(Level(3))
+            );
+            assert_eq!(
+                components.component_keys::<Level>().await.unwrap(),
+                Some(vec![card.id()])
+            );
+
+            components
+                .delete_component::<Level>(&mut card)
+                .await
+                .unwrap();
+            assert!(!card.has_component::<Level>());
+            assert!(card.changes().was_deleted(&TypeId::of::<Level>()));
+            assert!(!components.component_exists::<Level>(&card).await.unwrap());
+
+            components.set_component(&mut card, Level(5)).await.unwrap();
+            card.components_attached_mut()
+                .insert(TypeId::of::<Unregistered>());
+
+            // Registered components are still deleted when others fail.
+            let err = components.clear_components(&mut card).await.unwrap_err();
+            assert_eq!(err.len(), 1);
+            assert!(card.components_attached().is_empty());
+            assert!(!components.component_exists::<Level>(&card).await.unwrap());
+        }));
+
+        let kinds: Vec<EventKind> = events.try_iter().map(|ev| ev.kind()).collect();
+        assert_eq!(
+            kinds,
+            vec![
+                EventKind::ComponentSet,
+                EventKind::ComponentDeleted,
+                EventKind::ComponentSet,
+                EventKind::ComponentDeleted,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_delete_components() {
+        let pool = Arc::new(BlockingPool::new(2).unwrap());
+        let mut components = AsyncComponentManager::new();
+        components
+            .register_component(
+                "Level",
+                BlockingComponentBackend::new(
+                    LocalComponentStorage::<Card, Level>::new(),
+                    pool.clone(),
+                ),
+            )
+            .unwrap();
+
+        let backend = BlockingEntityBackend::new(LocalEntityStorage::<Card>::new(), pool);
+        let store = AsyncStore::new(Arc::new(backend)).with_components(components);
+
+        // Level isn't registered with this, so clearing components
+        // synchronously would fail.
+        let cm = Arc::new(ComponentManager::new());
+
+        block_on(assert_send(async {
+            for id in 1u64..=2 {
+                let mut card = Card::new(id.into(), cm.clone(), HashSet::new());
+                store
+                    .components()
+                    .set_component(&mut card, Level(id))
+                    .await
+                    .unwrap();
+                store.store(card).await.unwrap();
+            }
+
+            let level_keys = || async { store.components().component_keys::<Level>().await };
+            assert_eq!(level_keys().await.unwrap().unwrap().len(), 2);
+
+            store.delete(1u64.into(), cm.clone()).await.unwrap();
+            assert_eq!(level_keys().await.unwrap(), Some(vec![2u64.into()]));
+
+            {
+                let mut handle = store.load_mut(2u64.into(), cm.clone()).await.unwrap();
+                handle.delete().await.unwrap();
+                assert!(!handle.exists());
+            }
+
+            assert_eq!(level_keys().await.unwrap(), Some(vec![]));
+            assert!(!store.exists(2u64.into()).await.unwrap());
+        }));
+    }
+
+    #[test]
+    fn test_concurrent_load() {
+        let pool = Arc::new(BlockingPool::new(4).unwrap());
+        let cm = Arc::new(ComponentManager::new());
+        let id: Snowflake = 1u64.into();
+
+        let slow = SlowBackend {
+            inner: LocalEntityStorage::new(),
+            loads: AtomicUsize::new(0),
+            barrier: Barrier::new(2),
+        };
+        slow.inner
+            .store(id, &Card::new(id, cm.clone(), HashSet::new()))
+            .unwrap();
+
+        let backend = BlockingEntityBackend::new(slow, pool);
+        let store = Arc::new(AsyncStore::new(Arc::new(backend)));
+
+        let mut threads = Vec::new();
+        for _ in 0..4 {
+            let store = store.clone();
+            let cm = cm.clone();
+            threads.push(thread::spawn(move || {
+                block_on(async {
+                    let handle = store.load(id, cm).await.unwrap();
+                    assert!(handle.exists());
+                });
+            }));
+        }
+
+        // Only one load should be waiting on the backend; let it through.
+        let inner = store.backend.backend().clone();
+        inner.barrier.wait();
+
+        for thread in threads {
+            thread.join().unwrap();
+        }
+
+        assert_eq!(inner.loads.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn test_locked_handle_yields() {
+        let pool = Arc::new(BlockingPool::new(2).unwrap());
+        let backend = BlockingEntityBackend::new(LocalEntityStorage::<Card>::new(), pool);
+        let store = AsyncStore::new(Arc::new(backend));
+        let cm = Arc::new(ComponentManager::new());
+        let id: Snowflake = 1u64.into();
+
+        block_on(store.store(Card::new(id, cm.clone(), HashSet::new()))).unwrap();
+        let handle = block_on(store.load_mut(id, cm.clone())).unwrap();
+
+        // Waiting on a locked handle should suspend the task rather than
+        // blocking this thread, and it should be woken once the handle is
+        // released.
+        let waker = Arc::new(CountingWaker(AtomicUsize::new(0)));
+        let task_waker = Waker::from(waker.clone());
+        let mut cx = Context::from_waker(&task_waker);
+        let mut load = Box::pin(store.load(id, cm.clone()));
+
+        assert!(load.as_mut().poll(&mut cx).is_pending());
+        assert_eq!(waker.0.load(Ordering::SeqCst), 0);
+
+        drop(handle);
+        assert!(waker.0.load(Ordering::SeqCst) > 0);
+
+        match load.as_mut().poll(&mut cx) {
+            Poll::Ready(Ok(handle)) => assert!(handle.exists()),
+            _ => panic!("load should have finished"),
+        }
+    }
+
+    #[test]
+    fn test_cancelled_init() {
+        let once = AsyncOnce::new();
+
+        // A task that gives up partway through initialization lets the
+        // next one take over.
+        let guard = block_on(once.begin()).unwrap();
+        drop(guard);
+        assert!(!once.is_done());
+
+        block_on(once.begin()).unwrap().finish();
+        assert!(once.is_done());
+        assert!(block_on(once.begin()).is_none());
+    }
+}
@@ -0,0 +1,404 @@
+//! Runs blocking storage backends from async code.
+//!
+//! [`EntityBackends`](EntityBackend) and
+//! [`ComponentBackends`](ComponentBackend) block the calling thread while
+//! they wait for storage. Calling them directly from an async task would
+//! block an executor thread too, so the adapters in this module run them
+//! on a dedicated [`BlockingPool`] instead, and implement the
+//! [`AsyncEntityBackend`] and [`AsyncComponentBackend`] traits on top of
+//! them.
+//!
+//! # Example
+//!
+//! ```
+//! use akashi::{Card, Entity, EntityManager};
+//! use akashi::ecs::blocking::{block_on, BlockingEntityBackend, BlockingPool};
+//! use akashi::local_storage::LocalEntityStorage;
+//! use std::sync::Arc;
+//!
+//! let pool = Arc::new(BlockingPool::new(4).unwrap());
+//! let mut manager = EntityManager::new();
+//! manager
+//!     .register_async_entity(BlockingEntityBackend::new(
+//!         LocalEntityStorage::<Card>::new(),
+//!         pool.clone(),
+//!     ))
+//!     .unwrap();
+//!
+//! block_on(async {
+//!     let card: Card = manager.create(1u64.into()).unwrap();
+//!     manager.store_async(card).await.unwrap();
+//!
+//!     let handle = manager.load_async::<Card>(1u64.into()).await.unwrap();
+//!     assert!(handle.exists());
+//! });
+//! ```
+
+use super::async_store::{AsyncComponentBackend, AsyncEntityBackend, BoxFuture};
+use super::{Component, ComponentBackend, ComponentManager, Entity, EntityBackend};
+use crate::snowflake::Snowflake;
+use crate::util::Result;
+
+use std::fmt;
+use std::future::Future;
+use std::panic::{self, AssertUnwindSafe};
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll, Wake, Waker};
+use std::thread::{self, Thread};
+
+use parking_lot::Mutex;
+
+/// A pool of threads for running blocking storage calls.
+///
+/// The threads in a `BlockingPool` are separate from whatever executor
+/// is running your async code, so blocking them doesn't hold up other
+/// tasks. Size the pool according to how many storage calls you expect
+/// to be waiting on at once (for example, the size of your database
+/// connection pool).
+pub struct BlockingPool {
+    pool: rayon::ThreadPool,
+}
+
+impl BlockingPool {
+    /// Creates a new pool with the given number of threads.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the pool's threads couldn't be started.
+    pub fn new(threads: usize) -> Result<BlockingPool> {
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(threads)
+            .thread_name(|index| format!("akashi-blocking-{}", index))
+            .build()?;
+
+        Ok(BlockingPool { pool })
+    }
+
+    /// Gets the number of threads in this pool.
+    pub fn threads(&self) -> usize {
+        self.pool.current_num_threads()
+    }
+
+    /// Runs a function on this pool, returning a future that resolves to
+    /// its result.
+    ///
+    /// If the function panics, the panic is resumed in whichever task
+    /// polls the returned future.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use akashi::ecs::blocking::{block_on, BlockingPool};
+    ///
+    /// let pool = BlockingPool::new(1).unwrap();
+    /// let sum = block_on(pool.spawn(|| (1..=10).sum::<u64>()));
+    /// assert_eq!(sum, 55);
+    /// ```
+    pub fn spawn<F, R>(&self, f: F) -> Blocking<R>
+    where
+        F: FnOnce() -> R + Send + 'static,
+        R: Send + 'static,
+    {
+        let shared = Arc::new(Mutex::new(Slot {
+            result: None,
+            waker: None,
+        }));
+
+        let job_shared = shared.clone();
+        self.pool.spawn(move || {
+            let result = panic::catch_unwind(AssertUnwindSafe(f));
+
+            let waker = {
+                let mut slot = job_shared.lock();
+                slot.result = Some(result);
+                slot.waker.take()
+            };
+
+            if let Some(waker) = waker {
+                waker.wake();
+            }
+        });
+
+        Blocking { shared }
+    }
+}
+
+impl fmt::Debug for BlockingPool {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "BlockingPool {{ {} threads }}", self.threads())
+    }
+}
+
+struct Slot<R> {
+    result: Option<thread::Result<R>>,
+    waker: Option<Waker>,
+}
+
+/// A future for the result of a function running on a [`BlockingPool`].
+///
+/// Dropping this future doesn't stop the function from running; its
+/// result is just discarded.
+pub struct Blocking<R> {
+    shared: Arc<Mutex<Slot<R>>>,
+}
+
+impl<R> Future for Blocking<R> {
+    type Output = R;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<R> {
+        let mut slot = self.shared.lock();
+
+        match slot.result.take() {
+            Some(Ok(value)) => Poll::Ready(value),
+            Some(Err(payload)) => {
+                drop(slot);
+                panic::resume_unwind(payload)
+            }
+            None => {
+                slot.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+impl<R> fmt::Debug for Blocking<R> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let done = self.shared.lock().result.is_some();
+        write!(f, "Blocking {{ done: {} }}", done)
+    }
+}
+
+struct ThreadWaker(Thread);
+
+impl Wake for ThreadWaker {
+    fn wake(self: Arc<Self>) {
+        self.0.unpark();
+    }
+}
+
+/// Runs a future to completion on the current thread, blocking until it
+/// finishes.
+///
+/// This is meant for calling async APIs from synchronous code (and for
+/// examples and tests); async code should `.await` futures instead.
+pub fn block_on<F: Future>(future: F) -> F::Output {
+    let waker = Waker::from(Arc::new(ThreadWaker(thread::current())));
+    let mut cx = Context::from_waker(&waker);
+    let mut future = Box::pin(future);
+
+    loop {
+        match future.as_mut().poll(&mut cx) {
+            Poll::Ready(value) => return value,
+            Poll::Pending => thread::park(),
+        }
+    }
+}
+
+/// Acts as an [`AsyncEntityBackend`] by running a blocking
+/// [`EntityBackend`] on a [`BlockingPool`].
+///
+/// Since the wrapped backend runs on another thread, [`Entities`](Entity)
+/// are cloned before being handed to it for storing.
+pub struct BlockingEntityBackend<U> {
+    backend: Arc<U>,
+    pool: Arc<BlockingPool>,
+}
+
+impl<U> BlockingEntityBackend<U> {
+    /// Wraps a blocking backend, running its methods on `pool`.
+    pub fn new(backend: U, pool: Arc<BlockingPool>) -> BlockingEntityBackend<U> {
+        BlockingEntityBackend {
+            backend: Arc::new(backend),
+            pool,
+        }
+    }
+
+    /// Gets the wrapped backend.
+    pub fn backend(&self) -> &Arc<U> {
+        &self.backend
+    }
+
+    /// Gets the pool that the wrapped backend runs on.
+    pub fn pool(&self) -> &Arc<BlockingPool> {
+        &self.pool
+    }
+}
+
+impl<T, U> AsyncEntityBackend<T> for BlockingEntityBackend<U>
+where
+    T: Entity + Clone + 'static,
+    U: EntityBackend<T> + Sync + Send + 'static,
+{
+    fn load(
+        &self,
+        id: Snowflake,
+        cm: Arc<ComponentManager<T>>,
+    ) -> BoxFuture<'_, Result<Option<T>>> {
+        let backend = self.backend.clone();
+        Box::pin(self.pool.spawn(move || backend.load(id, cm)))
+    }
+
+    fn exists(&self, id: Snowflake) -> BoxFuture<'_, Result<bool>> {
+        let backend = self.backend.clone();
+        Box::pin(self.pool.spawn(move || backend.exists(id)))
+    }
+
+    fn store<'a>(&'a self, id: Snowflake, object: &'a T) -> BoxFuture<'a, Result<()>> {
+        let backend = self.backend.clone();
+        let object = object.clone();
+        Box::pin(self.pool.spawn(move || backend.store(id, &object)))
+    }
+
+    fn delete(&self, id: Snowflake) -> BoxFuture<'_, Result<()>> {
+        let backend = self.backend.clone();
+        Box::pin(self.pool.spawn(move || backend.delete(id)))
+    }
+
+    fn keys(&self, page: u64, limit: u64) -> BoxFuture<'_, Result<Vec<Snowflake>>> {
+        let backend = self.backend.clone();
+        Box::pin(self.pool.spawn(move || backend.keys(page, limit)))
+    }
+}
+
+impl<U> fmt::Debug for BlockingEntityBackend<U> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "BlockingEntityBackend<{}>", std::any::type_name::<U>())
+    }
+}
+
+/// Acts as an [`AsyncComponentBackend`] by running a blocking
+/// [`ComponentBackend`] on a [`BlockingPool`].
+///
+/// Since the wrapped backend runs on another thread, [`Entities`](Entity)
+/// are cloned before being handed to it.
+pub struct BlockingComponentBackend<V> {
+    backend: Arc<V>,
+    pool: Arc<BlockingPool>,
+}
+
+impl<V> BlockingComponentBackend<V> {
+    /// Wraps a blocking backend, running its methods on `pool`.
+    pub fn new(backend: V, pool: Arc<BlockingPool>) -> BlockingComponentBackend<V> {
+        BlockingComponentBackend {
+            backend: Arc::new(backend),
+            pool,
+        }
+    }
+
+    /// Gets the wrapped backend.
+    pub fn backend(&self) -> &Arc<V> {
+        &self.backend
+    }
+
+    /// Gets the pool that the wrapped backend runs on.
+    pub fn pool(&self) -> &Arc<BlockingPool> {
+        &self.pool
+    }
+}
+
+impl<T, U, V> AsyncComponentBackend<T, U> for BlockingComponentBackend<V>
+where
+    T: Entity + Clone + 'static,
+    U: Component<T> + Send + 'static,
+    V: ComponentBackend<T, U> + Sync + Send + 'static,
+{
+    fn load<'a>(&'a self, entity: &'a T) -> BoxFuture<'a, Result<Option<U>>> {
+        let backend = self.backend.clone();
+        let entity = entity.clone();
+        Box::pin(self.pool.spawn(move || backend.load(&entity)))
+    }
+
+    fn store<'a>(&'a self, entity: &'a T, component: U) -> BoxFuture<'a, Result<()>> {
+        let backend = self.backend.clone();
+        let entity = entity.clone();
+        Box::pin(self.pool.spawn(move || backend.store(&entity, component)))
+    }
+
+    fn exists<'a>(&'a self, entity: &'a T) -> BoxFuture<'a, Result<bool>> {
+        let backend = self.backend.clone();
+        let entity = entity.clone();
+        Box::pin(self.pool.spawn(move || backend.exists(&entity)))
+    }
+
+    fn delete<'a>(&'a self, entity: &'a T) -> BoxFuture<'a, Result<()>> {
+        let backend = self.backend.clone();
+        let entity = entity.clone();
+        Box::pin(self.pool.spawn(move || backend.delete(&entity)))
+    }
+
+    fn keys(&self) -> BoxFuture<'_, Result<Option<Vec<Snowflake>>>> {
+        let backend = self.backend.clone();
+        Box::pin(self.pool.spawn(move || backend.keys()))
+    }
+}
+
+impl<V> fmt::Debug for BlockingComponentBackend<V> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "BlockingComponentBackend<{}>",
+            std::any::type_name::<V>()
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::local_storage::{LocalComponentStorage, LocalEntityStorage};
+    use crate::Card;
+
+    use std::collections::HashSet;
+
+    #[derive(Clone, Debug, PartialEq)]
+    struct Level(u64);
+    impl Component<Card> for Level {}
+
+    #[test]
+    fn test_spawn_runs_off_thread() {
+        let pool = BlockingPool::new(2).unwrap();
+        let caller = thread::current().id();
+
+        let worker = block_on(pool.spawn(|| thread::current().id()));
+        assert_ne!(worker, caller);
+    }
+
+    #[test]
+    #[should_panic(expected = "job failed")]
+    fn test_spawn_resumes_panics() {
+        let pool = BlockingPool::new(1).unwrap();
+        block_on(pool.spawn(|| -> () { panic!("job failed") }));
+    }
+
+    #[test]
+    fn test_blocking_backends() {
+        let pool = Arc::new(BlockingPool::new(2).unwrap());
+        let cm = Arc::new(ComponentManager::new());
+        let entities = BlockingEntityBackend::new(LocalEntityStorage::<Card>::new(), pool.clone());
+        let components =
+            BlockingComponentBackend::new(LocalComponentStorage::<Card, Level>::new(), pool);
+
+        let card = Card::new(1u64.into(), cm.clone(), HashSet::new());
+
+        block_on(async {
+            entities.store(card.id(), &card).await.unwrap();
+            assert!(entities.exists(card.id()).await.unwrap());
+            assert_eq!(entities.keys(0, 10).await.unwrap(), vec![card.id()]);
+
+            let loaded = entities.load(card.id(), cm.clone()).await.unwrap();
+            assert_eq!(loaded.unwrap().id(), card.id());
+
+            components.store(&card, Level(3)).await.unwrap();
+            assert!(components.exists(&card).await.unwrap());
+            assert_eq!(components.load(&card).await.unwrap(), Some(Level(3)));
+
+            components.delete(&card).await.unwrap();
+            assert_eq!(components.load(&card).await.unwrap(), None);
+
+            entities.delete(card.id()).await.unwrap();
+            assert!(!entities.exists(card.id()).await.unwrap());
+        });
+    }
+}
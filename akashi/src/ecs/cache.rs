@@ -0,0 +1,692 @@
+//! An optional write-behind cache for [`Stores`](super::Store).
+//!
+//! Without a cache, a [`Store`](super::Store) only keeps weak references
+//! to the handles it gives out, so an [`Entity`] is loaded from storage
+//! again once every reference to its handle has been dropped, and dirty
+//! entities get written back as soon as that happens.
+//!
+//! A cache keeps recently-used handles resident instead. Entities that
+//! were loaded mutably are written back in batches whenever the cache is
+//! flushed, which happens on an interval, once enough entities are
+//! waiting to be written, when entities are evicted, or when
+//! [`Store::flush`](super::Store::flush) is called.
+
+use super::entity::Entity;
+use super::entity_store::{StoreHandle, StoreReference};
+use crate::clock::{Clock, SystemClock};
+use crate::snowflake::Snowflake;
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+use failure::{Error, Fail};
+use parking_lot::{Mutex, RwLock};
+
+/// Configures how a [`Store`](super::Store)'s cache evicts and flushes
+/// [`Entities`](Entity).
+///
+/// # Example
+///
+/// ```
+/// use akashi::ecs::CachePolicy;
+/// use std::time::Duration;
+///
+/// // Keep up to 1000 entities resident for at most 10 minutes each,
+/// // writing back changes every 5 seconds or whenever 100 entities
+/// // have been loaded for writing, whichever happens first.
+/// let policy = CachePolicy::new(1000)
+///     .with_ttl(Duration::from_secs(600))
+///     .with_flush_interval(Duration::from_secs(5))
+///     .with_flush_threshold(100);
+///
+/// assert_eq!(policy.capacity(), 1000);
+/// assert_eq!(policy.flush_threshold(), Some(100));
+/// ```
+#[derive(Debug, Clone)]
+pub struct CachePolicy {
+    capacity: usize,
+    ttl: Option<Duration>,
+    flush_interval: Option<Duration>,
+    flush_threshold: Option<usize>,
+    clock: Arc<dyn Clock>,
+}
+
+impl CachePolicy {
+    /// Creates a new policy for a cache that holds up to `capacity`
+    /// entities, evicting the least recently used ones first.
+    ///
+    /// By default, entities don't expire, and changes are only written
+    /// back when entities are evicted or the cache is flushed explicitly.
+    pub fn new(capacity: usize) -> CachePolicy {
+        CachePolicy {
+            capacity,
+            ttl: None,
+            flush_interval: None,
+            flush_threshold: None,
+            clock: Arc::new(SystemClock),
+        }
+    }
+
+    /// Evicts entities once they've been in the cache for longer than
+    /// `ttl`, so that they get reloaded from storage.
+    pub fn with_ttl(mut self, ttl: Duration) -> CachePolicy {
+        self.ttl = Some(ttl);
+        self
+    }
+
+    /// Writes back changes whenever the cache is used and `interval` has
+    /// passed since the last flush.
+    pub fn with_flush_interval(mut self, interval: Duration) -> CachePolicy {
+        self.flush_interval = Some(interval);
+        self
+    }
+
+    /// Writes back changes once `threshold` entities have been loaded
+    /// for writing since the last flush.
+    pub fn with_flush_threshold(mut self, threshold: usize) -> CachePolicy {
+        self.flush_threshold = Some(threshold);
+        self
+    }
+
+    /// Sets the [`Clock`] used to decide when entities expire and when
+    /// changes are due to be written back.
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> CachePolicy {
+        self.clock = clock;
+        self
+    }
+
+    /// Gets the maximum number of entities that can be cached at once.
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Gets how long entities can stay in the cache, if limited.
+    pub fn ttl(&self) -> Option<Duration> {
+        self.ttl
+    }
+
+    /// Gets how often changes are written back, if on an interval.
+    pub fn flush_interval(&self) -> Option<Duration> {
+        self.flush_interval
+    }
+
+    /// Gets how many entities can be waiting to be written back before
+    /// they are flushed, if limited.
+    pub fn flush_threshold(&self) -> Option<usize> {
+        self.flush_threshold
+    }
+
+    /// Gets the [`Clock`] this policy uses.
+    pub fn clock(&self) -> &Arc<dyn Clock> {
+        &self.clock
+    }
+}
+
+// Policies using different clock objects aren't the same, even if the
+// clocks happen to read the same time.
+impl PartialEq for CachePolicy {
+    fn eq(&self, other: &CachePolicy) -> bool {
+        self.capacity == other.capacity
+            && self.ttl == other.ttl
+            && self.flush_interval == other.flush_interval
+            && self.flush_threshold == other.flush_threshold
+            && Arc::as_ptr(&self.clock) as *const () == Arc::as_ptr(&other.clock) as *const ()
+    }
+}
+
+impl Eq for CachePolicy {}
+
+/// Returned when an [`Entity`] could not be written back to storage,
+/// either while flushing a cache or while dropping its
+/// [`StoreHandle`].
+#[derive(Fail, Debug)]
+#[fail(display = "Could not write back entity {}: {}", id, error)]
+pub struct FlushError {
+    id: Snowflake,
+    error: Error,
+}
+
+impl FlushError {
+    pub(crate) fn new(id: Snowflake, error: Error) -> FlushError {
+        FlushError { id, error }
+    }
+
+    /// Gets the ID of the [`Entity`] that could not be written.
+    pub fn id(&self) -> Snowflake {
+        self.id
+    }
+
+    /// Gets the error returned by the storage backend.
+    pub fn error(&self) -> &Error {
+        &self.error
+    }
+}
+
+/// Returned from [`Store::flush`](super::Store::flush) when any
+/// [`Entities`](Entity) could not be written back to storage.
+#[derive(Fail, Debug)]
+pub struct FlushErrors {
+    errors: Vec<FlushError>,
+}
+
+impl fmt::Display for FlushErrors {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "failed to write back {} entities:", self.errors.len())?;
+        for err in self.errors.iter() {
+            writeln!(f, "{}", err)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl FlushErrors {
+    pub(crate) fn new(errors: Vec<FlushError>) -> FlushErrors {
+        FlushErrors { errors }
+    }
+
+    /// Gets the errors for each [`Entity`] that could not be written.
+    pub fn errors(&self) -> &[FlushError] {
+        &self.errors
+    }
+}
+
+type ErrorCallback = Box<dyn Fn(&FlushError) + Send + Sync + 'static>;
+
+/// Collects errors from writes that happen in the background, either
+/// passing them to a callback or queueing them up to be taken later.
+#[derive(Default)]
+pub(crate) struct ErrorSink {
+    callback: RwLock<Option<ErrorCallback>>,
+    queue: Mutex<Vec<FlushError>>,
+}
+
+impl ErrorSink {
+    pub(crate) fn set_callback(&self, callback: ErrorCallback) {
+        *self.callback.write() = Some(callback);
+    }
+
+    pub(crate) fn report(&self, error: FlushError) {
+        match &*self.callback.read() {
+            Some(callback) => callback(&error),
+            None => self.queue.lock().push(error),
+        }
+    }
+
+    pub(crate) fn take(&self) -> Vec<FlushError> {
+        self.queue.lock().drain(..).collect()
+    }
+}
+
+impl fmt::Debug for ErrorSink {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "ErrorSink {{ {} queued }}", self.queue.lock().len())
+    }
+}
+
+struct CacheEntry<T: Entity + 'static> {
+    handle: StoreReference<StoreHandle<T>>,
+    inserted: SystemTime,
+    last_used: u64,
+}
+
+struct CacheState<T: Entity + 'static> {
+    entries: HashMap<Snowflake, CacheEntry<T>>,
+    recency: BTreeMap<u64, Snowflake>,
+    pending: HashSet<Snowflake>,
+    tick: u64,
+    last_flush: SystemTime,
+}
+
+impl<T: Entity + 'static> CacheState<T> {
+    fn remove(&mut self, id: Snowflake) -> Option<StoreReference<StoreHandle<T>>> {
+        let entry = self.entries.remove(&id)?;
+        self.recency.remove(&entry.last_used);
+        self.pending.remove(&id);
+        Some(entry.handle)
+    }
+}
+
+/// Keeps [`StoreHandles`](StoreHandle) resident for a
+/// [`Store`](super::Store).
+pub(crate) struct Cache<T: Entity + 'static> {
+    policy: CachePolicy,
+    state: Mutex<CacheState<T>>,
+}
+
+// Handles taken out of the cache that need to be written back.
+type Flushable<T> = Vec<(Snowflake, StoreReference<StoreHandle<T>>)>;
+
+impl<T: Entity + 'static> Cache<T> {
+    pub(crate) fn new(policy: CachePolicy) -> Cache<T> {
+        let now = policy.clock.now();
+        Cache {
+            policy,
+            state: Mutex::new(CacheState {
+                entries: HashMap::new(),
+                recency: BTreeMap::new(),
+                pending: HashSet::new(),
+                tick: 0,
+                last_flush: now,
+            }),
+        }
+    }
+
+    pub(crate) fn policy(&self) -> &CachePolicy {
+        &self.policy
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.state.lock().entries.len()
+    }
+
+    fn expired(&self, entry: &CacheEntry<T>, now: SystemTime) -> bool {
+        match self.policy.ttl {
+            Some(ttl) => elapsed(entry.inserted, now) >= ttl,
+            None => false,
+        }
+    }
+
+    /// Evicts an entity if it has expired, returning its handle.
+    pub(crate) fn expire(&self, id: Snowflake) -> Flushable<T> {
+        let mut state = self.state.lock();
+        let now = self.policy.clock.now();
+
+        match state.entries.get(&id) {
+            Some(entry) if self.expired(entry, now) => {
+                state.remove(id).map(|h| (id, h)).into_iter().collect()
+            }
+            _ => Vec::new(),
+        }
+    }
+
+    /// Adds a handle to the cache (or marks it as recently used),
+    /// returning any handles that need to be written back as a result.
+    pub(crate) fn insert(
+        &self,
+        id: Snowflake,
+        handle: &StoreReference<StoreHandle<T>>,
+        write: bool,
+    ) -> Flushable<T> {
+        let mut state = self.state.lock();
+        let now = self.policy.clock.now();
+
+        state.tick += 1;
+        let tick = state.tick;
+
+        let prev = state.entries.get_mut(&id).map(|entry| {
+            let prev = entry.last_used;
+            entry.last_used = tick;
+            prev
+        });
+
+        match prev {
+            Some(prev) => {
+                state.recency.remove(&prev);
+            }
+            None => {
+                state.entries.insert(
+                    id,
+                    CacheEntry {
+                        handle: handle.clone(),
+                        inserted: now,
+                        last_used: tick,
+                    },
+                );
+            }
+        }
+
+        state.recency.insert(tick, id);
+
+        let mut flushable = Vec::new();
+        while state.entries.len() > self.policy.capacity {
+            let lru = match state.recency.values().next() {
+                Some(lru) => *lru,
+                None => break,
+            };
+
+            if let Some(handle) = state.remove(lru) {
+                flushable.push((lru, handle));
+            }
+        }
+
+        let threshold_hit = match self.policy.flush_threshold {
+            Some(threshold) => state.pending.len() >= threshold,
+            None => false,
+        };
+
+        let interval_hit = match self.policy.flush_interval {
+            Some(interval) => elapsed(state.last_flush, now) >= interval,
+            None => false,
+        };
+
+        if threshold_hit || interval_hit {
+            flushable.extend(self.take_pending(&mut state, now));
+        }
+
+        // This has to come after flushing, since the caller is about to
+        // make changes that haven't been written back yet.
+        if write && state.entries.contains_key(&id) {
+            state.pending.insert(id);
+        }
+
+        flushable
+    }
+
+    /// Takes every handle that may need to be written back, evicting
+    /// expired entities along the way.
+    pub(crate) fn drain(&self) -> Flushable<T> {
+        let mut state = self.state.lock();
+        let now = self.policy.clock.now();
+        self.take_pending(&mut state, now)
+    }
+
+    /// Takes every handle out of the cache.
+    pub(crate) fn clear(&self) -> Flushable<T> {
+        let mut state = self.state.lock();
+        state.recency.clear();
+        state.pending.clear();
+        state.last_flush = self.policy.clock.now();
+        state
+            .entries
+            .drain()
+            .map(|(id, e)| (id, e.handle))
+            .collect()
+    }
+
+    /// Puts a handle that couldn't be written back yet into the queue
+    /// of handles that need to be written back, if it's still cached.
+    pub(crate) fn requeue(&self, id: Snowflake) {
+        let mut state = self.state.lock();
+        if state.entries.contains_key(&id) {
+            state.pending.insert(id);
+        }
+    }
+
+    fn take_pending(&self, state: &mut CacheState<T>, now: SystemTime) -> Flushable<T> {
+        let expired: Vec<Snowflake> = state
+            .entries
+            .iter()
+            .filter(|(_id, entry)| self.expired(entry, now))
+            .map(|(id, _entry)| *id)
+            .collect();
+
+        let mut flushable: Flushable<T> = expired
+            .into_iter()
+            .filter_map(|id| state.remove(id).map(|handle| (id, handle)))
+            .collect();
+
+        let pending: Vec<Snowflake> = state.pending.drain().collect();
+        flushable.extend(pending.into_iter().filter_map(|id| {
+            state
+                .entries
+                .get(&id)
+                .map(|entry| (id, entry.handle.clone()))
+        }));
+
+        state.last_flush = now;
+        flushable
+    }
+}
+
+// Treats time as standing still if the clock goes backwards.
+fn elapsed(since: SystemTime, now: SystemTime) -> Duration {
+    now.duration_since(since).unwrap_or_default()
+}
+
+impl<T: Entity + 'static> fmt::Debug for Cache<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Cache {{ {} entries, {:?} }}", self.len(), self.policy)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::card::Card;
+    use crate::clock::ManualClock;
+    use crate::ecs::{ComponentManager, EntityBackend, Store};
+    use crate::local_storage::LocalEntityStorage;
+    use crate::util::Result;
+
+    use failure::err_msg;
+    use std::collections::HashSet;
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    struct CountingBackend {
+        inner: LocalEntityStorage<Card>,
+        loads: AtomicUsize,
+        stores: AtomicUsize,
+        fail: AtomicBool,
+    }
+
+    impl CountingBackend {
+        fn new() -> CountingBackend {
+            CountingBackend {
+                inner: LocalEntityStorage::new(),
+                loads: AtomicUsize::new(0),
+                stores: AtomicUsize::new(0),
+                fail: AtomicBool::new(false),
+            }
+        }
+    }
+
+    impl EntityBackend<Card> for CountingBackend {
+        fn load(&self, id: Snowflake, cm: Arc<ComponentManager<Card>>) -> Result<Option<Card>> {
+            self.loads.fetch_add(1, Ordering::SeqCst);
+            self.inner.load(id, cm)
+        }
+
+        fn exists(&self, id: Snowflake) -> Result<bool> {
+            self.inner.exists(id)
+        }
+
+        fn store(&self, id: Snowflake, object: &Card) -> Result<()> {
+            if self.fail.load(Ordering::SeqCst) {
+                return Err(err_msg("backend unavailable"));
+            }
+
+            self.stores.fetch_add(1, Ordering::SeqCst);
+            self.inner.store(id, object)
+        }
+
+        fn delete(&self, id: Snowflake) -> Result<()> {
+            self.inner.delete(id)
+        }
+
+        fn keys(&self, page: u64, limit: u64) -> Result<Vec<Snowflake>> {
+            self.inner.keys(page, limit)
+        }
+    }
+
+    type TestStore = Store<Card, CountingBackend>;
+
+    fn new_store(policy: CachePolicy, ids: &[u64]) -> (TestStore, Arc<CountingBackend>) {
+        let backend = Arc::new(CountingBackend::new());
+        let cm = Arc::new(ComponentManager::new());
+        for id in ids {
+            let card = Card::new((*id).into(), cm.clone(), HashSet::new());
+            backend.inner.store((*id).into(), &card).unwrap();
+        }
+
+        (Store::new(backend.clone()).with_cache(policy), backend)
+    }
+
+    fn cm() -> Arc<ComponentManager<Card>> {
+        Arc::new(ComponentManager::new())
+    }
+
+    // Loads an entity mutably and marks it as changed.
+    fn touch(store: &TestStore, id: u64) {
+        let mut handle = store.load_mut(id.into(), cm()).unwrap();
+        handle
+            .get_mut()
+            .unwrap()
+            .changes_mut()
+            .mark_attachments_changed();
+    }
+
+    #[test]
+    fn test_resident() {
+        let (store, backend) = new_store(CachePolicy::new(10), &[1]);
+
+        store.load(1u64.into(), cm()).unwrap();
+        store.load(1u64.into(), cm()).unwrap();
+        assert_eq!(backend.loads.load(Ordering::SeqCst), 1);
+
+        store.clear_cache().unwrap();
+        store.load(1u64.into(), cm()).unwrap();
+        assert_eq!(backend.loads.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn test_lru_eviction() {
+        let (store, backend) = new_store(CachePolicy::new(2), &[1, 2, 3]);
+
+        store.load(1u64.into(), cm()).unwrap();
+        store.load(2u64.into(), cm()).unwrap();
+        store.load(1u64.into(), cm()).unwrap();
+        store.load(3u64.into(), cm()).unwrap();
+        assert_eq!(store.cached(), 2);
+        assert_eq!(backend.loads.load(Ordering::SeqCst), 3);
+
+        // 2 was the least recently used, so it should have been evicted.
+        store.load(1u64.into(), cm()).unwrap();
+        assert_eq!(backend.loads.load(Ordering::SeqCst), 3);
+        store.load(2u64.into(), cm()).unwrap();
+        assert_eq!(backend.loads.load(Ordering::SeqCst), 4);
+    }
+
+    #[test]
+    fn test_ttl() {
+        let policy = CachePolicy::new(10).with_ttl(Duration::from_secs(0));
+        let (store, backend) = new_store(policy, &[1]);
+
+        store.load(1u64.into(), cm()).unwrap();
+        store.load(1u64.into(), cm()).unwrap();
+        assert_eq!(backend.loads.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn test_clock() {
+        let clock = Arc::new(ManualClock::new(SystemTime::UNIX_EPOCH));
+        let policy = CachePolicy::new(10)
+            .with_ttl(Duration::from_secs(60))
+            .with_flush_interval(Duration::from_secs(5))
+            .with_clock(clock.clone());
+        let (store, backend) = new_store(policy, &[1, 2]);
+
+        touch(&store, 1);
+        store.load(2u64.into(), cm()).unwrap();
+        assert_eq!(backend.stores.load(Ordering::SeqCst), 0);
+
+        // The flush interval has passed, so the next use of the cache
+        // writes back the change.
+        clock.advance(Duration::from_secs(5));
+        store.load(2u64.into(), cm()).unwrap();
+        assert_eq!(backend.stores.load(Ordering::SeqCst), 1);
+        assert_eq!(backend.loads.load(Ordering::SeqCst), 2);
+
+        // Both entities have expired now, so they're loaded again.
+        clock.advance(Duration::from_secs(60));
+        store.load(2u64.into(), cm()).unwrap();
+        assert_eq!(backend.loads.load(Ordering::SeqCst), 3);
+    }
+
+    #[test]
+    fn test_write_behind() {
+        let (store, backend) = new_store(CachePolicy::new(10), &[1]);
+
+        touch(&store, 1);
+        assert_eq!(backend.stores.load(Ordering::SeqCst), 0);
+
+        store.flush().unwrap();
+        assert_eq!(backend.stores.load(Ordering::SeqCst), 1);
+
+        // Nothing has changed since the last flush.
+        store.flush().unwrap();
+        assert_eq!(backend.stores.load(Ordering::SeqCst), 1);
+
+        // Evicted entities are written back too.
+        touch(&store, 1);
+        store.clear_cache().unwrap();
+        assert_eq!(backend.stores.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn test_flush_threshold() {
+        let policy = CachePolicy::new(10).with_flush_threshold(2);
+        let (store, backend) = new_store(policy, &[1, 2, 3]);
+
+        touch(&store, 1);
+        touch(&store, 2);
+        assert_eq!(backend.stores.load(Ordering::SeqCst), 0);
+
+        touch(&store, 3);
+        assert_eq!(backend.stores.load(Ordering::SeqCst), 2);
+
+        store.flush().unwrap();
+        assert_eq!(backend.stores.load(Ordering::SeqCst), 3);
+    }
+
+    #[test]
+    fn test_locked_entities_skipped() {
+        let (store, backend) = new_store(CachePolicy::new(10), &[1]);
+
+        let mut handle = store.load_mut(1u64.into(), cm()).unwrap();
+        handle
+            .get_mut()
+            .unwrap()
+            .changes_mut()
+            .mark_attachments_changed();
+
+        store.flush().unwrap();
+        assert_eq!(backend.stores.load(Ordering::SeqCst), 0);
+
+        drop(handle);
+        store.flush().unwrap();
+        assert_eq!(backend.stores.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn test_flush_errors() {
+        let policy = CachePolicy::new(10).with_flush_threshold(1);
+        let (store, backend) = new_store(policy, &[1, 2, 3]);
+        backend.fail.store(true, Ordering::SeqCst);
+
+        // Explicit flushes return their errors directly.
+        touch(&store, 1);
+        let err = store.flush().unwrap_err();
+        let errors = err.downcast_ref::<FlushErrors>().unwrap();
+        assert_eq!(errors.errors().len(), 1);
+        assert_eq!(errors.errors()[0].id(), 1u64.into());
+
+        // Flushes that happen in the background queue them up instead.
+        // Loading 2 flushes 1 (which failed before), but 2 itself won't be
+        // written back until the next flush.
+        touch(&store, 2);
+        let errors = store.take_flush_errors();
+        let ids: Vec<Snowflake> = errors.iter().map(|e| e.id()).collect();
+        assert_eq!(ids, vec![1u64.into()]);
+        assert!(store.take_flush_errors().is_empty());
+
+        // ...or pass them to a callback, if one is set.
+        let reported = Arc::new(AtomicUsize::new(0));
+        let counter = reported.clone();
+        store.on_flush_error(move |_e| {
+            counter.fetch_add(1, Ordering::SeqCst);
+        });
+
+        touch(&store, 3);
+        assert_eq!(reported.load(Ordering::SeqCst), 2);
+        assert!(store.take_flush_errors().is_empty());
+
+        // Failed writes are retried on the next flush.
+        backend.fail.store(false, Ordering::SeqCst);
+        store.flush().unwrap();
+        assert_eq!(backend.stores.load(Ordering::SeqCst), 3);
+    }
+}
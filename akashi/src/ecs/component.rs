@@ -2,13 +2,17 @@
 
 use super::component_store::{ComponentBackend, ComponentTypeData};
 use super::entity::Entity;
+use super::event::{Event, EventBus, EventKind};
+use super::transaction::TransactionalBackend;
 use super::TypeNotFoundError;
+use crate::snowflake::Snowflake;
 use crate::util::Result;
 
 use std::any;
 use std::any::TypeId;
 use std::collections::HashMap;
 use std::fmt;
+use std::sync::Arc;
 
 use failure::format_err;
 
@@ -23,9 +27,80 @@ use downcast_rs::{Downcast, DowncastSync};
 ///
 /// This trait doesn't provide anything on its own, but it does
 /// allow a type to interact with the rest of the ECS code.
-pub trait Component<T>: Downcast {}
+///
+/// It can be implemented with `#[derive(Component)]`. By default, the
+/// derived implementation lets the type be attached to any [`Entity`];
+/// `#[component(entity = ...)]` limits it to one or more specific kinds
+/// of entity.
+///
+/// ```
+/// use akashi::ecs::Component;
+/// use akashi::{Card, Player};
+///
+/// #[derive(Component)]
+/// #[component(entity = Card, entity = Player)]
+/// struct Favorite(bool);
+///
+/// #[derive(Component)]
+/// struct Note(String);
+///
+/// fn attachable<E, C: Component<E>>() {}
+/// attachable::<Card, Favorite>();
+/// attachable::<Player, Note>();
+/// ```
+pub trait Component<T>: Downcast {
+    /// Gets the `TypeId` that an [`Entity`] must have attached to match
+    /// a [`Query`](super::Query) for this type.
+    ///
+    /// This is used to implement optional query members, and shouldn't
+    /// be overridden.
+    #[doc(hidden)]
+    fn query_type_id() -> Option<TypeId>
+    where
+        Self: Sized,
+    {
+        Some(TypeId::of::<Self>())
+    }
+
+    /// Loads this type as part of a [`Query`](super::Query) row, returning
+    /// `Ok(None)` if the row should be skipped.
+    ///
+    /// This is used to implement optional query members, and shouldn't
+    /// be overridden.
+    #[doc(hidden)]
+    fn query_fetch(entity: &T) -> Result<Option<Self>>
+    where
+        Self: Sized,
+        T: Entity,
+    {
+        entity.get_component()
+    }
+}
 downcast_rs::impl_downcast!(Component<T>);
 
+/// Lets [`Queries`](super::Query) fetch [`Components`](Component) that
+/// might not be attached.
+///
+/// Optional members don't restrict which [`Entities`](Entity) a query
+/// matches; they are fetched as `None` for [`Entities`](Entity) that
+/// don't have the [`Component`] attached.
+impl<T, U> Component<T> for Option<U>
+where
+    T: 'static,
+    U: Component<T> + 'static,
+{
+    fn query_type_id() -> Option<TypeId> {
+        None
+    }
+
+    fn query_fetch(entity: &T) -> Result<Option<Option<U>>>
+    where
+        T: Entity,
+    {
+        entity.get_component::<U>().map(Some)
+    }
+}
+
 /// Used as a helper for downcasting [`ComponentManagers`](ComponentManager).
 ///
 /// You probably shouldn't use this yourself.
@@ -53,21 +128,46 @@ impl<T: Entity + 'static> ComponentManagerDowncast for ComponentManager<T> {}
 /// for which no backing store has been registered with
 /// [`register_component`](ComponentManager::register_component) will return
 /// [`TypeNotFoundError`].
+///
+/// Successful changes to [`Component`] data are reported to the
+/// manager's [`EventBus`].
 pub struct ComponentManager<T: Entity + 'static> {
     component_types: HashMap<TypeId, ComponentTypeData<T>>,
     component_names: HashMap<TypeId, String>,
     component_names_inv: HashMap<String, TypeId>,
+    events: Arc<EventBus>,
 }
 
 impl<T: Entity + 'static> ComponentManager<T> {
     pub fn new() -> ComponentManager<T> {
+        ComponentManager::with_events(Arc::new(EventBus::new()))
+    }
+
+    /// Creates a new `ComponentManager` that reports changes to the given
+    /// [`EventBus`].
+    pub fn with_events(events: Arc<EventBus>) -> ComponentManager<T> {
         ComponentManager {
             component_types: HashMap::new(),
             component_names: HashMap::new(),
             component_names_inv: HashMap::new(),
+            events,
         }
     }
 
+    /// Gets the [`EventBus`] that this manager reports changes to.
+    pub fn events(&self) -> &Arc<EventBus> {
+        &self.events
+    }
+
+    fn emit(&self, kind: EventKind, entity: &T, type_id: &TypeId) {
+        self.events.emit(Event::component::<T>(
+            kind,
+            entity.id(),
+            *type_id,
+            self.component_name(type_id),
+        ));
+    }
+
     /// Registers a backing storage object and unique name for a
     /// [`Component`] type.
     ///
@@ -114,8 +214,11 @@ impl<T: Entity + 'static> ComponentManager<T> {
 
     /// Save data for a [`Component`] to the appropriate backing store.
     pub fn set_component<U: Component<T> + 'static>(&self, entity: &T, component: U) -> Result<()> {
-        if let Some(data) = self.component_types.get(&TypeId::of::<U>()) {
-            (data.store)(entity, Box::new(component))
+        let type_id = TypeId::of::<U>();
+        if let Some(data) = self.component_types.get(&type_id) {
+            (data.store)(entity, Box::new(component))?;
+            self.emit(EventKind::ComponentSet, entity, &type_id);
+            Ok(())
         } else {
             Err(TypeNotFoundError::new(any::type_name::<U>().to_owned()).into())
         }
@@ -142,8 +245,11 @@ impl<T: Entity + 'static> ComponentManager<T> {
     /// Delete the data for an attached [`Component`] from its registered
     /// backing store.
     pub fn delete_component<U: Component<T> + 'static>(&self, entity: &T) -> Result<()> {
-        if let Some(data) = self.component_types.get(&TypeId::of::<U>()) {
-            (data.delete)(entity)
+        let type_id = TypeId::of::<U>();
+        if let Some(data) = self.component_types.get(&type_id) {
+            (data.delete)(entity)?;
+            self.emit(EventKind::ComponentDeleted, entity, &type_id);
+            Ok(())
         } else {
             Err(TypeNotFoundError::new(any::type_name::<U>().to_owned()).into())
         }
@@ -154,12 +260,79 @@ impl<T: Entity + 'static> ComponentManager<T> {
     /// This should probably only be used internally.
     pub fn delete_component_by_id(&self, entity: &T, type_id: &TypeId) -> Result<()> {
         if let Some(data) = self.component_types.get(&type_id) {
-            (data.delete)(entity)
+            (data.delete)(entity)?;
+            self.emit(EventKind::ComponentDeleted, entity, type_id);
+            Ok(())
+        } else {
+            Err(TypeNotFoundError::new(format!("{:?}", type_id)).into())
+        }
+    }
+
+    /// Load the data for a [`Component`] with the associated `TypeId`,
+    /// without downcasting it.
+    ///
+    /// This should probably only be used internally.
+    pub fn get_component_by_id(
+        &self,
+        entity: &T,
+        type_id: &TypeId,
+    ) -> Result<Option<Box<dyn Component<T> + 'static>>> {
+        if let Some(data) = self.component_types.get(type_id) {
+            (data.load)(entity)
         } else {
             Err(TypeNotFoundError::new(format!("{:?}", type_id)).into())
         }
     }
 
+    /// Save data for a [`Component`] with the associated `TypeId` to the
+    /// appropriate backing store.
+    ///
+    /// This should probably only be used internally.
+    pub fn set_component_by_id(
+        &self,
+        entity: &T,
+        type_id: &TypeId,
+        component: Box<dyn Component<T> + 'static>,
+    ) -> Result<()> {
+        if let Some(data) = self.component_types.get(type_id) {
+            (data.store)(entity, component)?;
+            self.emit(EventKind::ComponentSet, entity, type_id);
+            Ok(())
+        } else {
+            Err(TypeNotFoundError::new(format!("{:?}", type_id)).into())
+        }
+    }
+
+    /// List the IDs of all entities with stored data for a [`Component`]
+    /// type.
+    ///
+    /// Returns `Ok(None)` if the backing store for the [`Component`] type
+    /// doesn't support listing its contents.
+    pub fn component_keys<U: Component<T> + 'static>(&self) -> Result<Option<Vec<Snowflake>>> {
+        self.component_keys_by_id(&TypeId::of::<U>())
+    }
+
+    /// List the IDs of all entities with stored data for the
+    /// [`Component`] type with the associated `TypeId`.
+    ///
+    /// This should probably only be used internally.
+    pub fn component_keys_by_id(&self, type_id: &TypeId) -> Result<Option<Vec<Snowflake>>> {
+        if let Some(data) = self.component_types.get(type_id) {
+            (data.keys)()
+        } else {
+            Err(TypeNotFoundError::new(format!("{:?}", type_id)).into())
+        }
+    }
+
+    /// Gets the transaction support for each registered [`Component`]
+    /// backing store that has any.
+    pub fn transactional_backends(&self) -> Vec<Arc<dyn TransactionalBackend>> {
+        self.component_types
+            .values()
+            .filter_map(|data| data.transactional.clone())
+            .collect()
+    }
+
     /// Check to see if associated [`Component`] data exists for the given
     /// entity and Component type.
     pub fn component_exists<U: Component<T> + 'static>(&self, entity: &T) -> Result<bool> {
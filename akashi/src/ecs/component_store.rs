@@ -2,6 +2,8 @@
 
 use super::component::Component;
 use super::entity::Entity;
+use super::transaction::TransactionalBackend;
+use crate::snowflake::Snowflake;
 use crate::util::Result;
 
 use std::any;
@@ -34,6 +36,72 @@ where
     /// Delete the stored [`Component`] data associated with the given
     /// `Entity`, if any.
     fn delete(&self, entity: &T) -> Result<()>;
+
+    /// Loads [`Component`] data for several [`Entities`](Entity) at once.
+    ///
+    /// The returned `Vec` has one entry for each `Entity` in `entities`,
+    /// in the same order. The default implementation calls
+    /// [`load`](ComponentBackend::load) once per `Entity`; backends that
+    /// can fetch several records in one round trip should override this.
+    fn load_many(&self, entities: &[&T]) -> Result<Vec<Option<U>>> {
+        entities.iter().map(|entity| self.load(entity)).collect()
+    }
+
+    /// Saves [`Component`] data for several [`Entities`](Entity) at once.
+    ///
+    /// The default implementation calls
+    /// [`store`](ComponentBackend::store) once per `Entity`, stopping at
+    /// the first error.
+    fn store_many(&self, components: Vec<(&T, U)>) -> Result<()> {
+        for (entity, component) in components {
+            self.store(entity, component)?;
+        }
+
+        Ok(())
+    }
+
+    /// Checks to see which of several [`Entities`](Entity) have any stored
+    /// [`Component`] data.
+    ///
+    /// The default implementation calls
+    /// [`exists`](ComponentBackend::exists) once per `Entity`.
+    fn exists_many(&self, entities: &[&T]) -> Result<Vec<bool>> {
+        entities.iter().map(|entity| self.exists(entity)).collect()
+    }
+
+    /// Deletes the stored [`Component`] data for several
+    /// [`Entities`](Entity) at once.
+    ///
+    /// The default implementation calls
+    /// [`delete`](ComponentBackend::delete) once per `Entity`, stopping at
+    /// the first error.
+    fn delete_many(&self, entities: &[&T]) -> Result<()> {
+        for entity in entities {
+            self.delete(entity)?;
+        }
+
+        Ok(())
+    }
+
+    /// Lists the IDs of all [`Entities`](Entity) that have [`Component`]
+    /// data stored in this backend.
+    ///
+    /// Backends that can't list their contents can leave this as the
+    /// default implementation, which returns `Ok(None)`. Queries over
+    /// these components will then fall back to checking each stored
+    /// `Entity` individually.
+    fn keys(&self) -> Result<Option<Vec<Snowflake>>> {
+        Ok(None)
+    }
+
+    /// Gets an object that can be used to make changes to this backend
+    /// within a transaction, if supported.
+    ///
+    /// Backends that don't support transactions can leave this as the
+    /// default implementation, which returns `None`.
+    fn transactional(&self) -> Option<Arc<dyn TransactionalBackend>> {
+        None
+    }
 }
 
 type ComponentLoadFn<T> =
@@ -46,6 +114,8 @@ type ComponentExistsFn<T> = Box<dyn Fn(&T) -> Result<bool> + Sync + Send>;
 
 type ComponentDeleteFn<T> = Box<dyn Fn(&T) -> Result<()> + Sync + Send>;
 
+type ComponentKeysFn = Box<dyn Fn() -> Result<Option<Vec<Snowflake>>> + Sync + Send>;
+
 /// Used internally by [`ComponentManager`](super::ComponentManager) as a
 /// proxy to [`ComponentBackend`] trait methods.
 pub struct ComponentTypeData<T: Entity + 'static> {
@@ -53,6 +123,8 @@ pub struct ComponentTypeData<T: Entity + 'static> {
     pub store: ComponentBackendFn<T>,
     pub exists: ComponentExistsFn<T>,
     pub delete: ComponentDeleteFn<T>,
+    pub keys: ComponentKeysFn,
+    pub transactional: Option<Arc<dyn TransactionalBackend>>,
 }
 
 impl<T> fmt::Debug for ComponentTypeData<T>
@@ -74,10 +146,12 @@ where
         U: Component<T> + 'static,
         V: ComponentBackend<T, U> + Sync + Send + 'static,
     {
+        let transactional = store.transactional();
         let s1 = Arc::new(store);
         let s2 = s1.clone();
         let s3 = s1.clone();
         let s4 = s1.clone();
+        let s5 = s1.clone();
 
         ComponentTypeData {
             load: Box::new(move |ent: &T| {
@@ -103,6 +177,8 @@ where
             ),
             exists: Box::new(move |ent: &T| s3.exists(ent)),
             delete: Box::new(move |ent: &T| s4.delete(ent)),
+            keys: Box::new(move || s5.keys()),
+            transactional,
         }
     }
 }
@@ -192,4 +268,38 @@ where
     fn delete(&self, entity: &E) -> Result<()> {
         self.wrapped.delete(entity)
     }
+
+    fn load_many(&self, entities: &[&E]) -> Result<Vec<Option<T>>> {
+        Ok(self
+            .wrapped
+            .load_many(entities)?
+            .into_iter()
+            .map(|res| res.map(|other_type: F| other_type.into()))
+            .collect())
+    }
+
+    fn store_many(&self, components: Vec<(&E, T)>) -> Result<()> {
+        self.wrapped.store_many(
+            components
+                .into_iter()
+                .map(|(entity, component)| (entity, component.into()))
+                .collect(),
+        )
+    }
+
+    fn exists_many(&self, entities: &[&E]) -> Result<Vec<bool>> {
+        self.wrapped.exists_many(entities)
+    }
+
+    fn delete_many(&self, entities: &[&E]) -> Result<()> {
+        self.wrapped.delete_many(entities)
+    }
+
+    fn keys(&self) -> Result<Option<Vec<Snowflake>>> {
+        self.wrapped.keys()
+    }
+
+    fn transactional(&self) -> Option<Arc<dyn TransactionalBackend>> {
+        self.wrapped.transactional()
+    }
 }
@@ -9,6 +9,7 @@ use std::any;
 use std::any::TypeId;
 use std::collections::HashSet;
 use std::fmt;
+use std::mem;
 use std::result;
 use std::sync::Arc;
 
@@ -35,6 +36,53 @@ use failure::{Error, Fail};
 /// types for which no backing store has been registered with
 /// [`ComponentManager::register_component`](ComponentManager::register_component)
 /// will return [`TypeNotFoundError`](TypeNotFoundError).
+///
+/// # Deriving
+///
+/// New kinds of entities can implement this trait with
+/// `#[derive(Entity)]`. The struct needs fields for the entity's ID,
+/// component manager, attached components, preloaded components, and
+/// changes; these are found by name, or can be marked with attributes
+/// such as `#[entity(id)]`. Any other fields are set to their default
+/// values when entities are created by [`Entity::new`]. Marking the
+/// struct with `#[entity(clone)]` implements `Clone` as well, which most
+/// [`EntityBackends`](super::EntityBackend) need.
+///
+/// ```
+/// use akashi::ecs::{ChangeSet, Component, ComponentManager, Entity, EntityManager};
+/// use akashi::local_storage::{LocalComponentStorage, LocalEntityStorage};
+/// use akashi::Snowflake;
+/// use dashmap::DashMap;
+/// use std::any::TypeId;
+/// use std::collections::HashSet;
+/// use std::sync::Arc;
+///
+/// #[derive(Entity)]
+/// #[entity(clone)]
+/// struct Guild {
+///     #[entity(id)]
+///     guild_id: Snowflake,
+///     component_manager: Arc<ComponentManager<Guild>>,
+///     components_attached: HashSet<TypeId>,
+///     component_preloads: DashMap<TypeId, Box<dyn Component<Guild> + Send + Sync>>,
+///     changes: ChangeSet,
+/// }
+///
+/// #[derive(Component, Clone, Debug, PartialEq)]
+/// #[component(entity = Guild)]
+/// struct Motto(String);
+///
+/// let mut manager = EntityManager::new();
+/// manager.register_entity(LocalEntityStorage::<Guild>::new()).unwrap();
+/// manager
+///     .register_component("Motto", LocalComponentStorage::<Guild, Motto>::new())
+///     .unwrap();
+///
+/// let mut guild: Guild = manager.create(1u64.into()).unwrap();
+/// guild.set_component(Motto("Never give up".to_owned())).unwrap();
+/// assert_eq!(guild.id(), 1u64.into());
+/// manager.store(guild).unwrap();
+/// ```
 pub trait Entity: Sized + Send + Sync + 'static {
     fn new(
         id: Snowflake,
@@ -46,12 +94,25 @@ pub trait Entity: Sized + Send + Sync + 'static {
     /// Components.
     fn id(&self) -> Snowflake;
 
-    /// Checks whether any [`Components`](Component) have been modified
-    /// on this Entity.
-    fn dirty(&self) -> bool;
+    /// Gets the changes made to this Entity since it was loaded or last
+    /// stored.
+    fn changes(&self) -> &ChangeSet;
 
-    /// Get this entity's 'dirty' flag.
-    fn dirty_mut(&mut self) -> &mut bool;
+    /// Gets a mutable reference to the changes made to this Entity since
+    /// it was loaded or last stored.
+    fn changes_mut(&mut self) -> &mut ChangeSet;
+
+    /// Checks whether any changes have been made to this Entity since it
+    /// was loaded or last stored.
+    fn dirty(&self) -> bool {
+        !self.changes().is_empty()
+    }
+
+    /// Takes the set of changes made to this Entity, leaving it marked as
+    /// clean.
+    fn take_changes(&mut self) -> ChangeSet {
+        mem::take(self.changes_mut())
+    }
 
     /// Gets a reference to the [`ComponentManager`]
     /// used to perform operations on this Entity.
@@ -98,8 +159,8 @@ pub trait Entity: Sized + Send + Sync + 'static {
         self.component_manager()
             .set_component::<T>(&self, component)
             .map(|_v| {
-                self.components_attached_mut().insert(TypeId::of::<T>());
-                *self.dirty_mut() = true;
+                let attached = self.components_attached_mut().insert(TypeId::of::<T>());
+                self.changes_mut().record_set(TypeId::of::<T>(), attached);
             })
     }
 
@@ -138,8 +199,9 @@ pub trait Entity: Sized + Send + Sync + 'static {
 
     /// Deletes an attached [`Component`] from this Entity.
     fn delete_component<T: Component<Self> + 'static>(&mut self) -> Result<()> {
-        self.components_attached_mut().remove(&TypeId::of::<T>());
-        *self.dirty_mut() = true;
+        let detached = self.components_attached_mut().remove(&TypeId::of::<T>());
+        self.changes_mut()
+            .record_delete(TypeId::of::<T>(), detached);
         self.component_manager().delete_component::<T>(&self)
     }
 
@@ -161,7 +223,10 @@ pub trait Entity: Sized + Send + Sync + 'static {
             }
         }
 
-        self.components_attached_mut().clear();
+        let detached: Vec<TypeId> = self.components_attached_mut().drain().collect();
+        for type_id in detached {
+            self.changes_mut().record_delete(type_id, true);
+        }
 
         if err.len() > 0 {
             Err(err)
@@ -171,6 +236,134 @@ pub trait Entity: Sized + Send + Sync + 'static {
     }
 }
 
+/// Tracks which [`Components`](Component) have been changed on an
+/// [`Entity`].
+///
+/// [`Entity`] storage backends can use this to work out which parts of
+/// an [`Entity`] actually need to be written; see
+/// [`EntityBackend::store_changes`](super::EntityBackend::store_changes).
+///
+/// # Example
+///
+/// ```
+/// use akashi::{Card, Component, Entity, EntityManager};
+/// use akashi::local_storage::{LocalEntityStorage, LocalComponentStorage};
+/// use std::any::TypeId;
+///
+/// #[derive(Clone)]
+/// struct Level(u64);
+/// impl Component<Card> for Level {}
+///
+/// let mut manager = EntityManager::new();
+/// manager.register_entity(LocalEntityStorage::<Card>::new()).unwrap();
+/// manager
+///     .register_component("Level", LocalComponentStorage::<Card, Level>::new())
+///     .unwrap();
+///
+/// let mut card: Card = manager.create(1u64.into()).unwrap();
+/// assert!(!card.dirty());
+///
+/// card.set_component(Level(1)).unwrap();
+/// assert!(card.changes().was_set(&TypeId::of::<Level>()));
+/// assert!(card.changes().attachments_changed());
+/// manager.store(card).unwrap();
+///
+/// // Storing an Entity marks it as clean.
+/// let mut handle = manager.load_mut::<Card>(1u64.into()).unwrap();
+/// let card = handle.get_mut().unwrap();
+/// assert!(!card.dirty());
+///
+/// // Updating an already-attached Component doesn't change which
+/// // Components are attached.
+/// card.set_component(Level(2)).unwrap();
+/// assert!(card.dirty());
+/// assert!(!card.changes().attachments_changed());
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ChangeSet {
+    set: HashSet<TypeId>,
+    deleted: HashSet<TypeId>,
+    attachments_changed: bool,
+}
+
+impl ChangeSet {
+    /// Creates a new, empty `ChangeSet`.
+    pub fn new() -> ChangeSet {
+        ChangeSet::default()
+    }
+
+    /// Checks whether no changes have been recorded.
+    pub fn is_empty(&self) -> bool {
+        self.set.is_empty() && self.deleted.is_empty() && !self.attachments_changed
+    }
+
+    /// Gets the `TypeId`s of each [`Component`] that has been attached or
+    /// updated.
+    pub fn set_components(&self) -> &HashSet<TypeId> {
+        &self.set
+    }
+
+    /// Gets the `TypeId`s of each [`Component`] that has been deleted.
+    pub fn deleted_components(&self) -> &HashSet<TypeId> {
+        &self.deleted
+    }
+
+    /// Checks whether a [`Component`] type has been attached or updated.
+    pub fn was_set(&self, type_id: &TypeId) -> bool {
+        self.set.contains(type_id)
+    }
+
+    /// Checks whether a [`Component`] type has been deleted.
+    pub fn was_deleted(&self, type_id: &TypeId) -> bool {
+        self.deleted.contains(type_id)
+    }
+
+    /// Checks whether a [`Component`] type has been changed at all.
+    pub fn contains(&self, type_id: &TypeId) -> bool {
+        self.was_set(type_id) || self.was_deleted(type_id)
+    }
+
+    /// Checks whether the set of [`Components`](Component) attached to
+    /// the [`Entity`] has changed, as opposed to just the data for
+    /// already-attached [`Components`](Component).
+    pub fn attachments_changed(&self) -> bool {
+        self.attachments_changed
+    }
+
+    /// Records that a [`Component`] was attached or updated.
+    ///
+    /// `attached` should be true if the [`Component`] wasn't attached
+    /// before.
+    pub fn record_set(&mut self, type_id: TypeId, attached: bool) {
+        self.deleted.remove(&type_id);
+        self.set.insert(type_id);
+        self.attachments_changed |= attached;
+    }
+
+    /// Records that a [`Component`] was deleted.
+    ///
+    /// `detached` should be true if the [`Component`] was attached
+    /// before.
+    pub fn record_delete(&mut self, type_id: TypeId, detached: bool) {
+        self.set.remove(&type_id);
+        self.deleted.insert(type_id);
+        self.attachments_changed |= detached;
+    }
+
+    /// Marks the [`Entity`] as needing to be fully written back to
+    /// storage, even if no [`Component`] data has changed.
+    pub fn mark_attachments_changed(&mut self) {
+        self.attachments_changed = true;
+    }
+
+    /// Clears all recorded changes.
+    pub fn clear(&mut self) {
+        self.set.clear();
+        self.deleted.clear();
+        self.attachments_changed = false;
+    }
+}
+
 /// This failure type collects errors from [`Entity::clear_components`].
 #[derive(Fail, Debug)]
 pub struct ClearComponentsError {
@@ -1,9 +1,17 @@
 //! A manager for creating, loading, and storing [`Entities`](Entity).
+use super::async_store::{
+    AsyncComponentBackend, AsyncComponentManager, AsyncEntityBackend, AsyncEntityStore,
+    AsyncReadReference, AsyncStore, AsyncStoreHandle, AsyncWriteReference,
+};
+use super::cache::CachePolicy;
 use super::component::ComponentManagerDowncast;
 use super::entity_store::{
-    EntityBackend, EntityStore, EntityStoreDowncast, EntityStoreDowncastHelper, ReadReference,
-    StoreHandle, WriteReference,
+    EntityBackend, EntityStore, EntityStoreDowncast, EntityStoreDowncastHelper, KeyIter,
+    ReadReference, StoreHandle, WriteReference,
 };
+use super::event::{Event, EventBus, EventKind};
+use super::query::{Query, QueryComponents};
+use super::transaction::{Transaction, TransactionalBackend};
 use super::{Component, ComponentBackend, ComponentManager, Entity, Store, TypeNotFoundError};
 use crate::snowflake::Snowflake;
 use crate::util::Result;
@@ -11,13 +19,14 @@ use crate::util::Result;
 use failure::{err_msg, format_err};
 
 use std::any;
-use std::any::TypeId;
+use std::any::{Any, TypeId};
 use std::collections::{HashMap, HashSet};
 use std::sync::Arc;
 
 #[doc(hidden)]
 pub struct EntityTypeData {
-    store: Box<dyn EntityStoreDowncast>,
+    store: Option<Box<dyn EntityStoreDowncast>>,
+    async_store: Option<Box<dyn Any + Send + Sync>>,
     component_manager: Arc<dyn ComponentManagerDowncast>,
 }
 
@@ -36,6 +45,13 @@ pub struct EntityTypeData {
 /// (`load`, `store`, `delete`, etc.) with types that do not exist will return
 /// a [`TypeNotFoundError`].
 ///
+/// # Events
+///
+/// Changes made through an `EntityManager` (or any of the
+/// [`Stores`](super::Store), [`ComponentManagers`](ComponentManager), and
+/// handles it gives out) are reported to its [`EventBus`], which can be
+/// accessed with [`events`](EntityManager::events).
+///
 /// # Example
 ///
 /// ```
@@ -101,6 +117,7 @@ pub struct EntityTypeData {
 /// ```
 pub struct EntityManager {
     types: HashMap<TypeId, EntityTypeData>,
+    events: Arc<EventBus>,
 }
 
 impl EntityManager {
@@ -108,9 +125,19 @@ impl EntityManager {
     pub fn new() -> EntityManager {
         EntityManager {
             types: HashMap::new(),
+            events: Arc::new(EventBus::new()),
         }
     }
 
+    /// Gets the [`EventBus`] that changes to [`Entities`](Entity) and
+    /// [`Components`](Component) managed by this `EntityManager` are
+    /// reported to.
+    ///
+    /// See the [`EventBus`] documentation for more details.
+    pub fn events(&self) -> &Arc<EventBus> {
+        &self.events
+    }
+
     /// Registers an [`Entity`] type and its associated storage backend.
     ///
     /// # Errors
@@ -141,6 +168,152 @@ impl EntityManager {
     where
         T: Entity + 'static,
         U: EntityBackend<T> + Sync + Send + 'static,
+    {
+        let store = Store::<T, U>::with_events(Arc::new(backend), self.events.clone());
+        self.register_store(store)
+    }
+
+    /// Registers an [`Entity`] type and its associated storage backend,
+    /// with a cache in front of the backend.
+    ///
+    /// See the [`cache`](super::cache) module for more details.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the [`Entity`] type has already
+    /// been registered before.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use akashi::{Card, Entity, EntityManager};
+    /// use akashi::ecs::CachePolicy;
+    /// use akashi::local_storage::LocalEntityStorage;
+    ///
+    /// let mut manager = EntityManager::new();
+    /// manager
+    ///     .register_entity_with_cache(LocalEntityStorage::<Card>::new(), CachePolicy::new(100))
+    ///     .unwrap();
+    ///
+    /// let card: Card = manager.create(1u64.into()).unwrap();
+    /// manager.store(card).unwrap();
+    ///
+    /// // Changes made through mutable handles are written back when the
+    /// // cache is flushed.
+    /// manager.load_mut::<Card>(1u64.into()).unwrap();
+    /// manager.flush::<Card>().unwrap();
+    /// ```
+    pub fn register_entity_with_cache<T, U>(
+        &mut self,
+        backend: U,
+        policy: CachePolicy,
+    ) -> Result<()>
+    where
+        T: Entity + 'static,
+        U: EntityBackend<T> + Sync + Send + 'static,
+    {
+        let store =
+            Store::<T, U>::with_events(Arc::new(backend), self.events.clone()).with_cache(policy);
+        self.register_store(store)
+    }
+
+    /// Registers an [`Entity`] type with an already-configured
+    /// [`Store`](super::Store).
+    ///
+    /// This can be used to set options on the [`Store`](super::Store),
+    /// such as its [`CachePolicy`] or [`DropPolicy`](super::DropPolicy),
+    /// before registering it. The [`Store`](super::Store) should be
+    /// created with [`Store::with_events`](super::Store::with_events),
+    /// using this manager's [`EventBus`], so that changes made through it
+    /// are reported.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the [`Entity`] type has already
+    /// been registered before.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use akashi::{Card, EntityManager};
+    /// use akashi::ecs::{DropPolicy, Store};
+    /// use akashi::local_storage::LocalEntityStorage;
+    /// use std::sync::Arc;
+    ///
+    /// let mut manager = EntityManager::new();
+    /// let store = Store::with_events(
+    ///     Arc::new(LocalEntityStorage::<Card>::new()),
+    ///     manager.events().clone(),
+    /// )
+    /// .with_drop_policy(DropPolicy::Panic);
+    ///
+    /// manager.register_store(store).unwrap();
+    /// ```
+    pub fn register_store<T, U>(&mut self, store: Store<T, U>) -> Result<()>
+    where
+        T: Entity + 'static,
+        U: EntityBackend<T> + Sync + Send + 'static,
+    {
+        if self.types.contains_key(&TypeId::of::<T>()) {
+            return Err(format_err!(
+                "entity type already registered: {}",
+                any::type_name::<T>()
+            ));
+        }
+
+        let dc_helper = EntityStoreDowncastHelper(Box::new(store));
+        let type_data = EntityTypeData {
+            store: Some(Box::new(dc_helper)),
+            async_store: None,
+            component_manager: Arc::new(ComponentManager::<T>::with_events(self.events.clone())),
+        };
+
+        self.types.insert(TypeId::of::<T>(), type_data);
+
+        Ok(())
+    }
+
+    /// Registers an [`Entity`] type and its associated async storage
+    /// backend.
+    ///
+    /// [`Entities`](Entity) of this type can then be accessed with the
+    /// async methods on this manager, such as
+    /// [`load_async`](EntityManager::load_async), but not with their
+    /// synchronous counterparts. [`Components`](Component) for the type
+    /// are registered with [`register_component`](EntityManager::register_component)
+    /// as usual.
+    ///
+    /// To use a blocking [`EntityBackend`], wrap it in a
+    /// [`BlockingEntityBackend`](super::blocking::BlockingEntityBackend).
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the [`Entity`] type has already
+    /// been registered before.
+    pub fn register_async_entity<T, U>(&mut self, backend: U) -> Result<()>
+    where
+        T: Entity + 'static,
+        U: AsyncEntityBackend<T> + 'static,
+    {
+        let store = AsyncStore::<T, U>::with_events(Arc::new(backend), self.events.clone());
+        self.register_async_store(store)
+    }
+
+    /// Registers an [`Entity`] type with an already-configured
+    /// [`AsyncStore`].
+    ///
+    /// As with [`register_store`](EntityManager::register_store), the
+    /// [`AsyncStore`] should be created with
+    /// [`AsyncStore::with_events`], using this manager's [`EventBus`].
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the [`Entity`] type has already
+    /// been registered before.
+    pub fn register_async_store<T, U>(&mut self, store: AsyncStore<T, U>) -> Result<()>
+    where
+        T: Entity + 'static,
+        U: AsyncEntityBackend<T> + 'static,
     {
         if self.types.contains_key(&TypeId::of::<T>()) {
             return Err(format_err!(
@@ -149,10 +322,11 @@ impl EntityManager {
             ));
         }
 
-        let dc_helper = EntityStoreDowncastHelper(Box::new(Store::<T, U>::new(Arc::new(backend))));
+        let dyn_store: Box<dyn AsyncEntityStore<T>> = Box::new(store);
         let type_data = EntityTypeData {
-            store: Box::new(dc_helper),
-            component_manager: Arc::new(ComponentManager::<T>::new()),
+            store: None,
+            async_store: Some(Box::new(dyn_store)),
+            component_manager: Arc::new(ComponentManager::<T>::with_events(self.events.clone())),
         };
 
         self.types.insert(TypeId::of::<T>(), type_data);
@@ -221,6 +395,50 @@ impl EntityManager {
         cm_ref.register_component(name, backend)
     }
 
+    /// Registers a backing storage object and unique name for a
+    /// [`Component`] type attached to an [`Entity`] type registered for
+    /// async access.
+    ///
+    /// The [`Component`] is registered with the [`AsyncComponentManager`]
+    /// for the [`Entity`] type's [`AsyncStore`], which uses it to delete
+    /// [`Component`] data along with [`Entities`](Entity). To use a
+    /// blocking [`ComponentBackend`], wrap it in a
+    /// [`BlockingComponentBackend`](super::blocking::BlockingComponentBackend).
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the [`Entity`] type isn't
+    /// registered for async access, if the [`Component`] type has already
+    /// been registered, or if the [`AsyncComponentManager`] is in use
+    /// elsewhere.
+    pub fn register_async_component<T, U, V>(&mut self, name: &str, backend: V) -> Result<()>
+    where
+        T: Entity + 'static,
+        U: Component<T> + 'static,
+        V: AsyncComponentBackend<T, U> + 'static,
+    {
+        let type_data = self
+            .types
+            .get_mut(&TypeId::of::<T>())
+            .ok_or_else(|| format_err!("entity type not registered: {}", any::type_name::<T>()))?;
+
+        let store = type_data
+            .async_store
+            .as_mut()
+            .ok_or_else(|| {
+                format_err!(
+                    "entity type not registered for async access: {}",
+                    any::type_name::<T>()
+                )
+            })?
+            .downcast_mut::<Box<dyn AsyncEntityStore<T>>>()
+            .expect("failed to downcast AsyncEntityStore");
+
+        Arc::get_mut(store.components_mut())
+            .ok_or_else(|| err_msg("could not get exclusive access to AsyncComponentManager"))?
+            .register_component(name, backend)
+    }
+
     fn get_type_data<'a, T>(
         &'a self,
     ) -> Option<(&'a (dyn EntityStore<T> + 'static), Arc<ComponentManager<T>>)>
@@ -232,6 +450,7 @@ impl EntityManager {
 
         let store_ref = type_data
             .store
+            .as_ref()?
             .downcast_ref::<EntityStoreDowncastHelper<T>>()
             .expect("failed to downcast EntityStore wrapper");
 
@@ -244,6 +463,41 @@ impl EntityManager {
         Some((&*store_ref.0, cm))
     }
 
+    fn get_async_type_data<'a, T>(
+        &'a self,
+    ) -> Result<(
+        &'a (dyn AsyncEntityStore<T> + 'static),
+        Arc<ComponentManager<T>>,
+    )>
+    where
+        T: Entity + 'static,
+    {
+        let type_data = self
+            .types
+            .get(&TypeId::of::<T>())
+            .ok_or_else(|| TypeNotFoundError::new(String::from(any::type_name::<T>())))?;
+
+        let store = type_data
+            .async_store
+            .as_ref()
+            .ok_or_else(|| {
+                format_err!(
+                    "entity type not registered for async access: {}",
+                    any::type_name::<T>()
+                )
+            })?
+            .downcast_ref::<Box<dyn AsyncEntityStore<T>>>()
+            .expect("failed to downcast AsyncEntityStore");
+
+        let cm = type_data
+            .component_manager
+            .clone()
+            .downcast_arc::<ComponentManager<T>>()
+            .expect("failed to downcast ComponentManager");
+
+        Ok((&**store, cm))
+    }
+
     fn get_store_dyn<'a, T>(&'a self) -> Option<&'a (dyn EntityStore<T> + 'static)>
     where
         T: Entity + 'static,
@@ -253,6 +507,7 @@ impl EntityManager {
 
         let store_ref = type_data
             .store
+            .as_ref()?
             .downcast_ref::<EntityStoreDowncastHelper<T>>()
             .expect("failed to downcast EntityStore wrapper");
 
@@ -294,6 +549,7 @@ impl EntityManager {
 
         let store_ref = type_data
             .store
+            .as_ref()?
             .downcast_ref::<EntityStoreDowncastHelper<T>>()
             .expect("failed to downcast EntityStore wrapper");
 
@@ -380,6 +636,9 @@ impl EntityManager {
             .downcast_arc::<ComponentManager<T>>()
             .expect("failed to downcast ComponentManager");
 
+        self.events
+            .emit(Event::entity::<T>(EventKind::EntityCreated, id));
+
         Some(T::new(id, cm, HashSet::new()))
     }
 
@@ -570,6 +829,219 @@ impl EntityManager {
         store.exists(id)
     }
 
+    /// Loads immutable (read-locked) references to several [`Entities`](Entity)
+    /// of the same type at once.
+    ///
+    /// [`Entities`](Entity) that aren't already loaded are fetched from
+    /// storage together, rather than one at a time; see
+    /// [`Store::load_many`](super::Store::load_many). The returned
+    /// references are in the same order as `ids`.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use akashi::Card;
+    /// use akashi::EntityManager;
+    /// use akashi::local_storage::LocalEntityStorage;
+    ///
+    /// let mut manager = EntityManager::new();
+    /// manager.register_entity(LocalEntityStorage::<Card>::new()).unwrap();
+    ///
+    /// let cards: Vec<Card> = (1..=500u64)
+    ///     .map(|id| manager.create(id.into()).unwrap())
+    ///     .collect();
+    /// manager.store_many(cards).unwrap();
+    ///
+    /// let ids: Vec<_> = (1..=500u64).map(|id| id.into()).collect();
+    /// let handles = manager.load_many::<Card>(&ids).unwrap();
+    /// assert_eq!(handles.len(), 500);
+    /// assert!(handles.iter().all(|handle| handle.exists()));
+    /// ```
+    pub fn load_many<T>(&self, ids: &[Snowflake]) -> Result<Vec<ReadReference<StoreHandle<T>>>>
+    where
+        T: Entity + 'static,
+    {
+        let (store, cm) = self
+            .get_type_data()
+            .ok_or_else(|| TypeNotFoundError::new(String::from(any::type_name::<T>())))?;
+
+        store.load_many(ids, cm)
+    }
+
+    /// Stores several [`Entities`](Entity) of the same type to their
+    /// configured storage backend at once.
+    pub fn store_many<T>(&self, entities: Vec<T>) -> Result<()>
+    where
+        T: Entity + 'static,
+    {
+        let ent_store = self
+            .get_store_dyn()
+            .ok_or_else(|| TypeNotFoundError::new(String::from(any::type_name::<T>())))?;
+
+        ent_store.store_many(entities)
+    }
+
+    /// Deletes several [`Entities`](Entity) of the same type from their
+    /// configured storage backend at once.
+    pub fn delete_many<T>(&self, ids: &[Snowflake]) -> Result<()>
+    where
+        T: Entity + 'static,
+    {
+        let (store, cm) = self
+            .get_type_data::<T>()
+            .ok_or_else(|| TypeNotFoundError::new(String::from(any::type_name::<T>())))?;
+
+        store.delete_many(ids, cm)
+    }
+
+    /// Checks which of several [`Entities`](Entity) of the same type exist.
+    ///
+    /// The returned `Vec` is in the same order as `ids`.
+    pub fn exists_many<T>(&self, ids: &[Snowflake]) -> Result<Vec<bool>>
+    where
+        T: Entity + 'static,
+    {
+        let store = self
+            .get_store_dyn::<T>()
+            .ok_or_else(|| TypeNotFoundError::new(String::from(any::type_name::<T>())))?;
+
+        store.exists_many(ids)
+    }
+
+    /// Loads an immutable (read-locked) reference to an [`Entity`] from
+    /// its configured async storage backend.
+    ///
+    /// The [`Entity`] type must have been registered with
+    /// [`register_async_entity`](EntityManager::register_async_entity).
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use akashi::{Card, EntityManager};
+    /// use akashi::ecs::blocking::{block_on, BlockingEntityBackend, BlockingPool};
+    /// use akashi::local_storage::LocalEntityStorage;
+    /// use std::sync::Arc;
+    ///
+    /// let pool = Arc::new(BlockingPool::new(2).unwrap());
+    /// let mut manager = EntityManager::new();
+    /// manager
+    ///     .register_async_entity(BlockingEntityBackend::new(LocalEntityStorage::<Card>::new(), pool))
+    ///     .unwrap();
+    ///
+    /// block_on(async {
+    ///     let card: Card = manager.create(1u64.into()).unwrap();
+    ///     manager.store_async(card).await.unwrap();
+    ///
+    ///     let handle = manager.load_async::<Card>(1u64.into()).await.unwrap();
+    ///     assert!(handle.get().is_some());
+    /// });
+    /// ```
+    pub async fn load_async<T>(
+        &self,
+        id: Snowflake,
+    ) -> Result<AsyncReadReference<AsyncStoreHandle<T>>>
+    where
+        T: Entity + 'static,
+    {
+        let (store, cm) = self.get_async_type_data::<T>()?;
+        store.load(id, cm).await
+    }
+
+    /// Loads a mutable (write-locked) reference to an [`Entity`] from its
+    /// configured async storage backend.
+    ///
+    /// See the [`async_store`](super::async_store) module for notes on
+    /// holding the returned reference across `.await`s.
+    pub async fn load_mut_async<T>(
+        &self,
+        id: Snowflake,
+    ) -> Result<AsyncWriteReference<AsyncStoreHandle<T>>>
+    where
+        T: Entity + 'static,
+    {
+        let (store, cm) = self.get_async_type_data::<T>()?;
+        store.load_mut(id, cm).await
+    }
+
+    /// Stores an [`Entity`] object to its configured async storage backend.
+    pub async fn store_async<T>(&self, entity: T) -> Result<()>
+    where
+        T: Entity + 'static,
+    {
+        let (store, _cm) = self.get_async_type_data::<T>()?;
+        store.store(entity).await
+    }
+
+    /// Deletes an [`Entity`] object from its configured async storage
+    /// backend by ID.
+    pub async fn delete_async<T>(&self, id: Snowflake) -> Result<()>
+    where
+        T: Entity + 'static,
+    {
+        let (store, cm) = self.get_async_type_data::<T>()?;
+        store.delete(id, cm).await
+    }
+
+    /// Checks whether an [`Entity`] object with the given ID exists in its
+    /// configured async storage backend.
+    pub async fn exists_async<T>(&self, id: Snowflake) -> Result<bool>
+    where
+        T: Entity + 'static,
+    {
+        let (store, _cm) = self.get_async_type_data::<T>()?;
+        store.exists(id).await
+    }
+
+    /// Gets the [`AsyncComponentManager`] for an [`Entity`] type
+    /// registered for async access.
+    ///
+    /// [`Components`](Component) for these [`Entities`](Entity) are
+    /// registered using
+    /// [`register_async_component`](EntityManager::register_async_component).
+    pub fn async_components<T>(&self) -> Result<Arc<AsyncComponentManager<T>>>
+    where
+        T: Entity + 'static,
+    {
+        let (store, _cm) = self.get_async_type_data::<T>()?;
+        Ok(store.components().clone())
+    }
+
+    /// Writes back any changes to cached [`Entities`](Entity) of a type.
+    ///
+    /// This does nothing if the type was registered without a cache.
+    ///
+    /// # Errors
+    ///
+    /// See [`Store::flush`](super::Store::flush).
+    pub fn flush<T>(&self) -> Result<()>
+    where
+        T: Entity + 'static,
+    {
+        let store = self
+            .get_store_dyn::<T>()
+            .ok_or_else(|| TypeNotFoundError::new(String::from(any::type_name::<T>())))?;
+
+        store.flush()
+    }
+
+    /// Writes back any changes to cached [`Entities`](Entity) of every
+    /// registered type.
+    ///
+    /// Every type is flushed, even if flushing an earlier one fails; the
+    /// first error encountered is returned.
+    pub fn flush_all(&self) -> Result<()> {
+        let mut res = Ok(());
+        for store in self.types.values().filter_map(|data| data.store.as_ref()) {
+            if let Err(e) = store.flush() {
+                if res.is_ok() {
+                    res = Err(e);
+                }
+            }
+        }
+
+        res
+    }
+
     /// Gets a listing of all stored object IDs for the given [`Entity`] type.
     ///
     /// # Example
@@ -604,4 +1076,145 @@ impl EntityManager {
 
         store.keys(page, limit)
     }
+
+    /// Iterates over all stored IDs for the given [`Entity`] type, in
+    /// ascending order.
+    ///
+    /// Unlike [`keys`](EntityManager::keys), this pages through IDs using
+    /// the last ID seen rather than an offset; see [`KeyIter`] for details
+    /// and an example.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use akashi::{Card, EntityManager, Snowflake, SnowflakeGenerator};
+    /// use akashi::local_storage::LocalEntityStorage;
+    /// use std::time::{Duration, SystemTime};
+    ///
+    /// let mut manager = EntityManager::new();
+    /// manager.register_entity(LocalEntityStorage::<Card>::new()).unwrap();
+    ///
+    /// // Card IDs from before our time range start...
+    /// manager.store(manager.create::<Card>(1u64.into()).unwrap()).unwrap();
+    ///
+    /// // ... and a card created just now.
+    /// let start = SystemTime::now() - Duration::from_secs(60);
+    /// let id = SnowflakeGenerator::new(0, 0).generate();
+    /// manager.store(manager.create::<Card>(id).unwrap()).unwrap();
+    ///
+    /// // Find cards created within the last minute.
+    /// let recent = manager
+    ///     .iter_keys::<Card>()
+    ///     .unwrap()
+    ///     .created_between(start, SystemTime::now() + Duration::from_secs(1))
+    ///     .collect::<Result<Vec<Snowflake>, _>>()
+    ///     .unwrap();
+    /// assert_eq!(recent, vec![id]);
+    /// ```
+    pub fn iter_keys<T>(&self) -> Result<KeyIter<'_, T>>
+    where
+        T: Entity + 'static,
+    {
+        let store = self
+            .get_store_dyn::<T>()
+            .ok_or_else(|| TypeNotFoundError::new(String::from(any::type_name::<T>())))?;
+
+        Ok(KeyIter::new(store))
+    }
+
+    /// Creates a [`Query`] over all stored [`Entities`](Entity) of a type
+    /// that have a set of [`Components`](Component) attached.
+    ///
+    /// The components to fetch are given as a tuple; each row returned by
+    /// the query contains the ID of a matching Entity, followed by each of
+    /// the requested components.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use akashi::{Card, Component, Entity, EntityManager, Snowflake};
+    /// use akashi::local_storage::{LocalEntityStorage, LocalComponentStorage};
+    ///
+    /// #[derive(Clone)]
+    /// struct Attack(u64);
+    /// impl Component<Card> for Attack {}
+    ///
+    /// #[derive(Clone)]
+    /// struct Defense(u64);
+    /// impl Component<Card> for Defense {}
+    ///
+    /// let mut manager = EntityManager::new();
+    /// manager.register_entity(LocalEntityStorage::<Card>::new()).unwrap();
+    /// manager
+    ///     .register_component("Attack", LocalComponentStorage::<Card, Attack>::new())
+    ///     .unwrap();
+    /// manager
+    ///     .register_component("Defense", LocalComponentStorage::<Card, Defense>::new())
+    ///     .unwrap();
+    ///
+    /// // Store one card with both components, and one with only one.
+    /// let mut card: Card = manager.create(1u64.into()).unwrap();
+    /// card.set_component(Attack(10)).unwrap();
+    /// card.set_component(Defense(5)).unwrap();
+    /// manager.store(card).unwrap();
+    ///
+    /// let mut card: Card = manager.create(2u64.into()).unwrap();
+    /// card.set_component(Attack(20)).unwrap();
+    /// manager.store(card).unwrap();
+    ///
+    /// // Only the first card has both components attached.
+    /// let query = manager.query::<Card, (Attack, Defense)>();
+    /// let rows: Vec<(Snowflake, Attack, Defense)> = query
+    ///     .iter()
+    ///     .unwrap()
+    ///     .collect::<Result<_, _>>()
+    ///     .unwrap();
+    ///
+    /// assert_eq!(rows.len(), 1);
+    /// assert_eq!(rows[0].0, 1u64.into());
+    /// assert_eq!(rows[0].1 .0, 10);
+    /// assert_eq!(rows[0].2 .0, 5);
+    ///
+    /// // Queries can also filter on components without fetching them.
+    /// let ids = manager
+    ///     .query::<Card, (Attack,)>()
+    ///     .without::<Defense>()
+    ///     .ids()
+    ///     .unwrap();
+    ///
+    /// assert_eq!(ids, vec![2u64.into()]);
+    /// ```
+    pub fn query<T, Q>(&self) -> Query<'_, T, Q>
+    where
+        T: Entity + 'static,
+        Q: QueryComponents<T>,
+    {
+        Query::new(self)
+    }
+
+    /// Starts a new [`Transaction`], for applying changes to multiple
+    /// [`Entities`](Entity) and [`Components`](Component) all at once.
+    ///
+    /// See the [`Transaction`] documentation for more details.
+    pub fn transaction(&self) -> Transaction<'_> {
+        Transaction::new(self)
+    }
+
+    /// Gets the transaction support for the storage backends used by an
+    /// [`Entity`] type and its registered [`Components`](Component).
+    pub(crate) fn transactional_backends<T>(&self) -> Result<Vec<Arc<dyn TransactionalBackend>>>
+    where
+        T: Entity + 'static,
+    {
+        let (store, cm) = self
+            .get_type_data::<T>()
+            .ok_or_else(|| TypeNotFoundError::new(String::from(any::type_name::<T>())))?;
+
+        let mut backends = cm.transactional_backends();
+        if let Some(backend) = store.transactional() {
+            backends.push(backend);
+        }
+
+        Ok(backends)
+    }
 }
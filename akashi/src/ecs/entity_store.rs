@@ -1,9 +1,13 @@
 //! Akashi's storage system for [`Entities`](Entity).
 
 use std::any;
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::fmt;
+use std::iter;
 use std::ops::Deref;
 use std::sync::{Arc, Weak};
+use std::thread;
+use std::time::{Duration, SystemTime};
 
 extern crate stable_deref_trait;
 use stable_deref_trait::CloneStableDeref;
@@ -12,7 +16,11 @@ use dashmap::DashMap;
 use downcast_rs::{Downcast, DowncastSync};
 use parking_lot::{Once, RwLock, RwLockReadGuard, RwLockWriteGuard};
 
-use super::{ComponentManager, Entity};
+use super::cache::{Cache, CachePolicy, ErrorSink, FlushError, FlushErrors};
+use super::event::{Event, EventBus, EventKind};
+use super::transaction::TransactionalBackend;
+use super::{ChangeSet, ComponentManager, Entity};
+use crate::clock::{Clock, SystemClock};
 use crate::snowflake::Snowflake;
 use crate::util::Result;
 
@@ -78,8 +86,44 @@ where
     fn get_store<'a>(&'a self) -> &'a Store<T, U>;
 }
 
+/// Decides what happens when a dirty [`StoreHandle`] can't be written
+/// back to storage as it's dropped.
+///
+/// Whatever the policy, errors that aren't otherwise handled are
+/// reported the same way as errors from [`Store`] cache flushes: they
+/// are passed to the callback set with
+/// [`Store::on_flush_error`], or queued up to be retrieved with
+/// [`Store::take_flush_errors`].
+///
+/// To handle write errors yourself instead, call
+/// [`StoreHandle::close`] on handles before dropping them.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub enum DropPolicy {
+    /// Report the error straight away.
+    #[default]
+    Report,
+
+    /// Retry the write up to `attempts` more times, waiting for
+    /// `backoff` before the first retry and twice as long before each
+    /// retry after that, then report the error if the write still fails.
+    /// The wait stops growing once it reaches [`Duration::MAX`].
+    ///
+    /// Note that this blocks whichever thread dropped the handle. The
+    /// waits go through the [`Store`]'s [`Clock`], so they can be run in
+    /// virtual time; see [`Store::with_clock`].
+    Retry { attempts: u32, backoff: Duration },
+
+    /// Panic in debug builds, to make lost writes obvious during
+    /// development. In release builds, this reports the error instead.
+    Panic,
+}
+
 /// A shared handle to an [`Entity`] and its storage backend.
 ///
+/// If the [`Entity`] in a handle is dirty when the handle is dropped, it
+/// will be written back to storage. Errors from that write are handled
+/// according to the [`DropPolicy`] of the [`Store`] the handle came from.
+///
 /// # Errors
 ///
 /// Most of the methods associated with `StoreHandle` call methods on
@@ -90,6 +134,10 @@ where
     T: Entity + 'static,
 {
     backend: Arc<dyn EntityBackend<T> + Sync + Send + 'static>,
+    events: Arc<EventBus>,
+    errors: Arc<ErrorSink>,
+    drop_policy: DropPolicy,
+    clock: Arc<dyn Clock>,
     id: Snowflake,
     object: Option<T>,
 }
@@ -98,17 +146,63 @@ impl<T> StoreHandle<T>
 where
     T: Entity + 'static,
 {
-    fn new<U>(backend: Arc<U>, id: Snowflake, object: Option<T>) -> StoreHandle<T>
+    fn new<U>(store: &Store<T, U>, id: Snowflake, object: Option<T>) -> StoreHandle<T>
     where
         U: EntityBackend<T> + Sync + Send + 'static,
     {
         StoreHandle {
-            backend,
+            backend: store.backend.clone(),
+            events: store.events.clone(),
+            errors: store.errors.clone(),
+            drop_policy: store.drop_policy,
+            clock: store.clock.clone(),
             id,
             object,
         }
     }
 
+    /// Writes back the [`Entity`] in a handle if it's dirty, then
+    /// releases the handle.
+    ///
+    /// This does the same thing that dropping the handle would, except
+    /// that any error from writing back the [`Entity`] is returned
+    /// instead of being handled by the [`Store`]'s [`DropPolicy`].
+    ///
+    /// If the write fails, the [`Entity`] is left dirty, so its changes
+    /// aren't lost: the write is tried again once the handle is dropped,
+    /// and errors from that are handled by the [`DropPolicy`] as usual.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use akashi::{Card, Component, Entity, EntityManager};
+    /// use akashi::ecs::StoreHandle;
+    /// use akashi::local_storage::{LocalEntityStorage, LocalComponentStorage};
+    ///
+    /// #[derive(Clone)]
+    /// struct Level(u64);
+    /// impl Component<Card> for Level {}
+    ///
+    /// let mut manager = EntityManager::new();
+    /// manager.register_entity(LocalEntityStorage::<Card>::new()).unwrap();
+    /// manager
+    ///     .register_component("Level", LocalComponentStorage::<Card, Level>::new())
+    ///     .unwrap();
+    ///
+    /// let card: Card = manager.create(1u64.into()).unwrap();
+    /// manager.store(card).unwrap();
+    ///
+    /// let mut handle = manager.load_mut::<Card>(1u64.into()).unwrap();
+    /// handle.get_mut().unwrap().set_component(Level(2)).unwrap();
+    ///
+    /// // Attaching the component made the card dirty, so it gets written
+    /// // back here.
+    /// StoreHandle::close(handle).unwrap();
+    /// ```
+    pub fn close(mut handle: WriteReference<StoreHandle<T>>) -> Result<()> {
+        handle.write_back()
+    }
+
     /// Gets a reference to the object within this handle.
     pub fn get(&self) -> Option<&T> {
         self.object.as_ref()
@@ -124,6 +218,13 @@ where
         self.object.replace(object)
     }
 
+    /// Takes the object out of this handle, leaving it empty.
+    ///
+    /// Unlike [`delete`](StoreHandle::delete), this doesn't touch storage.
+    pub fn take(&mut self) -> Option<T> {
+        self.object.take()
+    }
+
     /// Gets the ID of the [`Entity`] in this handle.
     pub fn id(&self) -> Snowflake {
         self.id
@@ -134,12 +235,31 @@ where
         self.object.is_some()
     }
 
+    /// Gets the changes made to the [`Entity`] in this handle since it
+    /// was loaded or last stored, if there is one.
+    pub fn changes(&self) -> Option<&ChangeSet> {
+        self.object.as_ref().map(|obj| obj.changes())
+    }
+
     /// Puts whatever is in this handle into storage.
+    ///
+    /// The [`Entity`] isn't marked as clean afterwards, so its changes
+    /// are still written back when the handle is closed or flushed.
     pub fn store(&self) -> Result<()> {
-        match &self.object {
-            None => self.backend.delete(self.id),
-            Some(obj) => self.backend.store(self.id, &obj),
-        }
+        let event = match &self.object {
+            None => {
+                self.backend.delete(self.id)?;
+                Event::entity::<T>(EventKind::EntityDeleted, self.id)
+            }
+            Some(obj) => {
+                self.backend.store(self.id, obj)?;
+                Event::entity::<T>(EventKind::EntityStored, self.id)
+                    .with_changes(obj.changes().clone())
+            }
+        };
+
+        self.events.emit(event);
+        Ok(())
     }
 
     /// Clears out the data in this handle, then deletes the [`Entity`]
@@ -150,12 +270,44 @@ where
         }
 
         self.object = None;
-        self.backend.delete(self.id)
+        self.backend.delete(self.id)?;
+        self.events
+            .emit(Event::entity::<T>(EventKind::EntityDeleted, self.id));
+        Ok(())
     }
 
     fn set_object(&mut self, object: Option<T>) {
         self.object = object;
     }
+
+    /// Puts whatever is in this handle into storage, then marks it as
+    /// clean.
+    pub(crate) fn store_clean(&mut self) -> Result<()> {
+        self.store()?;
+        if let Some(entity) = &mut self.object {
+            entity.changes_mut().clear();
+        }
+
+        Ok(())
+    }
+
+    /// Writes the object in this handle to storage if it's dirty, then
+    /// marks it as clean.
+    fn write_back(&mut self) -> Result<()> {
+        let entity = match &mut self.object {
+            Some(entity) if entity.dirty() => entity,
+            _ => return Ok(()),
+        };
+
+        self.backend
+            .store_changes(self.id, entity, entity.changes())?;
+
+        let changes = entity.take_changes();
+        self.events
+            .emit(Event::entity::<T>(EventKind::EntityStored, self.id).with_changes(changes));
+
+        Ok(())
+    }
 }
 
 impl<T> Drop for StoreHandle<T>
@@ -163,15 +315,44 @@ where
     T: Entity + 'static,
 {
     fn drop(&mut self) {
-        if let Some(entity) = &self.object {
-            if entity.dirty() {
-                // No good way of handling errors here
-                let _e = self.backend.store(self.id, &entity);
+        let mut res = self.write_back();
+
+        if let DropPolicy::Retry { attempts, backoff } = self.drop_policy {
+            for delay in retry_delays(attempts, backoff) {
+                if res.is_ok() {
+                    break;
+                }
+
+                self.clock.sleep(delay);
+                res = self.write_back();
             }
         }
+
+        if let Err(e) = res {
+            let error = FlushError::new(self.id, e);
+
+            // Panicking while already unwinding would abort the process.
+            if self.drop_policy == DropPolicy::Panic
+                && cfg!(debug_assertions)
+                && !thread::panicking()
+            {
+                panic!("{}", error);
+            }
+
+            self.errors.report(error);
+        }
     }
 }
 
+// The waits before each retry for DropPolicy::Retry. These double each
+// time, saturating instead of overflowing.
+fn retry_delays(attempts: u32, backoff: Duration) -> impl Iterator<Item = Duration> {
+    iter::successors(Some(backoff), |delay| {
+        Some(delay.checked_mul(2).unwrap_or(Duration::MAX))
+    })
+    .take(attempts as usize)
+}
+
 #[doc(hidden)]
 #[derive(Clone)]
 pub struct StoredHandleData<T>
@@ -201,13 +382,61 @@ where
 /// Most of the methods associated with `Store` call methods on storage
 /// backend objects. Errors returned by these methods will bubble up
 /// through `Store`'s methods.
+///
+/// # Caching
+///
+/// By default, `Store`s only keep [`StoreHandles`](StoreHandle) around for
+/// as long as something else is using them. A cache can be added with
+/// [`with_cache`](Store::with_cache) to keep frequently-used
+/// [`Entities`](Entity) loaded, and to write back changes to them in
+/// batches; see the [`cache`](super::cache) module for details.
+///
+/// Errors from writing back [`Entities`](Entity) in the background (either
+/// from cache flushes, or from dirty handles being dropped; see
+/// [`DropPolicy`]) are passed to the callback set with
+/// [`on_flush_error`](Store::on_flush_error), or queued up to be retrieved
+/// with [`take_flush_errors`](Store::take_flush_errors) if no callback has
+/// been set.
+///
+/// # Example
+///
+/// ```
+/// use akashi::{Card, Entity};
+/// use akashi::ecs::{CachePolicy, ComponentManager, Store};
+/// use akashi::local_storage::LocalEntityStorage;
+/// use std::collections::HashSet;
+/// use std::sync::Arc;
+///
+/// let cm = Arc::new(ComponentManager::new());
+/// let store = Store::new(Arc::new(LocalEntityStorage::new()))
+///     .with_cache(CachePolicy::new(100).with_flush_threshold(10));
+///
+/// let card = Card::new(1u64.into(), cm.clone(), HashSet::new());
+/// store.store(card).unwrap();
+///
+/// // The card stays cached even though nothing else is using it.
+/// assert_eq!(store.cached(), 1);
+///
+/// {
+///     let handle = store.load_mut(1u64.into(), cm.clone()).unwrap();
+///     // Any changes made through the handle will be written back
+///     // during the next flush.
+/// }
+///
+/// store.flush().unwrap();
+/// ```
 pub struct Store<T, U>
 where
     T: Entity + 'static,
     U: EntityBackend<T> + Sync + Send + 'static,
 {
     backend: Arc<U>,
+    events: Arc<EventBus>,
     refs: DashMap<Snowflake, StoredHandleData<T>>,
+    cache: Option<Cache<T>>,
+    errors: Arc<ErrorSink>,
+    drop_policy: DropPolicy,
+    clock: Arc<dyn Clock>,
 }
 
 impl<T, U> Store<T, U>
@@ -217,15 +446,183 @@ where
 {
     /// Creates a new `Store` using the given storage backend.
     pub fn new(backend: Arc<U>) -> Store<T, U> {
+        Store::with_events(backend, Arc::new(EventBus::new()))
+    }
+
+    /// Creates a new `Store` using the given storage backend, which
+    /// reports changes to the given [`EventBus`].
+    pub fn with_events(backend: Arc<U>, events: Arc<EventBus>) -> Store<T, U> {
         Store {
             backend,
+            events,
             refs: DashMap::new(),
+            cache: None,
+            errors: Arc::new(ErrorSink::default()),
+            drop_policy: DropPolicy::default(),
+            clock: Arc::new(SystemClock),
         }
     }
 
+    /// Sets how errors from writing back dirty [`Entities`](Entity) when
+    /// their handles are dropped should be handled, and returns the
+    /// `Store`.
+    pub fn with_drop_policy(mut self, policy: DropPolicy) -> Store<T, U> {
+        self.drop_policy = policy;
+        self
+    }
+
+    /// Gets how errors from writing back dirty [`Entities`](Entity) when
+    /// their handles are dropped are handled.
+    pub fn drop_policy(&self) -> DropPolicy {
+        self.drop_policy
+    }
+
+    /// Sets the [`Clock`] that handles wait on between write-back
+    /// attempts under [`DropPolicy::Retry`], and returns the `Store`.
+    ///
+    /// This doesn't affect the cache, which reads the time from the
+    /// [`Clock`] in its [`CachePolicy`].
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Store<T, U> {
+        self.clock = clock;
+        self
+    }
+
+    /// Gets the [`Clock`] that handles from this `Store` wait on.
+    pub fn clock(&self) -> &Arc<dyn Clock> {
+        &self.clock
+    }
+
+    /// Adds a cache to this `Store`, using the given policy, and returns
+    /// the `Store`.
+    pub fn with_cache(mut self, policy: CachePolicy) -> Store<T, U> {
+        self.cache = Some(Cache::new(policy));
+        self
+    }
+
+    /// Gets the policy used by this `Store`'s cache, if it has one.
+    pub fn cache_policy(&self) -> Option<&CachePolicy> {
+        self.cache.as_ref().map(|cache| cache.policy())
+    }
+
+    /// Gets the number of [`Entities`](Entity) currently held in this
+    /// `Store`'s cache.
+    pub fn cached(&self) -> usize {
+        self.cache.as_ref().map_or(0, |cache| cache.len())
+    }
+
+    /// Sets a callback to be called with errors from writing back
+    /// [`Entities`](Entity) in the background, either when cached
+    /// [`Entities`](Entity) are flushed or when dirty handles are dropped.
+    pub fn on_flush_error<F>(&self, callback: F)
+    where
+        F: Fn(&FlushError) + Send + Sync + 'static,
+    {
+        self.errors.set_callback(Box::new(callback));
+    }
+
+    /// Takes all queued errors from writing back [`Entities`](Entity) in
+    /// the background.
+    ///
+    /// Errors are only queued if no callback has been set with
+    /// [`on_flush_error`](Store::on_flush_error).
+    pub fn take_flush_errors(&self) -> Vec<FlushError> {
+        self.errors.take()
+    }
+
+    /// Writes back all changes to cached [`Entities`](Entity), and evicts
+    /// any that have expired.
+    ///
+    /// [`Entities`](Entity) that are locked by someone else are skipped,
+    /// and will be written back during a later flush.
+    ///
+    /// # Errors
+    ///
+    /// If any [`Entities`](Entity) could not be written back, this
+    /// returns a [`FlushErrors`] error listing them. They will be retried
+    /// during the next flush, if they are still cached.
+    pub fn flush(&self) -> Result<()> {
+        let cache = match &self.cache {
+            Some(cache) => cache,
+            None => return Ok(()),
+        };
+
+        let errors = self.write_back(cache, cache.drain());
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(FlushErrors::new(errors).into())
+        }
+    }
+
+    /// Writes back all changes to cached [`Entities`](Entity), then
+    /// empties the cache.
+    ///
+    /// # Errors
+    ///
+    /// As with [`flush`](Store::flush), this returns a [`FlushErrors`]
+    /// error if any [`Entities`](Entity) could not be written back.
+    pub fn clear_cache(&self) -> Result<()> {
+        let cache = match &self.cache {
+            Some(cache) => cache,
+            None => return Ok(()),
+        };
+
+        let errors = self.write_back(cache, cache.clear());
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(FlushErrors::new(errors).into())
+        }
+    }
+
+    fn write_back(
+        &self,
+        cache: &Cache<T>,
+        handles: Vec<(Snowflake, StoreReference<StoreHandle<T>>)>,
+    ) -> Vec<FlushError> {
+        let mut errors = Vec::new();
+        for (id, handle) in handles {
+            // Don't wait on handles that are in use, in case they're
+            // locked by the current thread.
+            match handle.try_write() {
+                Some(mut handle) => {
+                    if let Err(e) = handle.write_back() {
+                        cache.requeue(id);
+                        errors.push(FlushError::new(id, e));
+                    }
+                }
+                None => cache.requeue(id),
+            }
+        }
+
+        errors
+    }
+
+    /// Adds a handle to the cache, if there is one, and writes back any
+    /// handles that need to be flushed as a result.
+    fn cache_handle(&self, id: Snowflake, handle: &StoreReference<StoreHandle<T>>, write: bool) {
+        if let Some(cache) = &self.cache {
+            let flushable = cache.insert(id, handle, write);
+            for error in self.write_back(cache, flushable) {
+                self.errors.report(error);
+            }
+        }
+    }
+
+    /// Gets the [`EventBus`] that this store reports changes to.
+    pub fn events(&self) -> &Arc<EventBus> {
+        &self.events
+    }
+
     /// Retrieves or creates a possibly-uninitialized [`StoreHandle`] from
     /// the underlying hashmap.
     fn get_handle(&self, id: Snowflake) -> HandleData<T> {
+        if let Some(cache) = &self.cache {
+            for error in self.write_back(cache, cache.expire(id)) {
+                self.errors.report(error);
+            }
+        }
+
         let mut entry = self.refs.entry(id).or_insert_with(|| StoredHandleData {
             initializer: Arc::new(Once::new()),
             handle: Weak::new(),
@@ -237,7 +634,7 @@ where
                 handle: strong,
             }
         } else {
-            let handle: StoreHandle<T> = StoreHandle::new(self.backend.clone(), id, None);
+            let handle: StoreHandle<T> = StoreHandle::new(self, id, None);
             let initializer = Arc::new(Once::new());
             let strong = Arc::new(RwLock::new(handle));
 
@@ -269,7 +666,12 @@ where
                     res = Err(e);
                     write_handle.set_object(None);
                 }
-                Ok(data) => {
+                Ok(mut data) => {
+                    // Freshly-loaded entities haven't been changed yet.
+                    if let Some(entity) = &mut data {
+                        entity.changes_mut().clear();
+                    }
+
                     write_handle.set_object(data);
                 }
             };
@@ -281,12 +683,77 @@ where
         }
     }
 
+    // Initializes several handles at once, loading data for all of them
+    // with a single batched call to the backend.
+    //
+    // Each handle is still initialized through its own `initializer`, so
+    // handles that another thread initializes first are left alone (and
+    // the data loaded for them here is discarded).
+    fn initialize_handles(
+        &self,
+        ids: &[Snowflake],
+        cm: Arc<ComponentManager<T>>,
+        handles: &[HandleData<T>],
+    ) -> Result<()> {
+        let mut seen: HashSet<Snowflake> = HashSet::with_capacity(ids.len());
+        let pending: Vec<Snowflake> = ids
+            .iter()
+            .zip(handles)
+            .filter(|(id, handle_data)| {
+                !handle_data.initializer.state().done() && seen.insert(**id)
+            })
+            .map(|(id, _)| *id)
+            .collect();
+
+        let mut loaded: HashMap<Snowflake, Option<T>> = if pending.is_empty() {
+            HashMap::new()
+        } else {
+            let data = self.backend.load_many(&pending, cm.clone())?;
+            pending.into_iter().zip(data).collect()
+        };
+
+        let mut res: Result<()> = Ok(());
+        for (id, handle_data) in ids.iter().zip(handles) {
+            handle_data.initializer.call_once(|| {
+                let mut write_handle = handle_data.handle.write();
+
+                // Fall back to loading by ID if the backend returned fewer
+                // results than it was asked for.
+                let data = match loaded.remove(id) {
+                    Some(data) => Ok(data),
+                    None => self.backend.load(*id, cm.clone()),
+                };
+
+                match data {
+                    Err(e) => {
+                        res = Err(e);
+                        write_handle.set_object(None);
+                    }
+                    Ok(mut data) => {
+                        if let Some(entity) = &mut data {
+                            entity.changes_mut().clear();
+                        }
+
+                        write_handle.set_object(data);
+                    }
+                }
+            });
+
+            if res.is_err() {
+                break;
+            }
+        }
+
+        res
+    }
+
     pub fn load_handle(
         &self,
         id: Snowflake,
         cm: Arc<ComponentManager<T>>,
     ) -> Result<StoreReference<StoreHandle<T>>> {
         let handle_data = self.initialize_handle(id, cm, self.get_handle(id))?;
+        self.cache_handle(id, &handle_data.handle, true);
         Ok(handle_data.handle.clone())
     }
 
@@ -303,6 +770,7 @@ where
         cm: Arc<ComponentManager<T>>,
     ) -> Result<ReadReference<StoreHandle<T>>> {
         let handle_data = self.initialize_handle(id, cm, self.get_handle(id))?;
+        self.cache_handle(id, &handle_data.handle, false);
         Ok(read_store_reference(handle_data.handle))
     }
 
@@ -319,6 +787,7 @@ where
         cm: Arc<ComponentManager<T>>,
     ) -> Result<WriteReference<StoreHandle<T>>> {
         let handle_data = self.initialize_handle(id, cm, self.get_handle(id))?;
+        self.cache_handle(id, &handle_data.handle, true);
         Ok(write_store_reference(handle_data.handle))
     }
 
@@ -339,19 +808,22 @@ where
         handle_data.initializer.call_once(|| {
             let mut handle = handle_data.handle.write();
             handle.set_object(object.take());
-            initializer_result = Some(handle.store());
+            initializer_result = Some(handle.store_clean());
         });
 
-        if let Some(obj) = object {
+        let res = if let Some(obj) = object {
             let mut handle = handle_data.handle.write();
             handle.set_object(Some(obj));
-            handle.store()
+            handle.store_clean()
         } else {
             // This should be safe, because in the initializer,
             // object.take() is immediately followed by setting
             // initializer_result to some result.
             initializer_result.unwrap()
-        }
+        };
+
+        self.cache_handle(id, &handle_data.handle, false);
+        res
     }
 
     /// Moves the given [`Entity`] into a store handle without writing
@@ -361,6 +833,7 @@ where
     pub fn insert(&self, object: T) -> WriteReference<StoreHandle<T>> {
         let id = object.id();
         let handle_data = self.get_handle(id);
+        self.cache_handle(id, &handle_data.handle, true);
 
         // If the initializer gets called, `object` gets set to None,
         // and initializer_result is filled in with the result value.
@@ -413,10 +886,230 @@ where
         }
     }
 
+    /// Gets immutable references to the handles for several
+    /// [`Entities`](Entity) at once.
+    ///
+    /// This works like calling [`load`](Store::load) for each ID, except
+    /// that any [`Entities`](Entity) that need to be loaded from storage are
+    /// fetched with a single call to [`EntityBackend::load_many`]. The
+    /// returned references are in the same order as `ids`, and IDs that
+    /// appear more than once get a reference each.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use akashi::{Card, Entity};
+    /// use akashi::ecs::{ComponentManager, Store};
+    /// use akashi::local_storage::LocalEntityStorage;
+    /// use std::collections::HashSet;
+    /// use std::sync::Arc;
+    ///
+    /// let cm = Arc::new(ComponentManager::new());
+    /// let store = Store::new(Arc::new(LocalEntityStorage::new()));
+    ///
+    /// let cards: Vec<Card> = (1..=3u64)
+    ///     .map(|id| Card::new(id.into(), cm.clone(), HashSet::new()))
+    ///     .collect();
+    /// store.store_many(cards).unwrap();
+    ///
+    /// let ids = [1u64.into(), 2u64.into(), 4u64.into()];
+    /// let handles = store.load_many(&ids, cm).unwrap();
+    /// assert!(handles[0].exists());
+    /// assert!(handles[1].exists());
+    /// assert!(!handles[2].exists());
+    /// ```
+    pub fn load_many(
+        &self,
+        ids: &[Snowflake],
+        cm: Arc<ComponentManager<T>>,
+    ) -> Result<Vec<ReadReference<StoreHandle<T>>>> {
+        let handles: Vec<HandleData<T>> = ids.iter().map(|id| self.get_handle(*id)).collect();
+        self.initialize_handles(ids, cm, &handles)?;
+
+        let mut seen: HashSet<Snowflake> = HashSet::with_capacity(ids.len());
+        Ok(ids
+            .iter()
+            .zip(handles)
+            .map(|(id, handle_data)| {
+                self.cache_handle(*id, &handle_data.handle, false);
+
+                if seen.insert(*id) {
+                    read_store_reference(handle_data.handle)
+                } else {
+                    // This thread already holds a read lock on the handle,
+                    // so a plain `read` could deadlock behind a waiting
+                    // writer.
+                    HandleReadRef::new(handle_data.handle, |s| s.read_recursive())
+                }
+            })
+            .collect())
+    }
+
+    /// Puts several [`Entities`](Entity) into storage at once, overwriting
+    /// any previously stored data with the same IDs.
+    ///
+    /// The [`Entities`](Entity) are written with a single call to
+    /// [`EntityBackend::store_many`]. If more than one [`Entity`] has the
+    /// same ID, only the last one is stored.
+    pub fn store_many(&self, mut objects: Vec<T>) -> Result<()> {
+        let mut seen: HashSet<Snowflake> = HashSet::with_capacity(objects.len());
+        objects.reverse();
+        objects.retain(|object| seen.insert(object.id()));
+
+        // Lock handles in ID order, so that concurrent batches can't
+        // deadlock against each other.
+        objects.sort_by_key(|object| object.id());
+
+        let handles: Vec<HandleData<T>> = objects
+            .into_iter()
+            .map(|object| {
+                let handle_data = self.get_handle(object.id());
+                let mut object = Some(object);
+
+                handle_data.initializer.call_once(|| {
+                    handle_data.handle.write().set_object(object.take());
+                });
+
+                if let Some(obj) = object {
+                    handle_data.handle.write().set_object(Some(obj));
+                }
+
+                handle_data
+            })
+            .collect();
+
+        {
+            let mut locked: Vec<WriteReference<StoreHandle<T>>> = handles
+                .iter()
+                .map(|handle_data| write_store_reference(handle_data.handle.clone()))
+                .collect();
+
+            let entities: Vec<&T> = locked.iter().filter_map(|handle| handle.get()).collect();
+            self.backend.store_many(&entities)?;
+
+            for handle in locked.iter_mut() {
+                let id = handle.id();
+                if let Some(obj) = handle.get_mut() {
+                    let changes = obj.take_changes();
+                    self.events.emit(
+                        Event::entity::<T>(EventKind::EntityStored, id).with_changes(changes),
+                    );
+                }
+            }
+        }
+
+        for handle_data in handles {
+            let id = handle_data.handle.read().id();
+            self.cache_handle(id, &handle_data.handle, false);
+        }
+
+        Ok(())
+    }
+
+    /// Deletes several [`Entities`](Entity) from storage at once.
+    ///
+    /// As with [`delete`](Store::delete), the [`Entities`](Entity) are
+    /// loaded first (with a single call to [`EntityBackend::load_many`]) so
+    /// that their attached [`Components`](crate::Component) are deleted as
+    /// well. The [`Entities`](Entity) themselves are then removed with a
+    /// single call to [`EntityBackend::delete_many`].
+    pub fn delete_many(&self, ids: &[Snowflake], cm: Arc<ComponentManager<T>>) -> Result<()> {
+        let mut ids: Vec<Snowflake> = ids.to_vec();
+        ids.sort();
+        ids.dedup();
+
+        let handles: Vec<HandleData<T>> = ids.iter().map(|id| self.get_handle(*id)).collect();
+        self.initialize_handles(&ids, cm, &handles)?;
+
+        {
+            let mut locked: Vec<WriteReference<StoreHandle<T>>> = handles
+                .iter()
+                .map(|handle_data| write_store_reference(handle_data.handle.clone()))
+                .collect();
+
+            for handle in locked.iter_mut() {
+                if let Some(obj) = handle.get_mut() {
+                    obj.clear_components()?;
+                }
+
+                handle.set_object(None);
+            }
+
+            self.backend.delete_many(&ids)?;
+        }
+
+        for (id, handle_data) in ids.iter().zip(handles) {
+            self.events
+                .emit(Event::entity::<T>(EventKind::EntityDeleted, *id));
+            self.cache_handle(*id, &handle_data.handle, true);
+        }
+
+        Ok(())
+    }
+
+    /// Checks to see which of several [`Entities`](Entity) exist.
+    ///
+    /// [`Entities`](Entity) that haven't already been loaded are checked
+    /// with a single call to [`EntityBackend::exists_many`]. The returned
+    /// `Vec` is in the same order as `ids`.
+    pub fn exists_many(&self, ids: &[Snowflake]) -> Result<Vec<bool>> {
+        let mut found: Vec<Option<bool>> = Vec::with_capacity(ids.len());
+        let mut pending: Vec<Snowflake> = Vec::new();
+
+        for id in ids {
+            let handle_data = self.get_handle(*id);
+
+            if handle_data.initializer.state().done() {
+                found.push(Some(handle_data.handle.read().exists()));
+            } else {
+                found.push(None);
+                pending.push(*id);
+            }
+        }
+
+        let mut checked = if pending.is_empty() {
+            Vec::new()
+        } else {
+            self.backend.exists_many(&pending)?
+        };
+
+        // Check anything the backend left out one at a time, rather than
+        // assuming it doesn't exist.
+        for id in pending.iter().skip(checked.len()) {
+            checked.push(self.backend.exists(*id)?);
+        }
+
+        let mut checked = checked.into_iter();
+        Ok(found
+            .into_iter()
+            .map(|res| res.or_else(|| checked.next()).unwrap())
+            .collect())
+    }
+
     /// Retrieves a list of [`Entity`] IDs from storage.
     pub fn keys(&self, page: u64, limit: u64) -> Result<Vec<Snowflake>> {
         self.backend.keys(page, limit)
     }
+
+    /// Retrieves up to `limit` [`Entity`] IDs that come after `after`,
+    /// in ascending order.
+    ///
+    /// See [`EntityBackend::keys_after`] for details.
+    pub fn keys_after(&self, after: Option<Snowflake>, limit: u64) -> Result<Vec<Snowflake>> {
+        self.backend.keys_after(after, limit)
+    }
+
+    /// Iterates over every stored [`Entity`] ID, in ascending order.
+    ///
+    /// See [`KeyIter`] for details.
+    pub fn iter_keys(&self) -> KeyIter<'_, T> {
+        KeyIter::new(self)
+    }
+
+    /// Gets the storage backend's transaction support, if any.
+    pub fn transactional(&self) -> Option<Arc<dyn TransactionalBackend>> {
+        self.backend.transactional()
+    }
 }
 
 /// Used as a 'stepping stone' when downcasting from an `EntityStoreDowncast`
@@ -433,10 +1126,18 @@ pub struct EntityStoreDowncastHelper<T: Entity + 'static>(pub Box<dyn EntityStor
 ///
 /// You probably shouldn't use this yourself.
 #[doc(hidden)]
-pub trait EntityStoreDowncast: Downcast + Send + Sync + 'static {}
+pub trait EntityStoreDowncast: Downcast + Send + Sync + 'static {
+    /// Flushes the wrapped [`EntityStore`], without needing to know its
+    /// [`Entity`] type.
+    fn flush(&self) -> Result<()>;
+}
 downcast_rs::impl_downcast!(EntityStoreDowncast);
 
-impl<T: Entity + 'static> EntityStoreDowncast for EntityStoreDowncastHelper<T> {}
+impl<T: Entity + 'static> EntityStoreDowncast for EntityStoreDowncastHelper<T> {
+    fn flush(&self) -> Result<()> {
+        self.0.flush()
+    }
+}
 
 /// An interface for loading and storing [`Entities`](Entity).
 ///
@@ -467,7 +1168,20 @@ where
     fn insert(&self, object: T) -> WriteReference<StoreHandle<T>>;
     fn delete(&self, id: Snowflake, cm: Arc<ComponentManager<T>>) -> Result<()>;
     fn exists(&self, id: Snowflake) -> Result<bool>;
+
+    fn load_many(
+        &self,
+        ids: &[Snowflake],
+        cm: Arc<ComponentManager<T>>,
+    ) -> Result<Vec<ReadReference<StoreHandle<T>>>>;
+
+    fn store_many(&self, objects: Vec<T>) -> Result<()>;
+    fn delete_many(&self, ids: &[Snowflake], cm: Arc<ComponentManager<T>>) -> Result<()>;
+    fn exists_many(&self, ids: &[Snowflake]) -> Result<Vec<bool>>;
     fn keys(&self, page: u64, limit: u64) -> Result<Vec<Snowflake>>;
+    fn keys_after(&self, after: Option<Snowflake>, limit: u64) -> Result<Vec<Snowflake>>;
+    fn transactional(&self) -> Option<Arc<dyn TransactionalBackend>>;
+    fn flush(&self) -> Result<()>;
 }
 
 downcast_rs::impl_downcast!(sync EntityStore<T> where T: Entity + 'static);
@@ -509,9 +1223,199 @@ where
         self.exists(id)
     }
 
+    fn load_many(
+        &self,
+        ids: &[Snowflake],
+        cm: Arc<ComponentManager<T>>,
+    ) -> Result<Vec<ReadReference<StoreHandle<T>>>> {
+        self.load_many(ids, cm)
+    }
+
+    fn store_many(&self, objects: Vec<T>) -> Result<()> {
+        self.store_many(objects)
+    }
+
+    fn delete_many(&self, ids: &[Snowflake], cm: Arc<ComponentManager<T>>) -> Result<()> {
+        self.delete_many(ids, cm)
+    }
+
+    fn exists_many(&self, ids: &[Snowflake]) -> Result<Vec<bool>> {
+        self.exists_many(ids)
+    }
+
     fn keys(&self, page: u64, limit: u64) -> Result<Vec<Snowflake>> {
         self.keys(page, limit)
     }
+
+    fn keys_after(&self, after: Option<Snowflake>, limit: u64) -> Result<Vec<Snowflake>> {
+        self.keys_after(after, limit)
+    }
+
+    fn transactional(&self) -> Option<Arc<dyn TransactionalBackend>> {
+        self.transactional()
+    }
+
+    fn flush(&self) -> Result<()> {
+        self.flush()
+    }
+}
+
+/// Iterates over stored [`Entity`] IDs in ascending order, fetching them
+/// from storage in batches.
+///
+/// Each batch picks up after the last ID returned by the one before it
+/// (using [`EntityBackend::keys_after`]), so IDs aren't skipped or
+/// repeated if [`Entities`](Entity) are added or removed while iterating.
+/// Since [`Snowflakes`](Snowflake) sort by creation time, this also
+/// iterates over [`Entities`](Entity) from oldest to newest.
+///
+/// Errors from storage are returned as items; iteration stops after the
+/// first one.
+///
+/// # Example
+///
+/// ```
+/// use akashi::{Card, EntityManager};
+/// use akashi::local_storage::LocalEntityStorage;
+///
+/// let mut manager = EntityManager::new();
+/// manager.register_entity(LocalEntityStorage::<Card>::new()).unwrap();
+///
+/// for id in 1..=10u64 {
+///     manager.store(manager.create::<Card>(id.into()).unwrap()).unwrap();
+/// }
+///
+/// let mut keys = manager.iter_keys::<Card>().unwrap().with_batch_size(4);
+/// let first: Vec<_> = keys.by_ref().take(3).map(|id| id.unwrap()).collect();
+/// assert_eq!(first, vec![1u64.into(), 2u64.into(), 3u64.into()]);
+///
+/// // The cursor can be saved and used to resume iterating later.
+/// let cursor = keys.cursor().unwrap();
+/// let rest = manager
+///     .iter_keys::<Card>()
+///     .unwrap()
+///     .after(cursor)
+///     .collect::<Result<Vec<_>, _>>()
+///     .unwrap();
+/// assert_eq!(rest.len(), 7);
+/// ```
+pub struct KeyIter<'a, T>
+where
+    T: Entity + 'static,
+{
+    store: &'a dyn EntityStore<T>,
+    cursor: Option<Snowflake>,
+    end: Option<Snowflake>,
+    batch_size: u64,
+    buffer: VecDeque<Snowflake>,
+    done: bool,
+}
+
+impl<'a, T> KeyIter<'a, T>
+where
+    T: Entity + 'static,
+{
+    /// Creates a new iterator over all of the IDs in a store.
+    pub fn new(store: &'a dyn EntityStore<T>) -> KeyIter<'a, T> {
+        KeyIter {
+            store,
+            cursor: None,
+            end: None,
+            batch_size: 100,
+            buffer: VecDeque::new(),
+            done: false,
+        }
+    }
+
+    /// Sets how many IDs to fetch from storage at a time.
+    ///
+    /// Defaults to 100.
+    pub fn with_batch_size(mut self, batch_size: u64) -> KeyIter<'a, T> {
+        self.batch_size = batch_size.max(1);
+        self
+    }
+
+    /// Only iterates over IDs that come after `id`.
+    pub fn after(mut self, id: Snowflake) -> KeyIter<'a, T> {
+        self.cursor = Some(id);
+        self.buffer.clear();
+        self
+    }
+
+    /// Only iterates over IDs that come before `id`.
+    pub fn before(mut self, id: Snowflake) -> KeyIter<'a, T> {
+        self.end = Some(id);
+        self
+    }
+
+    /// Only iterates over IDs for [`Entities`](Entity) created at or
+    /// after `start`, and before `end`, according to
+    /// [`Snowflake::timestamp`].
+    pub fn created_between(mut self, start: SystemTime, end: SystemTime) -> KeyIter<'a, T> {
+        let first = u64::from(Snowflake::first_at(start));
+        self.cursor = first.checked_sub(1).map(Snowflake::from);
+        self.buffer.clear();
+        self.before(Snowflake::first_at(end))
+    }
+
+    /// Gets the last ID returned by this iterator, if any.
+    ///
+    /// This can be passed to [`after`](KeyIter::after) on a new iterator
+    /// to pick up where this one left off.
+    pub fn cursor(&self) -> Option<Snowflake> {
+        self.cursor
+    }
+
+    fn fetch(&mut self) -> Result<()> {
+        let ids = self.store.keys_after(self.cursor, self.batch_size)?;
+        if (ids.len() as u64) < self.batch_size {
+            self.done = true;
+        }
+
+        self.buffer.extend(ids);
+        Ok(())
+    }
+}
+
+impl<'a, T> Iterator for KeyIter<'a, T>
+where
+    T: Entity + 'static,
+{
+    type Item = Result<Snowflake>;
+
+    fn next(&mut self) -> Option<Result<Snowflake>> {
+        if self.buffer.is_empty() && !self.done {
+            if let Err(e) = self.fetch() {
+                self.done = true;
+                return Some(Err(e));
+            }
+        }
+
+        let id = self.buffer.pop_front()?;
+        if let Some(end) = self.end {
+            if id >= end {
+                self.buffer.clear();
+                self.done = true;
+                return None;
+            }
+        }
+
+        self.cursor = Some(id);
+        Some(Ok(id))
+    }
+}
+
+impl<'a, T> fmt::Debug for KeyIter<'a, T>
+where
+    T: Entity + 'static,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("KeyIter")
+            .field("cursor", &self.cursor)
+            .field("end", &self.end)
+            .field("batch_size", &self.batch_size)
+            .finish()
+    }
 }
 
 impl<T, U> fmt::Debug for Store<T, U>
@@ -530,6 +1434,10 @@ where
     }
 }
 
+// How many IDs to request at a time when EntityBackend::keys_after falls
+// back to reading every ID.
+const KEYS_PAGE_SIZE: u64 = 1000;
+
 /// This trait is used to mark backing storage objects for [`Entities`](Entity).
 ///
 /// Structs that implement this trait can be used as backing storage
@@ -546,11 +1454,138 @@ pub trait EntityBackend<T: Entity + 'static> {
     /// Saves data for an [`Entity`] to storage.
     fn store(&self, id: Snowflake, object: &T) -> Result<()>;
 
+    /// Saves the parts of an [`Entity`] that have changed since it was
+    /// loaded or last stored.
+    ///
+    /// This is used when writing back dirty [`Entities`](Entity) from
+    /// their [`StoreHandles`](StoreHandle), such as when the handles are
+    /// dropped or flushed from a cache. Backends that can skip work based
+    /// on what changed should override this; the default implementation
+    /// stores the whole [`Entity`] with [`store`](EntityBackend::store).
+    fn store_changes(&self, id: Snowflake, object: &T, _changes: &ChangeSet) -> Result<()> {
+        self.store(id, object)
+    }
+
     /// Deletes data for an [`Entity`] from storage.
     fn delete(&self, id: Snowflake) -> Result<()>;
 
     /// Retrieve a list of [`Entity`] IDs from storage.
     fn keys(&self, page: u64, limit: u64) -> Result<Vec<Snowflake>>;
+
+    /// Retrieves up to `limit` [`Entity`] IDs that come after `after`, in
+    /// ascending order, or the first `limit` IDs if `after` is `None`.
+    ///
+    /// Unlike [`keys`](EntityBackend::keys), which skips over a number of
+    /// IDs, this picks up from the last ID seen, so results don't shift
+    /// around when [`Entities`](Entity) are added or removed between
+    /// calls.
+    ///
+    /// The default implementation reads every ID through
+    /// [`keys`](EntityBackend::keys) on each call, and should be
+    /// overridden by any backend that can look up IDs in order.
+    fn keys_after(&self, after: Option<Snowflake>, limit: u64) -> Result<Vec<Snowflake>> {
+        let mut ids: Vec<Snowflake> = Vec::new();
+        let mut page = 0;
+
+        loop {
+            let batch = self.keys(page, KEYS_PAGE_SIZE)?;
+            let last_page = (batch.len() as u64) < KEYS_PAGE_SIZE;
+
+            ids.extend(batch.into_iter().filter(|id| match after {
+                Some(after) => *id > after,
+                None => true,
+            }));
+
+            if last_page {
+                break;
+            }
+
+            page += 1;
+        }
+
+        ids.sort_unstable();
+        ids.truncate(limit as usize);
+        Ok(ids)
+    }
+
+    /// Loads data for several [`Entities`](Entity) from storage at once.
+    ///
+    /// The returned `Vec` has one entry for each ID in `ids`, in the same
+    /// order. The default implementation calls
+    /// [`load`](EntityBackend::load) once per ID; backends that can fetch
+    /// several records in one round trip should override this.
+    fn load_many(&self, ids: &[Snowflake], cm: Arc<ComponentManager<T>>) -> Result<Vec<Option<T>>> {
+        ids.iter().map(|id| self.load(*id, cm.clone())).collect()
+    }
+
+    /// Checks to see which of several [`Entities`](Entity) exist in
+    /// storage.
+    ///
+    /// The returned `Vec` has one entry for each ID in `ids`, in the same
+    /// order. The default implementation calls
+    /// [`exists`](EntityBackend::exists) once per ID.
+    fn exists_many(&self, ids: &[Snowflake]) -> Result<Vec<bool>> {
+        ids.iter().map(|id| self.exists(*id)).collect()
+    }
+
+    /// Saves data for several [`Entities`](Entity) to storage at once,
+    /// using the ID of each [`Entity`] as its key.
+    ///
+    /// The default implementation calls [`store`](EntityBackend::store)
+    /// once per [`Entity`], stopping at the first error.
+    fn store_many(&self, objects: &[&T]) -> Result<()> {
+        for object in objects {
+            self.store(object.id(), object)?;
+        }
+
+        Ok(())
+    }
+
+    /// Deletes data for several [`Entities`](Entity) from storage at once.
+    ///
+    /// The default implementation calls [`delete`](EntityBackend::delete)
+    /// once per ID, stopping at the first error.
+    fn delete_many(&self, ids: &[Snowflake]) -> Result<()> {
+        for id in ids {
+            self.delete(*id)?;
+        }
+
+        Ok(())
+    }
+
+    /// Gets an object that can be used to make changes to this backend
+    /// within a transaction, if supported.
+    ///
+    /// Backends that don't support transactions can leave this as the
+    /// default implementation, which returns `None`.
+    /// [`Transactions`](super::transaction::Transaction) will then fall
+    /// back to undoing changes made to this backend if they fail.
+    fn transactional(&self) -> Option<Arc<dyn TransactionalBackend>> {
+        None
+    }
+}
+
+/// Saves an [`Entity`] for backends that only record which
+/// [`Components`](super::Component) are attached to it, skipping the
+/// write if those haven't changed and the [`Entity`] is already stored.
+///
+/// Such backends can use this to implement
+/// [`EntityBackend::store_changes`].
+pub(crate) fn store_attachment_changes<T, B>(
+    backend: &B,
+    id: Snowflake,
+    object: &T,
+    changes: &ChangeSet,
+) -> Result<()>
+where
+    T: Entity + 'static,
+    B: EntityBackend<T> + ?Sized,
+{
+    if !changes.attachments_changed() && backend.exists(id)? {
+        return Ok(());
+    }
+
+    backend.store(id, object)
 }
 
 #[cfg(test)]
@@ -559,11 +1594,13 @@ mod tests {
 
     use std::any::TypeId;
     use std::collections::{HashMap, HashSet};
-    use std::sync::{mpsc, Arc, Barrier, RwLock};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::{mpsc, Arc, Barrier, Mutex, RwLock};
     use std::thread;
 
     use dashmap::DashMap;
 
+    use crate::clock::ManualClock;
     use crate::ecs::Component;
     use crate::snowflake::SnowflakeGenerator;
 
@@ -574,7 +1611,7 @@ mod tests {
         cm: Arc<ComponentManager<MockStoredData>>,
         components_attached: HashSet<TypeId>,
         component_preloads: DashMap<TypeId, Box<dyn Component<Self> + Send + Sync + 'static>>,
-        dirty: bool,
+        changes: ChangeSet,
     }
 
     impl MockStoredData {
@@ -591,7 +1628,7 @@ mod tests {
                 cm,
                 components_attached: HashSet::new(),
                 component_preloads: DashMap::new(),
-                dirty: false,
+                changes: ChangeSet::new(),
             }
         }
 
@@ -631,12 +1668,12 @@ mod tests {
             &self.component_preloads
         }
 
-        fn dirty(&self) -> bool {
-            self.dirty
+        fn changes(&self) -> &ChangeSet {
+            &self.changes
         }
 
-        fn dirty_mut(&mut self) -> &mut bool {
-            &mut self.dirty
+        fn changes_mut(&mut self) -> &mut ChangeSet {
+            &mut self.changes
         }
     }
 
@@ -644,7 +1681,7 @@ mod tests {
         fn clone(&self) -> Self {
             Self {
                 id: self.id,
-                dirty: self.dirty,
+                changes: self.changes.clone(),
                 cm: self.cm.clone(),
                 components_attached: self.components_attached.clone(),
                 component_preloads: DashMap::new(),
@@ -657,6 +1694,10 @@ mod tests {
     struct MockEntityBackend {
         data: RwLock<HashMap<Snowflake, MockStoredData>>,
         remove_on_load: bool,
+        fail_stores: bool,
+        short_exists_many: bool,
+        store_attempts: AtomicUsize,
+        batch_loads: AtomicUsize,
     }
 
     impl MockEntityBackend {
@@ -664,12 +1705,24 @@ mod tests {
             MockEntityBackend {
                 data: RwLock::new(HashMap::new()),
                 remove_on_load: false,
+                fail_stores: false,
+                short_exists_many: false,
+                store_attempts: AtomicUsize::new(0),
+                batch_loads: AtomicUsize::new(0),
             }
         }
 
         fn set_remove_on_load(&mut self, flag: bool) {
             self.remove_on_load = flag;
         }
+
+        fn set_fail_stores(&mut self, flag: bool) {
+            self.fail_stores = flag;
+        }
+
+        fn set_short_exists_many(&mut self, flag: bool) {
+            self.short_exists_many = flag;
+        }
     }
     impl EntityBackend<MockStoredData> for MockEntityBackend {
         fn exists(&self, id: Snowflake) -> Result<bool> {
@@ -694,6 +1747,11 @@ mod tests {
         }
 
         fn store(&self, id: Snowflake, data: &MockStoredData) -> Result<()> {
+            self.store_attempts.fetch_add(1, Ordering::SeqCst);
+            if self.fail_stores {
+                return Err(failure::err_msg("store failed"));
+            }
+
             let mut map = self.data.write().unwrap();
             map.insert(id, data.clone());
 
@@ -707,6 +1765,39 @@ mod tests {
             Ok(())
         }
 
+        fn load_many(
+            &self,
+            ids: &[Snowflake],
+            _cm: Arc<ComponentManager<MockStoredData>>,
+        ) -> Result<Vec<Option<MockStoredData>>> {
+            self.batch_loads.fetch_add(1, Ordering::SeqCst);
+
+            let mut map = self.data.write().unwrap();
+            Ok(ids
+                .iter()
+                .map(|id| {
+                    if self.remove_on_load {
+                        map.remove(id)
+                    } else {
+                        map.get(id).cloned()
+                    }
+                })
+                .collect())
+        }
+
+        fn exists_many(&self, ids: &[Snowflake]) -> Result<Vec<bool>> {
+            // Drop the last result, to check that callers don't rely on
+            // getting one for every ID.
+            let map = self.data.read().unwrap();
+            let n = if self.short_exists_many {
+                ids.len().saturating_sub(1)
+            } else {
+                ids.len()
+            };
+
+            Ok(ids[..n].iter().map(|id| map.contains_key(id)).collect())
+        }
+
         fn keys(&self, page: u64, limit: u64) -> Result<Vec<Snowflake>> {
             let ids: Vec<Snowflake>;
             let start_index = page * limit;
@@ -727,7 +1818,7 @@ mod tests {
 
     #[test]
     fn test_exists() {
-        let mut snowflake_gen = SnowflakeGenerator::new(0, 0);
+        let snowflake_gen = SnowflakeGenerator::new(0, 0);
         let backend = Arc::new(MockEntityBackend::new());
         let data = MockStoredData::new(
             snowflake_gen.generate(),
@@ -746,7 +1837,7 @@ mod tests {
 
     #[test]
     fn test_load_nonexistent() {
-        let mut snowflake_gen = SnowflakeGenerator::new(0, 0);
+        let snowflake_gen = SnowflakeGenerator::new(0, 0);
         let backend = Arc::new(MockEntityBackend::new());
         let store = MockStore::new(backend);
         let handle = store
@@ -758,7 +1849,7 @@ mod tests {
 
     #[test]
     fn test_load() {
-        let mut snowflake_gen = SnowflakeGenerator::new(0, 0);
+        let snowflake_gen = SnowflakeGenerator::new(0, 0);
         let backend = Arc::new(MockEntityBackend::new());
         let data = MockStoredData::new(
             snowflake_gen.generate(),
@@ -785,7 +1876,7 @@ mod tests {
     #[test]
     fn test_concurrent_load() {
         // Create some test data to load.
-        let mut snowflake_gen = SnowflakeGenerator::new(0, 0);
+        let snowflake_gen = SnowflakeGenerator::new(0, 0);
         let backend = Arc::new(MockEntityBackend::new());
         let id = snowflake_gen.generate();
         let data = MockStoredData::new(id, "foo".to_owned(), 1, Arc::new(ComponentManager::new()));
@@ -826,7 +1917,7 @@ mod tests {
     #[test]
     fn test_concurrent_access() {
         // Create some test data to load.
-        let mut snowflake_gen = SnowflakeGenerator::new(0, 0);
+        let snowflake_gen = SnowflakeGenerator::new(0, 0);
         let mut backend = MockEntityBackend::new();
         let id = snowflake_gen.generate();
         let data = MockStoredData::new(id, "foo".to_owned(), 1, Arc::new(ComponentManager::new()));
@@ -904,7 +1995,7 @@ mod tests {
     #[test]
     fn test_multiple_single_thread_access() {
         // Create some test data to load.
-        let mut snowflake_gen = SnowflakeGenerator::new(0, 0);
+        let snowflake_gen = SnowflakeGenerator::new(0, 0);
         let backend = MockEntityBackend::new();
         let id = snowflake_gen.generate();
         let data = MockStoredData::new(id, "foo".to_owned(), 1, Arc::new(ComponentManager::new()));
@@ -933,9 +2024,235 @@ mod tests {
         assert_eq!(data_1.field_b, data.field_b);
     }
 
+    #[test]
+    fn test_load_many() {
+        let snowflake_gen = SnowflakeGenerator::new(0, 0);
+        let backend = Arc::new(MockEntityBackend::new());
+        let cm = Arc::new(ComponentManager::new());
+        let ids: Vec<Snowflake> = (0..3).map(|_| snowflake_gen.generate()).collect();
+
+        for (i, id) in ids.iter().enumerate() {
+            let data = MockStoredData::new(*id, format!("data {}", i), i as u64, cm.clone());
+            backend.store(*id, &data).unwrap();
+        }
+
+        let store = MockStore::new(backend.clone());
+        let missing = snowflake_gen.generate();
+
+        // Already-loaded entities shouldn't be loaded again.
+        let loaded = store.load(ids[0], cm.clone()).unwrap();
+
+        let request = [ids[0], ids[1], missing, ids[2], ids[1]];
+        let handles = store.load_many(&request, cm).unwrap();
+        assert_eq!(backend.batch_loads.load(Ordering::SeqCst), 1);
+
+        assert_eq!(handles.len(), request.len());
+        for (id, handle) in request.iter().zip(handles.iter()) {
+            assert_eq!(handle.id(), *id);
+        }
+
+        assert_eq!(
+            handles[0].get().unwrap().field_a,
+            loaded.get().unwrap().field_a
+        );
+        assert_eq!(handles[1].get().unwrap().field_b, 1);
+        assert!(!handles[2].exists());
+        assert_eq!(handles[3].get().unwrap().field_b, 2);
+        assert_eq!(handles[4].get().unwrap().field_b, 1);
+    }
+
+    #[test]
+    fn test_load_many_duplicates_with_writer() {
+        let snowflake_gen = SnowflakeGenerator::new(0, 0);
+        let backend = Arc::new(MockEntityBackend::new());
+        let cm = Arc::new(ComponentManager::new());
+        let id = snowflake_gen.generate();
+        backend
+            .store(
+                id,
+                &MockStoredData::new(id, "data".to_owned(), 0, cm.clone()),
+            )
+            .unwrap();
+
+        let store = Arc::new(MockStore::new(backend));
+        let done = Arc::new(AtomicUsize::new(0));
+
+        // A writer that keeps trying to lock the handle shouldn't be able
+        // to get in between the read locks for a repeated ID.
+        let writer = {
+            let store = store.clone();
+            let cm = cm.clone();
+            let done = done.clone();
+            thread::spawn(move || {
+                while done.load(Ordering::SeqCst) == 0 {
+                    let mut handle = store.load_mut(id, cm.clone()).unwrap();
+                    handle.get_mut().unwrap().field_b += 1;
+                }
+            })
+        };
+
+        for _ in 0..10_000 {
+            let handles = store.load_many(&[id, id, id], cm.clone()).unwrap();
+            assert_eq!(handles.len(), 3);
+        }
+
+        done.store(1, Ordering::SeqCst);
+        writer.join().unwrap();
+    }
+
+    #[test]
+    fn test_concurrent_load_many() {
+        let snowflake_gen = SnowflakeGenerator::new(0, 0);
+        let mut backend = MockEntityBackend::new();
+        let ids: Vec<Snowflake> = (0..20).map(|_| snowflake_gen.generate()).collect();
+
+        // As in test_concurrent_access, loading an entity more than once
+        // would make later loads come up empty.
+        backend.set_remove_on_load(true);
+        for id in ids.iter() {
+            let data =
+                MockStoredData::new(*id, "foo".to_owned(), 1, Arc::new(ComponentManager::new()));
+            backend.store(*id, &data).unwrap();
+        }
+
+        let store = Arc::new(MockStore::new(Arc::new(backend)));
+        let barrier = Arc::new(Barrier::new(10));
+        let mut threads = Vec::with_capacity(10);
+
+        for i in 0..10 {
+            let b_clone = barrier.clone();
+            let s_clone = store.clone();
+            let ids = ids.clone();
+
+            threads.push(thread::spawn(move || {
+                let cm = Arc::new(ComponentManager::new());
+                b_clone.wait();
+
+                // Mix batched and individual loads of the same entities.
+                if i % 2 == 0 {
+                    let handles = s_clone.load_many(&ids, cm).unwrap();
+                    assert!(handles.iter().all(|handle| handle.exists()));
+                    b_clone.wait();
+                } else {
+                    let handles: Vec<_> = ids
+                        .iter()
+                        .rev()
+                        .map(|id| s_clone.load(*id, cm.clone()).unwrap())
+                        .collect();
+                    assert!(handles.iter().all(|handle| handle.exists()));
+                    b_clone.wait();
+                }
+            }));
+        }
+
+        for thread in threads {
+            thread.join().unwrap();
+        }
+    }
+
+    #[test]
+    fn test_batch_store_delete() {
+        let snowflake_gen = SnowflakeGenerator::new(0, 0);
+        let backend = Arc::new(MockEntityBackend::new());
+        let store = MockStore::new(backend.clone());
+        let cm = Arc::new(ComponentManager::new());
+        let ids: Vec<Snowflake> = (0..4).map(|_| snowflake_gen.generate()).collect();
+
+        let mut objects: Vec<MockStoredData> = ids
+            .iter()
+            .map(|id| MockStoredData::new(*id, "foo".to_owned(), 1, cm.clone()))
+            .collect();
+
+        // Later duplicates replace earlier ones.
+        objects.push(MockStoredData::new(ids[0], "bar".to_owned(), 2, cm.clone()));
+        store.store_many(objects).unwrap();
+
+        assert_eq!(backend.store_attempts.load(Ordering::SeqCst), 4);
+        assert_eq!(
+            backend.data.read().unwrap().get(&ids[0]).unwrap().field_a,
+            "bar"
+        );
+
+        let missing = snowflake_gen.generate();
+        assert_eq!(
+            store.exists_many(&[ids[0], missing, ids[3]]).unwrap(),
+            vec![true, false, true]
+        );
+
+        store.delete_many(&ids[..2], cm.clone()).unwrap();
+        assert_eq!(
+            store.exists_many(&ids).unwrap(),
+            vec![false, false, true, true]
+        );
+        assert!(!backend.exists(ids[0]).unwrap());
+        assert!(backend.exists(ids[2]).unwrap());
+    }
+
+    #[test]
+    fn test_exists_many_short_results() {
+        let snowflake_gen = SnowflakeGenerator::new(0, 0);
+        let mut backend = MockEntityBackend::new();
+        backend.set_short_exists_many(true);
+
+        let cm = Arc::new(ComponentManager::new());
+        let ids: Vec<Snowflake> = (0..3).map(|_| snowflake_gen.generate()).collect();
+        for id in &ids[1..] {
+            let data = MockStoredData::new(*id, "foo".to_owned(), 1, cm.clone());
+            backend.store(*id, &data).unwrap();
+        }
+
+        let store = MockStore::new(Arc::new(backend));
+        assert_eq!(store.exists_many(&ids).unwrap(), vec![false, true, true]);
+    }
+
+    #[test]
+    fn test_iter_keys() {
+        let snowflake_gen = SnowflakeGenerator::new(0, 0);
+        let backend = Arc::new(MockEntityBackend::new());
+        let cm = Arc::new(ComponentManager::new());
+        let mut ids: Vec<Snowflake> = (0..5).map(|_| snowflake_gen.generate()).collect();
+
+        for id in ids.iter() {
+            let data = MockStoredData::new(*id, "foo".to_owned(), 1, cm.clone());
+            backend.store(*id, &data).unwrap();
+        }
+
+        // The mock backend lists keys in no particular order, so this
+        // exercises the default keys_after implementation.
+        let store = MockStore::new(backend.clone());
+        assert_eq!(store.keys_after(None, 2).unwrap(), ids[..2].to_vec());
+        assert_eq!(
+            store.keys_after(Some(ids[1]), 10).unwrap(),
+            ids[2..].to_vec()
+        );
+
+        let mut keys = store.iter_keys().with_batch_size(2);
+        assert_eq!(keys.next().unwrap().unwrap(), ids[0]);
+        assert_eq!(keys.next().unwrap().unwrap(), ids[1]);
+
+        // Deleting already-seen entities and adding new ones mid-iteration
+        // doesn't cause anything to be skipped or repeated.
+        backend.delete(ids[0]).unwrap();
+        let new_id = snowflake_gen.generate();
+        let data = MockStoredData::new(new_id, "foo".to_owned(), 1, cm.clone());
+        backend.store(new_id, &data).unwrap();
+        ids.push(new_id);
+
+        let rest = keys.collect::<Result<Vec<_>>>().unwrap();
+        assert_eq!(rest, ids[2..].to_vec());
+
+        let bounded = store
+            .iter_keys()
+            .after(ids[1])
+            .before(ids[4])
+            .collect::<Result<Vec<_>>>()
+            .unwrap();
+        assert_eq!(bounded, ids[2..4].to_vec());
+    }
+
     #[test]
     fn test_store() {
-        let mut snowflake_gen = SnowflakeGenerator::new(0, 0);
+        let snowflake_gen = SnowflakeGenerator::new(0, 0);
         let id = snowflake_gen.generate();
         let cm = Arc::new(ComponentManager::new());
         let data = MockStoredData::new(id, "foo".to_owned(), 1, cm.clone());
@@ -981,7 +2298,7 @@ mod tests {
 
         let cm = Arc::new(cm);
 
-        let mut snowflake_gen = SnowflakeGenerator::new(0, 0);
+        let snowflake_gen = SnowflakeGenerator::new(0, 0);
         let id = snowflake_gen.generate();
 
         {
@@ -999,4 +2316,161 @@ mod tests {
 
         assert_eq!(component.0, 50);
     }
+
+    // Inserts a dirty entity into a store, then drops its handle.
+    fn drop_dirty(store: &MockStore) -> Snowflake {
+        let snowflake_gen = SnowflakeGenerator::new(0, 0);
+        let id = snowflake_gen.generate();
+        let mut data =
+            MockStoredData::new(id, "foo".to_owned(), 1, Arc::new(ComponentManager::new()));
+        data.changes.mark_attachments_changed();
+
+        store.insert(data);
+        id
+    }
+
+    #[test]
+    fn test_drop_policy_report() {
+        let mut backend = MockEntityBackend::new();
+        backend.set_fail_stores(true);
+        let store = MockStore::new(Arc::new(backend));
+
+        let id = drop_dirty(&store);
+        let errors = store.take_flush_errors();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].id(), id);
+    }
+
+    #[test]
+    fn test_drop_policy_retry() {
+        let mut backend = MockEntityBackend::new();
+        backend.set_fail_stores(true);
+        let backend = Arc::new(backend);
+        let start = SystemTime::UNIX_EPOCH + Duration::from_secs(1_600_000_000);
+        let clock = Arc::new(ManualClock::new(start));
+        let store = MockStore::new(backend.clone())
+            .with_clock(clock.clone())
+            .with_drop_policy(DropPolicy::Retry {
+                attempts: 2,
+                backoff: Duration::from_secs(10),
+            });
+
+        drop_dirty(&store);
+        assert_eq!(backend.store_attempts.load(Ordering::SeqCst), 3);
+        assert_eq!(store.take_flush_errors().len(), 1);
+        assert_eq!(clock.now(), start + Duration::from_secs(30));
+    }
+
+    // Records how long it's asked to sleep for, without sleeping.
+    #[derive(Debug, Default)]
+    struct SleepLog(Mutex<Vec<Duration>>);
+
+    impl Clock for SleepLog {
+        fn now(&self) -> SystemTime {
+            SystemTime::UNIX_EPOCH
+        }
+
+        fn sleep(&self, duration: Duration) {
+            self.0.lock().unwrap().push(duration);
+        }
+    }
+
+    #[test]
+    fn test_drop_policy_retry_many() {
+        let mut backend = MockEntityBackend::new();
+        backend.set_fail_stores(true);
+        let backend = Arc::new(backend);
+        let clock = Arc::new(SleepLog::default());
+        let store = MockStore::new(backend.clone())
+            .with_clock(clock.clone())
+            .with_drop_policy(DropPolicy::Retry {
+                attempts: 66,
+                backoff: Duration::from_secs(1),
+            });
+
+        drop_dirty(&store);
+        assert_eq!(backend.store_attempts.load(Ordering::SeqCst), 67);
+        assert_eq!(store.take_flush_errors().len(), 1);
+
+        let sleeps = clock.0.lock().unwrap();
+        assert_eq!(sleeps.len(), 66);
+        assert_eq!(sleeps[63], Duration::from_secs(1 << 63));
+        assert_eq!(sleeps[65], Duration::MAX);
+    }
+
+    #[test]
+    fn test_retry_delays() {
+        let delays: Vec<Duration> = retry_delays(3, Duration::from_millis(5)).collect();
+        assert_eq!(
+            delays,
+            vec![
+                Duration::from_millis(5),
+                Duration::from_millis(10),
+                Duration::from_millis(20)
+            ]
+        );
+
+        // Doubling a 1s backoff 64 times overflows a Duration.
+        let delays: Vec<Duration> = retry_delays(64, Duration::from_secs(1)).collect();
+        assert_eq!(delays.len(), 64);
+        assert_eq!(delays[63], Duration::from_secs(1 << 63));
+
+        let delays: Vec<Duration> = retry_delays(66, Duration::from_secs(1)).collect();
+        assert_eq!(delays[64], Duration::MAX);
+        assert_eq!(delays[65], Duration::MAX);
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "Could not write back entity")]
+    fn test_drop_policy_panic() {
+        let mut backend = MockEntityBackend::new();
+        backend.set_fail_stores(true);
+        let store = MockStore::new(Arc::new(backend)).with_drop_policy(DropPolicy::Panic);
+
+        drop_dirty(&store);
+    }
+
+    #[test]
+    fn test_close() {
+        let mut backend = MockEntityBackend::new();
+        backend.set_fail_stores(true);
+        let backend = Arc::new(backend);
+        let store = MockStore::new(backend.clone());
+
+        let snowflake_gen = SnowflakeGenerator::new(0, 0);
+        let id = snowflake_gen.generate();
+        let mut data =
+            MockStoredData::new(id, "foo".to_owned(), 1, Arc::new(ComponentManager::new()));
+        data.changes.mark_attachments_changed();
+
+        let handle = store.insert(data);
+        assert!(StoreHandle::close(handle).is_err());
+
+        // The entity was left dirty, so dropping the handle tried to write
+        // it back again and reported the second failure.
+        assert_eq!(backend.store_attempts.load(Ordering::SeqCst), 2);
+        assert_eq!(store.take_flush_errors().len(), 1);
+    }
+
+    #[test]
+    fn test_close_leaves_dirty() {
+        let mut backend = MockEntityBackend::new();
+        backend.set_fail_stores(true);
+        let store = MockStore::new(Arc::new(backend)).with_cache(CachePolicy::new(10));
+
+        let snowflake_gen = SnowflakeGenerator::new(0, 0);
+        let id = snowflake_gen.generate();
+        let cm = Arc::new(ComponentManager::new());
+        let mut data = MockStoredData::new(id, "foo".to_owned(), 1, cm.clone());
+        data.changes.mark_attachments_changed();
+
+        let handle = store.insert(data);
+        assert!(StoreHandle::close(handle).is_err());
+
+        // The cache is still holding the handle, and the entity should
+        // still be waiting to be written.
+        let handle = store.load(id, cm).unwrap();
+        assert!(handle.get().unwrap().dirty());
+    }
 }
@@ -0,0 +1,663 @@
+//! Notifications for changes to [`Entities`](Entity) and
+//! [`Components`](Component).
+
+use super::component::Component;
+use super::entity::{ChangeSet, Entity};
+use crate::snowflake::Snowflake;
+
+use std::any;
+use std::any::TypeId;
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::Arc;
+use std::thread::{self, ThreadId};
+
+use parking_lot::{Mutex, RwLock};
+
+/// The kinds of changes that an [`EventBus`] reports.
+#[derive(Debug, Copy, Clone, Hash, PartialEq, Eq)]
+pub enum EventKind {
+    /// A new [`Entity`] object was created with
+    /// [`EntityManager::create`](super::EntityManager::create).
+    EntityCreated,
+
+    /// An [`Entity`] was written to storage.
+    EntityStored,
+
+    /// An [`Entity`] was deleted from storage.
+    EntityDeleted,
+
+    /// [`Component`] data was attached to an [`Entity`], or updated.
+    ComponentSet,
+
+    /// [`Component`] data was deleted from an [`Entity`].
+    ComponentDeleted,
+}
+
+/// Describes a single change to an [`Entity`] or one of its
+/// [`Components`](Component).
+///
+/// Events are only emitted for changes that were successfully made in
+/// storage. Events for changes made by a
+/// [`Transaction`](super::Transaction) are held back until it commits,
+/// and dropped if it gets rolled back.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Event {
+    kind: EventKind,
+    entity_type: TypeId,
+    entity_type_name: &'static str,
+    entity_id: Snowflake,
+    component_type: Option<TypeId>,
+    component_name: Option<String>,
+    changes: Option<ChangeSet>,
+}
+
+impl Event {
+    /// Creates a new event describing a change to an [`Entity`].
+    pub fn entity<T: Entity + 'static>(kind: EventKind, id: Snowflake) -> Event {
+        Event {
+            kind,
+            entity_type: TypeId::of::<T>(),
+            entity_type_name: any::type_name::<T>(),
+            entity_id: id,
+            component_type: None,
+            component_name: None,
+            changes: None,
+        }
+    }
+
+    /// Attaches the set of changes that were written to storage to this
+    /// event, and returns it.
+    pub fn with_changes(mut self, changes: ChangeSet) -> Event {
+        self.changes = Some(changes);
+        self
+    }
+
+    /// Creates a new event describing a change to a [`Component`]
+    /// attached to an [`Entity`].
+    ///
+    /// `name` should be the name the [`Component`] type was registered
+    /// with, if any.
+    pub fn component<T: Entity + 'static>(
+        kind: EventKind,
+        id: Snowflake,
+        component_type: TypeId,
+        name: Option<&str>,
+    ) -> Event {
+        Event {
+            component_type: Some(component_type),
+            component_name: name.map(|s| s.to_owned()),
+            ..Event::entity::<T>(kind, id)
+        }
+    }
+
+    /// Gets what kind of change this event describes.
+    pub fn kind(&self) -> EventKind {
+        self.kind
+    }
+
+    /// Gets the `TypeId` of the [`Entity`] type that was changed.
+    pub fn entity_type(&self) -> TypeId {
+        self.entity_type
+    }
+
+    /// Gets the name of the [`Entity`] type that was changed.
+    pub fn entity_type_name(&self) -> &'static str {
+        self.entity_type_name
+    }
+
+    /// Gets the ID of the [`Entity`] that was changed.
+    pub fn entity_id(&self) -> Snowflake {
+        self.entity_id
+    }
+
+    /// Gets the `TypeId` of the [`Component`] type that was changed, for
+    /// component events.
+    pub fn component_type(&self) -> Option<TypeId> {
+        self.component_type
+    }
+
+    /// Gets the registered name of the [`Component`] type that was
+    /// changed, for component events.
+    pub fn component_name(&self) -> Option<&str> {
+        self.component_name.as_deref()
+    }
+
+    /// Gets the changes to the [`Entity`] that were written to storage,
+    /// for [`EntityStored`](EventKind::EntityStored) events.
+    ///
+    /// These describe what changed since the [`Entity`] was loaded or
+    /// last stored.
+    pub fn changes(&self) -> Option<&ChangeSet> {
+        self.changes.as_ref()
+    }
+
+    /// Checks whether this event is about a particular [`Entity`] type.
+    pub fn is_entity<T: Entity + 'static>(&self) -> bool {
+        self.entity_type == TypeId::of::<T>()
+    }
+
+    /// Checks whether this event is about a particular [`Component`] type.
+    pub fn is_component<T, U>(&self) -> bool
+    where
+        T: Entity + 'static,
+        U: Component<T> + 'static,
+    {
+        self.component_type == Some(TypeId::of::<U>())
+    }
+}
+
+/// Selects which [`Events`](Event) a subscriber receives.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EventFilter {
+    /// Matches every event.
+    All,
+
+    /// Matches events for [`Entities`](Entity) with the given `TypeId`,
+    /// including changes to their [`Components`](Component).
+    Entity(TypeId),
+
+    /// Matches events for [`Components`](Component) with the given
+    /// `TypeId`.
+    Component(TypeId),
+
+    /// Matches events for [`Components`](Component) registered under the
+    /// given name.
+    ComponentName(String),
+}
+
+impl EventFilter {
+    /// Matches events for an [`Entity`] type and its
+    /// [`Components`](Component).
+    pub fn entity<T: Entity + 'static>() -> EventFilter {
+        EventFilter::Entity(TypeId::of::<T>())
+    }
+
+    /// Matches events for a [`Component`] type.
+    pub fn component<T, U>() -> EventFilter
+    where
+        T: Entity + 'static,
+        U: Component<T> + 'static,
+    {
+        EventFilter::Component(TypeId::of::<U>())
+    }
+
+    /// Matches events for the [`Component`] type registered under a name.
+    pub fn component_name(name: &str) -> EventFilter {
+        EventFilter::ComponentName(name.to_owned())
+    }
+
+    /// Checks whether an event passes this filter.
+    pub fn matches(&self, event: &Event) -> bool {
+        match self {
+            EventFilter::All => true,
+            EventFilter::Entity(type_id) => event.entity_type == *type_id,
+            EventFilter::Component(type_id) => event.component_type == Some(*type_id),
+            EventFilter::ComponentName(name) => event.component_name() == Some(name.as_str()),
+        }
+    }
+}
+
+/// Identifies a subscription to an [`EventBus`], so that it can be
+/// cancelled later.
+#[derive(Debug, Copy, Clone, Hash, PartialEq, Eq, PartialOrd, Ord)]
+pub struct SubscriptionId(u64);
+
+enum Sink {
+    Callback(Box<dyn Fn(&Event) + Send + Sync + 'static>),
+    Channel(Mutex<Sender<Event>>),
+}
+
+struct Subscriber {
+    id: SubscriptionId,
+    filter: EventFilter,
+    sink: Sink,
+}
+
+impl Subscriber {
+    // Returns false if this subscriber can no longer receive events.
+    fn deliver(&self, event: &Event) -> bool {
+        match &self.sink {
+            Sink::Callback(f) => {
+                f(event);
+                true
+            }
+            Sink::Channel(sender) => sender.lock().send(event.clone()).is_ok(),
+        }
+    }
+}
+
+/// Distributes [`Events`](Event) describing changes to
+/// [`Entities`](Entity) and [`Components`](Component) to subscribers.
+///
+/// Each [`EntityManager`](super::EntityManager) has an `EventBus`, which
+/// is shared with the [`Stores`](super::Store) and
+/// [`ComponentManagers`](super::ComponentManager) it creates.
+///
+/// Subscribers can either be callbacks, which are called synchronously
+/// by whichever thread made the change, or channels, which can be read
+/// from at any later point. Channel subscriptions are dropped
+/// automatically once their [`Receiver`] is dropped.
+///
+/// Callbacks are called while the changed [`Entity`] may still be
+/// locked, so they should not try to load it themselves; send the event
+/// somewhere else to be handled (or use a channel) instead.
+///
+/// Changes made as part of a [`Transaction`](super::Transaction) are
+/// reported once the whole transaction has been committed. A transaction
+/// that gets rolled back doesn't emit any events, either for its changes
+/// or for the compensating changes used to undo them.
+///
+/// # Example
+///
+/// ```
+/// use akashi::{Card, Component, Entity, EntityManager};
+/// use akashi::ecs::event::{EventFilter, EventKind};
+/// use akashi::local_storage::{LocalEntityStorage, LocalComponentStorage};
+///
+/// #[derive(Clone)]
+/// struct Level(u64);
+/// impl Component<Card> for Level {}
+///
+/// let mut manager = EntityManager::new();
+/// manager.register_entity(LocalEntityStorage::<Card>::new()).unwrap();
+/// manager
+///     .register_component("Level", LocalComponentStorage::<Card, Level>::new())
+///     .unwrap();
+///
+/// // Listen for changes to Level components.
+/// let (_id, events) = manager
+///     .events()
+///     .subscribe_channel(EventFilter::component_name("Level"));
+///
+/// let mut card: Card = manager.create(1u64.into()).unwrap();
+/// card.set_component(Level(5)).unwrap();
+/// manager.store(card).unwrap();
+///
+/// let event = events.try_recv().unwrap();
+/// assert_eq!(event.kind(), EventKind::ComponentSet);
+/// assert_eq!(event.entity_id(), 1u64.into());
+/// assert!(event.is_component::<Card, Level>());
+///
+/// // Storing the Card doesn't involve any Level components.
+/// assert!(events.try_recv().is_err());
+/// ```
+pub struct EventBus {
+    next_id: AtomicU64,
+    subscribers: RwLock<Vec<Arc<Subscriber>>>,
+    deferring: AtomicUsize,
+    deferred: Mutex<HashMap<ThreadId, Deferred>>,
+}
+
+/// Events held back on one thread, with the position in `events` where
+/// each nested call to [`EventBus::defer`] started.
+#[derive(Default)]
+struct Deferred {
+    events: Vec<Event>,
+    starts: Vec<usize>,
+}
+
+impl EventBus {
+    /// Creates a new `EventBus` with no subscribers.
+    pub fn new() -> EventBus {
+        EventBus {
+            next_id: AtomicU64::new(0),
+            subscribers: RwLock::new(Vec::new()),
+            deferring: AtomicUsize::new(0),
+            deferred: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn add(&self, filter: EventFilter, sink: Sink) -> SubscriptionId {
+        let id = SubscriptionId(self.next_id.fetch_add(1, Ordering::Relaxed));
+        self.subscribers
+            .write()
+            .push(Arc::new(Subscriber { id, filter, sink }));
+
+        id
+    }
+
+    /// Subscribes a callback to events that pass a filter.
+    pub fn subscribe<F>(&self, filter: EventFilter, callback: F) -> SubscriptionId
+    where
+        F: Fn(&Event) + Send + Sync + 'static,
+    {
+        self.add(filter, Sink::Callback(Box::new(callback)))
+    }
+
+    /// Subscribes a channel to events that pass a filter, returning the
+    /// receiving end of the channel.
+    pub fn subscribe_channel(&self, filter: EventFilter) -> (SubscriptionId, Receiver<Event>) {
+        let (sender, receiver) = mpsc::channel();
+        let id = self.add(filter, Sink::Channel(Mutex::new(sender)));
+        (id, receiver)
+    }
+
+    /// Cancels a subscription.
+    ///
+    /// Returns `false` if no subscription with the given ID exists.
+    pub fn unsubscribe(&self, id: SubscriptionId) -> bool {
+        let mut subscribers = self.subscribers.write();
+        let len = subscribers.len();
+        subscribers.retain(|s| s.id != id);
+        subscribers.len() != len
+    }
+
+    /// Gets the number of active subscriptions.
+    pub fn subscriber_count(&self) -> usize {
+        self.subscribers.read().len()
+    }
+
+    /// Holds back events emitted by the current thread until the
+    /// returned guard is released.
+    ///
+    /// Calls can be nested; events are only sent once the outermost guard
+    /// is released.
+    pub(crate) fn defer(&self) -> DeferredEvents<'_> {
+        self.deferring.fetch_add(1, Ordering::SeqCst);
+
+        let mut deferred = self.deferred.lock();
+        let entry = deferred.entry(thread::current().id()).or_default();
+        entry.starts.push(entry.events.len());
+
+        DeferredEvents {
+            bus: self,
+            released: false,
+        }
+    }
+
+    /// Stops holding back the events emitted by the current thread since
+    /// the last call to [`defer`](EventBus::defer).
+    ///
+    /// If `keep` is `false`, those events are dropped.
+    fn release(&self, keep: bool) {
+        let events = {
+            let mut deferred = self.deferred.lock();
+            let id = thread::current().id();
+            let entry = deferred
+                .get_mut(&id)
+                .expect("events released without being deferred");
+
+            let start = entry.starts.pop().unwrap();
+            if !keep {
+                entry.events.truncate(start);
+            }
+
+            if entry.starts.is_empty() {
+                deferred.remove(&id).unwrap().events
+            } else {
+                Vec::new()
+            }
+        };
+
+        self.deferring.fetch_sub(1, Ordering::SeqCst);
+        for event in events {
+            self.emit(event);
+        }
+    }
+
+    /// Sends an event to every subscriber whose filter it passes.
+    pub fn emit(&self, event: Event) {
+        if self.deferring.load(Ordering::SeqCst) > 0 {
+            let mut deferred = self.deferred.lock();
+            if let Some(entry) = deferred.get_mut(&thread::current().id()) {
+                entry.events.push(event);
+                return;
+            }
+        }
+
+        // Work from a snapshot, so that callbacks are free to subscribe
+        // or unsubscribe without deadlocking.
+        let subscribers: Vec<Arc<Subscriber>> = {
+            let subscribers = self.subscribers.read();
+            if subscribers.is_empty() {
+                return;
+            }
+
+            subscribers
+                .iter()
+                .filter(|s| s.filter.matches(&event))
+                .cloned()
+                .collect()
+        };
+
+        let closed: Vec<SubscriptionId> = subscribers
+            .iter()
+            .filter(|s| !s.deliver(&event))
+            .map(|s| s.id)
+            .collect();
+
+        if !closed.is_empty() {
+            self.subscribers.write().retain(|s| !closed.contains(&s.id));
+        }
+    }
+}
+
+/// Holds back events emitted by the current thread, as returned by
+/// [`EventBus::defer`].
+///
+/// Dropping this without calling [`release`](DeferredEvents::release)
+/// drops the events.
+pub(crate) struct DeferredEvents<'a> {
+    bus: &'a EventBus,
+    released: bool,
+}
+
+impl<'a> DeferredEvents<'a> {
+    /// Stops holding back events, sending them to subscribers if `keep`
+    /// is `true` and dropping them otherwise.
+    pub(crate) fn release(mut self, keep: bool) {
+        self.released = true;
+        self.bus.release(keep);
+    }
+}
+
+impl<'a> Drop for DeferredEvents<'a> {
+    fn drop(&mut self) {
+        if !self.released {
+            self.bus.release(false);
+        }
+    }
+}
+
+impl Default for EventBus {
+    fn default() -> EventBus {
+        EventBus::new()
+    }
+}
+
+impl fmt::Debug for EventBus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "EventBus {{ {} subscribers }}", self.subscriber_count())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::card::Card;
+    use crate::ecs::EntityManager;
+    use crate::local_storage::{LocalComponentStorage, LocalEntityStorage};
+    use crate::player::Player;
+
+    use std::sync::atomic::AtomicUsize;
+
+    #[derive(Clone)]
+    struct Level;
+    impl Component<Card> for Level {}
+
+    fn new_manager() -> EntityManager {
+        let mut manager = EntityManager::new();
+        manager
+            .register_entity(LocalEntityStorage::<Card>::new())
+            .unwrap();
+        manager
+            .register_entity(LocalEntityStorage::<Player>::new())
+            .unwrap();
+        manager
+            .register_component("Level", LocalComponentStorage::<Card, Level>::new())
+            .unwrap();
+        manager
+    }
+
+    fn kinds(events: &Receiver<Event>) -> Vec<EventKind> {
+        events.try_iter().map(|e| e.kind()).collect()
+    }
+
+    #[test]
+    fn test_entity_events() {
+        let manager = new_manager();
+        let (_id, events) = manager
+            .events()
+            .subscribe_channel(EventFilter::entity::<Card>());
+
+        let mut card: Card = manager.create(1u64.into()).unwrap();
+        card.set_component(Level).unwrap();
+        manager.store(card).unwrap();
+
+        {
+            let mut handle = manager.load_mut::<Card>(1u64.into()).unwrap();
+            handle.get_mut().unwrap().set_component(Level).unwrap();
+            handle.store().unwrap();
+        }
+
+        manager.delete::<Card>(1u64.into()).unwrap();
+
+        // Players don't pass the filter.
+        let player: Player = manager.create(2u64.into()).unwrap();
+        manager.store(player).unwrap();
+
+        assert_eq!(
+            kinds(&events),
+            vec![
+                EventKind::EntityCreated,
+                EventKind::ComponentSet,
+                EventKind::EntityStored,
+                EventKind::ComponentSet,
+                EventKind::EntityStored,
+                // Storing through a handle leaves it dirty, so it's
+                // written back again when the handle is dropped.
+                EventKind::EntityStored,
+                EventKind::ComponentDeleted,
+                EventKind::EntityDeleted,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_component_filters() {
+        let manager = new_manager();
+        let bus = manager.events();
+        let (_a, by_type) = bus.subscribe_channel(EventFilter::component::<Card, Level>());
+        let (_b, by_name) = bus.subscribe_channel(EventFilter::component_name("Level"));
+        let (_c, other) = bus.subscribe_channel(EventFilter::component_name("Other"));
+
+        let mut card: Card = manager.create(1u64.into()).unwrap();
+        card.set_component(Level).unwrap();
+
+        let event = by_type.try_recv().unwrap();
+        assert_eq!(event.component_name(), Some("Level"));
+        assert_eq!(event.entity_type(), TypeId::of::<Card>());
+        assert!(by_type.try_recv().is_err());
+
+        assert_eq!(by_name.try_recv().unwrap(), event);
+        assert!(other.try_recv().is_err());
+    }
+
+    #[test]
+    fn test_stored_changes() {
+        let manager = new_manager();
+        let (_id, events) = manager.events().subscribe_channel(EventFilter::All);
+
+        let mut card: Card = manager.create(1u64.into()).unwrap();
+        card.set_component(Level).unwrap();
+        manager.store(card).unwrap();
+
+        let stored = events
+            .try_iter()
+            .find(|e| e.kind() == EventKind::EntityStored)
+            .unwrap();
+
+        let changes = stored.changes().unwrap();
+        assert!(changes.was_set(&TypeId::of::<Level>()));
+        assert!(changes.attachments_changed());
+    }
+
+    #[test]
+    fn test_callbacks() {
+        let manager = new_manager();
+        let count = Arc::new(AtomicUsize::new(0));
+
+        let counter = count.clone();
+        let id = manager.events().subscribe(EventFilter::All, move |_e| {
+            counter.fetch_add(1, Ordering::SeqCst);
+        });
+
+        let card: Card = manager.create(1u64.into()).unwrap();
+        manager.store(card).unwrap();
+        assert_eq!(count.load(Ordering::SeqCst), 2);
+
+        assert!(manager.events().unsubscribe(id));
+        assert!(!manager.events().unsubscribe(id));
+
+        manager.delete::<Card>(1u64.into()).unwrap();
+        assert_eq!(count.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn test_transaction_events() {
+        let manager = new_manager();
+        let card: Card = manager.create(1u64.into()).unwrap();
+        manager.store(card).unwrap();
+
+        let (_id, events) = manager.events().subscribe_channel(EventFilter::All);
+        let seen = Arc::new(AtomicUsize::new(0));
+        let mut txn = manager.transaction();
+        txn.set_component::<Card, _>(1u64.into(), Level)
+            .update_component::<Card, Level, _>(1u64.into(), |_prev| {
+                // Nothing should be sent until the transaction commits.
+                seen.store(events.try_iter().count(), Ordering::SeqCst);
+                Ok(Level)
+            });
+        txn.commit().unwrap();
+
+        assert_eq!(seen.load(Ordering::SeqCst), 0);
+        assert_eq!(
+            kinds(&events),
+            vec![
+                EventKind::ComponentSet,
+                EventKind::EntityStored,
+                EventKind::ComponentSet,
+                EventKind::EntityStored,
+            ]
+        );
+
+        let mut txn = manager.transaction();
+        txn.delete_component::<Card, Level>(1u64.into())
+            .update_component::<Card, Level, _>(1u64.into(), |_prev| {
+                Err(failure::format_err!("update failed"))
+            });
+        assert!(txn.commit().is_err());
+        assert!(events.try_recv().is_err());
+
+        // Events are sent normally once the transaction is over.
+        manager.delete::<Card>(1u64.into()).unwrap();
+        assert!(!kinds(&events).is_empty());
+    }
+
+    #[test]
+    fn test_dropped_receiver() {
+        let bus = EventBus::new();
+        let (_id, events) = bus.subscribe_channel(EventFilter::All);
+        assert_eq!(bus.subscriber_count(), 1);
+
+        bus.emit(Event::entity::<Card>(EventKind::EntityStored, 1u64.into()));
+        assert_eq!(events.try_iter().count(), 1);
+
+        drop(events);
+        bus.emit(Event::entity::<Card>(EventKind::EntityStored, 1u64.into()));
+        assert_eq!(bus.subscriber_count(), 0);
+    }
+}
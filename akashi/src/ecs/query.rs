@@ -0,0 +1,540 @@
+//! Queries over [`Entities`](Entity) by attached [`Component`] types.
+
+use super::{Component, Entity, EntityManager, TypeNotFoundError};
+use crate::snowflake::Snowflake;
+use crate::util::Result;
+
+use std::any;
+use std::any::TypeId;
+use std::collections::HashSet;
+use std::fmt;
+use std::marker::PhantomData;
+use std::vec;
+
+/// How many [`Entity`] IDs to request from storage at a time when
+/// listing every stored entity.
+const KEY_PAGE_SIZE: u64 = 256;
+
+/// A set of [`Component`] types to be fetched by a [`Query`].
+///
+/// This trait is implemented for tuples of up to six [`Component`] types
+/// attached to the same [`Entity`] type. Each row returned by a query is
+/// a tuple containing the ID of a matching entity, followed by each of
+/// the requested components.
+///
+/// Members can also be wrapped in an `Option`, in which case entities
+/// are matched whether or not they have that [`Component`] attached, and
+/// their rows contain `None` if they don't:
+///
+/// ```
+/// use akashi::{Card, Component, Entity, EntityManager, Snowflake};
+/// use akashi::local_storage::{LocalComponentStorage, LocalEntityStorage};
+///
+/// #[derive(Clone, Debug, PartialEq)]
+/// struct Level(u64);
+/// impl Component<Card> for Level {}
+///
+/// #[derive(Clone, Debug, PartialEq)]
+/// struct Nickname(String);
+/// impl Component<Card> for Nickname {}
+///
+/// let mut manager = EntityManager::new();
+/// manager.register_entity(LocalEntityStorage::<Card>::new()).unwrap();
+/// manager
+///     .register_component("Level", LocalComponentStorage::<Card, Level>::new())
+///     .unwrap();
+/// manager
+///     .register_component("Nickname", LocalComponentStorage::<Card, Nickname>::new())
+///     .unwrap();
+///
+/// let mut card: Card = manager.create(1u64.into()).unwrap();
+/// card.set_component(Level(5)).unwrap();
+/// manager.store(card).unwrap();
+///
+/// let rows: Vec<(Snowflake, Level, Option<Nickname>)> = manager
+///     .query::<Card, (Level, Option<Nickname>)>()
+///     .iter()
+///     .unwrap()
+///     .collect::<Result<_, _>>()
+///     .unwrap();
+/// assert_eq!(rows, vec![(1u64.into(), Level(5), None)]);
+/// ```
+pub trait QueryComponents<T: Entity + 'static> {
+    /// The type of each row returned by a [`Query`].
+    type Row;
+
+    /// Gets the `TypeId`s of each required [`Component`] type in this
+    /// set, leaving out optional members.
+    fn type_ids() -> Vec<TypeId>;
+
+    /// Loads a row of [`Component`] data from an [`Entity`].
+    ///
+    /// Returns `Ok(None)` if any of the required components could not be
+    /// found.
+    fn fetch(entity: &T) -> Result<Option<Self::Row>>;
+}
+
+macro_rules! impl_query_components {
+    ($($name:ident),+) => {
+        impl<T, $($name),+> QueryComponents<T> for ($($name,)+)
+        where
+            T: Entity + 'static,
+            $($name: Component<T> + 'static),+
+        {
+            type Row = (Snowflake, $($name),+);
+
+            fn type_ids() -> Vec<TypeId> {
+                vec![$($name::query_type_id()),+].into_iter().flatten().collect()
+            }
+
+            #[allow(non_snake_case)]
+            fn fetch(entity: &T) -> Result<Option<Self::Row>> {
+                $(
+                    let $name: $name = match $name::query_fetch(entity)? {
+                        Some(component) => component,
+                        None => return Ok(None),
+                    };
+                )+
+
+                Ok(Some((entity.id(), $($name),+)))
+            }
+        }
+    };
+}
+
+impl_query_components!(A);
+impl_query_components!(A, B);
+impl_query_components!(A, B, C);
+impl_query_components!(A, B, C, D);
+impl_query_components!(A, B, C, D, E);
+impl_query_components!(A, B, C, D, E, F);
+
+/// A query for [`Entities`](Entity) of a single type that have a given
+/// set of [`Components`](Component) attached.
+///
+/// Queries are created using [`EntityManager::query`].
+///
+/// Where possible, the list of matching entities is built using the
+/// [`keys`](super::ComponentBackend::keys) method of each involved
+/// component's storage backend, so that only entities that actually
+/// match need to be loaded.
+pub struct Query<'a, T, Q>
+where
+    T: Entity + 'static,
+    Q: QueryComponents<T>,
+{
+    manager: &'a EntityManager,
+    required: HashSet<TypeId>,
+    excluded: HashSet<TypeId>,
+    types: PhantomData<fn() -> (T, Q)>,
+}
+
+impl<'a, T, Q> Query<'a, T, Q>
+where
+    T: Entity + 'static,
+    Q: QueryComponents<T>,
+{
+    pub(super) fn new(manager: &'a EntityManager) -> Query<'a, T, Q> {
+        Query {
+            manager,
+            required: Q::type_ids().into_iter().collect(),
+            excluded: HashSet::new(),
+            types: PhantomData,
+        }
+    }
+
+    /// Only match entities that also have the given [`Component`] type
+    /// attached, without fetching it.
+    pub fn with<U: Component<T> + 'static>(mut self) -> Query<'a, T, Q> {
+        self.required.insert(TypeId::of::<U>());
+        self
+    }
+
+    /// Only match entities that do _not_ have the given [`Component`] type
+    /// attached.
+    pub fn without<U: Component<T> + 'static>(mut self) -> Query<'a, T, Q> {
+        self.excluded.insert(TypeId::of::<U>());
+        self
+    }
+
+    /// Gets a sorted list of IDs for the entities matched by this query.
+    ///
+    /// Unlike [`iter`](Query::iter), this doesn't load any
+    /// [`Component`] data.
+    pub fn ids(&self) -> Result<Vec<Snowflake>> {
+        let mut ids = Vec::new();
+        for id in self.candidate_ids()? {
+            let handle = self.manager.load::<T>(id)?;
+            if let Some(entity) = handle.get() {
+                if matches(entity, &self.required, &self.excluded) {
+                    ids.push(id);
+                }
+            }
+        }
+
+        Ok(ids)
+    }
+
+    /// Iterates over the rows returned by this query, in order of
+    /// entity ID.
+    ///
+    /// Each matching [`Entity`] is loaded using a read-locked handle,
+    /// which is released before the row is returned. Attempting to
+    /// iterate over a query while holding a write-locked handle to a
+    /// matching entity on the same thread will deadlock.
+    pub fn iter(&self) -> Result<QueryIter<'a, T, Q>> {
+        Ok(QueryIter {
+            manager: self.manager,
+            ids: self.candidate_ids()?.into_iter(),
+            required: self.required.clone(),
+            excluded: self.excluded.clone(),
+            types: PhantomData,
+        })
+    }
+
+    fn candidate_ids(&self) -> Result<Vec<Snowflake>> {
+        candidate_ids::<T>(self.manager, &self.required, &self.excluded)
+    }
+}
+
+impl<'a, T, Q> fmt::Debug for Query<'a, T, Q>
+where
+    T: Entity + 'static,
+    Q: QueryComponents<T>,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Query<{}, {}> {{ {} required, {} excluded }}",
+            any::type_name::<T>(),
+            any::type_name::<Q>(),
+            self.required.len(),
+            self.excluded.len()
+        )
+    }
+}
+
+/// An iterator over the rows returned by a [`Query`].
+pub struct QueryIter<'a, T, Q>
+where
+    T: Entity + 'static,
+    Q: QueryComponents<T>,
+{
+    manager: &'a EntityManager,
+    ids: vec::IntoIter<Snowflake>,
+    required: HashSet<TypeId>,
+    excluded: HashSet<TypeId>,
+    types: PhantomData<fn() -> (T, Q)>,
+}
+
+impl<'a, T, Q> Iterator for QueryIter<'a, T, Q>
+where
+    T: Entity + 'static,
+    Q: QueryComponents<T>,
+{
+    type Item = Result<Q::Row>;
+
+    fn next(&mut self) -> Option<Result<Q::Row>> {
+        for id in &mut self.ids {
+            let handle = match self.manager.load::<T>(id) {
+                Ok(handle) => handle,
+                Err(e) => return Some(Err(e)),
+            };
+
+            let entity = match handle.get() {
+                Some(entity) => entity,
+                None => continue,
+            };
+
+            if !matches(entity, &self.required, &self.excluded) {
+                continue;
+            }
+
+            match Q::fetch(entity) {
+                Ok(Some(row)) => return Some(Ok(row)),
+                Ok(None) => continue,
+                Err(e) => return Some(Err(e)),
+            }
+        }
+
+        None
+    }
+}
+
+/// Checks whether an [`Entity`] has all of the `required` component types
+/// attached, and none of the `excluded` ones.
+pub(crate) fn matches<T: Entity + 'static>(
+    entity: &T,
+    required: &HashSet<TypeId>,
+    excluded: &HashSet<TypeId>,
+) -> bool {
+    let attached = entity.components_attached();
+    required.is_subset(attached) && excluded.is_disjoint(attached)
+}
+
+/// Builds a sorted list of [`Entity`] IDs that might match the given sets
+/// of required and excluded component types.
+///
+/// Component backends that can list their contents are used to narrow
+/// down the list; if none of the required component types can be listed,
+/// every stored entity ID is returned. Either way, the entities still need
+/// to be checked with [`matches`] once loaded.
+pub(crate) fn candidate_ids<T: Entity + 'static>(
+    manager: &EntityManager,
+    required: &HashSet<TypeId>,
+    excluded: &HashSet<TypeId>,
+) -> Result<Vec<Snowflake>> {
+    let cm = manager
+        .get_component_manager::<T>()
+        .ok_or_else(|| TypeNotFoundError::new(String::from(any::type_name::<T>())))?;
+
+    let mut candidates: Option<HashSet<Snowflake>> = None;
+    for type_id in required.iter() {
+        if let Some(keys) = cm.component_keys_by_id(type_id)? {
+            candidates = Some(match candidates {
+                None => keys.into_iter().collect(),
+                Some(prev) => keys.into_iter().filter(|k| prev.contains(k)).collect(),
+            });
+        }
+    }
+
+    let mut candidates = match candidates {
+        Some(candidates) => candidates,
+        None => all_keys::<T>(manager)?.into_iter().collect(),
+    };
+
+    for type_id in excluded.iter() {
+        if let Some(keys) = cm.component_keys_by_id(type_id)? {
+            for key in keys.iter() {
+                candidates.remove(key);
+            }
+        }
+    }
+
+    let mut ids: Vec<Snowflake> = candidates.into_iter().collect();
+    ids.sort_unstable();
+    Ok(ids)
+}
+
+/// Lists every stored [`Entity`] ID of a given type.
+fn all_keys<T: Entity + 'static>(manager: &EntityManager) -> Result<Vec<Snowflake>> {
+    let mut ids = Vec::new();
+    let mut page = 0;
+
+    loop {
+        let keys = manager.keys::<T>(page, KEY_PAGE_SIZE)?;
+        let n_keys = keys.len() as u64;
+        ids.extend(keys);
+
+        if n_keys < KEY_PAGE_SIZE {
+            return Ok(ids);
+        }
+
+        page += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::card::Card;
+    use crate::ecs::ComponentBackend;
+    use crate::local_storage::{LocalComponentStorage, LocalEntityStorage};
+    use crate::snowflake::SnowflakeGenerator;
+
+    #[derive(Clone, Debug, PartialEq)]
+    struct TestComponentA(u64);
+    impl Component<Card> for TestComponentA {}
+
+    #[derive(Clone, Debug, PartialEq)]
+    struct TestComponentB(u64);
+    impl Component<Card> for TestComponentB {}
+
+    #[derive(Clone, Debug, PartialEq)]
+    struct TestComponentC(u64);
+    impl Component<Card> for TestComponentC {}
+
+    /// A component backend that can't list its contents.
+    struct UnlistedStorage(LocalComponentStorage<Card, TestComponentC>);
+
+    impl ComponentBackend<Card, TestComponentC> for UnlistedStorage {
+        fn load(&self, entity: &Card) -> Result<Option<TestComponentC>> {
+            self.0.load(entity)
+        }
+
+        fn store(&self, entity: &Card, component: TestComponentC) -> Result<()> {
+            self.0.store(entity, component)
+        }
+
+        fn exists(&self, entity: &Card) -> Result<bool> {
+            self.0.exists(entity)
+        }
+
+        fn delete(&self, entity: &Card) -> Result<()> {
+            self.0.delete(entity)
+        }
+    }
+
+    struct Fixtures {
+        manager: EntityManager,
+        ids: Vec<Snowflake>,
+    }
+
+    // Creates 8 cards, with every possible combination of the three test
+    // component types attached.
+    fn fixtures() -> Fixtures {
+        let snowflake_gen = SnowflakeGenerator::new(0, 0);
+        let mut manager = EntityManager::new();
+
+        manager
+            .register_entity(LocalEntityStorage::<Card>::new())
+            .unwrap();
+        manager
+            .register_component("A", LocalComponentStorage::<Card, TestComponentA>::new())
+            .unwrap();
+        manager
+            .register_component("B", LocalComponentStorage::<Card, TestComponentB>::new())
+            .unwrap();
+        manager
+            .register_component("C", UnlistedStorage(LocalComponentStorage::new()))
+            .unwrap();
+
+        let mut ids = Vec::new();
+        for i in 0..8 {
+            let mut card: Card = manager.create(snowflake_gen.generate()).unwrap();
+            if i & 1 != 0 {
+                card.set_component(TestComponentA(i)).unwrap();
+            }
+
+            if i & 2 != 0 {
+                card.set_component(TestComponentB(i * 10)).unwrap();
+            }
+
+            if i & 4 != 0 {
+                card.set_component(TestComponentC(i * 100)).unwrap();
+            }
+
+            ids.push(card.id());
+            manager.store(card).unwrap();
+        }
+
+        Fixtures { manager, ids }
+    }
+
+    #[test]
+    fn test_query_rows() {
+        let fixtures = fixtures();
+        let rows: Vec<(Snowflake, TestComponentA, TestComponentB)> = fixtures
+            .manager
+            .query::<Card, (TestComponentA, TestComponentB)>()
+            .iter()
+            .unwrap()
+            .collect::<Result<_>>()
+            .unwrap();
+
+        assert_eq!(
+            rows,
+            vec![
+                (fixtures.ids[3], TestComponentA(3), TestComponentB(30)),
+                (fixtures.ids[7], TestComponentA(7), TestComponentB(70)),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_query_filters() {
+        let fixtures = fixtures();
+
+        let ids = fixtures
+            .manager
+            .query::<Card, (TestComponentA,)>()
+            .without::<TestComponentB>()
+            .ids()
+            .unwrap();
+        assert_eq!(ids, vec![fixtures.ids[1], fixtures.ids[5]]);
+
+        let ids = fixtures
+            .manager
+            .query::<Card, (TestComponentA,)>()
+            .with::<TestComponentC>()
+            .ids()
+            .unwrap();
+        assert_eq!(ids, vec![fixtures.ids[5], fixtures.ids[7]]);
+
+        let ids = fixtures
+            .manager
+            .query::<Card, (TestComponentA,)>()
+            .without::<TestComponentC>()
+            .ids()
+            .unwrap();
+        assert_eq!(ids, vec![fixtures.ids[1], fixtures.ids[3]]);
+    }
+
+    #[test]
+    fn test_query_unlisted() {
+        // Querying only for components that can't be listed falls back
+        // to scanning every stored entity.
+        let fixtures = fixtures();
+        let rows: Vec<(Snowflake, TestComponentC)> = fixtures
+            .manager
+            .query::<Card, (TestComponentC,)>()
+            .iter()
+            .unwrap()
+            .collect::<Result<_>>()
+            .unwrap();
+
+        let ids: Vec<Snowflake> = rows.iter().map(|r| r.0).collect();
+        assert_eq!(
+            ids,
+            vec![
+                fixtures.ids[4],
+                fixtures.ids[5],
+                fixtures.ids[6],
+                fixtures.ids[7]
+            ]
+        );
+        assert_eq!(rows[0].1, TestComponentC(400));
+    }
+
+    #[test]
+    fn test_query_optional() {
+        let fixtures = fixtures();
+        let rows: Vec<(Snowflake, TestComponentA, Option<TestComponentB>)> = fixtures
+            .manager
+            .query::<Card, (TestComponentA, Option<TestComponentB>)>()
+            .iter()
+            .unwrap()
+            .collect::<Result<_>>()
+            .unwrap();
+
+        assert_eq!(
+            rows,
+            vec![
+                (fixtures.ids[1], TestComponentA(1), None),
+                (fixtures.ids[3], TestComponentA(3), Some(TestComponentB(30))),
+                (fixtures.ids[5], TestComponentA(5), None),
+                (fixtures.ids[7], TestComponentA(7), Some(TestComponentB(70))),
+            ]
+        );
+
+        // Optional members don't narrow down the matched entities.
+        assert_eq!(
+            <(TestComponentA, Option<TestComponentB>)>::type_ids(),
+            vec![TypeId::of::<TestComponentA>()]
+        );
+
+        let ids = fixtures
+            .manager
+            .query::<Card, (Option<TestComponentC>,)>()
+            .ids()
+            .unwrap();
+        assert_eq!(ids, fixtures.ids);
+    }
+
+    #[test]
+    fn test_query_unregistered() {
+        let manager = EntityManager::new();
+        assert!(manager.query::<Card, (TestComponentA,)>().iter().is_err());
+    }
+}
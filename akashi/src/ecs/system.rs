@@ -0,0 +1,609 @@
+//! Systems and a scheduler for running them over stored [`Entities`](Entity).
+
+use super::query;
+use super::{Component, Entity, EntityManager};
+use crate::util::Result;
+
+use std::any;
+use std::any::TypeId;
+use std::collections::HashSet;
+use std::fmt;
+use std::result;
+
+use failure::{Error, Fail};
+use rayon::prelude::*;
+
+/// Identifies a [`Component`] type attached to a specific [`Entity`] type.
+///
+/// Some types (such as [`Snowflake`](crate::Snowflake)) implement
+/// `Component` for more than one `Entity` type, so both are needed to tell
+/// whether two [`Systems`](System) conflict.
+type ComponentKey = (TypeId, TypeId);
+
+/// Declares which [`Component`] types a [`System`] reads and writes.
+///
+/// The [`Scheduler`] uses these declarations to decide which systems can
+/// safely run in parallel, and to decide which [`Entities`](Entity) a
+/// system should run on.
+///
+/// # Example
+///
+/// ```
+/// use akashi::Card;
+/// use akashi::components::AttachedCardType;
+/// use akashi::ecs::SystemAccess;
+///
+/// let access = SystemAccess::new().read::<Card, AttachedCardType>();
+/// assert!(access.reads::<Card, AttachedCardType>());
+/// assert!(!access.writes::<Card, AttachedCardType>());
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct SystemAccess {
+    reads: HashSet<ComponentKey>,
+    writes: HashSet<ComponentKey>,
+    excluded: HashSet<ComponentKey>,
+}
+
+impl SystemAccess {
+    /// Creates a new `SystemAccess` that declares no reads or writes.
+    pub fn new() -> SystemAccess {
+        SystemAccess {
+            reads: HashSet::new(),
+            writes: HashSet::new(),
+            excluded: HashSet::new(),
+        }
+    }
+
+    /// Declares that a [`Component`] type is read.
+    pub fn read<T, U>(mut self) -> SystemAccess
+    where
+        T: Entity + 'static,
+        U: Component<T> + 'static,
+    {
+        self.reads.insert((TypeId::of::<T>(), TypeId::of::<U>()));
+        self
+    }
+
+    /// Declares that a [`Component`] type is written.
+    ///
+    /// Written components are implicitly read as well.
+    pub fn write<T, U>(mut self) -> SystemAccess
+    where
+        T: Entity + 'static,
+        U: Component<T> + 'static,
+    {
+        self.writes.insert((TypeId::of::<T>(), TypeId::of::<U>()));
+        self
+    }
+
+    /// Declares that a [`System`] should only run on entities that do
+    /// _not_ have a [`Component`] type attached.
+    ///
+    /// Excluded components aren't considered to be read, and don't cause
+    /// conflicts with other systems.
+    pub fn without<T, U>(mut self) -> SystemAccess
+    where
+        T: Entity + 'static,
+        U: Component<T> + 'static,
+    {
+        self.excluded.insert((TypeId::of::<T>(), TypeId::of::<U>()));
+        self
+    }
+
+    /// Checks whether a [`Component`] type is read (or written).
+    pub fn reads<T, U>(&self) -> bool
+    where
+        T: Entity + 'static,
+        U: Component<T> + 'static,
+    {
+        let key = (TypeId::of::<T>(), TypeId::of::<U>());
+        self.reads.contains(&key) || self.writes.contains(&key)
+    }
+
+    /// Checks whether a [`Component`] type is written.
+    pub fn writes<T, U>(&self) -> bool
+    where
+        T: Entity + 'static,
+        U: Component<T> + 'static,
+    {
+        self.writes
+            .contains(&(TypeId::of::<T>(), TypeId::of::<U>()))
+    }
+
+    /// Checks whether running two systems with these access declarations
+    /// at the same time could cause a data race.
+    ///
+    /// Two declarations conflict if either one writes a [`Component`]
+    /// type that the other reads or writes.
+    pub fn conflicts_with(&self, other: &SystemAccess) -> bool {
+        self.writes
+            .iter()
+            .any(|k| other.reads.contains(k) || other.writes.contains(k))
+            || other.writes.iter().any(|k| self.reads.contains(k))
+    }
+
+    /// Gets the `TypeId`s of all [`Components`](Component) read or written
+    /// for the given [`Entity`] type.
+    fn component_types<T: Entity + 'static>(&self) -> HashSet<TypeId> {
+        let ent_type = TypeId::of::<T>();
+        self.reads
+            .iter()
+            .chain(self.writes.iter())
+            .filter(|(e, _)| *e == ent_type)
+            .map(|(_, c)| *c)
+            .collect()
+    }
+
+    /// Gets the `TypeId`s of all [`Components`](Component) excluded for
+    /// the given [`Entity`] type.
+    fn excluded_types<T: Entity + 'static>(&self) -> HashSet<TypeId> {
+        let ent_type = TypeId::of::<T>();
+        self.excluded
+            .iter()
+            .filter(|(e, _)| *e == ent_type)
+            .map(|(_, c)| *c)
+            .collect()
+    }
+}
+
+/// Represents a System within Akashi's Entity-Component-System
+/// architecture.
+///
+/// Systems hold game logic. Each System operates on a single [`Entity`]
+/// type, and is run by a [`Scheduler`] once for every stored Entity of
+/// that type that has all of the [`Components`](Component) declared by
+/// [`access`](System::access) attached (and none of the excluded ones).
+///
+/// # Example
+///
+/// ```
+/// use akashi::{Card, Component, Entity, EntityManager};
+/// use akashi::ecs::{Scheduler, System, SystemAccess};
+/// use akashi::local_storage::{LocalComponentStorage, LocalEntityStorage};
+///
+/// #[derive(Clone)]
+/// struct Level(u64);
+/// impl Component<Card> for Level {}
+///
+/// // Levels up every Card with a Level component.
+/// struct LevelUp;
+///
+/// impl System for LevelUp {
+///     type Entity = Card;
+///
+///     fn access(&self) -> SystemAccess {
+///         SystemAccess::new().write::<Card, Level>()
+///     }
+///
+///     fn run(&self, card: &mut Card, _manager: &EntityManager) -> Result<(), failure::Error> {
+///         let level: Level = card.get_component()?.unwrap();
+///         card.set_component(Level(level.0 + 1))
+///     }
+/// }
+///
+/// let mut manager = EntityManager::new();
+/// manager.register_entity(LocalEntityStorage::<Card>::new()).unwrap();
+/// manager
+///     .register_component("Level", LocalComponentStorage::<Card, Level>::new())
+///     .unwrap();
+///
+/// let mut card: Card = manager.create(123456789u64.into()).unwrap();
+/// card.set_component(Level(1)).unwrap();
+/// manager.store(card).unwrap();
+///
+/// let mut scheduler = Scheduler::new();
+/// scheduler.add_system("LevelUp", LevelUp);
+/// scheduler.run(&manager).unwrap();
+///
+/// let handle = manager.load::<Card>(123456789u64.into()).unwrap();
+/// let level: Level = handle.get().unwrap().get_component().unwrap().unwrap();
+/// assert_eq!(level.0, 2);
+/// ```
+pub trait System: Send + Sync + 'static {
+    /// The type of [`Entity`] this System runs on.
+    type Entity: Entity + 'static;
+
+    /// Declares which [`Component`] types this System reads and writes.
+    fn access(&self) -> SystemAccess;
+
+    /// Runs this System on a single matching [`Entity`].
+    ///
+    /// If the Entity has been modified when this method returns, it will
+    /// be written back to storage.
+    fn run(&self, entity: &mut Self::Entity, manager: &EntityManager) -> Result<()>;
+}
+
+/// An object-safe wrapper around [`System`] trait objects, used to erase
+/// the associated [`Entity`] type.
+trait SystemRunner: Send + Sync {
+    fn name(&self) -> &str;
+    fn access(&self) -> &SystemAccess;
+    fn run(&self, manager: &EntityManager) -> Result<()>;
+}
+
+struct SystemData<S: System> {
+    name: String,
+    access: SystemAccess,
+    required: HashSet<TypeId>,
+    excluded: HashSet<TypeId>,
+    system: S,
+}
+
+impl<S: System> SystemRunner for SystemData<S> {
+    fn name(&self) -> &str {
+        self.name.as_str()
+    }
+
+    fn access(&self) -> &SystemAccess {
+        &self.access
+    }
+
+    fn run(&self, manager: &EntityManager) -> Result<()> {
+        let ids = query::candidate_ids::<S::Entity>(manager, &self.required, &self.excluded)?;
+
+        for id in ids.into_iter() {
+            let mut handle = manager.load_mut::<S::Entity>(id)?;
+            let entity = match handle.get_mut() {
+                Some(entity) => entity,
+                None => continue,
+            };
+
+            if !query::matches(entity, &self.required, &self.excluded) {
+                continue;
+            }
+
+            self.system.run(entity, manager)?;
+
+            if entity.dirty() {
+                handle.store_clean()?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Runs [`Systems`](System) over the [`Entities`](Entity) stored in an
+/// [`EntityManager`].
+///
+/// Systems are grouped into stages. Systems within the same stage have
+/// no conflicting [`Component`] accesses, and are run in parallel;
+/// stages themselves are run one after another.
+///
+/// Systems are assigned to stages in the order they were added, such
+/// that a System always runs after any previously-added System it
+/// conflicts with.
+pub struct Scheduler {
+    systems: Vec<Box<dyn SystemRunner>>,
+    stages: Vec<Vec<usize>>,
+}
+
+impl Scheduler {
+    /// Creates a new, empty `Scheduler`.
+    pub fn new() -> Scheduler {
+        Scheduler {
+            systems: Vec::new(),
+            stages: Vec::new(),
+        }
+    }
+
+    /// Adds a [`System`] to this scheduler under the given name.
+    pub fn add_system<S: System>(&mut self, name: &str, system: S) {
+        let access = system.access();
+        let required = access.component_types::<S::Entity>();
+        let excluded = access.excluded_types::<S::Entity>();
+        let index = self.systems.len();
+
+        // Place the new system in the stage following the last stage
+        // that contains a conflicting system.
+        let stage = self
+            .stages
+            .iter()
+            .rposition(|stage| {
+                stage
+                    .iter()
+                    .any(|&i| self.systems[i].access().conflicts_with(&access))
+            })
+            .map_or(0, |i| i + 1);
+
+        self.systems.push(Box::new(SystemData {
+            name: name.to_owned(),
+            access,
+            required,
+            excluded,
+            system,
+        }));
+
+        if stage == self.stages.len() {
+            self.stages.push(vec![index]);
+        } else {
+            self.stages[stage].push(index);
+        }
+    }
+
+    /// Gets the names of the systems in each stage, in the order that
+    /// the stages will be run.
+    pub fn stages(&self) -> Vec<Vec<&str>> {
+        self.stages
+            .iter()
+            .map(|stage| stage.iter().map(|&i| self.systems[i].name()).collect())
+            .collect()
+    }
+
+    /// Runs every registered [`System`] once.
+    ///
+    /// # Errors
+    ///
+    /// Errors returned by systems within a stage are collected into a
+    /// [`RunSystemsError`]. If any system in a stage fails, the remaining
+    /// systems in that stage will still run, but later stages will not.
+    pub fn run(&self, manager: &EntityManager) -> result::Result<(), RunSystemsError> {
+        for stage in self.stages.iter() {
+            let errors: Vec<(String, Error)> = stage
+                .par_iter()
+                .filter_map(|&i| {
+                    let system = &self.systems[i];
+                    system
+                        .run(manager)
+                        .err()
+                        .map(|e| (system.name().to_owned(), e))
+                })
+                .collect();
+
+            if !errors.is_empty() {
+                return Err(RunSystemsError { errors });
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Default for Scheduler {
+    fn default() -> Scheduler {
+        Scheduler::new()
+    }
+}
+
+impl fmt::Debug for Scheduler {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} {{ {} systems, {} stages }}",
+            any::type_name::<Scheduler>(),
+            self.systems.len(),
+            self.stages.len()
+        )
+    }
+}
+
+/// This failure type collects errors from [`Scheduler::run`].
+#[derive(Fail, Debug)]
+pub struct RunSystemsError {
+    errors: Vec<(String, Error)>,
+}
+
+impl RunSystemsError {
+    /// Gets the name of each failed system, along with the error it
+    /// returned.
+    pub fn errors(&self) -> &[(String, Error)] {
+        &self.errors
+    }
+}
+
+impl fmt::Display for RunSystemsError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "failed to run systems due to errors:")?;
+        for (name, err) in self.errors.iter() {
+            writeln!(f, "{}: {}", name, err)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::card::Card;
+    use crate::local_storage::{LocalComponentStorage, LocalEntityStorage};
+    use crate::player::Player;
+    use crate::snowflake::{Snowflake, SnowflakeGenerator};
+
+    use failure::format_err;
+
+    #[derive(Clone)]
+    struct Counter(u64);
+    impl Component<Card> for Counter {}
+
+    #[derive(Clone)]
+    struct Marker;
+    impl Component<Card> for Marker {}
+    impl Component<Player> for Marker {}
+
+    struct Increment;
+
+    impl System for Increment {
+        type Entity = Card;
+
+        fn access(&self) -> SystemAccess {
+            SystemAccess::new().write::<Card, Counter>()
+        }
+
+        fn run(&self, card: &mut Card, _manager: &EntityManager) -> Result<()> {
+            let counter: Counter = card.get_component()?.unwrap();
+            card.set_component(Counter(counter.0 + 1))
+        }
+    }
+
+    struct ReadMarker;
+
+    impl System for ReadMarker {
+        type Entity = Card;
+
+        fn access(&self) -> SystemAccess {
+            SystemAccess::new().read::<Card, Marker>()
+        }
+
+        fn run(&self, _card: &mut Card, _manager: &EntityManager) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    struct IncrementUnmarked;
+
+    impl System for IncrementUnmarked {
+        type Entity = Card;
+
+        fn access(&self) -> SystemAccess {
+            SystemAccess::new()
+                .write::<Card, Counter>()
+                .without::<Card, Marker>()
+        }
+
+        fn run(&self, card: &mut Card, manager: &EntityManager) -> Result<()> {
+            Increment.run(card, manager)
+        }
+    }
+
+    struct FailingSystem;
+
+    impl System for FailingSystem {
+        type Entity = Card;
+
+        fn access(&self) -> SystemAccess {
+            SystemAccess::new().read::<Card, Counter>()
+        }
+
+        fn run(&self, _card: &mut Card, _manager: &EntityManager) -> Result<()> {
+            Err(format_err!("system failed"))
+        }
+    }
+
+    fn new_manager() -> EntityManager {
+        let mut manager = EntityManager::new();
+        manager
+            .register_entity(LocalEntityStorage::<Card>::new())
+            .unwrap();
+        manager
+            .register_component("Counter", LocalComponentStorage::<Card, Counter>::new())
+            .unwrap();
+        manager
+            .register_component("Marker", LocalComponentStorage::<Card, Marker>::new())
+            .unwrap();
+        manager
+    }
+
+    fn get_counter(manager: &EntityManager, id: Snowflake) -> Option<u64> {
+        let handle = manager.load::<Card>(id).unwrap();
+        let counter: Option<Counter> = handle.get().unwrap().get_component().unwrap();
+        counter.map(|c| c.0)
+    }
+
+    #[test]
+    fn test_access_conflicts() {
+        let read_a = SystemAccess::new().read::<Card, Counter>();
+        let write_a = SystemAccess::new().write::<Card, Counter>();
+        let write_b = SystemAccess::new().write::<Card, Marker>();
+        let write_b_player = SystemAccess::new().write::<Player, Marker>();
+
+        assert!(!read_a.conflicts_with(&read_a));
+        assert!(read_a.conflicts_with(&write_a));
+        assert!(write_a.conflicts_with(&read_a));
+        assert!(write_a.conflicts_with(&write_a));
+        assert!(!write_a.conflicts_with(&write_b));
+
+        // The same Component type attached to different Entity types
+        // doesn't conflict.
+        assert!(!write_b.conflicts_with(&write_b_player));
+
+        // Neither do excluded Component types.
+        let without_b = SystemAccess::new().without::<Card, Marker>();
+        assert!(!without_b.conflicts_with(&write_b));
+    }
+
+    #[test]
+    fn test_stages() {
+        let mut scheduler = Scheduler::new();
+        scheduler.add_system("inc1", Increment);
+        scheduler.add_system("marker", ReadMarker);
+        scheduler.add_system("inc2", Increment);
+        scheduler.add_system("fail", FailingSystem);
+
+        assert_eq!(
+            scheduler.stages(),
+            vec![vec!["inc1", "marker"], vec!["inc2"], vec!["fail"]]
+        );
+    }
+
+    #[test]
+    fn test_run_matching() {
+        let snowflake_gen = SnowflakeGenerator::new(0, 0);
+        let manager = new_manager();
+
+        let mut with_counter: Card = manager.create(snowflake_gen.generate()).unwrap();
+        with_counter.set_component(Counter(0)).unwrap();
+        let with_id = with_counter.id();
+        manager.store(with_counter).unwrap();
+
+        let without_counter: Card = manager.create(snowflake_gen.generate()).unwrap();
+        let without_id = without_counter.id();
+        manager.store(without_counter).unwrap();
+
+        let mut scheduler = Scheduler::new();
+        scheduler.add_system("inc1", Increment);
+        scheduler.add_system("inc2", Increment);
+        scheduler.run(&manager).unwrap();
+
+        // Only the card with a Counter attached should be updated.
+        assert_eq!(get_counter(&manager, with_id), Some(2));
+        assert_eq!(get_counter(&manager, without_id), None);
+    }
+
+    #[test]
+    fn test_run_without() {
+        let snowflake_gen = SnowflakeGenerator::new(0, 0);
+        let manager = new_manager();
+
+        let mut marked: Card = manager.create(snowflake_gen.generate()).unwrap();
+        marked.set_component(Counter(0)).unwrap();
+        marked.set_component(Marker).unwrap();
+        let marked_id = marked.id();
+        manager.store(marked).unwrap();
+
+        let mut unmarked: Card = manager.create(snowflake_gen.generate()).unwrap();
+        unmarked.set_component(Counter(0)).unwrap();
+        let unmarked_id = unmarked.id();
+        manager.store(unmarked).unwrap();
+
+        let mut scheduler = Scheduler::new();
+        scheduler.add_system("inc", IncrementUnmarked);
+        scheduler.run(&manager).unwrap();
+
+        assert_eq!(get_counter(&manager, marked_id), Some(0));
+        assert_eq!(get_counter(&manager, unmarked_id), Some(1));
+    }
+
+    #[test]
+    fn test_run_errors() {
+        let snowflake_gen = SnowflakeGenerator::new(0, 0);
+        let manager = new_manager();
+
+        let mut card: Card = manager.create(snowflake_gen.generate()).unwrap();
+        card.set_component(Counter(0)).unwrap();
+        let id = card.id();
+        manager.store(card).unwrap();
+
+        let mut scheduler = Scheduler::new();
+        scheduler.add_system("fail", FailingSystem);
+        scheduler.add_system("inc", Increment);
+
+        let err = scheduler.run(&manager).unwrap_err();
+        assert_eq!(err.errors().len(), 1);
+        assert_eq!(err.errors()[0].0, "fail");
+
+        // The stage after the failing one should not have run.
+        assert_eq!(get_counter(&manager, id), Some(0));
+    }
+}
@@ -0,0 +1,734 @@
+//! Atomic groups of changes spanning multiple [`Entities`](Entity).
+
+use super::entity_store::{StoreHandle, WriteReference};
+use super::{Component, Entity, EntityManager};
+use crate::snowflake::Snowflake;
+use crate::util::Result;
+
+use std::any;
+use std::any::{Any, TypeId};
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Arc;
+
+use failure::{format_err, Error};
+
+/// An extension trait for storage backends that support real
+/// transactions.
+///
+/// [`EntityBackend`](super::EntityBackend) and
+/// [`ComponentBackend`](super::ComponentBackend) implementations can
+/// return an implementation of this trait from their `transactional`
+/// methods. A [`Transaction`] will then begin a transaction on the
+/// backend before making any changes, and either commit or roll it back
+/// once all changes have been applied.
+///
+/// Several backends can share the same `TransactionalBackend` (for example,
+/// storage objects backed by tables in the same database); each distinct
+/// `TransactionalBackend` object is only used once per [`Transaction`].
+pub trait TransactionalBackend: Send + Sync {
+    /// Begins a new transaction.
+    fn begin(&self) -> Result<()>;
+
+    /// Commits the current transaction.
+    fn commit(&self) -> Result<()>;
+
+    /// Rolls back the current transaction.
+    fn rollback(&self) -> Result<()>;
+}
+
+/// Write-locked handles for every [`Entity`] touched by a [`Transaction`].
+struct HandleLocks(HashMap<(TypeId, Snowflake), Box<dyn Any>>);
+
+impl HandleLocks {
+    fn get<T: Entity + 'static>(&mut self, id: Snowflake) -> &mut StoreHandle<T> {
+        let handle = self
+            .0
+            .get_mut(&(TypeId::of::<T>(), id))
+            .and_then(|handle| handle.downcast_mut::<WriteReference<StoreHandle<T>>>())
+            .expect("entity handle not locked by transaction");
+
+        &mut *handle
+    }
+}
+
+type LockFn = fn(&EntityManager, Snowflake) -> Result<Box<dyn Any>>;
+type BackendsFn = fn(&EntityManager) -> Result<Vec<Arc<dyn TransactionalBackend>>>;
+type UndoFn = Box<dyn FnOnce(&mut HandleLocks) -> Result<()>>;
+type ApplyFn<'a> = Box<dyn FnOnce(&mut HandleLocks, &mut Vec<UndoFn>) -> Result<()> + 'a>;
+
+struct Operation<'a> {
+    entity_type: TypeId,
+    id: Snowflake,
+    lock: LockFn,
+    backends: BackendsFn,
+    apply: ApplyFn<'a>,
+}
+
+fn lock_handle<T: Entity + 'static>(
+    manager: &EntityManager,
+    id: Snowflake,
+) -> Result<Box<dyn Any>> {
+    Ok(Box::new(manager.load_mut::<T>(id)?))
+}
+
+fn not_found<T: Entity + 'static>(id: Snowflake) -> Error {
+    format_err!("entity not found: {} {}", any::type_name::<T>(), id)
+}
+
+/// A group of changes to [`Entities`](Entity) and their
+/// [`Components`](Component) that are applied all at once.
+///
+/// Transactions are created using [`EntityManager::transaction`].
+/// Operations added to a transaction aren't performed until
+/// [`commit`](Transaction::commit) is called, at which point they are
+/// applied in the order they were added.
+///
+/// # Atomicity
+///
+/// While committing, the handles for every [`Entity`] involved in the
+/// transaction are write-locked, so other threads can't observe or make
+/// changes partway through.
+///
+/// Storage backends that provide a [`TransactionalBackend`] have all of
+/// their changes made within a real backend transaction. For backends
+/// that don't, if any operation fails, every operation that was already
+/// applied is undone by writing back the data it replaced. This
+/// compensating rollback is best-effort: if the backend fails again while
+/// undoing changes, some changes may remain.
+///
+/// [`Events`](super::Event) for the changes made by a transaction are
+/// only emitted once it has been committed, after its handles have been
+/// unlocked. Nothing is emitted for a transaction that gets rolled back.
+///
+/// # Example
+///
+/// ```
+/// use akashi::{Card, Entity, EntityManager, Player};
+/// use akashi::components::Resource;
+/// use akashi::local_storage::{LocalComponentStorage, LocalEntityStorage};
+///
+/// let mut manager = EntityManager::new();
+/// manager.register_entity(LocalEntityStorage::<Player>::new()).unwrap();
+/// manager.register_entity(LocalEntityStorage::<Card>::new()).unwrap();
+/// manager
+///     .register_component("Resource", LocalComponentStorage::<Player, Resource>::new())
+///     .unwrap();
+///
+/// let mut player: Player = manager.create(1u64.into()).unwrap();
+/// player.set_component(Resource::new(100, None, None)).unwrap();
+/// manager.store(player).unwrap();
+///
+/// // Spend some of the player's currency and create a new card for them,
+/// // all at once.
+/// let card: Card = manager.create(2u64.into()).unwrap();
+///
+/// let mut txn = manager.transaction();
+/// txn.set_component::<Player, _>(1u64.into(), Resource::new(90, None, None));
+/// txn.store(card);
+/// txn.commit().unwrap();
+///
+/// assert!(manager.exists::<Card>(2u64.into()).unwrap());
+///
+/// let handle = manager.load::<Player>(1u64.into()).unwrap();
+/// let rsc: Resource = handle.get().unwrap().get_component().unwrap().unwrap();
+/// assert_eq!(rsc.val(), 90);
+/// ```
+pub struct Transaction<'a> {
+    manager: &'a EntityManager,
+    ops: Vec<Operation<'a>>,
+}
+
+impl<'a> Transaction<'a> {
+    pub(crate) fn new(manager: &'a EntityManager) -> Transaction<'a> {
+        Transaction {
+            manager,
+            ops: Vec::new(),
+        }
+    }
+
+    fn push<T, F>(&mut self, id: Snowflake, apply: F) -> &mut Self
+    where
+        T: Entity + 'static,
+        F: FnOnce(&mut HandleLocks, &mut Vec<UndoFn>) -> Result<()> + 'a,
+    {
+        self.ops.push(Operation {
+            entity_type: TypeId::of::<T>(),
+            id,
+            lock: lock_handle::<T>,
+            backends: EntityManager::transactional_backends::<T>,
+            apply: Box::new(apply),
+        });
+
+        self
+    }
+
+    /// Puts the given [`Entity`] into storage, overwriting any previously
+    /// stored [`Entity`] data with the same ID.
+    ///
+    /// As with [`EntityManager::store`], this only stores the `Entity`
+    /// itself. To attach [`Components`](Component) to a new `Entity` as
+    /// part of the same transaction, use
+    /// [`set_component`](Transaction::set_component) after calling this.
+    pub fn store<T>(&mut self, entity: T) -> &mut Self
+    where
+        T: Entity + 'static,
+    {
+        let id = entity.id();
+
+        self.push::<T, _>(id, move |locks, undo| {
+            let handle = locks.get::<T>(id);
+            let prev = handle.replace(entity);
+
+            undo.push(Box::new(move |locks| {
+                let handle = locks.get::<T>(id);
+                match prev {
+                    Some(prev) => {
+                        handle.replace(prev);
+                    }
+                    None => {
+                        handle.take();
+                    }
+                };
+
+                handle.store_clean()
+            }));
+
+            handle.store_clean()
+        })
+    }
+
+    /// Attaches a [`Component`] to a stored [`Entity`], or updates an
+    /// already-attached [`Component`].
+    ///
+    /// Committing the transaction will fail if no `Entity` with the given
+    /// ID exists (unless it was stored earlier in the same transaction).
+    pub fn set_component<T, U>(&mut self, id: Snowflake, component: U) -> &mut Self
+    where
+        T: Entity + 'static,
+        U: Component<T> + 'static,
+    {
+        self.update_component::<T, U, _>(id, move |_prev| Ok(component))
+    }
+
+    /// Attaches a [`Component`] to a stored [`Entity`], computing its new
+    /// value from its current value (if any) while committing.
+    ///
+    /// Since the `Entity` is locked while the transaction is committed,
+    /// this can be used to make changes based on the latest stored data,
+    /// such as debiting a [`Resource`](crate::components::Resource).
+    /// If `f` returns an error, the transaction is rolled back.
+    ///
+    /// `f` can borrow anything that outlives the transaction, including
+    /// the [`EntityManager`] itself. It must not try to load any of the
+    /// `Entities` involved in the transaction, since they are locked.
+    ///
+    /// Committing the transaction will fail if no `Entity` with the given
+    /// ID exists (unless it was stored earlier in the same transaction).
+    pub fn update_component<T, U, F>(&mut self, id: Snowflake, f: F) -> &mut Self
+    where
+        T: Entity + 'static,
+        U: Component<T> + 'static,
+        F: FnOnce(Option<&U>) -> Result<U> + 'a,
+    {
+        self.push::<T, _>(id, move |locks, undo| {
+            let handle = locks.get::<T>(id);
+            let entity = handle.get_mut().ok_or_else(|| not_found::<T>(id))?;
+
+            let prev: Option<U> = if entity.has_component::<U>() {
+                entity.get_component()?
+            } else {
+                None
+            };
+
+            let component = f(prev.as_ref())?;
+
+            undo.push(Box::new(move |locks| {
+                let handle = locks.get::<T>(id);
+                let entity = handle.get_mut().ok_or_else(|| not_found::<T>(id))?;
+                match prev {
+                    Some(prev) => entity.set_component(prev)?,
+                    None => entity.delete_component::<U>()?,
+                };
+
+                handle.store_clean()
+            }));
+
+            entity.set_component(component)?;
+            handle.store_clean()
+        })
+    }
+
+    /// Deletes an attached [`Component`] from a stored [`Entity`].
+    ///
+    /// Nothing happens if the `Entity` doesn't exist or doesn't have the
+    /// `Component` attached.
+    pub fn delete_component<T, U>(&mut self, id: Snowflake) -> &mut Self
+    where
+        T: Entity + 'static,
+        U: Component<T> + 'static,
+    {
+        self.push::<T, _>(id, move |locks, undo| {
+            let handle = locks.get::<T>(id);
+            let entity = match handle.get_mut() {
+                Some(entity) if entity.has_component::<U>() => entity,
+                _ => return Ok(()),
+            };
+
+            let prev: Option<U> = entity.get_component()?;
+
+            undo.push(Box::new(move |locks| {
+                let handle = locks.get::<T>(id);
+                let entity = handle.get_mut().ok_or_else(|| not_found::<T>(id))?;
+                if let Some(prev) = prev {
+                    entity.set_component(prev)?;
+                }
+
+                handle.store_clean()
+            }));
+
+            entity.delete_component::<U>()?;
+            handle.store_clean()
+        })
+    }
+
+    /// Deletes an [`Entity`] and all of its attached
+    /// [`Components`](Component) from storage.
+    ///
+    /// Nothing happens if the `Entity` doesn't exist.
+    pub fn delete<T>(&mut self, id: Snowflake) -> &mut Self
+    where
+        T: Entity + 'static,
+    {
+        self.push::<T, _>(id, move |locks, undo| {
+            let handle = locks.get::<T>(id);
+            let entity = match handle.get() {
+                Some(entity) => entity,
+                None => return Ok(()),
+            };
+
+            // Save the data for each attached component first, so that
+            // they can be put back if we need to undo this.
+            let attached = entity.components_attached().clone();
+            let mut saved = Vec::new();
+            for type_id in attached.iter() {
+                if let Some(component) = entity
+                    .component_manager()
+                    .get_component_by_id(entity, type_id)?
+                {
+                    saved.push((*type_id, component));
+                }
+            }
+
+            let mut entity = handle.take().unwrap();
+            let res = entity
+                .clear_components()
+                .map_err(Error::from)
+                .and_then(|_v| handle.store_clean());
+
+            undo.push(Box::new(move |locks| {
+                for (type_id, component) in saved {
+                    entity
+                        .component_manager()
+                        .set_component_by_id(&entity, &type_id, component)?;
+                }
+
+                *entity.components_attached_mut() = attached;
+
+                let handle = locks.get::<T>(id);
+                handle.replace(entity);
+                handle.store_clean()
+            }));
+
+            res
+        })
+    }
+
+    /// Applies all of the operations in this transaction.
+    ///
+    /// # Errors
+    ///
+    /// If any operation fails, all changes made by this transaction are
+    /// rolled back, and a [`TransactionError`] is returned containing
+    /// the original error, along with any errors that occurred while
+    /// rolling back.
+    ///
+    /// Errors that occur while locking or loading the
+    /// [`Entities`](Entity) involved are returned as-is, since nothing has
+    /// been changed at that point.
+    pub fn commit(self) -> Result<()> {
+        let manager = self.manager;
+
+        // Lock every handle up front, in a consistent order, so that
+        // concurrent transactions can't deadlock each other.
+        let mut keys: Vec<(TypeId, Snowflake, LockFn)> = self
+            .ops
+            .iter()
+            .map(|op| (op.entity_type, op.id, op.lock))
+            .collect();
+        keys.sort_by_key(|k| (k.0, k.1));
+        keys.dedup_by_key(|k| (k.0, k.1));
+
+        let mut locks = HandleLocks(HashMap::new());
+        for (entity_type, id, lock) in keys {
+            locks.0.insert((entity_type, id), lock(manager, id)?);
+        }
+
+        let mut backends: Vec<Arc<dyn TransactionalBackend>> = Vec::new();
+        for op in self.ops.iter() {
+            for backend in (op.backends)(manager)? {
+                let ptr = Arc::as_ptr(&backend) as *const ();
+                if !backends.iter().any(|b| Arc::as_ptr(b) as *const () == ptr) {
+                    backends.push(backend);
+                }
+            }
+        }
+
+        for (i, backend) in backends.iter().enumerate() {
+            if let Err(e) = backend.begin() {
+                let rollback_errors = backends[..i]
+                    .iter()
+                    .filter_map(|b| b.rollback().err())
+                    .collect();
+
+                return Err(TransactionError::new(e, rollback_errors).into());
+            }
+        }
+
+        // Hold back events until we know whether the changes stick.
+        let events = manager.events().defer();
+
+        let mut undo: Vec<UndoFn> = Vec::new();
+        let mut committed = 0;
+        let mut res = Ok(());
+
+        for op in self.ops {
+            res = (op.apply)(&mut locks, &mut undo);
+            if res.is_err() {
+                break;
+            }
+        }
+
+        if res.is_ok() {
+            for backend in backends.iter() {
+                res = backend.commit();
+                if res.is_err() {
+                    break;
+                }
+
+                committed += 1;
+            }
+        }
+
+        if let Err(e) = res {
+            let mut rollback_errors = Vec::new();
+            for f in undo.into_iter().rev() {
+                if let Err(e) = f(&mut locks) {
+                    rollback_errors.push(e);
+                }
+            }
+
+            for backend in backends[committed..].iter() {
+                if let Err(e) = backend.rollback() {
+                    rollback_errors.push(e);
+                }
+            }
+
+            drop(locks);
+            events.release(false);
+            return Err(TransactionError::new(e, rollback_errors).into());
+        }
+
+        drop(locks);
+        events.release(true);
+        Ok(())
+    }
+}
+
+impl<'a> fmt::Debug for Transaction<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Transaction {{ {} operations }}", self.ops.len())
+    }
+}
+
+/// This failure type is returned when committing a [`Transaction`] fails.
+#[derive(Fail, Debug)]
+pub struct TransactionError {
+    error: Error,
+    rollback_errors: Vec<Error>,
+}
+
+impl TransactionError {
+    fn new(error: Error, rollback_errors: Vec<Error>) -> TransactionError {
+        TransactionError {
+            error,
+            rollback_errors,
+        }
+    }
+
+    /// Gets the error that caused the transaction to fail.
+    pub fn error(&self) -> &Error {
+        &self.error
+    }
+
+    /// Gets any errors that occurred while rolling back the transaction.
+    ///
+    /// If this is non-empty, some changes made by the transaction may not
+    /// have been undone.
+    pub fn rollback_errors(&self) -> &[Error] {
+        &self.rollback_errors
+    }
+}
+
+impl fmt::Display for TransactionError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "transaction failed: {}", self.error)?;
+        if !self.rollback_errors.is_empty() {
+            write!(f, "\nerrors while rolling back:")?;
+            for err in self.rollback_errors.iter() {
+                write!(f, "\n{}", err)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::card::Card;
+    use crate::ecs::{ComponentBackend, ComponentManager, EntityBackend};
+    use crate::local_storage::{LocalComponentStorage, LocalEntityStorage};
+
+    use std::sync::Mutex;
+
+    #[derive(Clone, Debug, PartialEq)]
+    struct Counter(u64);
+    impl Component<Card> for Counter {}
+
+    #[derive(Clone, Debug, PartialEq)]
+    struct Label(String);
+    impl Component<Card> for Label {}
+
+    /// A component backend that refuses to store empty labels.
+    struct FlakyStorage(LocalComponentStorage<Card, Label>);
+
+    impl ComponentBackend<Card, Label> for FlakyStorage {
+        fn load(&self, entity: &Card) -> Result<Option<Label>> {
+            self.0.load(entity)
+        }
+
+        fn store(&self, entity: &Card, component: Label) -> Result<()> {
+            if component.0.is_empty() {
+                Err(format_err!("empty label"))
+            } else {
+                self.0.store(entity, component)
+            }
+        }
+
+        fn exists(&self, entity: &Card) -> Result<bool> {
+            self.0.exists(entity)
+        }
+
+        fn delete(&self, entity: &Card) -> Result<()> {
+            self.0.delete(entity)
+        }
+    }
+
+    /// A transactional entity backend that records calls to its
+    /// `TransactionalBackend` methods.
+    struct RecordingStorage {
+        inner: LocalEntityStorage<Card>,
+        log: Arc<Recorder>,
+    }
+
+    #[derive(Default)]
+    struct Recorder(Mutex<Vec<&'static str>>);
+
+    impl TransactionalBackend for Recorder {
+        fn begin(&self) -> Result<()> {
+            self.0.lock().unwrap().push("begin");
+            Ok(())
+        }
+
+        fn commit(&self) -> Result<()> {
+            self.0.lock().unwrap().push("commit");
+            Ok(())
+        }
+
+        fn rollback(&self) -> Result<()> {
+            self.0.lock().unwrap().push("rollback");
+            Ok(())
+        }
+    }
+
+    impl EntityBackend<Card> for RecordingStorage {
+        fn load(&self, id: Snowflake, cm: Arc<ComponentManager<Card>>) -> Result<Option<Card>> {
+            self.inner.load(id, cm)
+        }
+
+        fn exists(&self, id: Snowflake) -> Result<bool> {
+            self.inner.exists(id)
+        }
+
+        fn store(&self, id: Snowflake, object: &Card) -> Result<()> {
+            self.inner.store(id, object)
+        }
+
+        fn delete(&self, id: Snowflake) -> Result<()> {
+            self.inner.delete(id)
+        }
+
+        fn keys(&self, page: u64, limit: u64) -> Result<Vec<Snowflake>> {
+            self.inner.keys(page, limit)
+        }
+
+        fn transactional(&self) -> Option<Arc<dyn TransactionalBackend>> {
+            Some(self.log.clone())
+        }
+    }
+
+    fn new_manager() -> EntityManager {
+        let mut manager = EntityManager::new();
+        manager
+            .register_entity(LocalEntityStorage::<Card>::new())
+            .unwrap();
+        manager
+            .register_component("Counter", LocalComponentStorage::<Card, Counter>::new())
+            .unwrap();
+        manager
+            .register_component("Label", FlakyStorage(LocalComponentStorage::new()))
+            .unwrap();
+        manager
+    }
+
+    fn counter(manager: &EntityManager, id: u64) -> Option<Counter> {
+        let handle = manager.load::<Card>(id.into()).unwrap();
+        handle.get().unwrap().get_component().unwrap()
+    }
+
+    #[test]
+    fn test_commit() {
+        let manager = new_manager();
+
+        let mut card: Card = manager.create(1u64.into()).unwrap();
+        card.set_component(Counter(5)).unwrap();
+        manager.store(card).unwrap();
+
+        let mut txn = manager.transaction();
+        txn.set_component::<Card, _>(1u64.into(), Counter(6))
+            .store(manager.create::<Card>(2u64.into()).unwrap())
+            .set_component::<Card, _>(2u64.into(), Counter(1))
+            .set_component::<Card, _>(2u64.into(), Label("new".to_owned()));
+        txn.commit().unwrap();
+
+        assert_eq!(counter(&manager, 1), Some(Counter(6)));
+        assert_eq!(counter(&manager, 2), Some(Counter(1)));
+
+        let mut txn = manager.transaction();
+        txn.delete::<Card>(1u64.into())
+            .delete_component::<Card, Label>(2u64.into());
+        txn.commit().unwrap();
+
+        assert!(!manager.exists::<Card>(1u64.into()).unwrap());
+
+        let handle = manager.load::<Card>(2u64.into()).unwrap();
+        assert!(!handle.get().unwrap().has_component::<Label>());
+    }
+
+    #[test]
+    fn test_update_component() {
+        let manager = new_manager();
+
+        let mut card: Card = manager.create(1u64.into()).unwrap();
+        card.set_component(Counter(5)).unwrap();
+        manager.store(card).unwrap();
+
+        let mut txn = manager.transaction();
+        txn.update_component::<Card, Counter, _>(1u64.into(), |prev| {
+            Ok(Counter(prev.unwrap().0 + 1))
+        })
+        .update_component::<Card, Counter, _>(1u64.into(), |prev| Ok(Counter(prev.unwrap().0 * 2)));
+        txn.commit().unwrap();
+        assert_eq!(counter(&manager, 1), Some(Counter(12)));
+
+        let mut txn = manager.transaction();
+        txn.update_component::<Card, Counter, _>(1u64.into(), |_prev| Ok(Counter(0)))
+            .update_component::<Card, Counter, _>(1u64.into(), |_prev| {
+                Err(format_err!("update failed"))
+            });
+        assert!(txn.commit().is_err());
+        assert_eq!(counter(&manager, 1), Some(Counter(12)));
+    }
+
+    #[test]
+    fn test_compensating_rollback() {
+        let manager = new_manager();
+
+        let mut card: Card = manager.create(1u64.into()).unwrap();
+        card.set_component(Counter(5)).unwrap();
+        card.set_component(Label("old".to_owned())).unwrap();
+        manager.store(card).unwrap();
+
+        let mut txn = manager.transaction();
+        txn.set_component::<Card, _>(1u64.into(), Counter(6))
+            .store(manager.create::<Card>(2u64.into()).unwrap())
+            .delete::<Card>(1u64.into())
+            .set_component::<Card, _>(2u64.into(), Label(String::new()));
+
+        let err = txn.commit().unwrap_err();
+        let err = err.downcast_ref::<TransactionError>().unwrap();
+        assert!(err.rollback_errors().is_empty());
+
+        // Everything should be back the way it was.
+        assert!(!manager.exists::<Card>(2u64.into()).unwrap());
+        assert_eq!(counter(&manager, 1), Some(Counter(5)));
+
+        let handle = manager.load::<Card>(1u64.into()).unwrap();
+        let label: Label = handle.get().unwrap().get_component().unwrap().unwrap();
+        assert_eq!(label.0, "old");
+    }
+
+    #[test]
+    fn test_missing_entity() {
+        let manager = new_manager();
+
+        let mut txn = manager.transaction();
+        txn.store(manager.create::<Card>(1u64.into()).unwrap())
+            .set_component::<Card, _>(2u64.into(), Counter(1));
+        assert!(txn.commit().is_err());
+
+        assert!(!manager.exists::<Card>(1u64.into()).unwrap());
+    }
+
+    #[test]
+    fn test_transactional_backend() {
+        let log = Arc::new(Recorder::default());
+        let mut manager = EntityManager::new();
+        manager
+            .register_entity(RecordingStorage {
+                inner: LocalEntityStorage::new(),
+                log: log.clone(),
+            })
+            .unwrap();
+        manager
+            .register_component("Counter", LocalComponentStorage::<Card, Counter>::new())
+            .unwrap();
+
+        let mut txn = manager.transaction();
+        txn.store(manager.create::<Card>(1u64.into()).unwrap())
+            .store(manager.create::<Card>(2u64.into()).unwrap());
+        txn.commit().unwrap();
+        assert_eq!(*log.0.lock().unwrap(), vec!["begin", "commit"]);
+
+        log.0.lock().unwrap().clear();
+
+        let mut txn = manager.transaction();
+        txn.set_component::<Card, _>(1u64.into(), Counter(1))
+            .set_component::<Card, _>(3u64.into(), Counter(1));
+        assert!(txn.commit().is_err());
+        assert_eq!(*log.0.lock().unwrap(), vec!["begin", "rollback"]);
+    }
+}
@@ -0,0 +1,504 @@
+//! Storage systems that persist data to files on disk.
+//!
+//! Each storage object keeps its data in a single JSON file within a given
+//! directory, and also keeps a copy of that data in memory. Every change is
+//! written out by writing a complete new copy of the file alongside the old
+//! one, then atomically renaming it into place, so a crash partway through a
+//! write will leave the previous version of the file intact.
+//!
+//! Since every write rewrites the entire file, these backends are best
+//! suited to small amounts of data, such as for prototyping or small
+//! single-server deployments. Only one process should use a given data
+//! file at a time.
+
+use std::collections::{BTreeMap, HashSet};
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Write};
+use std::marker::PhantomData;
+use std::ops::Bound;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock, RwLockReadGuard};
+
+use failure::format_err;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::ecs::{
+    store_attachment_changes, ChangeSet, Component, ComponentBackend, ComponentManager, Entity,
+    EntityBackend,
+};
+use crate::snowflake::Snowflake;
+use crate::util::Result;
+
+/// A JSON file containing a map of values keyed by [`Snowflake`], along
+/// with an in-memory copy of its contents.
+struct DataFile<V> {
+    path: PathBuf,
+    data: RwLock<BTreeMap<Snowflake, V>>,
+}
+
+impl<V> DataFile<V>
+where
+    V: Serialize + DeserializeOwned,
+{
+    /// Opens (or creates) the data file with the given name in `dir`.
+    fn open(dir: &Path, name: &str) -> Result<DataFile<V>> {
+        fs::create_dir_all(dir)?;
+
+        let path = dir.join(format!("{}.json", name));
+
+        // A leftover temporary file means we crashed before the rename
+        // in `write` could happen, so the main file is still intact.
+        let tmp_path = temp_path(&path);
+        if tmp_path.exists() {
+            fs::remove_file(&tmp_path)?;
+        }
+
+        let data = match File::open(&path) {
+            Ok(f) => serde_json::from_reader(BufReader::new(f))?,
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => BTreeMap::new(),
+            Err(e) => return Err(e.into()),
+        };
+
+        Ok(DataFile {
+            path,
+            data: RwLock::new(data),
+        })
+    }
+
+    fn read(&self) -> Result<RwLockReadGuard<'_, BTreeMap<Snowflake, V>>> {
+        self.data
+            .read()
+            .map_err(|_e| format_err!("storage lock poisoned"))
+    }
+
+    /// Sets (or removes, if `value` is `None`) the value for an ID, then
+    /// writes the updated map to disk.
+    ///
+    /// If writing to disk fails, the in-memory change is undone.
+    fn set(&self, id: Snowflake, value: Option<V>) -> Result<()> {
+        self.set_many(vec![(id, value)])
+    }
+
+    /// Sets (or removes) the values for several IDs, then writes the
+    /// updated map to disk once.
+    ///
+    /// If writing to disk fails, all of the in-memory changes are undone.
+    fn set_many(&self, values: Vec<(Snowflake, Option<V>)>) -> Result<()> {
+        let mut data = self
+            .data
+            .write()
+            .map_err(|_e| format_err!("storage lock poisoned"))?;
+
+        let mut changed = false;
+        let mut prevs: Vec<(Snowflake, Option<V>)> = Vec::with_capacity(values.len());
+        for (id, value) in values {
+            let prev = match value {
+                Some(value) => {
+                    changed = true;
+                    data.insert(id, value)
+                }
+                None => {
+                    let prev = data.remove(&id);
+                    changed |= prev.is_some();
+                    prev
+                }
+            };
+
+            prevs.push((id, prev));
+        }
+
+        if !changed {
+            return Ok(());
+        }
+
+        if let Err(e) = self.write(&data) {
+            for (id, prev) in prevs.into_iter().rev() {
+                match prev {
+                    Some(prev) => data.insert(id, prev),
+                    None => data.remove(&id),
+                };
+            }
+
+            return Err(e);
+        }
+
+        Ok(())
+    }
+
+    fn write(&self, data: &BTreeMap<Snowflake, V>) -> Result<()> {
+        let tmp_path = temp_path(&self.path);
+
+        {
+            let mut writer = BufWriter::new(File::create(&tmp_path)?);
+            serde_json::to_writer(&mut writer, data)?;
+            writer.flush()?;
+            writer.get_ref().sync_all()?;
+        }
+
+        fs::rename(&tmp_path, &self.path)?;
+        sync_parent_dir(&self.path)
+    }
+}
+
+pub(crate) fn temp_path(path: &Path) -> PathBuf {
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    PathBuf::from(tmp)
+}
+
+/// Makes sure a rename within a directory has been persisted to disk.
+#[cfg(unix)]
+pub(crate) fn sync_parent_dir(path: &Path) -> Result<()> {
+    if let Some(dir) = path.parent() {
+        File::open(dir)?.sync_all()?;
+    }
+
+    Ok(())
+}
+
+#[cfg(not(unix))]
+pub(crate) fn sync_parent_dir(_path: &Path) -> Result<()> {
+    Ok(())
+}
+
+/// The data stored for each [`Entity`] by [`FileEntityStorage`].
+#[derive(Serialize, Deserialize)]
+struct EntityRecord {
+    components: Vec<String>,
+}
+
+/// File-based [`Entity`] storage backend.
+///
+/// Entities are stored as a list of the names of their attached
+/// [`Components`](Component), as given to
+/// [`ComponentManager::register_component`]. The component data itself
+/// needs to be stored separately (such as with [`FileComponentStorage`]).
+pub struct FileEntityStorage<T: Entity + 'static> {
+    file: DataFile<EntityRecord>,
+    pd: PhantomData<T>,
+}
+
+impl<T> FileEntityStorage<T>
+where
+    T: Entity + 'static,
+{
+    /// Opens the file-based storage with the given name within a
+    /// directory, creating it if it doesn't exist.
+    pub fn open<P: AsRef<Path>>(dir: P, name: &str) -> Result<FileEntityStorage<T>> {
+        Ok(FileEntityStorage {
+            file: DataFile::open(dir.as_ref(), name)?,
+            pd: PhantomData,
+        })
+    }
+
+    fn to_record(obj: &T) -> EntityRecord {
+        let cm = obj.component_manager();
+        let mut components: Vec<String> = obj
+            .components_attached()
+            .iter()
+            .filter_map(|type_id| cm.component_name(type_id))
+            .map(|name| name.to_owned())
+            .collect();
+        components.sort_unstable();
+
+        EntityRecord { components }
+    }
+
+    fn from_record(id: Snowflake, record: &EntityRecord, cm: Arc<ComponentManager<T>>) -> T {
+        let components: HashSet<_> = record
+            .components
+            .iter()
+            .filter_map(|name| cm.component_type_id(name).copied())
+            .collect();
+
+        T::new(id, cm, components)
+    }
+}
+
+impl<T> EntityBackend<T> for FileEntityStorage<T>
+where
+    T: Entity + 'static,
+{
+    fn exists(&self, id: Snowflake) -> Result<bool> {
+        Ok(self.file.read()?.contains_key(&id))
+    }
+
+    fn load(&self, id: Snowflake, cm: Arc<ComponentManager<T>>) -> Result<Option<T>> {
+        let data = self.file.read()?;
+        Ok(data
+            .get(&id)
+            .map(|record| Self::from_record(id, record, cm)))
+    }
+
+    fn store(&self, id: Snowflake, obj: &T) -> Result<()> {
+        self.file.set(id, Some(Self::to_record(obj)))
+    }
+
+    fn store_changes(&self, id: Snowflake, obj: &T, changes: &ChangeSet) -> Result<()> {
+        // Records only list attached components.
+        store_attachment_changes(self, id, obj, changes)
+    }
+
+    fn delete(&self, id: Snowflake) -> Result<()> {
+        self.file.set(id, None)
+    }
+
+    fn keys(&self, page: u64, limit: u64) -> Result<Vec<Snowflake>> {
+        let data = self.file.read()?;
+        Ok(data
+            .keys()
+            .skip((page * limit) as usize)
+            .take(limit as usize)
+            .copied()
+            .collect())
+    }
+
+    fn keys_after(&self, after: Option<Snowflake>, limit: u64) -> Result<Vec<Snowflake>> {
+        let start = match after {
+            Some(id) => Bound::Excluded(id),
+            None => Bound::Unbounded,
+        };
+
+        let data = self.file.read()?;
+        Ok(data
+            .range((start, Bound::Unbounded))
+            .take(limit as usize)
+            .map(|(id, _)| *id)
+            .collect())
+    }
+
+    fn load_many(&self, ids: &[Snowflake], cm: Arc<ComponentManager<T>>) -> Result<Vec<Option<T>>> {
+        let data = self.file.read()?;
+        Ok(ids
+            .iter()
+            .map(|id| {
+                data.get(id)
+                    .map(|record| Self::from_record(*id, record, cm.clone()))
+            })
+            .collect())
+    }
+
+    fn exists_many(&self, ids: &[Snowflake]) -> Result<Vec<bool>> {
+        let data = self.file.read()?;
+        Ok(ids.iter().map(|id| data.contains_key(id)).collect())
+    }
+
+    fn store_many(&self, objects: &[&T]) -> Result<()> {
+        self.file.set_many(
+            objects
+                .iter()
+                .map(|obj| (obj.id(), Some(Self::to_record(obj))))
+                .collect(),
+        )
+    }
+
+    fn delete_many(&self, ids: &[Snowflake]) -> Result<()> {
+        self.file
+            .set_many(ids.iter().map(|id| (*id, None)).collect())
+    }
+}
+
+/// File-based [`Component`] storage backend.
+///
+/// This can store any [`Component`] type that can be serialized and
+/// deserialized using `serde`.
+pub struct FileComponentStorage<T, U>
+where
+    T: Entity + 'static,
+    U: Component<T> + Serialize + DeserializeOwned + 'static,
+{
+    file: DataFile<Value>,
+    pd: PhantomData<(T, fn() -> U)>,
+}
+
+impl<T, U> FileComponentStorage<T, U>
+where
+    T: Entity + 'static,
+    U: Component<T> + Serialize + DeserializeOwned + 'static,
+{
+    /// Opens the file-based storage with the given name within a
+    /// directory, creating it if it doesn't exist.
+    ///
+    /// Using the name the [`Component`] type is registered under is
+    /// a good choice here.
+    pub fn open<P: AsRef<Path>>(dir: P, name: &str) -> Result<FileComponentStorage<T, U>> {
+        Ok(FileComponentStorage {
+            file: DataFile::open(dir.as_ref(), name)?,
+            pd: PhantomData,
+        })
+    }
+}
+
+impl<T, U> ComponentBackend<T, U> for FileComponentStorage<T, U>
+where
+    T: Entity + 'static,
+    U: Component<T> + Serialize + DeserializeOwned + 'static,
+{
+    fn load(&self, entity: &T) -> Result<Option<U>> {
+        let data = self.file.read()?;
+        match data.get(&entity.id()) {
+            Some(value) => Ok(Some(U::deserialize(value)?)),
+            None => Ok(None),
+        }
+    }
+
+    fn store(&self, entity: &T, component: U) -> Result<()> {
+        self.file
+            .set(entity.id(), Some(serde_json::to_value(component)?))
+    }
+
+    fn exists(&self, entity: &T) -> Result<bool> {
+        Ok(self.file.read()?.contains_key(&entity.id()))
+    }
+
+    fn delete(&self, entity: &T) -> Result<()> {
+        self.file.set(entity.id(), None)
+    }
+
+    fn keys(&self) -> Result<Option<Vec<Snowflake>>> {
+        Ok(Some(self.file.read()?.keys().copied().collect()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::card::Card;
+    use crate::ecs::EntityManager;
+    use crate::snowflake::SnowflakeGenerator;
+
+    #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+    struct TestComponent {
+        name: String,
+        value: u64,
+    }
+
+    impl Component<Card> for TestComponent {}
+
+    fn new_manager(dir: &Path) -> EntityManager {
+        let mut manager = EntityManager::new();
+        manager
+            .register_entity(FileEntityStorage::<Card>::open(dir, "cards").unwrap())
+            .unwrap();
+        manager
+            .register_component(
+                "TestComponent",
+                FileComponentStorage::<Card, TestComponent>::open(dir, "TestComponent").unwrap(),
+            )
+            .unwrap();
+        manager
+    }
+
+    #[test]
+    fn test_persistence() {
+        let dir = tempfile::tempdir().unwrap();
+        let snowflake_gen = SnowflakeGenerator::new(0, 0);
+        let id = snowflake_gen.generate();
+        let component = TestComponent {
+            name: "foo".to_owned(),
+            value: 50,
+        };
+
+        {
+            let manager = new_manager(dir.path());
+            let mut card: Card = manager.create(id).unwrap();
+            card.set_component(component.clone()).unwrap();
+            manager.store(card).unwrap();
+        }
+
+        // Open everything again, and make sure our data is still there.
+        let manager = new_manager(dir.path());
+        assert_eq!(manager.keys::<Card>(0, 20).unwrap(), vec![id]);
+
+        let handle = manager.load::<Card>(id).unwrap();
+        let card = handle.get().unwrap();
+        assert!(card.has_component::<TestComponent>());
+
+        let loaded: TestComponent = card.get_component().unwrap().unwrap();
+        assert_eq!(loaded, component);
+    }
+
+    #[test]
+    fn test_delete() {
+        let dir = tempfile::tempdir().unwrap();
+        let snowflake_gen = SnowflakeGenerator::new(0, 0);
+        let id = snowflake_gen.generate();
+
+        {
+            let manager = new_manager(dir.path());
+            let mut card: Card = manager.create(id).unwrap();
+            card.set_component(TestComponent {
+                name: "foo".to_owned(),
+                value: 50,
+            })
+            .unwrap();
+            manager.store(card).unwrap();
+            manager.delete::<Card>(id).unwrap();
+        }
+
+        let storage: FileComponentStorage<Card, TestComponent> =
+            FileComponentStorage::open(dir.path(), "TestComponent").unwrap();
+        assert_eq!(storage.keys().unwrap(), Some(vec![]));
+
+        let manager = new_manager(dir.path());
+        assert!(!manager.exists::<Card>(id).unwrap());
+    }
+
+    #[test]
+    fn test_interrupted_write() {
+        let dir = tempfile::tempdir().unwrap();
+        let snowflake_gen = SnowflakeGenerator::new(0, 0);
+        let id = snowflake_gen.generate();
+
+        {
+            let manager = new_manager(dir.path());
+            let card: Card = manager.create(id).unwrap();
+            manager.store(card).unwrap();
+        }
+
+        // Simulate a crash in the middle of writing out a new version of
+        // the entity data file.
+        fs::write(dir.path().join("cards.json.tmp"), b"{\"12").unwrap();
+
+        let manager = new_manager(dir.path());
+        assert!(manager.exists::<Card>(id).unwrap());
+        assert!(!dir.path().join("cards.json.tmp").exists());
+    }
+
+    #[test]
+    fn test_batches() {
+        let dir = tempfile::tempdir().unwrap();
+        let snowflake_gen = SnowflakeGenerator::new(0, 0);
+        let ids: Vec<Snowflake> = (0..3).map(|_| snowflake_gen.generate()).collect();
+        let missing = snowflake_gen.generate();
+
+        {
+            let manager = new_manager(dir.path());
+            let cards: Vec<Card> = ids.iter().map(|id| manager.create(*id).unwrap()).collect();
+            manager.store_many(cards).unwrap();
+        }
+
+        let storage = FileEntityStorage::<Card>::open(dir.path(), "cards").unwrap();
+        let mut query = ids.clone();
+        query.push(missing);
+        assert_eq!(
+            storage.exists_many(&query).unwrap(),
+            vec![true, true, true, false]
+        );
+
+        let cm = Arc::new(ComponentManager::new());
+        let loaded = storage.load_many(&query, cm).unwrap();
+        assert_eq!(loaded.len(), 4);
+        for (id, card) in ids.iter().zip(loaded.iter()) {
+            assert_eq!(card.as_ref().unwrap().id(), *id);
+        }
+        assert!(loaded[3].is_none());
+
+        storage.delete_many(&ids[..2]).unwrap();
+        let storage = FileEntityStorage::<Card>::open(dir.path(), "cards").unwrap();
+        assert_eq!(storage.keys(0, 10).unwrap(), vec![ids[2]]);
+    }
+}
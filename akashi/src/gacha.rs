@@ -0,0 +1,44 @@
+//! Gacha mechanics: banners, pulls, and pity.
+//!
+//! A [`Banner`] is made up of several [`RarityTiers`](RarityTier), each
+//! containing a pool of [`CardType`](crate::components::CardType) IDs.
+//! Pulling from a banner picks a tier by weight, then picks a card type
+//! from within that tier.
+//!
+//! Banners can also be configured with soft and hard pity (tracked per
+//! player using the [`PityState`] component), guaranteed minimum rarities
+//! for multi-pulls, and featured card types with increased rates.
+//!
+//! [`Banner::pull`] takes care of the whole process of pulling for a
+//! player: debiting the cost of the pull from their
+//! [`Resource`](crate::components::Resource), creating new
+//! [`Cards`](crate::Card) for each result, and adding them to the
+//! player's [`Inventory`](crate::components::Inventory).
+//! Random numbers for each pull come from an injected
+//! [`RngSource`](crate::rng::RngSource), and every [`Pull`] carries a
+//! record of the random numbers it used, so that it can be replayed with
+//! [`Banner::replay`].
+//!
+//! For disclosure purposes, [`RateTable`] computes the exact drop rate of
+//! each card type on a banner (including the effects of pity), and
+//! [`verify`] checks those rates against a large number of simulated
+//! pulls.
+
+pub mod banner;
+pub mod pity;
+pub mod rates;
+pub mod verify;
+
+#[doc(inline)]
+pub use banner::{Banner, Draw, PityConfig, Pull, RarityTier};
+
+#[doc(inline)]
+pub use pity::PityState;
+
+#[doc(inline)]
+pub use rates::{CardRate, PullMode, RarityRate, RateTable};
+
+#[doc(inline)]
+pub use verify::{verify, VerificationReport};
+
+pub use banner::InvalidBanner;
@@ -0,0 +1,948 @@
+//! Banners: the pools of cards that players pull from.
+
+use failure::{format_err, Fail};
+use rand::Rng;
+
+use super::pity::PityState;
+use crate::card::Card;
+use crate::components::{AttachedCardType, Inventory, Resource};
+use crate::ecs::{Entity, EntityManager, TypeNotFoundError};
+use crate::player::Player;
+use crate::rng::{GameRng, RngRecord, RngSource};
+use crate::snowflake::{Snowflake, SnowflakeGenerator};
+use crate::util::Result;
+
+/// A tier of rarity within a [`Banner`], containing a pool of
+/// [`CardType`](crate::components::CardType) IDs.
+#[derive(Clone, Debug)]
+pub struct RarityTier {
+    rarity: u32,
+    weight: u64,
+    pool: Vec<Snowflake>,
+    featured: Vec<Snowflake>,
+    featured_rate: f64,
+}
+
+impl RarityTier {
+    /// Creates a new `RarityTier`.
+    ///
+    /// Higher `rarity` values are rarer. Each pull picks a tier with
+    /// probability proportional to its `weight`, then picks one of the
+    /// card types in its `pool` uniformly at random.
+    pub fn new(rarity: u32, weight: u64, pool: Vec<Snowflake>) -> RarityTier {
+        RarityTier {
+            rarity,
+            weight,
+            pool,
+            featured: Vec::new(),
+            featured_rate: 0.0,
+        }
+    }
+
+    /// Adds featured (rate-up) card types to this tier.
+    ///
+    /// Whenever this tier is picked, one of the featured card types is
+    /// given with probability `rate` (between 0 and 1); otherwise a card
+    /// type is picked from the regular pool as usual.
+    pub fn with_featured(mut self, featured: Vec<Snowflake>, rate: f64) -> RarityTier {
+        self.featured = featured;
+        self.featured_rate = rate;
+        self
+    }
+
+    /// Gets this tier's rarity.
+    pub fn rarity(&self) -> u32 {
+        self.rarity
+    }
+
+    /// Gets this tier's base weight.
+    pub fn weight(&self) -> u64 {
+        self.weight
+    }
+
+    /// Gets the IDs of the regular card types in this tier.
+    pub fn pool(&self) -> &[Snowflake] {
+        &self.pool
+    }
+
+    /// Gets the IDs of the featured card types in this tier.
+    pub fn featured(&self) -> &[Snowflake] {
+        &self.featured
+    }
+
+    /// Gets the probability of getting a featured card type when this
+    /// tier is picked.
+    pub fn featured_rate(&self) -> f64 {
+        if self.pool.is_empty() {
+            1.0
+        } else if self.featured.is_empty() {
+            0.0
+        } else {
+            self.featured_rate
+        }
+    }
+}
+
+/// Soft and hard pity settings for a [`Banner`].
+///
+/// Pity applies to the banner's top rarity tier, and is based on how many
+/// pulls a player has made since last getting a card of that rarity
+/// (as tracked by [`PityState`]).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PityConfig {
+    soft_start: u32,
+    soft_step: u64,
+    hard: u32,
+}
+
+impl PityConfig {
+    /// Creates a new `PityConfig`.
+    ///
+    /// Starting with the `soft_start`-th pull since the last top-rarity
+    /// card, the top tier's weight is increased by `soft_step` for each
+    /// pull (so the `soft_start`-th pull gets `soft_step` added, the next
+    /// gets twice that, and so on).
+    ///
+    /// The `hard`-th pull is guaranteed to be a top-rarity card.
+    ///
+    /// Either kind of pity can be disabled by passing 0 for `soft_start`
+    /// or `hard`.
+    pub fn new(soft_start: u32, soft_step: u64, hard: u32) -> PityConfig {
+        PityConfig {
+            soft_start,
+            soft_step,
+            hard,
+        }
+    }
+
+    /// Gets the pull number at which soft pity starts.
+    pub fn soft_start(&self) -> u32 {
+        self.soft_start
+    }
+
+    /// Gets how much the top tier's weight increases per pull once soft
+    /// pity starts.
+    pub fn soft_step(&self) -> u64 {
+        self.soft_step
+    }
+
+    /// Gets the pull number at which a top-rarity card is guaranteed.
+    pub fn hard(&self) -> u32 {
+        self.hard
+    }
+}
+
+/// The outcome of drawing a single card from a [`Banner`].
+#[derive(Clone, Debug, PartialEq)]
+pub struct Draw {
+    rarity: u32,
+    card_type: Snowflake,
+    featured: bool,
+    pulls_since_top: u32,
+    min_rarity: Option<u32>,
+}
+
+impl Draw {
+    /// Gets the rarity of the drawn card.
+    pub fn rarity(&self) -> u32 {
+        self.rarity
+    }
+
+    /// Gets the ID of the drawn [`CardType`](crate::components::CardType).
+    pub fn card_type(&self) -> Snowflake {
+        self.card_type
+    }
+
+    /// Checks whether the drawn card was one of its tier's featured card
+    /// types.
+    pub fn featured(&self) -> bool {
+        self.featured
+    }
+
+    /// Gets the number of pulls since the last top-rarity card at the
+    /// time of this draw.
+    pub fn pulls_since_top(&self) -> u32 {
+        self.pulls_since_top
+    }
+
+    /// Gets the minimum rarity that was guaranteed for this draw, if any.
+    pub fn min_rarity(&self) -> Option<u32> {
+        self.min_rarity
+    }
+}
+
+/// A new [`Card`] given to a player by [`Banner::pull`].
+///
+/// Each pull includes a record of the random numbers used to make it,
+/// which can be passed to [`Banner::replay`] to reproduce it.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Pull {
+    card_id: Snowflake,
+    draw: Draw,
+    rng: RngRecord,
+}
+
+impl Pull {
+    /// Gets the ID of the newly-created [`Card`].
+    pub fn card_id(&self) -> Snowflake {
+        self.card_id
+    }
+
+    /// Gets the outcome of the draw that produced the [`Card`].
+    pub fn draw(&self) -> &Draw {
+        &self.draw
+    }
+
+    /// Gets the record of the random numbers used for the draw.
+    pub fn rng(&self) -> &RngRecord {
+        &self.rng
+    }
+}
+
+/// A gacha banner, made up of several [`RarityTiers`](RarityTier).
+///
+/// Banners have a cost per pull, expressed as an amount to debit from
+/// a [`Player`]'s [`Resource`] component, and can optionally offer a
+/// discounted multi-pull with a guaranteed minimum rarity.
+///
+/// # Example
+///
+/// ```
+/// use akashi::gacha::{Banner, PityConfig, PityState, RarityTier};
+/// use rand::SeedableRng;
+/// use rand::rngs::StdRng;
+///
+/// let banner = Banner::new(
+///     "standard",
+///     vec![
+///         RarityTier::new(3, 940, vec![1u64.into(), 2u64.into()]),
+///         RarityTier::new(4, 50, vec![3u64.into()]),
+///         RarityTier::new(5, 10, vec![4u64.into()]).with_featured(vec![5u64.into()], 0.5),
+///     ],
+///     160,
+/// )
+/// .unwrap()
+/// .with_pity(PityConfig::new(75, 60, 90))
+/// .with_multi_pull(10, 1600, Some(4))
+/// .unwrap();
+///
+/// let mut rng = StdRng::seed_from_u64(1);
+/// let mut pity = PityState::new();
+/// let draws = banner.draw_many(10, &mut pity, &mut rng);
+///
+/// // Ten-pulls always include at least one card of rarity 4 or higher.
+/// assert_eq!(draws.len(), 10);
+/// assert!(draws.iter().any(|draw| draw.rarity() >= 4));
+/// assert_eq!(banner.cost(10), 1600);
+/// ```
+#[derive(Clone, Debug)]
+pub struct Banner {
+    name: String,
+    tiers: Vec<RarityTier>,
+    cost: i64,
+    pity: Option<PityConfig>,
+    multi_count: u32,
+    multi_cost: i64,
+    multi_guarantee: Option<u32>,
+}
+
+impl Banner {
+    /// Creates a new `Banner`, with the given cost per pull.
+    ///
+    /// By default, banners have no pity, and multi-pulls of 10 cost 10
+    /// times as much as a single pull with no guaranteed rarity.
+    ///
+    /// # Errors
+    ///
+    /// Returns an [`InvalidBanner`] error if there are no tiers, if any
+    /// two tiers have the same rarity, or if any tier has a zero weight,
+    /// no card types, or a featured rate outside of `0.0..=1.0`.
+    pub fn new(name: &str, mut tiers: Vec<RarityTier>, cost: i64) -> Result<Banner> {
+        if tiers.is_empty() {
+            return Err(InvalidBanner("banner has no tiers".to_owned()).into());
+        }
+
+        tiers.sort_by_key(|tier| tier.rarity);
+
+        for (i, tier) in tiers.iter().enumerate() {
+            if i > 0 && tiers[i - 1].rarity == tier.rarity {
+                return Err(
+                    InvalidBanner(format!("multiple tiers with rarity {}", tier.rarity)).into(),
+                );
+            }
+
+            if tier.weight == 0 {
+                return Err(InvalidBanner(format!("tier {} has zero weight", tier.rarity)).into());
+            }
+
+            if tier.pool.is_empty() && tier.featured.is_empty() {
+                return Err(
+                    InvalidBanner(format!("tier {} has no card types", tier.rarity)).into(),
+                );
+            }
+
+            if !(0.0..=1.0).contains(&tier.featured_rate) {
+                return Err(InvalidBanner(format!(
+                    "tier {} has invalid featured rate {}",
+                    tier.rarity, tier.featured_rate
+                ))
+                .into());
+            }
+        }
+
+        Ok(Banner {
+            name: name.to_owned(),
+            tiers,
+            cost,
+            pity: None,
+            multi_count: 10,
+            multi_cost: cost.saturating_mul(10),
+            multi_guarantee: None,
+        })
+    }
+
+    /// Sets this banner's pity configuration.
+    pub fn with_pity(mut self, pity: PityConfig) -> Banner {
+        self.pity = Some(pity);
+        self
+    }
+
+    /// Sets the number of pulls in a multi-pull, the cost of each
+    /// multi-pull, and the minimum rarity guaranteed in each multi-pull.
+    ///
+    /// # Errors
+    ///
+    /// Returns an [`InvalidBanner`] error if `count` is zero, or if no tier
+    /// has a rarity of at least `guaranteed_rarity`.
+    pub fn with_multi_pull(
+        mut self,
+        count: u32,
+        cost: i64,
+        guaranteed_rarity: Option<u32>,
+    ) -> Result<Banner> {
+        if count == 0 {
+            return Err(InvalidBanner("multi-pull count must be nonzero".to_owned()).into());
+        }
+
+        if let Some(rarity) = guaranteed_rarity {
+            if rarity > self.top_rarity() {
+                return Err(
+                    InvalidBanner(format!("no tier with rarity of at least {}", rarity)).into(),
+                );
+            }
+        }
+
+        self.multi_count = count;
+        self.multi_cost = cost;
+        self.multi_guarantee = guaranteed_rarity;
+        Ok(self)
+    }
+
+    /// Gets this banner's name, which is also used as its pity key.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Gets this banner's tiers, ordered from most to least common.
+    pub fn tiers(&self) -> &[RarityTier] {
+        &self.tiers
+    }
+
+    /// Gets this banner's pity configuration, if any.
+    pub fn pity(&self) -> Option<&PityConfig> {
+        self.pity.as_ref()
+    }
+
+    /// Gets the number of pulls in a multi-pull.
+    pub fn multi_count(&self) -> u32 {
+        self.multi_count
+    }
+
+    /// Gets the minimum rarity guaranteed in each multi-pull, if any.
+    pub fn multi_guarantee(&self) -> Option<u32> {
+        self.multi_guarantee
+    }
+
+    /// Gets the rarity of this banner's rarest tier.
+    pub fn top_rarity(&self) -> u32 {
+        self.tiers[self.tiers.len() - 1].rarity
+    }
+
+    /// Gets the total cost of making `count` pulls.
+    ///
+    /// Each complete group of [`multi_count`](Banner::multi_count) pulls
+    /// is charged at the multi-pull cost, and the rest at the single-pull
+    /// cost.
+    pub fn cost(&self, count: u32) -> i64 {
+        let multis = (count / self.multi_count) as i64;
+        let singles = (count % self.multi_count) as i64;
+
+        multis
+            .saturating_mul(self.multi_cost)
+            .saturating_add(singles.saturating_mul(self.cost))
+    }
+
+    /// Gets the weight of each tier for a pull, after applying pity.
+    ///
+    /// `pulls_since_top` is the number of pulls made since the last
+    /// top-rarity card, and tiers with a rarity lower than `min_rarity`
+    /// are given a weight of zero. A `min_rarity` above the top tier's
+    /// rarity is treated as the top tier's rarity, so that there's always
+    /// something to draw. The returned weights are in the same order as
+    /// [`tiers`](Banner::tiers).
+    pub fn weights(&self, pulls_since_top: u32, min_rarity: Option<u32>) -> Vec<u64> {
+        let top = self.tiers.len() - 1;
+        let pull_number = pulls_since_top.saturating_add(1);
+        let min_rarity = min_rarity.map(|min| min.min(self.top_rarity()));
+
+        if let Some(pity) = &self.pity {
+            if pity.hard > 0 && pull_number >= pity.hard {
+                let mut weights = vec![0; self.tiers.len()];
+                weights[top] = 1;
+                return weights;
+            }
+        }
+
+        let mut weights: Vec<u64> = self
+            .tiers
+            .iter()
+            .map(|tier| match min_rarity {
+                Some(min) if tier.rarity < min => 0,
+                _ => tier.weight,
+            })
+            .collect();
+
+        if let Some(pity) = &self.pity {
+            if pity.soft_start > 0 && pull_number >= pity.soft_start {
+                let steps = (pull_number - pity.soft_start + 1) as u64;
+                weights[top] = weights[top].saturating_add(pity.soft_step.saturating_mul(steps));
+            }
+        }
+
+        weights
+    }
+
+    /// Draws a single card from this banner.
+    ///
+    /// See [`weights`](Banner::weights) for the meaning of the
+    /// `pulls_since_top` and `min_rarity` parameters.
+    pub fn draw<R: Rng + ?Sized>(
+        &self,
+        pulls_since_top: u32,
+        min_rarity: Option<u32>,
+        rng: &mut R,
+    ) -> Draw {
+        let weights = self.weights(pulls_since_top, min_rarity);
+        let total: u64 = weights.iter().fold(0, |acc, w| acc.saturating_add(*w));
+
+        let mut roll = rng.gen_range(0, total);
+        let mut picked = weights.len() - 1;
+        for (i, weight) in weights.iter().enumerate() {
+            if roll < *weight {
+                picked = i;
+                break;
+            }
+
+            roll -= *weight;
+        }
+
+        let tier = &self.tiers[picked];
+        let featured =
+            !tier.featured.is_empty() && (tier.pool.is_empty() || rng.gen_bool(tier.featured_rate));
+
+        let choices = if featured { &tier.featured } else { &tier.pool };
+        let card_type = choices[rng.gen_range(0, choices.len())];
+
+        Draw {
+            rarity: tier.rarity,
+            card_type,
+            featured,
+            pulls_since_top,
+            min_rarity,
+        }
+    }
+
+    /// Draws `count` cards from this banner, updating the given
+    /// [`PityState`] as it goes.
+    ///
+    /// The multi-pull rarity guarantee is applied to each complete group
+    /// of [`multi_count`](Banner::multi_count) draws.
+    pub fn draw_many<R: Rng + ?Sized>(
+        &self,
+        count: u32,
+        pity: &mut PityState,
+        rng: &mut R,
+    ) -> Vec<Draw> {
+        let mut draws = Vec::with_capacity(count as usize);
+        self.draw_each(count, pity, rng, |draw, _rng| draws.push(draw));
+        draws
+    }
+
+    /// Does the work for [`draw_many`](Banner::draw_many), calling
+    /// `on_draw` after each draw.
+    fn draw_each<R, F>(&self, count: u32, pity: &mut PityState, rng: &mut R, mut on_draw: F)
+    where
+        R: Rng + ?Sized,
+        F: FnMut(Draw, &R),
+    {
+        let top = self.top_rarity();
+        let full_groups = (count / self.multi_count) * self.multi_count;
+
+        let mut pulls_since_top = pity.get(&self.name);
+        let mut group_satisfied = false;
+
+        for i in 0..count {
+            let position = i % self.multi_count;
+            if position == 0 {
+                group_satisfied = false;
+            }
+
+            let min_rarity = match self.multi_guarantee {
+                Some(rarity)
+                    if i < full_groups && position == self.multi_count - 1 && !group_satisfied =>
+                {
+                    Some(rarity)
+                }
+                _ => None,
+            };
+
+            let draw = self.draw(pulls_since_top, min_rarity, rng);
+
+            if let Some(rarity) = self.multi_guarantee {
+                if draw.rarity >= rarity {
+                    group_satisfied = true;
+                }
+            }
+
+            if draw.rarity == top {
+                pulls_since_top = 0;
+            } else {
+                pulls_since_top = pulls_since_top.saturating_add(1);
+            }
+
+            on_draw(draw, rng);
+        }
+
+        pity.set(&self.name, pulls_since_top);
+    }
+
+    /// Reproduces the draw made for a [`Pull`], using its recorded
+    /// random numbers.
+    ///
+    /// If the banner hasn't been changed since the pull was made, the
+    /// returned [`Draw`] will be identical to [`Pull::draw`].
+    pub fn replay(&self, pull: &Pull) -> Draw {
+        let mut rng = GameRng::replay(&pull.rng);
+        self.draw(pull.draw.pulls_since_top, pull.draw.min_rarity, &mut rng)
+    }
+
+    /// Makes `count` pulls from this banner for a [`Player`].
+    ///
+    /// This debits the cost of the pulls from the player's [`Resource`],
+    /// creates a new [`Card`] with an [`AttachedCardType`] for each
+    /// draw, adds the new cards to the player's [`Inventory`], and updates
+    /// the player's [`PityState`]. All of these changes are made in a
+    /// single [`Transaction`](crate::ecs::Transaction).
+    ///
+    /// Random numbers for the pulls come from a new [`GameRng`] created by
+    /// `rng_source` for the player, with the operation name
+    /// `gacha:<banner name>`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the player doesn't exist or doesn't have a
+    /// [`Resource`] attached, or an
+    /// [`InvalidSubtraction`](crate::components::InvalidSubtraction) error
+    /// if they can't afford the pulls. Errors from storage backends are
+    /// passed through as usual; if the pull fails partway through, no
+    /// changes are made.
+    pub fn pull(
+        &self,
+        manager: &EntityManager,
+        player_id: Snowflake,
+        count: u32,
+        snowflake_gen: &SnowflakeGenerator,
+        rng_source: &dyn RngSource,
+    ) -> Result<Vec<Pull>> {
+        let cost = self.cost(count);
+
+        let expected_pity: PityState = {
+            let handle = manager.load::<Player>(player_id)?;
+            let player = handle
+                .get()
+                .ok_or_else(|| format_err!("player not found: {}", player_id))?;
+
+            let mut rsc: Resource = player
+                .get_component()?
+                .ok_or_else(|| format_err!("player {} has no Resource", player_id))?;
+            rsc.checked_sub(cost.into())?;
+
+            player.get_component()?.unwrap_or_default()
+        };
+
+        let mut rng = rng_source.rng(player_id, &format!("gacha:{}", self.name));
+        let mut pity = expected_pity.clone();
+        let mut draws = Vec::with_capacity(count as usize);
+        let mut start = rng.position();
+
+        self.draw_each(count, &mut pity, &mut rng, |draw, rng| {
+            draws.push((draw, rng.record_since(start)));
+            start = rng.position();
+        });
+
+        let mut txn = manager.transaction();
+        txn.update_component::<Player, Resource, _>(player_id, move |rsc| {
+            let mut rsc = rsc
+                .cloned()
+                .ok_or_else(|| format_err!("player {} has no Resource", player_id))?;
+            rsc.checked_sub(cost.into())?;
+            Ok(rsc)
+        });
+
+        // The draws depend on the player's pity counters, so make sure
+        // nothing else changed them in the meantime.
+        txn.update_component::<Player, PityState, _>(player_id, move |current| {
+            if current.cloned().unwrap_or_default() != expected_pity {
+                return Err(format_err!("pity state changed during pull"));
+            }
+
+            Ok(pity)
+        });
+
+        let mut pulls = Vec::with_capacity(draws.len());
+        for (draw, rng) in draws {
+            let card: Card = manager
+                .create(snowflake_gen.generate())
+                .ok_or_else(|| TypeNotFoundError::new(String::from("Card")))?;
+            let card_id = card.id();
+
+            txn.store(card)
+                .set_component::<Card, _>(card_id, AttachedCardType::new(draw.card_type));
+            pulls.push(Pull { card_id, draw, rng });
+        }
+
+        let card_ids: Vec<Snowflake> = pulls.iter().map(|pull| pull.card_id).collect();
+        txn.update_component::<Player, Inventory, _>(player_id, move |inv| {
+            let mut ids: Vec<Snowflake> = match inv {
+                Some(inv) => inv.iter_ids().copied().collect(),
+                None => Vec::new(),
+            };
+
+            ids.extend(card_ids);
+            Ok(ids.into())
+        });
+
+        txn.commit()?;
+        Ok(pulls)
+    }
+}
+
+/// Returned when creating or configuring a [`Banner`] with invalid
+/// settings.
+#[derive(Fail, Debug)]
+#[fail(display = "Invalid banner configuration: {}", _0)]
+pub struct InvalidBanner(String);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::components::{CardTypeLayer, InventoryBackendWrapper};
+    use crate::ecs::TransactionError;
+    use crate::local_storage::{LocalComponentStorage, LocalEntityStorage};
+    use crate::rng::DeterministicSource;
+
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    fn ids(range: std::ops::Range<u64>) -> Vec<Snowflake> {
+        range.map(|id| id.into()).collect()
+    }
+
+    fn test_banner() -> Banner {
+        Banner::new(
+            "test",
+            vec![
+                RarityTier::new(3, 940, ids(0..10)),
+                RarityTier::new(4, 50, ids(10..15)),
+                RarityTier::new(5, 10, ids(15..17)).with_featured(ids(17..18), 0.5),
+            ],
+            10,
+        )
+        .unwrap()
+        .with_pity(PityConfig::new(50, 100, 80))
+        .with_multi_pull(10, 90, Some(4))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_invalid_banners() {
+        assert!(Banner::new("test", vec![], 10).is_err());
+        assert!(Banner::new("test", vec![RarityTier::new(3, 0, ids(0..1))], 10).is_err());
+        assert!(Banner::new("test", vec![RarityTier::new(3, 1, vec![])], 10).is_err());
+        assert!(Banner::new(
+            "test",
+            vec![
+                RarityTier::new(3, 1, ids(0..1)),
+                RarityTier::new(3, 1, ids(1..2))
+            ],
+            10
+        )
+        .is_err());
+        assert!(Banner::new(
+            "test",
+            vec![RarityTier::new(3, 1, ids(0..1)).with_featured(ids(1..2), 1.5)],
+            10
+        )
+        .is_err());
+        assert!(test_banner().with_multi_pull(10, 90, Some(6)).is_err());
+        assert!(test_banner().with_multi_pull(0, 90, None).is_err());
+    }
+
+    #[test]
+    fn test_weights() {
+        let banner = test_banner();
+
+        assert_eq!(banner.weights(0, None), vec![940, 50, 10]);
+        assert_eq!(banner.weights(0, Some(4)), vec![0, 50, 10]);
+
+        // Soft pity starts on the 50th pull since the last top-rarity card.
+        assert_eq!(banner.weights(48, None), vec![940, 50, 10]);
+        assert_eq!(banner.weights(49, None), vec![940, 50, 110]);
+        assert_eq!(banner.weights(50, None), vec![940, 50, 210]);
+
+        // Hard pity guarantees the top tier on the 80th pull.
+        assert_eq!(banner.weights(79, None), vec![0, 0, 1]);
+
+        // Minimum rarities past the top tier are clamped to it.
+        assert_eq!(banner.weights(0, Some(6)), vec![0, 0, 10]);
+    }
+
+    #[test]
+    fn test_min_rarity_above_top() {
+        let banner = test_banner();
+        let mut rng = StdRng::seed_from_u64(0);
+
+        for _ in 0..100 {
+            let draw = banner.draw(0, Some(u32::MAX), &mut rng);
+            assert_eq!(draw.rarity(), 5);
+        }
+    }
+
+    #[test]
+    fn test_hard_pity() {
+        let banner = test_banner();
+        let mut rng = StdRng::seed_from_u64(0);
+
+        for _ in 0..100 {
+            let draw = banner.draw(79, None, &mut rng);
+            assert_eq!(draw.rarity(), 5);
+        }
+
+        // Nobody should ever go 80 pulls without a top-rarity card.
+        let mut pity = PityState::new();
+        let mut since_top = 0;
+        for draw in banner.draw_many(2000, &mut pity, &mut rng) {
+            if draw.rarity() == 5 {
+                since_top = 0;
+            } else {
+                since_top += 1;
+                assert!(since_top < 80);
+            }
+        }
+
+        assert_eq!(pity.get("test"), since_top);
+    }
+
+    #[test]
+    fn test_multi_pull_guarantee() {
+        let banner = test_banner();
+        let mut rng = StdRng::seed_from_u64(0);
+
+        for _ in 0..200 {
+            let mut pity = PityState::new();
+            let draws = banner.draw_many(20, &mut pity, &mut rng);
+
+            for group in draws.chunks(10) {
+                assert!(group.iter().any(|draw| draw.rarity() >= 4));
+            }
+        }
+    }
+
+    #[test]
+    fn test_featured() {
+        let banner = Banner::new(
+            "test",
+            vec![RarityTier::new(5, 1, ids(0..2)).with_featured(ids(2..3), 1.0)],
+            10,
+        )
+        .unwrap();
+        let mut rng = StdRng::seed_from_u64(0);
+
+        for _ in 0..100 {
+            let draw = banner.draw(0, None, &mut rng);
+            assert!(draw.featured());
+            assert_eq!(draw.card_type(), 2u64.into());
+        }
+    }
+
+    #[test]
+    fn test_cost() {
+        let banner = test_banner();
+        assert_eq!(banner.cost(1), 10);
+        assert_eq!(banner.cost(10), 90);
+        assert_eq!(banner.cost(23), 210);
+    }
+
+    fn new_manager() -> EntityManager {
+        let mut manager = EntityManager::new();
+        manager
+            .register_entity(LocalEntityStorage::<Player>::new())
+            .unwrap();
+        manager
+            .register_entity(LocalEntityStorage::<Card>::new())
+            .unwrap();
+        manager
+            .register_component("Resource", LocalComponentStorage::<Player, Resource>::new())
+            .unwrap();
+        manager
+            .register_component(
+                "PityState",
+                LocalComponentStorage::<Player, PityState>::new(),
+            )
+            .unwrap();
+        manager
+            .register_component(
+                "Inventory",
+                InventoryBackendWrapper::new(LocalComponentStorage::<Player, Vec<Snowflake>>::new()),
+            )
+            .unwrap();
+        manager
+            .register_component(
+                "CardType",
+                CardTypeLayer::new(LocalComponentStorage::<Card, Snowflake>::new()),
+            )
+            .unwrap();
+        manager
+    }
+
+    #[test]
+    fn test_pull() {
+        let manager = new_manager();
+        let banner = test_banner();
+        let snowflake_gen = SnowflakeGenerator::new(0, 0);
+        let rng_source = DeterministicSource::from_u64(0);
+
+        let mut player: Player = manager.create(snowflake_gen.generate()).unwrap();
+        let player_id = player.id();
+        player
+            .set_component(Resource::new(100, Some(0), None))
+            .unwrap();
+        manager.store(player).unwrap();
+
+        let pulls = banner
+            .pull(&manager, player_id, 10, &snowflake_gen, &rng_source)
+            .unwrap();
+        assert_eq!(pulls.len(), 10);
+
+        for pull in pulls.iter() {
+            let handle = manager.load::<Card>(pull.card_id()).unwrap();
+            let card = handle.get().unwrap();
+            let card_type: AttachedCardType = card.get_component().unwrap().unwrap();
+            assert_eq!(card_type.type_id(), pull.draw().card_type());
+
+            // Each pull can be reproduced from its RNG record.
+            assert_eq!(banner.replay(pull), *pull.draw());
+        }
+
+        {
+            let handle = manager.load::<Player>(player_id).unwrap();
+            let player = handle.get().unwrap();
+
+            let rsc: Resource = player.get_component().unwrap().unwrap();
+            assert_eq!(rsc.val(), 10);
+
+            let inv: Inventory = player.get_component().unwrap().unwrap();
+            assert_eq!(inv.len(), 10);
+            assert!(pulls.iter().all(|pull| inv.contains(pull.card_id())));
+
+            let pity: PityState = player.get_component().unwrap().unwrap();
+            let since_top = pulls
+                .iter()
+                .rev()
+                .take_while(|pull| pull.draw().rarity() != 5)
+                .count();
+            assert_eq!(pity.get("test") as usize, since_top);
+        }
+
+        // The player can only afford one more single pull.
+        let err = banner
+            .pull(&manager, player_id, 10, &snowflake_gen, &rng_source)
+            .unwrap_err();
+        assert!(err
+            .downcast_ref::<crate::components::InvalidSubtraction>()
+            .is_some());
+
+        banner
+            .pull(&manager, player_id, 1, &snowflake_gen, &rng_source)
+            .unwrap();
+
+        let handle = manager.load::<Player>(player_id).unwrap();
+        let player = handle.get().unwrap();
+        let rsc: Resource = player.get_component().unwrap().unwrap();
+        assert_eq!(rsc.val(), 0);
+
+        let inv: Inventory = player.get_component().unwrap().unwrap();
+        assert_eq!(inv.len(), 11);
+    }
+
+    #[test]
+    fn test_pull_rollback() {
+        let mut manager = EntityManager::new();
+        manager
+            .register_entity(LocalEntityStorage::<Player>::new())
+            .unwrap();
+        manager
+            .register_entity(LocalEntityStorage::<Card>::new())
+            .unwrap();
+        manager
+            .register_component("Resource", LocalComponentStorage::<Player, Resource>::new())
+            .unwrap();
+        manager
+            .register_component(
+                "PityState",
+                LocalComponentStorage::<Player, PityState>::new(),
+            )
+            .unwrap();
+        manager
+            .register_component(
+                "CardType",
+                CardTypeLayer::new(LocalComponentStorage::<Card, Snowflake>::new()),
+            )
+            .unwrap();
+
+        // Inventory isn't registered, so the last step of the pull fails.
+        let banner = test_banner();
+        let snowflake_gen = SnowflakeGenerator::new(0, 0);
+        let rng_source = DeterministicSource::from_u64(0);
+
+        let mut player: Player = manager.create(snowflake_gen.generate()).unwrap();
+        let player_id = player.id();
+        player
+            .set_component(Resource::new(100, Some(0), None))
+            .unwrap();
+        manager.store(player).unwrap();
+
+        let err = banner
+            .pull(&manager, player_id, 1, &snowflake_gen, &rng_source)
+            .unwrap_err();
+        assert!(err.downcast_ref::<TransactionError>().is_some());
+
+        let handle = manager.load::<Player>(player_id).unwrap();
+        let player = handle.get().unwrap();
+        let rsc: Resource = player.get_component().unwrap().unwrap();
+        assert_eq!(rsc.val(), 100);
+        assert!(!player.has_component::<PityState>());
+        assert!(manager.keys::<Card>(0, 10).unwrap().is_empty());
+    }
+}
@@ -0,0 +1,50 @@
+//! Pity counters as [`Player`] components.
+
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::ecs::Component;
+use crate::player::Player;
+
+/// Tracks how many pulls a [`Player`] has made on each
+/// [`Banner`](super::Banner) since they last pulled a card of the banner's
+/// top rarity.
+///
+/// Counters are keyed by banner name, so banners that share a name also
+/// share pity.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PityState {
+    counters: BTreeMap<String, u32>,
+}
+
+impl PityState {
+    /// Creates a new `PityState` with all counters at zero.
+    pub fn new() -> PityState {
+        PityState {
+            counters: BTreeMap::new(),
+        }
+    }
+
+    /// Gets the number of pulls since the last top-rarity card for a
+    /// banner.
+    pub fn get(&self, banner: &str) -> u32 {
+        self.counters.get(banner).copied().unwrap_or(0)
+    }
+
+    /// Sets the pity counter for a banner.
+    pub fn set(&mut self, banner: &str, pulls: u32) {
+        if pulls == 0 {
+            self.counters.remove(banner);
+        } else {
+            self.counters.insert(banner.to_owned(), pulls);
+        }
+    }
+
+    /// Resets the pity counter for a banner to zero.
+    pub fn reset(&mut self, banner: &str) {
+        self.counters.remove(banner);
+    }
+}
+
+impl Component<Player> for PityState {}
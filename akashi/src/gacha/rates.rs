@@ -0,0 +1,723 @@
+//! Exact drop rates for [`Banners`](Banner), for disclosure to players.
+//!
+//! Drop rates are calculated by modelling a player's pity counter as a
+//! Markov chain, and computing how often they would get each result over
+//! a long run of pulls. This takes soft and hard pity, as well as
+//! multi-pull rarity guarantees, into account.
+
+use std::mem;
+
+use serde::{Deserialize, Serialize};
+
+use super::banner::Banner;
+use crate::snowflake::Snowflake;
+
+/// The upper limit on how many pity counter values are modelled.
+///
+/// Counter values past this are treated as if they were this one.
+const MAX_PITY_STATES: usize = 100_000;
+
+/// Pity counter values that are less likely than this to be reached are
+/// not modelled.
+const SURVIVAL_CUTOFF: f64 = 1e-15;
+
+/// A way of pulling from a [`Banner`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum PullMode {
+    /// Pulling one card at a time.
+    Single,
+
+    /// Pulling with multi-pulls of [`Banner::multi_count`] cards at a
+    /// time, including any guaranteed minimum rarity.
+    Multi,
+}
+
+/// The rates for a single rarity tier in a [`RateTable`].
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct RarityRate {
+    rarity: u32,
+    base: f64,
+    single: f64,
+    multi: f64,
+}
+
+impl RarityRate {
+    /// Gets the rarity of this tier.
+    pub fn rarity(&self) -> u32 {
+        self.rarity
+    }
+
+    /// Gets the probability of a pull being of this rarity, without
+    /// considering pity or multi-pull guarantees.
+    pub fn base(&self) -> f64 {
+        self.base
+    }
+
+    /// Gets the long-run probability of a pull being of this rarity,
+    /// when making single pulls.
+    pub fn single(&self) -> f64 {
+        self.single
+    }
+
+    /// Gets the long-run probability of a pull being of this rarity,
+    /// when making multi-pulls.
+    pub fn multi(&self) -> f64 {
+        self.multi
+    }
+
+    /// Gets the long-run probability of a pull being of this rarity in the
+    /// given mode.
+    pub fn effective(&self, mode: PullMode) -> f64 {
+        match mode {
+            PullMode::Single => self.single,
+            PullMode::Multi => self.multi,
+        }
+    }
+}
+
+/// The rates for a single [`CardType`](crate::components::CardType) in a
+/// [`RateTable`].
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct CardRate {
+    card_type: Snowflake,
+    rarity: u32,
+    featured: bool,
+    base: f64,
+    single: f64,
+    multi: f64,
+}
+
+impl CardRate {
+    /// Gets the ID of the card type.
+    pub fn card_type(&self) -> Snowflake {
+        self.card_type
+    }
+
+    /// Gets the rarity tier the card type is in.
+    pub fn rarity(&self) -> u32 {
+        self.rarity
+    }
+
+    /// Checks whether the card type is featured in its tier.
+    pub fn featured(&self) -> bool {
+        self.featured
+    }
+
+    /// Gets the probability of a pull giving this card type, without
+    /// considering pity or multi-pull guarantees.
+    pub fn base(&self) -> f64 {
+        self.base
+    }
+
+    /// Gets the long-run probability of a pull giving this card type,
+    /// when making single pulls.
+    pub fn single(&self) -> f64 {
+        self.single
+    }
+
+    /// Gets the long-run probability of a pull giving this card type,
+    /// when making multi-pulls.
+    pub fn multi(&self) -> f64 {
+        self.multi
+    }
+
+    /// Gets the long-run probability of a pull giving this card type in
+    /// the given mode.
+    pub fn effective(&self, mode: PullMode) -> f64 {
+        match mode {
+            PullMode::Single => self.single,
+            PullMode::Multi => self.multi,
+        }
+    }
+}
+
+/// A table of the drop rates for each rarity tier and card type in a
+/// [`Banner`].
+///
+/// Three rates are given for everything in the table:
+///  - the base rate, which is what the banner's weights alone would give;
+///  - the effective rate when making single pulls; and
+///  - the effective rate when making multi-pulls.
+///
+/// Effective rates are the fraction of all pulls that would give each
+/// result over a very long run of pulls, taking pity and multi-pull
+/// guarantees into account.
+///
+/// `RateTable` implements `Serialize`, so it can be exported to any
+/// format supported by `serde`.
+///
+/// # Example
+///
+/// ```
+/// use akashi::gacha::{Banner, PityConfig, RarityTier, RateTable};
+///
+/// let banner = Banner::new(
+///     "standard",
+///     vec![
+///         RarityTier::new(4, 99, vec![1u64.into()]),
+///         RarityTier::new(5, 1, vec![2u64.into()]),
+///     ],
+///     160,
+/// )
+/// .unwrap()
+/// .with_pity(PityConfig::new(0, 0, 90));
+///
+/// let table = RateTable::new(&banner);
+/// let top = &table.rarities()[1];
+///
+/// assert_eq!(top.rarity(), 5);
+/// assert!((top.base() - 0.01).abs() < 1e-12);
+///
+/// // Hard pity makes top-rarity cards noticeably more common.
+/// assert!(top.single() > 0.015);
+///
+/// let json = serde_json::to_string(&table).unwrap();
+/// ```
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct RateTable {
+    banner: String,
+    rarities: Vec<RarityRate>,
+    cards: Vec<CardRate>,
+}
+
+impl RateTable {
+    /// Computes the rate table for a [`Banner`].
+    pub fn new(banner: &Banner) -> RateTable {
+        let base_weights: Vec<f64> = banner
+            .tiers()
+            .iter()
+            .map(|tier| tier.weight() as f64)
+            .collect();
+        let base = normalize(&base_weights);
+
+        let single = effective_tier_rates(banner, 1, None);
+        let multi = effective_tier_rates(banner, banner.multi_count(), banner.multi_guarantee());
+
+        let rarities = banner
+            .tiers()
+            .iter()
+            .enumerate()
+            .map(|(i, tier)| RarityRate {
+                rarity: tier.rarity(),
+                base: base[i],
+                single: single[i],
+                multi: multi[i],
+            })
+            .collect();
+
+        let mut cards: Vec<CardRate> = Vec::new();
+        for (i, tier) in banner.tiers().iter().enumerate() {
+            let featured_rate = tier.featured_rate();
+            let groups = [
+                (tier.featured(), true, featured_rate),
+                (tier.pool(), false, 1.0 - featured_rate),
+            ];
+
+            for (card_types, featured, rate) in groups.iter() {
+                if card_types.is_empty() {
+                    continue;
+                }
+
+                let share = rate / (card_types.len() as f64);
+                for card_type in card_types.iter() {
+                    let existing = cards.iter_mut().find(|row| {
+                        row.card_type == *card_type
+                            && row.rarity == tier.rarity()
+                            && row.featured == *featured
+                    });
+
+                    match existing {
+                        Some(row) => {
+                            row.base += base[i] * share;
+                            row.single += single[i] * share;
+                            row.multi += multi[i] * share;
+                        }
+                        None => cards.push(CardRate {
+                            card_type: *card_type,
+                            rarity: tier.rarity(),
+                            featured: *featured,
+                            base: base[i] * share,
+                            single: single[i] * share,
+                            multi: multi[i] * share,
+                        }),
+                    }
+                }
+            }
+        }
+
+        RateTable {
+            banner: banner.name().to_owned(),
+            rarities,
+            cards,
+        }
+    }
+
+    /// Gets the name of the banner these rates are for.
+    pub fn banner(&self) -> &str {
+        &self.banner
+    }
+
+    /// Gets the rates for each rarity tier, ordered from most to least
+    /// common.
+    pub fn rarities(&self) -> &[RarityRate] {
+        &self.rarities
+    }
+
+    /// Gets the rates for each card type.
+    pub fn cards(&self) -> &[CardRate] {
+        &self.cards
+    }
+}
+
+fn normalize(weights: &[f64]) -> Vec<f64> {
+    let total: f64 = weights.iter().sum();
+    weights.iter().map(|w| w / total).collect()
+}
+
+/// Gets the probability of drawing each tier of a banner.
+fn tier_probabilities(banner: &Banner, pulls_since_top: u32, min_rarity: Option<u32>) -> Vec<f64> {
+    let weights: Vec<f64> = banner
+        .weights(pulls_since_top, min_rarity)
+        .into_iter()
+        .map(|w| w as f64)
+        .collect();
+
+    normalize(&weights)
+}
+
+/// Works out how many pity counter values need to be modelled.
+///
+/// The last value is treated as absorbing: pulls that would increase the
+/// counter past it leave it there instead.
+fn pity_states(banner: &Banner) -> usize {
+    let limit = match banner.pity() {
+        Some(pity) if pity.hard() > 0 => (pity.hard() as usize).min(MAX_PITY_STATES),
+        Some(pity) if pity.soft_start() > 0 && pity.soft_step() > 0 => MAX_PITY_STATES,
+        _ => return 1,
+    };
+
+    let top = banner.tiers().len() - 1;
+    let mut survival = 1.0;
+    let mut states = 1;
+
+    while survival > SURVIVAL_CUTOFF && states < limit {
+        survival *= 1.0 - tier_probabilities(banner, (states - 1) as u32, None)[top];
+        states += 1;
+    }
+
+    states
+}
+
+/// What happens over a group of pulls, starting from a given pity
+/// counter value.
+struct GroupOutcome {
+    /// The expected number of draws from each tier during the group.
+    tier_counts: Vec<f64>,
+
+    /// The probability of the counter ending up at each value after the
+    /// group, for each value it can end up at.
+    ends: Vec<(usize, f64)>,
+}
+
+/// Works out what happens over a group of `group_size` pulls starting
+/// from the counter value `start`, with an optional guaranteed rarity for
+/// the last pull in the group.
+///
+/// `plain` and `guaranteed` hold the tier probabilities for each counter
+/// value, without and with the guaranteed rarity.
+fn group_outcome(
+    banner: &Banner,
+    start: usize,
+    group_size: u32,
+    guarantee: Option<u32>,
+    plain: &[Vec<f64>],
+    guaranteed: &[Vec<f64>],
+) -> GroupOutcome {
+    let tiers = banner.tiers();
+    let top = tiers.len() - 1;
+    let states = plain.len();
+    let mut tier_counts = vec![0.0; tiers.len()];
+
+    // The counter either resets or goes up by one with each pull, so
+    // within a group it's either below `group_size` (if it has reset), or
+    // `start` plus the number of pulls so far (if it hasn't). The last
+    // slot here is for the second case; the others are for each counter
+    // value in the first case. Each slot holds the probability of
+    // the guarantee being unsatisfied or satisfied.
+    let climbing = group_size as usize;
+    let mut dist = vec![[0.0; 2]; climbing + 1];
+    let mut next = dist.clone();
+    dist[climbing][0] = 1.0;
+
+    for position in 0..group_size {
+        let climbing_counter = (start + position as usize).min(states - 1);
+
+        for (slot, probs) in dist.iter().enumerate() {
+            let counter = if slot == climbing {
+                climbing_counter
+            } else {
+                slot
+            };
+
+            for (satisfied, prob) in probs.iter().enumerate() {
+                if *prob == 0.0 {
+                    continue;
+                }
+
+                let tier_probs = if position == group_size - 1 && satisfied == 0 {
+                    &guaranteed[counter]
+                } else {
+                    &plain[counter]
+                };
+
+                for (i, tier_prob) in tier_probs.iter().enumerate() {
+                    let p = prob * tier_prob;
+                    if p == 0.0 {
+                        continue;
+                    }
+
+                    tier_counts[i] += p;
+
+                    let next_slot = if i == top {
+                        0
+                    } else if slot == climbing {
+                        climbing
+                    } else {
+                        (slot + 1).min(states - 1)
+                    };
+
+                    let next_satisfied = match guarantee {
+                        Some(rarity) if tiers[i].rarity() >= rarity => 1,
+                        _ => satisfied,
+                    };
+
+                    next[next_slot][next_satisfied] += p;
+                }
+            }
+        }
+
+        mem::swap(&mut dist, &mut next);
+        for probs in next.iter_mut() {
+            *probs = [0.0; 2];
+        }
+    }
+
+    let climbing_end = (start + group_size as usize).min(states - 1);
+    GroupOutcome {
+        tier_counts,
+        ends: dist
+            .iter()
+            .enumerate()
+            .map(|(slot, probs)| {
+                let counter = if slot == climbing { climbing_end } else { slot };
+
+                (counter, probs[0] + probs[1])
+            })
+            .filter(|(_, p)| *p > 0.0)
+            .collect(),
+    }
+}
+
+/// Computes the long-run probability of each tier being drawn, when
+/// pulling in groups of `group_size` with an optional guaranteed rarity
+/// for the last pull in each group.
+fn effective_tier_rates(banner: &Banner, group_size: u32, guarantee: Option<u32>) -> Vec<f64> {
+    let states = pity_states(banner);
+    let plain: Vec<Vec<f64>> = (0..states)
+        .map(|counter| tier_probabilities(banner, counter as u32, None))
+        .collect();
+    let guaranteed: Vec<Vec<f64>> = match guarantee {
+        Some(_) => (0..states)
+            .map(|counter| tier_probabilities(banner, counter as u32, guarantee))
+            .collect(),
+        None => plain.clone(),
+    };
+
+    let outcomes: Vec<GroupOutcome> = (0..states)
+        .map(|start| group_outcome(banner, start, group_size, guarantee, &plain, &guaranteed))
+        .collect();
+
+    let stationary = stationary_distribution(&outcomes, group_size as usize);
+
+    let mut rates = vec![0.0; banner.tiers().len()];
+    for (outcome, weight) in outcomes.iter().zip(stationary.iter()) {
+        for (i, count) in outcome.tier_counts.iter().enumerate() {
+            rates[i] += weight * count / (group_size as f64);
+        }
+    }
+
+    rates
+}
+
+/// Solves for the long-run distribution of the pity counter at the start
+/// of each group of pulls.
+///
+/// Over a group of `group_size` pulls, the counter either resets at some
+/// point (and so ends up below `group_size`), or goes up by `group_size`.
+/// Every higher value can therefore only be reached from lower values (or
+/// from itself, for the last, absorbing value), so each of them can be
+/// written in terms of the values below `group_size`. That leaves a
+/// system of `group_size` equations to solve, rather than one equation
+/// per counter value.
+fn stationary_distribution(outcomes: &[GroupOutcome], group_size: usize) -> Vec<f64> {
+    let n = outcomes.len();
+    let low = group_size.min(n);
+
+    let mut incoming: Vec<Vec<(usize, f64)>> = vec![Vec::new(); n];
+    for (start, outcome) in outcomes.iter().enumerate() {
+        for (end, p) in outcome.ends.iter() {
+            if *end >= low {
+                incoming[*end].push((start, *p));
+            }
+        }
+    }
+
+    // Write the probability of each counter value as a linear combination
+    // of the probabilities of the low values.
+    let mut coefs: Vec<Vec<f64>> = Vec::with_capacity(n);
+    for state in 0..n {
+        let mut coef = vec![0.0; low];
+        if state < low {
+            coef[state] = 1.0;
+        } else {
+            let mut stay = 0.0;
+            for (start, p) in incoming[state].iter() {
+                if *start == state {
+                    stay += p;
+                    continue;
+                }
+
+                for (value, start_value) in coef.iter_mut().zip(coefs[*start].iter()) {
+                    *value += p * start_value;
+                }
+            }
+
+            for value in coef.iter_mut() {
+                *value /= 1.0 - stay;
+            }
+        }
+
+        coefs.push(coef);
+    }
+
+    // Balance the probability flowing into each low value, replacing the
+    // last equation with the requirement that everything sums to 1.
+    let mut a = vec![vec![0.0; low + 1]; low];
+    for (start, outcome) in outcomes.iter().enumerate() {
+        for (end, p) in outcome.ends.iter() {
+            if *end < low {
+                for (value, start_value) in a[*end].iter_mut().zip(coefs[start].iter()) {
+                    *value += p * start_value;
+                }
+            }
+        }
+    }
+
+    for (i, row) in a.iter_mut().enumerate() {
+        row[i] -= 1.0;
+    }
+
+    a[low - 1] = vec![0.0; low + 1];
+    for coef in coefs.iter() {
+        for (value, c) in a[low - 1].iter_mut().zip(coef.iter()) {
+            *value += c;
+        }
+    }
+    a[low - 1][low] = 1.0;
+
+    let x = solve(a);
+    coefs
+        .iter()
+        .map(|coef| {
+            coef.iter()
+                .zip(x.iter())
+                .map(|(c, v)| c * v)
+                .sum::<f64>()
+                .max(0.0)
+        })
+        .collect()
+}
+
+/// Solves a system of linear equations, given as the rows of an augmented
+/// matrix.
+fn solve(mut a: Vec<Vec<f64>>) -> Vec<f64> {
+    let n = a.len();
+
+    // Gaussian elimination with partial pivoting.
+    for col in 0..n {
+        let pivot = (col..n)
+            .max_by(|x, y| a[*x][col].abs().partial_cmp(&a[*y][col].abs()).unwrap())
+            .unwrap();
+        a.swap(col, pivot);
+
+        let pivot_row = a[col].clone();
+        for (i, row) in a.iter_mut().enumerate() {
+            if i == col || row[col] == 0.0 {
+                continue;
+            }
+
+            let factor = row[col] / pivot_row[col];
+            for (value, pivot_value) in row.iter_mut().zip(pivot_row.iter()).skip(col) {
+                *value -= factor * pivot_value;
+            }
+        }
+    }
+
+    (0..n).map(|i| a[i][n] / a[i][i]).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::gacha::{PityConfig, RarityTier};
+
+    fn ids(range: std::ops::Range<u64>) -> Vec<Snowflake> {
+        range.map(|id| id.into()).collect()
+    }
+
+    fn assert_close(a: f64, b: f64) {
+        assert!((a - b).abs() < 1e-9, "{} != {}", a, b);
+    }
+
+    #[test]
+    fn test_base_rates() {
+        let banner = Banner::new(
+            "test",
+            vec![
+                RarityTier::new(3, 3, ids(0..3)),
+                RarityTier::new(5, 1, ids(3..5)).with_featured(ids(5..6), 0.5),
+            ],
+            10,
+        )
+        .unwrap();
+
+        let table = RateTable::new(&banner);
+        assert_close(table.rarities()[0].base(), 0.75);
+        assert_close(table.rarities()[1].base(), 0.25);
+
+        // Without pity or guarantees, the effective rates are the same.
+        for rarity in table.rarities() {
+            assert_close(rarity.single(), rarity.base());
+            assert_close(rarity.multi(), rarity.base());
+        }
+
+        for card in table.cards() {
+            let expected = match (card.rarity(), card.featured()) {
+                (3, false) => 0.25,
+                (5, true) => 0.125,
+                (5, false) => 0.0625,
+                _ => unreachable!(),
+            };
+
+            assert_close(card.base(), expected);
+        }
+
+        let total: f64 = table.cards().iter().map(|card| card.single()).sum();
+        assert_close(total, 1.0);
+    }
+
+    #[test]
+    fn test_hard_pity_rates() {
+        // Every other pull is guaranteed to be top rarity if the previous
+        // one wasn't, so the counter is at 0 two-thirds of the time.
+        let banner = Banner::new(
+            "test",
+            vec![
+                RarityTier::new(3, 1, ids(0..1)),
+                RarityTier::new(5, 1, ids(1..2)),
+            ],
+            10,
+        )
+        .unwrap()
+        .with_pity(PityConfig::new(0, 0, 2));
+
+        let table = RateTable::new(&banner);
+        assert_close(table.rarities()[1].single(), 2.0 / 3.0);
+        assert_close(table.rarities()[0].single(), 1.0 / 3.0);
+    }
+
+    #[test]
+    fn test_multi_pull_rates() {
+        let banner = Banner::new(
+            "test",
+            vec![
+                RarityTier::new(3, 1, ids(0..1)),
+                RarityTier::new(5, 1, ids(1..2)),
+            ],
+            10,
+        )
+        .unwrap()
+        .with_multi_pull(2, 20, Some(5))
+        .unwrap();
+
+        // The second pull in each pair is top rarity if the first wasn't.
+        let table = RateTable::new(&banner);
+        assert_close(table.rarities()[1].single(), 0.5);
+        assert_close(table.rarities()[1].multi(), (0.5 + 0.75) / 2.0);
+    }
+
+    #[test]
+    fn test_soft_pity_rates() {
+        let banner = Banner::new(
+            "test",
+            vec![
+                RarityTier::new(3, 99, ids(0..1)),
+                RarityTier::new(5, 1, ids(1..2)),
+            ],
+            10,
+        )
+        .unwrap()
+        .with_pity(PityConfig::new(10, 10, 0));
+
+        let table = RateTable::new(&banner);
+        let top = table.rarities()[1].single();
+        assert!(top > 0.01);
+        assert_close(top + table.rarities()[0].single(), 1.0);
+    }
+
+    #[test]
+    fn test_multi_pull_pity_rates() {
+        // With hard pity on every third pull, pairs of pulls start with
+        // the counter at 0, 1, and 2 in the ratio 4:2:1, and give 1, 1.25,
+        // and 1.5 top-rarity cards on average. Hard pity doesn't care how
+        // pulls are grouped, so this matches the single-pull rate.
+        let banner = Banner::new(
+            "test",
+            vec![
+                RarityTier::new(3, 1, ids(0..1)),
+                RarityTier::new(5, 1, ids(1..2)),
+            ],
+            10,
+        )
+        .unwrap()
+        .with_pity(PityConfig::new(0, 0, 3))
+        .with_multi_pull(2, 20, None)
+        .unwrap();
+
+        let table = RateTable::new(&banner);
+        let expected = (4.0 * 1.0 + 2.0 * 1.25 + 1.0 * 1.5) / 7.0 / 2.0;
+        assert_close(table.rarities()[1].multi(), expected);
+        assert_close(table.rarities()[1].single(), 4.0 / 7.0);
+    }
+
+    #[test]
+    fn test_long_pity_rates() {
+        // Hard pity that's practically never reached shouldn't have to be
+        // modelled in full, or affect the rates.
+        let banner = Banner::new(
+            "test",
+            vec![
+                RarityTier::new(3, 1, ids(0..1)),
+                RarityTier::new(5, 1, ids(1..2)),
+            ],
+            10,
+        )
+        .unwrap()
+        .with_pity(PityConfig::new(0, 0, u32::MAX));
+
+        let table = RateTable::new(&banner);
+        assert_close(table.rarities()[1].single(), 0.5);
+        assert_close(table.rarities()[1].multi(), 0.5);
+    }
+}
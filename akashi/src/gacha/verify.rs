@@ -0,0 +1,441 @@
+//! Statistical verification of published drop rates.
+//!
+//! [`verify`] simulates a large number of pulls from a [`Banner`], then
+//! uses a chi-squared goodness-of-fit test to check the observed results
+//! against the rates in a [`RateTable`].
+//!
+//! Note that pity makes consecutive pulls dependent on one another, so
+//! the test is only approximate for banners that use it. In practice this
+//! makes little difference as long as the number of pulls is large
+//! compared to the hard pity threshold.
+
+use std::collections::HashMap;
+
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+use super::banner::Banner;
+use super::pity::PityState;
+use super::rates::{PullMode, RateTable};
+use crate::snowflake::Snowflake;
+
+/// Categories with an expected count lower than this are pooled together
+/// before computing the chi-squared statistic.
+const MIN_EXPECTED: f64 = 5.0;
+
+/// The observed and expected number of draws for a single row of a
+/// [`RateTable`].
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ObservedRate {
+    card_type: Snowflake,
+    rarity: u32,
+    featured: bool,
+    expected: f64,
+    observed: u64,
+}
+
+impl ObservedRate {
+    /// Gets the ID of the card type.
+    pub fn card_type(&self) -> Snowflake {
+        self.card_type
+    }
+
+    /// Gets the rarity tier the card type is in.
+    pub fn rarity(&self) -> u32 {
+        self.rarity
+    }
+
+    /// Checks whether the card type is featured in its tier.
+    pub fn featured(&self) -> bool {
+        self.featured
+    }
+
+    /// Gets the number of draws of this card type expected from the
+    /// published rates.
+    pub fn expected(&self) -> f64 {
+        self.expected
+    }
+
+    /// Gets the number of draws of this card type that actually occurred.
+    pub fn observed(&self) -> u64 {
+        self.observed
+    }
+}
+
+/// The results of a call to [`verify`].
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct VerificationReport {
+    mode: PullMode,
+    draws: u64,
+    rows: Vec<ObservedRate>,
+    unexpected: u64,
+    chi_squared: f64,
+    degrees_of_freedom: u32,
+    p_value: f64,
+}
+
+impl VerificationReport {
+    /// Gets the pull mode that was simulated.
+    pub fn mode(&self) -> PullMode {
+        self.mode
+    }
+
+    /// Gets the number of draws that were simulated.
+    pub fn draws(&self) -> u64 {
+        self.draws
+    }
+
+    /// Gets the observed and expected counts for each row of the rate
+    /// table.
+    pub fn rows(&self) -> &[ObservedRate] {
+        &self.rows
+    }
+
+    /// Gets the number of draws that didn't match any row of the rate
+    /// table.
+    ///
+    /// If this is nonzero, the rate table doesn't describe the banner at
+    /// all, and the p-value is always zero.
+    pub fn unexpected(&self) -> u64 {
+        self.unexpected
+    }
+
+    /// Gets the chi-squared test statistic.
+    pub fn chi_squared(&self) -> f64 {
+        self.chi_squared
+    }
+
+    /// Gets the number of degrees of freedom for the chi-squared test.
+    pub fn degrees_of_freedom(&self) -> u32 {
+        self.degrees_of_freedom
+    }
+
+    /// Gets the probability of seeing results at least this far from the
+    /// published rates, if the published rates are correct.
+    pub fn p_value(&self) -> f64 {
+        self.p_value
+    }
+
+    /// Checks whether the observed results are consistent with the
+    /// published rates at the given significance level (for example,
+    /// `0.01`).
+    pub fn passed(&self, significance: f64) -> bool {
+        self.p_value >= significance
+    }
+}
+
+/// Simulates `draws` pulls from a banner, and checks the results against
+/// a [`RateTable`] with a chi-squared test.
+///
+/// Pulls are simulated as a single player pulling repeatedly, starting
+/// with no pity. In [`PullMode::Multi`], `draws` is rounded down to a
+/// whole number of multi-pulls.
+///
+/// Use a seeded [`GameRng`](crate::rng::GameRng) to make verification
+/// runs reproducible.
+///
+/// # Example
+///
+/// ```
+/// use akashi::gacha::{verify, Banner, PityConfig, PullMode, RarityTier, RateTable};
+/// use akashi::rng::GameRng;
+///
+/// let banner = Banner::new(
+///     "standard",
+///     vec![
+///         RarityTier::new(4, 9, vec![1u64.into(), 2u64.into()]),
+///         RarityTier::new(5, 1, vec![3u64.into()]),
+///     ],
+///     160,
+/// )
+/// .unwrap()
+/// .with_pity(PityConfig::new(0, 0, 20));
+///
+/// let table = RateTable::new(&banner);
+/// let mut rng = GameRng::new([42; 32]);
+/// let report = verify(&banner, &table, PullMode::Single, 20_000, &mut rng);
+///
+/// assert_eq!(report.draws(), 20_000);
+/// assert!(report.passed(0.001));
+/// ```
+pub fn verify<R: Rng + ?Sized>(
+    banner: &Banner,
+    table: &RateTable,
+    mode: PullMode,
+    draws: u64,
+    rng: &mut R,
+) -> VerificationReport {
+    let group = match mode {
+        PullMode::Single => 1,
+        PullMode::Multi => banner.multi_count(),
+    };
+
+    let groups = draws / u64::from(group);
+    let draws = groups * u64::from(group);
+
+    let mut counts: HashMap<(Snowflake, u32, bool), u64> = HashMap::new();
+    let mut pity = PityState::new();
+    for _ in 0..groups {
+        for draw in banner.draw_many(group, &mut pity, rng) {
+            *counts
+                .entry((draw.card_type(), draw.rarity(), draw.featured()))
+                .or_insert(0) += 1;
+        }
+    }
+
+    let rows: Vec<ObservedRate> = table
+        .cards()
+        .iter()
+        .map(|card| ObservedRate {
+            card_type: card.card_type(),
+            rarity: card.rarity(),
+            featured: card.featured(),
+            expected: card.effective(mode) * draws as f64,
+            observed: counts
+                .remove(&(card.card_type(), card.rarity(), card.featured()))
+                .unwrap_or(0),
+        })
+        .collect();
+
+    let unexpected = counts.values().sum();
+    let (chi_squared, degrees_of_freedom) = chi_squared(&rows);
+    let p_value = if unexpected > 0 {
+        0.0
+    } else {
+        chi_squared_p_value(chi_squared, degrees_of_freedom)
+    };
+
+    VerificationReport {
+        mode,
+        draws,
+        rows,
+        unexpected,
+        chi_squared,
+        degrees_of_freedom,
+        p_value,
+    }
+}
+
+/// Computes the chi-squared statistic and degrees of freedom for a set of
+/// observations, pooling categories with small expected counts.
+fn chi_squared(rows: &[ObservedRate]) -> (f64, u32) {
+    let mut bins: Vec<(f64, f64)> = Vec::with_capacity(rows.len());
+    let mut pooled = (0.0, 0.0);
+
+    for row in rows {
+        if row.expected >= MIN_EXPECTED {
+            bins.push((row.expected, row.observed as f64));
+        } else {
+            pooled.0 += row.expected;
+            pooled.1 += row.observed as f64;
+        }
+    }
+
+    if pooled.0 > 0.0 || pooled.1 > 0.0 {
+        let smallest = bins
+            .iter_mut()
+            .min_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
+
+        match smallest {
+            Some(bin) if pooled.0 < MIN_EXPECTED => {
+                bin.0 += pooled.0;
+                bin.1 += pooled.1;
+            }
+            _ => bins.push(pooled),
+        }
+    }
+
+    let statistic = bins
+        .iter()
+        .map(|(expected, observed)| {
+            if *expected > 0.0 {
+                (observed - expected).powi(2) / expected
+            } else if *observed > 0.0 {
+                f64::INFINITY
+            } else {
+                0.0
+            }
+        })
+        .sum();
+
+    (statistic, bins.len().saturating_sub(1) as u32)
+}
+
+/// Computes the probability of a chi-squared statistic at least as large
+/// as `statistic`, with the given degrees of freedom.
+pub fn chi_squared_p_value(statistic: f64, degrees_of_freedom: u32) -> f64 {
+    if degrees_of_freedom == 0 {
+        return 1.0;
+    }
+
+    if statistic.is_infinite() {
+        return 0.0;
+    }
+
+    gamma_q(f64::from(degrees_of_freedom) / 2.0, statistic / 2.0)
+}
+
+/// Computes the natural log of the gamma function, using the Lanczos
+/// approximation.
+fn ln_gamma(x: f64) -> f64 {
+    const COEFFICIENTS: [f64; 6] = [
+        76.180_091_729_471_46,
+        -86.505_320_329_416_77,
+        24.014_098_240_830_91,
+        -1.231_739_572_450_155,
+        0.001_208_650_973_866_179,
+        -0.000_005_395_239_384_953,
+    ];
+
+    let tmp = x + 5.5;
+    let tmp = tmp - (x + 0.5) * tmp.ln();
+
+    let mut y = x;
+    let mut series = 1.000_000_000_190_015;
+    for c in COEFFICIENTS.iter() {
+        y += 1.0;
+        series += c / y;
+    }
+
+    -tmp + (2.506_628_274_631_000_5 * series / x).ln()
+}
+
+/// Computes the regularized upper incomplete gamma function Q(a, x).
+fn gamma_q(a: f64, x: f64) -> f64 {
+    const MAX_ITERATIONS: usize = 1000;
+    const EPSILON: f64 = 1e-15;
+    const TINY: f64 = 1e-300;
+
+    if x <= 0.0 {
+        return 1.0;
+    }
+
+    let ln_prefix = a * x.ln() - x - ln_gamma(a);
+
+    if x < a + 1.0 {
+        // Series expansion of P(a, x).
+        let mut ap = a;
+        let mut term = 1.0 / a;
+        let mut sum = term;
+        for _ in 0..MAX_ITERATIONS {
+            ap += 1.0;
+            term *= x / ap;
+            sum += term;
+            if term.abs() < sum.abs() * EPSILON {
+                break;
+            }
+        }
+
+        (1.0 - sum * ln_prefix.exp()).max(0.0)
+    } else {
+        // Continued fraction for Q(a, x), using Lentz's method.
+        let mut b = x + 1.0 - a;
+        let mut c = 1.0 / TINY;
+        let mut d = 1.0 / b;
+        let mut h = d;
+        for i in 1..=MAX_ITERATIONS {
+            let an = -(i as f64) * (i as f64 - a);
+            b += 2.0;
+
+            d = an * d + b;
+            if d.abs() < TINY {
+                d = TINY;
+            }
+
+            c = b + an / c;
+            if c.abs() < TINY {
+                c = TINY;
+            }
+
+            d = 1.0 / d;
+            let delta = d * c;
+            h *= delta;
+            if (delta - 1.0).abs() < EPSILON {
+                break;
+            }
+        }
+
+        (h * ln_prefix.exp()).min(1.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::gacha::{PityConfig, RarityTier};
+    use crate::rng::GameRng;
+
+    fn ids(range: std::ops::Range<u64>) -> Vec<Snowflake> {
+        range.map(|id| id.into()).collect()
+    }
+
+    fn banner(top_weight: u64) -> Banner {
+        Banner::new(
+            "test",
+            vec![
+                RarityTier::new(3, 80, ids(0..4)),
+                RarityTier::new(4, 15, ids(4..6)).with_featured(ids(6..7), 0.5),
+                RarityTier::new(5, top_weight, ids(7..8)),
+            ],
+            10,
+        )
+        .unwrap()
+        .with_pity(PityConfig::new(15, 20, 25))
+        .with_multi_pull(10, 100, Some(4))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_p_values() {
+        // Critical values for a significance level of 0.05.
+        assert!((chi_squared_p_value(3.841, 1) - 0.05).abs() < 1e-4);
+        assert!((chi_squared_p_value(18.307, 10) - 0.05).abs() < 1e-4);
+        assert!((chi_squared_p_value(124.342, 100) - 0.05).abs() < 1e-4);
+
+        assert_eq!(chi_squared_p_value(0.0, 3), 1.0);
+        assert_eq!(chi_squared_p_value(5.0, 0), 1.0);
+        assert!(chi_squared_p_value(1000.0, 5) < 1e-100);
+    }
+
+    #[test]
+    fn test_verify_correct_rates() {
+        let banner = banner(5);
+        let table = RateTable::new(&banner);
+
+        for mode in [PullMode::Single, PullMode::Multi].iter() {
+            let mut rng = GameRng::new([1; 32]);
+            let report = verify(&banner, &table, *mode, 50_000, &mut rng);
+
+            assert_eq!(report.draws(), 50_000);
+            assert_eq!(report.unexpected(), 0);
+            assert_eq!(report.rows().len(), table.cards().len());
+            assert!(report.passed(0.001), "{:?}", report);
+
+            let total: u64 = report.rows().iter().map(|row| row.observed()).sum();
+            assert_eq!(total, 50_000);
+        }
+    }
+
+    #[test]
+    fn test_verify_wrong_rates() {
+        let banner = banner(5);
+        let table = RateTable::new(&self::banner(10));
+
+        let mut rng = GameRng::new([1; 32]);
+        let report = verify(&banner, &table, PullMode::Single, 50_000, &mut rng);
+        assert!(!report.passed(0.001));
+    }
+
+    #[test]
+    fn test_verify_unexpected_draws() {
+        let banner = banner(5);
+        let other = Banner::new("other", vec![RarityTier::new(3, 1, ids(100..101))], 10).unwrap();
+        let table = RateTable::new(&other);
+
+        let mut rng = GameRng::new([3; 32]);
+        let report = verify(&banner, &table, PullMode::Single, 1000, &mut rng);
+        assert_eq!(report.unexpected(), 1000);
+        assert_eq!(report.p_value(), 0.0);
+    }
+}
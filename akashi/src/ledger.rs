@@ -0,0 +1,649 @@
+//! An append-only audit trail of changes to player [`Resources`](Resource).
+//!
+//! A [`Ledger`] applies changes to a [`Resource`] on behalf of a player,
+//! and records each change as a [`LedgerEntry`] describing what was done,
+//! why, and how much the resource's value changed as a result. Entries are
+//! stored in a pluggable [`LedgerBackend`], and can never be modified or
+//! removed through this module.
+//!
+//! [`Ledger::reconcile`] replays a player's ledger and checks the result
+//! against their stored [`Resource`], which helps find changes that were
+//! made without being recorded.
+//!
+//! # Example
+//!
+//! ```
+//! use akashi::{EntityManager, Player};
+//! use akashi::components::Resource;
+//! use akashi::ledger::{Ledger, LocalLedger, ResourceOp};
+//! use akashi::local_storage::{LocalComponentStorage, LocalEntityStorage};
+//! use akashi::Entity;
+//!
+//! let mut manager = EntityManager::new();
+//! manager.register_entity(LocalEntityStorage::<Player>::new()).unwrap();
+//! manager
+//!     .register_component("Resource", LocalComponentStorage::<Player, Resource>::new())
+//!     .unwrap();
+//!
+//! let ledger = Ledger::new(LocalLedger::new());
+//! let player_id = 1u64.into();
+//! let mut rsc = Resource::new(0, Some(0), None);
+//!
+//! ledger
+//!     .record(player_id, &mut rsc, ResourceOp::CheckedAdd(300), "login_bonus", 10u64.into())
+//!     .unwrap();
+//! ledger
+//!     .record(player_id, &mut rsc, ResourceOp::CheckedSub(160), "gacha_pull", 11u64.into())
+//!     .unwrap();
+//!
+//! let mut player: Player = manager.create(player_id).unwrap();
+//! player.set_component(rsc).unwrap();
+//! manager.store(player).unwrap();
+//!
+//! let entries = ledger.entries(player_id).unwrap();
+//! assert_eq!(entries[1].delta(), -160);
+//! assert_eq!(entries[1].reason(), "gacha_pull");
+//!
+//! let report = ledger.reconcile(&manager, player_id).unwrap();
+//! assert!(report.is_consistent());
+//! assert_eq!(report.expected(), 140);
+//! ```
+
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+use std::time::SystemTime;
+
+use failure::format_err;
+use serde::{Deserialize, Serialize};
+
+use crate::clock::{Clock, SystemClock};
+use crate::components::Resource;
+use crate::ecs::{Entity, EntityManager};
+use crate::player::Player;
+use crate::snowflake::Snowflake;
+use crate::util::Result;
+
+/// A change to a [`Resource`], corresponding to one of its methods.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ResourceOp {
+    /// [`Resource::checked_set`]
+    CheckedSet(i64),
+
+    /// [`Resource::capped_set`]
+    CappedSet(i64),
+
+    /// [`Resource::checked_add`]
+    CheckedAdd(i64),
+
+    /// [`Resource::capped_add`]
+    CappedAdd(i64),
+
+    /// [`Resource::checked_sub`]
+    CheckedSub(i64),
+
+    /// [`Resource::capped_sub`]
+    CappedSub(i64),
+
+    /// [`Resource::soft_set_min`]
+    SoftSetMin(Option<i64>),
+
+    /// [`Resource::hard_set_min`]
+    HardSetMin(Option<i64>),
+
+    /// [`Resource::soft_set_max`]
+    SoftSetMax(Option<i64>),
+
+    /// [`Resource::hard_set_max`]
+    HardSetMax(Option<i64>),
+}
+
+impl ResourceOp {
+    /// Applies this change to a [`Resource`].
+    ///
+    /// # Errors
+    ///
+    /// Returns whatever error the corresponding [`Resource`] method
+    /// returns, in which case the `Resource` is left unchanged.
+    pub fn apply(&self, rsc: &mut Resource) -> Result<()> {
+        match *self {
+            ResourceOp::CheckedSet(val) => rsc.checked_set(val.into())?,
+            ResourceOp::CappedSet(val) => rsc.capped_set(val.into()),
+            ResourceOp::CheckedAdd(val) => rsc.checked_add(val.into())?,
+            ResourceOp::CappedAdd(val) => rsc.capped_add(val.into()),
+            ResourceOp::CheckedSub(val) => rsc.checked_sub(val.into())?,
+            ResourceOp::CappedSub(val) => rsc.capped_sub(val.into()),
+            ResourceOp::SoftSetMin(min) => rsc.soft_set_min(min)?,
+            ResourceOp::HardSetMin(min) => rsc.hard_set_min(min),
+            ResourceOp::SoftSetMax(max) => rsc.soft_set_max(max)?,
+            ResourceOp::HardSetMax(max) => rsc.hard_set_max(max),
+        };
+
+        Ok(())
+    }
+}
+
+/// A single recorded change to a player's [`Resource`].
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LedgerEntry {
+    player: Snowflake,
+    op: ResourceOp,
+    delta: i64,
+    balance: i64,
+    min: Option<i64>,
+    max: Option<i64>,
+    reason: String,
+    source: Snowflake,
+    timestamp: SystemTime,
+}
+
+impl LedgerEntry {
+    /// Gets the ID of the player whose [`Resource`] was changed.
+    pub fn player(&self) -> Snowflake {
+        self.player
+    }
+
+    /// Gets the change that was requested.
+    pub fn op(&self) -> ResourceOp {
+        self.op
+    }
+
+    /// Gets how much the [`Resource`]'s value actually changed by.
+    ///
+    /// This can differ from the requested amount for capped operations,
+    /// and can be nonzero for hard cap adjustments.
+    pub fn delta(&self) -> i64 {
+        self.delta
+    }
+
+    /// Gets the [`Resource`]'s value after the change.
+    pub fn balance(&self) -> i64 {
+        self.balance
+    }
+
+    /// Gets the [`Resource`]'s lower cap after the change.
+    pub fn min(&self) -> Option<i64> {
+        self.min
+    }
+
+    /// Gets the [`Resource`]'s upper cap after the change.
+    pub fn max(&self) -> Option<i64> {
+        self.max
+    }
+
+    /// Gets the reason code given for the change.
+    pub fn reason(&self) -> &str {
+        &self.reason
+    }
+
+    /// Gets the ID of the operation (such as a pull or purchase) that
+    /// caused the change.
+    pub fn source(&self) -> Snowflake {
+        self.source
+    }
+
+    /// Gets the time the change was recorded.
+    pub fn timestamp(&self) -> SystemTime {
+        self.timestamp
+    }
+}
+
+/// A storage backend for [`LedgerEntries`](LedgerEntry).
+///
+/// Backends only need to support appending entries and reading them back;
+/// entries are never modified or removed.
+pub trait LedgerBackend: Send + Sync {
+    /// Appends an entry to the ledger.
+    fn append(&self, entry: &LedgerEntry) -> Result<()>;
+
+    /// Gets every entry for a player, in the order they were appended.
+    fn entries(&self, player: Snowflake) -> Result<Vec<LedgerEntry>>;
+}
+
+/// In-memory [`LedgerBackend`].
+///
+/// This is mainly meant for use in testing and for prototyping. It has
+/// no provisions for storing data to a persistent medium.
+#[derive(Debug, Default)]
+pub struct LocalLedger {
+    entries: RwLock<HashMap<Snowflake, Vec<LedgerEntry>>>,
+}
+
+impl LocalLedger {
+    pub fn new() -> LocalLedger {
+        LocalLedger {
+            entries: RwLock::new(HashMap::new()),
+        }
+    }
+}
+
+impl LedgerBackend for LocalLedger {
+    fn append(&self, entry: &LedgerEntry) -> Result<()> {
+        let mut entries = self.entries.write().unwrap();
+        entries.entry(entry.player).or_default().push(entry.clone());
+        Ok(())
+    }
+
+    fn entries(&self, player: Snowflake) -> Result<Vec<LedgerEntry>> {
+        let entries = self.entries.read().unwrap();
+        Ok(entries.get(&player).cloned().unwrap_or_default())
+    }
+}
+
+/// Applies and records changes to player [`Resources`](Resource).
+#[derive(Clone)]
+pub struct Ledger {
+    backend: Arc<dyn LedgerBackend>,
+    clock: Arc<dyn Clock>,
+}
+
+impl Ledger {
+    /// Creates a new `Ledger` using the given backend.
+    pub fn new<B: LedgerBackend + 'static>(backend: B) -> Ledger {
+        Ledger {
+            backend: Arc::new(backend),
+            clock: Arc::new(SystemClock),
+        }
+    }
+
+    /// Sets the [`Clock`] used to timestamp new entries.
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Ledger {
+        self.clock = clock;
+        self
+    }
+
+    /// Applies a change to a player's [`Resource`] and records it.
+    ///
+    /// `reason` is a short code describing why the change was made, and
+    /// `source` is the ID of the operation that made it. The change is
+    /// only made to `rsc` in memory; it's up to the caller to store the
+    /// updated `Resource` afterwards.
+    ///
+    /// # Errors
+    ///
+    /// If the change fails, nothing is recorded. If recording the change
+    /// fails, or the change is too large to record as an `i64` delta,
+    /// `rsc` is put back the way it was.
+    pub fn record(
+        &self,
+        player: Snowflake,
+        rsc: &mut Resource,
+        op: ResourceOp,
+        reason: &str,
+        source: Snowflake,
+    ) -> Result<LedgerEntry> {
+        let prev = rsc.clone();
+        op.apply(rsc)?;
+
+        let delta = match rsc.val().checked_sub(prev.val()) {
+            Some(delta) => delta,
+            None => {
+                let err = format_err!(
+                    "resource change too large to record: {} to {}",
+                    prev.val(),
+                    rsc.val()
+                );
+                *rsc = prev;
+                return Err(err);
+            }
+        };
+
+        let entry = LedgerEntry {
+            player,
+            op,
+            delta,
+            balance: rsc.val(),
+            min: rsc.min(),
+            max: rsc.max(),
+            reason: reason.to_owned(),
+            source,
+            timestamp: self.clock.now(),
+        };
+
+        if let Err(e) = self.backend.append(&entry) {
+            *rsc = prev;
+            return Err(e);
+        }
+
+        Ok(entry)
+    }
+
+    /// Gets every recorded change for a player, oldest first.
+    pub fn entries(&self, player: Snowflake) -> Result<Vec<LedgerEntry>> {
+        self.backend.entries(player)
+    }
+
+    /// Replays a player's ledger and checks it against their stored
+    /// [`Resource`].
+    ///
+    /// Replaying starts from a value of zero, so the initial value of a
+    /// `Resource` should be recorded as well (for example, with a
+    /// [`ResourceOp::CheckedSet`] on a `Resource` with a value of zero).
+    pub fn reconcile(&self, manager: &EntityManager, player: Snowflake) -> Result<Reconciliation> {
+        let handle = manager.load::<Player>(player)?;
+        let entity = handle
+            .get()
+            .ok_or_else(|| format_err!("player not found: {}", player))?;
+
+        let rsc: Option<Resource> = entity.get_component()?;
+        let entries = self.entries(player)?;
+
+        Ok(Reconciliation::new(
+            player,
+            &entries,
+            rsc.map(|rsc| rsc.val()),
+        ))
+    }
+}
+
+/// A point in a ledger where an entry's recorded balance doesn't follow
+/// from the entries before it.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Discrepancy {
+    index: usize,
+    expected: i64,
+    recorded: i64,
+}
+
+impl Discrepancy {
+    /// Gets the position of the entry within the ledger.
+    pub fn index(&self) -> usize {
+        self.index
+    }
+
+    /// Gets the balance expected from replaying the previous entries.
+    pub fn expected(&self) -> i64 {
+        self.expected
+    }
+
+    /// Gets the balance recorded in the entry.
+    pub fn recorded(&self) -> i64 {
+        self.recorded
+    }
+}
+
+/// The results of reconciling a player's ledger against their stored
+/// [`Resource`].
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Reconciliation {
+    player: Snowflake,
+    entries: usize,
+    expected: i64,
+    actual: Option<i64>,
+    discrepancies: Vec<Discrepancy>,
+}
+
+impl Reconciliation {
+    /// Replays a list of entries and compares the result to a stored
+    /// value.
+    pub fn new(player: Snowflake, entries: &[LedgerEntry], actual: Option<i64>) -> Reconciliation {
+        let mut expected: i64 = 0;
+        let mut discrepancies = Vec::new();
+
+        for (index, entry) in entries.iter().enumerate() {
+            expected = expected.saturating_add(entry.delta);
+            if expected != entry.balance {
+                discrepancies.push(Discrepancy {
+                    index,
+                    expected,
+                    recorded: entry.balance,
+                });
+
+                // Carry on from the recorded balance, so that a single
+                // missing change is only reported once.
+                expected = entry.balance;
+            }
+        }
+
+        Reconciliation {
+            player,
+            entries: entries.len(),
+            expected,
+            actual,
+            discrepancies,
+        }
+    }
+
+    /// Gets the ID of the player whose ledger was reconciled.
+    pub fn player(&self) -> Snowflake {
+        self.player
+    }
+
+    /// Gets the number of ledger entries that were replayed.
+    pub fn entries(&self) -> usize {
+        self.entries
+    }
+
+    /// Gets the value expected from replaying the ledger.
+    pub fn expected(&self) -> i64 {
+        self.expected
+    }
+
+    /// Gets the value of the player's stored [`Resource`], if they have
+    /// one.
+    pub fn actual(&self) -> Option<i64> {
+        self.actual
+    }
+
+    /// Gets every point in the ledger where an entry's balance doesn't
+    /// follow from the entries before it.
+    pub fn discrepancies(&self) -> &[Discrepancy] {
+        &self.discrepancies
+    }
+
+    /// Checks that the ledger is internally consistent, and that
+    /// replaying it gives the stored value.
+    ///
+    /// A player with no stored [`Resource`] is treated as having a value
+    /// of zero.
+    pub fn is_consistent(&self) -> bool {
+        self.discrepancies.is_empty() && self.actual.unwrap_or(0) == self.expected
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::ManualClock;
+    use crate::components::InvalidSubtraction;
+    use crate::local_storage::{LocalComponentStorage, LocalEntityStorage};
+    use std::time::Duration;
+
+    struct FailingLedger;
+
+    impl LedgerBackend for FailingLedger {
+        fn append(&self, _entry: &LedgerEntry) -> Result<()> {
+            Err(format_err!("ledger unavailable"))
+        }
+
+        fn entries(&self, _player: Snowflake) -> Result<Vec<LedgerEntry>> {
+            Ok(Vec::new())
+        }
+    }
+
+    fn setup() -> EntityManager {
+        let mut manager = EntityManager::new();
+        manager
+            .register_entity(LocalEntityStorage::<Player>::new())
+            .unwrap();
+        manager
+            .register_component("Resource", LocalComponentStorage::<Player, Resource>::new())
+            .unwrap();
+        manager
+    }
+
+    fn store(manager: &EntityManager, id: Snowflake, rsc: Resource) {
+        let mut player: Player = manager.create(id).unwrap();
+        player.set_component(rsc).unwrap();
+        manager.store(player).unwrap();
+    }
+
+    #[test]
+    fn test_record() {
+        let ledger = Ledger::new(LocalLedger::new());
+        let player = Snowflake::from(1u64);
+        let source = Snowflake::from(2u64);
+        let mut rsc = Resource::new(0, Some(0), Some(100));
+
+        let ops = [
+            ResourceOp::CheckedSet(50),
+            ResourceOp::CappedAdd(80),
+            ResourceOp::CheckedSub(30),
+            ResourceOp::HardSetMax(Some(60)),
+        ];
+
+        for op in ops.iter() {
+            ledger
+                .record(player, &mut rsc, *op, "test", source)
+                .unwrap();
+        }
+
+        let err = ledger
+            .record(
+                player,
+                &mut rsc,
+                ResourceOp::CheckedSub(100),
+                "test",
+                source,
+            )
+            .unwrap_err();
+        assert!(err.downcast_ref::<InvalidSubtraction>().is_some());
+
+        let entries = ledger.entries(player).unwrap();
+        let deltas: Vec<i64> = entries.iter().map(|entry| entry.delta()).collect();
+        assert_eq!(deltas, vec![50, 50, -30, -10]);
+        assert_eq!(entries[3].balance(), 60);
+        assert_eq!(entries[3].max(), Some(60));
+        assert_eq!(entries[3].op(), ResourceOp::HardSetMax(Some(60)));
+        assert_eq!(rsc.val(), 60);
+
+        assert!(ledger.entries(Snowflake::from(5u64)).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_record_timestamp() {
+        let now = SystemTime::UNIX_EPOCH + Duration::from_secs(1_600_000_000);
+        let clock = Arc::new(ManualClock::new(now));
+        let ledger = Ledger::new(LocalLedger::new()).with_clock(clock.clone());
+        let player = Snowflake::from(1u64);
+        let mut rsc = Resource::new(0, None, None);
+
+        let first = ledger
+            .record(player, &mut rsc, ResourceOp::CheckedAdd(1), "test", player)
+            .unwrap();
+        clock.advance(Duration::from_secs(30));
+        let second = ledger
+            .record(player, &mut rsc, ResourceOp::CheckedAdd(1), "test", player)
+            .unwrap();
+
+        assert_eq!(first.timestamp(), now);
+        assert_eq!(second.timestamp(), now + Duration::from_secs(30));
+    }
+
+    #[test]
+    fn test_record_failure() {
+        let ledger = Ledger::new(FailingLedger);
+        let mut rsc = Resource::new(10, None, None);
+
+        assert!(ledger
+            .record(
+                Snowflake::from(1u64),
+                &mut rsc,
+                ResourceOp::CheckedAdd(5),
+                "test",
+                Snowflake::from(2u64)
+            )
+            .is_err());
+        assert_eq!(rsc.val(), 10);
+    }
+
+    #[test]
+    fn test_record_overflow() {
+        let ledger = Ledger::new(LocalLedger::new());
+        let player = Snowflake::from(1u64);
+        let mut rsc = Resource::new(i64::MIN, None, None);
+
+        assert!(ledger
+            .record(
+                player,
+                &mut rsc,
+                ResourceOp::CheckedSet(i64::MAX),
+                "test",
+                Snowflake::from(2u64)
+            )
+            .is_err());
+        assert_eq!(rsc.val(), i64::MIN);
+        assert!(ledger.entries(player).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_reconcile() {
+        let manager = setup();
+        let ledger = Ledger::new(LocalLedger::new());
+        let player = Snowflake::from(1u64);
+        let source = Snowflake::from(2u64);
+
+        let mut rsc = Resource::new(0, Some(0), None);
+        ledger
+            .record(
+                player,
+                &mut rsc,
+                ResourceOp::CheckedAdd(100),
+                "grant",
+                source,
+            )
+            .unwrap();
+        ledger
+            .record(
+                player,
+                &mut rsc,
+                ResourceOp::CheckedSub(40),
+                "spend",
+                source,
+            )
+            .unwrap();
+        store(&manager, player, rsc.clone());
+
+        let report = ledger.reconcile(&manager, player).unwrap();
+        assert!(report.is_consistent());
+        assert_eq!(report.entries(), 2);
+        assert_eq!(report.actual(), Some(60));
+
+        // A change that wasn't recorded.
+        rsc.checked_sub(10.into()).unwrap();
+        store(&manager, player, rsc);
+
+        let report = ledger.reconcile(&manager, player).unwrap();
+        assert!(!report.is_consistent());
+        assert!(report.discrepancies().is_empty());
+        assert_eq!(report.expected(), 60);
+        assert_eq!(report.actual(), Some(50));
+    }
+
+    #[test]
+    fn test_reconcile_gaps() {
+        let player = Snowflake::from(1u64);
+        let ledger = Ledger::new(LocalLedger::new());
+        let mut rsc = Resource::new(0, None, None);
+
+        ledger
+            .record(player, &mut rsc, ResourceOp::CheckedAdd(10), "a", player)
+            .unwrap();
+
+        // A change that was made between two recorded ones.
+        rsc.checked_add(5.into()).unwrap();
+
+        ledger
+            .record(player, &mut rsc, ResourceOp::CheckedAdd(10), "b", player)
+            .unwrap();
+
+        let entries = ledger.entries(player).unwrap();
+        let report = Reconciliation::new(player, &entries, Some(rsc.val()));
+
+        assert!(!report.is_consistent());
+        assert_eq!(report.discrepancies().len(), 1);
+        assert_eq!(report.discrepancies()[0].index(), 1);
+        assert_eq!(report.discrepancies()[0].expected(), 20);
+        assert_eq!(report.discrepancies()[0].recorded(), 25);
+        assert_eq!(report.expected(), 25);
+    }
+}
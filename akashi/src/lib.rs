@@ -2,8 +2,7 @@
 //!
 //! # Architecture
 //!
-//! Akashi uses an Entity-Component-System architecture (though at the
-//! moment only Entities and Components are really implemented).
+//! Akashi uses an Entity-Component-System architecture.
 //!
 //! Players and cards, within the Akashi framework, are **entities**:
 //! they aren't much more than a unique ID. Functionality is added by
@@ -11,6 +10,13 @@
 //! For example, inventories can be represented as components that
 //! are attached to players, while card images and text can be
 //! represented as components attached to cards.
+//!
+//! Game logic lives in **systems**, which declare the components they
+//! read and write, and are run over all matching entities by a
+//! [`Scheduler`](ecs::Scheduler).
+//!
+//! On top of this, the [`gacha`] module implements banners and pulls, and
+//! the [`trade`] module implements trading between players.
 
 #[macro_use]
 extern crate failure;
@@ -22,19 +28,36 @@ extern crate dashmap;
 extern crate downcast_rs;
 extern crate failure_derive;
 
+// Lets the derive macros refer to `::akashi` from within this crate, too.
+extern crate self as akashi;
+
 pub mod card;
+pub mod clock;
 pub mod components;
 pub mod ecs;
+pub mod file_storage;
+pub mod gacha;
+pub mod ledger;
 pub mod local_storage;
 pub mod player;
+pub mod rng;
 pub mod snowflake;
+#[cfg(feature = "sqlite")]
+pub mod sqlite_storage;
+pub mod trade;
 mod util;
 
+/// Items used by code generated by the derive macros.
+#[doc(hidden)]
+pub mod __private {
+    pub use dashmap::DashMap;
+}
+
 #[doc(inline)]
 pub use card::Card;
 
 #[doc(inline)]
-pub use ecs::{Component, ComponentBackend, Entity, EntityBackend, EntityManager};
+pub use ecs::{Component, ComponentBackend, Entity, EntityBackend, EntityManager, System};
 
 #[doc(inline)]
 pub use player::Player;
@@ -1,8 +1,9 @@
 //! Storage systems that work entirely in-memory, for testing and prototyping
 //! use.
 
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::marker::PhantomData;
+use std::ops::Bound;
 use std::sync::{Arc, RwLock};
 
 use failure::format_err;
@@ -16,7 +17,7 @@ use crate::util::Result;
 /// This is mainly meant for use in testing and for prototyping. It has
 /// no provisions for storing data to a persistent medium.
 pub struct LocalEntityStorage<T: Entity + Clone + 'static> {
-    data: RwLock<HashMap<Snowflake, T>>,
+    data: RwLock<BTreeMap<Snowflake, T>>,
 }
 
 impl<T> LocalEntityStorage<T>
@@ -25,7 +26,7 @@ where
 {
     pub fn new() -> LocalEntityStorage<T> {
         LocalEntityStorage {
-            data: RwLock::new(HashMap::new()),
+            data: RwLock::new(BTreeMap::new()),
         }
     }
 }
@@ -57,17 +58,26 @@ where
     }
 
     fn keys(&self, page: u64, limit: u64) -> Result<Vec<Snowflake>> {
-        let mut ids: Vec<Snowflake>;
-
         let data = self.data.read().unwrap();
-        ids = data.keys().copied().collect();
-        drop(data);
-
-        ids.sort_unstable();
-        Ok(ids
-            .into_iter()
+        Ok(data
+            .keys()
             .skip((page * limit) as usize)
             .take(limit as usize)
+            .copied()
+            .collect())
+    }
+
+    fn keys_after(&self, after: Option<Snowflake>, limit: u64) -> Result<Vec<Snowflake>> {
+        let start = match after {
+            Some(id) => Bound::Excluded(id),
+            None => Bound::Unbounded,
+        };
+
+        let data = self.data.read().unwrap();
+        Ok(data
+            .range((start, Bound::Unbounded))
+            .take(limit as usize)
+            .map(|(id, _)| *id)
             .collect())
     }
 }
@@ -136,4 +146,12 @@ where
         data_map.remove(&entity.id());
         Ok(())
     }
+
+    fn keys(&self) -> Result<Option<Vec<Snowflake>>> {
+        let data_map = self
+            .data
+            .read()
+            .map_err(|_e| format_err!("storage lock poisoned"))?;
+        Ok(Some(data_map.keys().copied().collect()))
+    }
 }
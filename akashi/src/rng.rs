@@ -0,0 +1,359 @@
+//! Seedable, replayable random number generation.
+//!
+//! Every random outcome in a game (such as gacha pulls) should come from a
+//! [`GameRng`], which is a ChaCha20-based CSPRNG that keeps track of the
+//! seed it was created with and how far into its output it has read.
+//! This makes it possible to record an [`RngRecord`] alongside each
+//! outcome, which can later be used to replay the outcome exactly.
+//!
+//! [`GameRngs`](GameRng) are usually created by an [`RngSource`], which
+//! can be injected wherever random outcomes are needed.
+//! [`EntropySource`] creates RNGs with fresh random seeds from the
+//! operating system, and should be used in production;
+//! [`DeterministicSource`] derives every seed from a single master seed,
+//! which is useful for tests and for reproducing bugs.
+//!
+//! # Example
+//!
+//! ```
+//! use akashi::Snowflake;
+//! use akashi::rng::{DeterministicSource, GameRng, RngSource};
+//! use rand::Rng;
+//!
+//! let source = DeterministicSource::new([7; 32]);
+//! let mut rng = source.rng(Snowflake::from(1u64), "loot");
+//!
+//! let start = rng.position();
+//! let rolls: Vec<u32> = (0..5).map(|_| rng.gen_range(0, 100)).collect();
+//! let record = rng.record_since(start);
+//!
+//! // The same rolls can be reproduced from the record later on.
+//! let mut replay = GameRng::replay(&record);
+//! let replayed: Vec<u32> = (0..5).map(|_| replay.gen_range(0, 100)).collect();
+//! assert_eq!(rolls, replayed);
+//! ```
+
+use std::collections::HashMap;
+
+use parking_lot::Mutex;
+use rand::rngs::OsRng;
+use rand::{CryptoRng, Error, RngCore, SeedableRng};
+use rand_chacha::ChaCha20Rng;
+use serde::{Deserialize, Serialize};
+
+use crate::snowflake::Snowflake;
+
+/// A deterministic, seedable random number generator.
+///
+/// This wraps a ChaCha20 stream cipher, and is suitable for cryptographic
+/// use. Each `GameRng` is identified by a 32-byte seed and a stream
+/// number, and its position within that stream can be saved and restored,
+/// so any sequence of outputs can be reproduced exactly.
+#[derive(Clone, Debug)]
+pub struct GameRng {
+    inner: ChaCha20Rng,
+    seed: [u8; 32],
+    stream: u64,
+}
+
+impl GameRng {
+    /// Creates a new `GameRng` from a seed, using stream 0.
+    pub fn new(seed: [u8; 32]) -> GameRng {
+        GameRng::with_stream(seed, 0)
+    }
+
+    /// Creates a new `GameRng` from a seed and stream number.
+    pub fn with_stream(seed: [u8; 32], stream: u64) -> GameRng {
+        let mut inner = ChaCha20Rng::from_seed(seed);
+        inner.set_stream(stream);
+
+        // This fills the output buffer, which `get_word_pos` expects to
+        // have happened already.
+        inner.set_word_pos(0);
+
+        GameRng {
+            inner,
+            seed,
+            stream,
+        }
+    }
+
+    /// Creates a new `GameRng` positioned at the start of the outputs
+    /// covered by an [`RngRecord`].
+    pub fn replay(record: &RngRecord) -> GameRng {
+        let mut rng = GameRng::with_stream(record.seed, record.stream);
+        rng.inner.set_word_pos(record.start);
+        rng
+    }
+
+    /// Gets the seed this RNG was created with.
+    pub fn seed(&self) -> [u8; 32] {
+        self.seed
+    }
+
+    /// Gets this RNG's stream number.
+    pub fn stream(&self) -> u64 {
+        self.stream
+    }
+
+    /// Gets the current position within this RNG's output, in 32-bit
+    /// words.
+    pub fn position(&self) -> u128 {
+        self.inner.get_word_pos()
+    }
+
+    /// Creates an [`RngRecord`] covering everything this RNG has output
+    /// since the given position.
+    pub fn record_since(&self, start: u128) -> RngRecord {
+        RngRecord {
+            seed: self.seed,
+            stream: self.stream,
+            start,
+            end: self.position(),
+        }
+    }
+}
+
+impl RngCore for GameRng {
+    fn next_u32(&mut self) -> u32 {
+        self.inner.next_u32()
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.inner.next_u64()
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        self.inner.fill_bytes(dest)
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), Error> {
+        self.inner.try_fill_bytes(dest)
+    }
+}
+
+impl CryptoRng for GameRng {}
+
+/// Identifies a sequence of outputs from a [`GameRng`].
+///
+/// Records can be stored or logged alongside random outcomes, then passed
+/// to [`GameRng::replay`] to reproduce them.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RngRecord {
+    seed: [u8; 32],
+    stream: u64,
+    start: u128,
+    end: u128,
+}
+
+impl RngRecord {
+    /// Gets the seed of the recorded RNG.
+    pub fn seed(&self) -> [u8; 32] {
+        self.seed
+    }
+
+    /// Gets the stream number of the recorded RNG.
+    pub fn stream(&self) -> u64 {
+        self.stream
+    }
+
+    /// Gets the position of the first recorded output, in 32-bit words.
+    pub fn start(&self) -> u128 {
+        self.start
+    }
+
+    /// Gets the position just after the last recorded output, in 32-bit
+    /// words.
+    pub fn end(&self) -> u128 {
+        self.end
+    }
+}
+
+/// A source of [`GameRngs`](GameRng) for individual operations.
+pub trait RngSource: Send + Sync {
+    /// Creates a new RNG to be used for a single operation (for example,
+    /// a pull on a particular banner) on behalf of a player.
+    fn rng(&self, player: Snowflake, operation: &str) -> GameRng;
+}
+
+/// Creates [`GameRngs`](GameRng) with fresh seeds from the operating
+/// system's random number generator.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct EntropySource;
+
+impl EntropySource {
+    /// Creates a new `EntropySource`.
+    pub fn new() -> EntropySource {
+        EntropySource
+    }
+}
+
+impl RngSource for EntropySource {
+    fn rng(&self, _player: Snowflake, _operation: &str) -> GameRng {
+        let mut seed = [0; 32];
+        OsRng.fill_bytes(&mut seed);
+        GameRng::new(seed)
+    }
+}
+
+/// Creates [`GameRngs`](GameRng) with seeds derived from a single
+/// master seed.
+///
+/// Each seed is derived from the master seed, the player ID and
+/// operation name, and a counter of how many RNGs this source has created
+/// for that player and operation. Two `DeterministicSources` with the same
+/// master seed will produce the same RNGs for each player and operation
+/// when given the same sequence of calls for them, however calls for
+/// different players and operations are interleaved.
+///
+/// This is meant for tests and for reproducing bugs; seeds produced by
+/// this source are only as unpredictable as the master seed.
+#[derive(Debug)]
+pub struct DeterministicSource {
+    seed: [u8; 32],
+    counters: Mutex<HashMap<(Snowflake, String), u64>>,
+}
+
+impl DeterministicSource {
+    /// Creates a new `DeterministicSource` from a master seed.
+    pub fn new(seed: [u8; 32]) -> DeterministicSource {
+        DeterministicSource {
+            seed,
+            counters: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Creates a new `DeterministicSource` from a `u64` master seed.
+    pub fn from_u64(seed: u64) -> DeterministicSource {
+        let mut master = [0; 32];
+        ChaCha20Rng::seed_from_u64(seed).fill_bytes(&mut master);
+        DeterministicSource::new(master)
+    }
+}
+
+/// Computes the 64-bit FNV-1a hash of some data.
+///
+/// Unlike the standard library's hashers, this is guaranteed to stay the
+/// same across Rust versions and platforms.
+fn fnv1a(data: &[u8], hash: u64) -> u64 {
+    data.iter().fold(hash, |hash, byte| {
+        (hash ^ u64::from(*byte)).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+impl RngSource for DeterministicSource {
+    fn rng(&self, player: Snowflake, operation: &str) -> GameRng {
+        let count = {
+            let mut counters = self.counters.lock();
+            let counter = counters.entry((player, operation.to_owned())).or_insert(0);
+            *counter += 1;
+            *counter - 1
+        };
+        let player: u64 = player.into();
+
+        let mut hash = 0xcbf2_9ce4_8422_2325;
+        hash = fnv1a(&player.to_le_bytes(), hash);
+        hash = fnv1a(&(operation.len() as u64).to_le_bytes(), hash);
+        hash = fnv1a(operation.as_bytes(), hash);
+        hash = fnv1a(&count.to_le_bytes(), hash);
+
+        let mut kdf = ChaCha20Rng::from_seed(self.seed);
+        kdf.set_stream(hash);
+
+        let mut seed = [0; 32];
+        kdf.fill_bytes(&mut seed);
+        GameRng::new(seed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use rand::Rng;
+
+    #[test]
+    fn test_replay() {
+        let mut rng = GameRng::with_stream([1; 32], 5);
+        let _skipped: u64 = rng.gen();
+
+        let start = rng.position();
+        let values: Vec<u64> = (0..10).map(|_| rng.gen()).collect();
+        let record = rng.record_since(start);
+        assert_eq!(record.end() - record.start(), 20);
+
+        let mut replay = GameRng::replay(&record);
+        let replayed: Vec<u64> = (0..10).map(|_| replay.gen()).collect();
+        assert_eq!(values, replayed);
+        assert_eq!(replay.position(), record.end());
+    }
+
+    #[test]
+    fn test_record_serialization() {
+        let mut rng = GameRng::new([3; 32]);
+        let _value: u32 = rng.gen();
+
+        let record = rng.record_since(0);
+        let serialized = serde_json::to_string(&record).unwrap();
+        let deserialized: RngRecord = serde_json::from_str(&serialized).unwrap();
+        assert_eq!(record, deserialized);
+    }
+
+    #[test]
+    fn test_deterministic_source() {
+        let a = DeterministicSource::from_u64(42);
+        let b = DeterministicSource::from_u64(42);
+        let player = Snowflake::from(1u64);
+
+        let seeds_a: Vec<[u8; 32]> = (0..3).map(|_| a.rng(player, "pull").seed()).collect();
+        let seeds_b: Vec<[u8; 32]> = (0..3).map(|_| b.rng(player, "pull").seed()).collect();
+        assert_eq!(seeds_a, seeds_b);
+
+        // Each call gets a different seed.
+        assert_ne!(seeds_a[0], seeds_a[1]);
+        assert_ne!(seeds_a[1], seeds_a[2]);
+
+        // So do different players and operations.
+        let c = DeterministicSource::from_u64(42);
+        assert_ne!(c.rng(Snowflake::from(2u64), "pull").seed(), seeds_a[0]);
+
+        let d = DeterministicSource::from_u64(42);
+        assert_ne!(d.rng(player, "trade").seed(), seeds_a[0]);
+    }
+
+    #[test]
+    fn test_deterministic_source_interleaved() {
+        let a = DeterministicSource::from_u64(42);
+        let b = DeterministicSource::from_u64(42);
+        let alice = Snowflake::from(1u64);
+        let bob = Snowflake::from(2u64);
+
+        // Calls for other players and operations don't change the seeds
+        // each player gets.
+        let alice_a: Vec<[u8; 32]> = (0..3)
+            .map(|_| {
+                let seed = a.rng(alice, "pull").seed();
+                a.rng(bob, "pull");
+                a.rng(alice, "trade");
+                seed
+            })
+            .collect();
+        let bob_a: Vec<[u8; 32]> = (0..2).map(|_| a.rng(bob, "pull").seed()).collect();
+
+        let alice_b: Vec<[u8; 32]> = (0..3).map(|_| b.rng(alice, "pull").seed()).collect();
+        let bob_b: Vec<[u8; 32]> = (0..5).map(|_| b.rng(bob, "pull").seed()).collect();
+
+        assert_eq!(alice_a, alice_b);
+        assert_eq!(bob_a, bob_b[3..]);
+    }
+
+    #[test]
+    fn test_entropy_source() {
+        let source = EntropySource::new();
+        let player = Snowflake::from(1u64);
+        assert_ne!(
+            source.rng(player, "pull").seed(),
+            source.rng(player, "pull").seed()
+        );
+    }
+}
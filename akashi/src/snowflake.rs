@@ -1,13 +1,32 @@
 //! Unique 64-bit IDs.
 
 use std::fmt;
-use std::thread;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
 use std::time::{Duration, SystemTime};
 
+use failure::Fail;
+
 extern crate serde;
 use serde::{Deserialize, Serialize};
 
+use crate::clock::{Clock, SystemClock};
 use crate::ecs::{Component, Entity};
+use crate::util::Result;
+
+pub mod encoding;
+pub mod layout;
+pub mod lease;
+
+#[doc(inline)]
+pub use encoding::{serde_string, Encoding};
+
+#[doc(inline)]
+pub use layout::{SnowflakeLayout, SnowflakeParts};
+
+pub use encoding::ParseSnowflakeError;
+pub use layout::{InvalidLayout, InvalidSnowflake};
+pub use lease::LeaseExpired;
 
 /// The epoch used when generating [`Snowflakes`](Snowflake), represented in
 /// milliseconds since the UNIX epoch.
@@ -15,32 +34,63 @@ use crate::ecs::{Component, Entity};
 /// This is currently the UNIX timestamp for midnight (UTC) on Dec. 15, 2018.
 pub const EPOCH_SECONDS: u64 = 1_544_832_000;
 
-const WORKER_ID_BITS: usize = 5;
-const GROUP_ID_BITS: usize = 5;
-const SEQUENCE_BITS: usize = 12;
+const TIMESTAMP_BITS: u32 = 41;
+const WORKER_ID_BITS: u32 = 5;
+const GROUP_ID_BITS: u32 = 5;
+const SEQUENCE_BITS: u32 = 12;
 
 /// The highest possible worker ID a [`Snowflake`] can contain.
-pub const MAX_WORKER_ID: u64 = (1 << (WORKER_ID_BITS + 1)) - 1;
+pub const MAX_WORKER_ID: u64 = (1 << WORKER_ID_BITS) - 1;
 
-/// The highest possible worker ID a [`Snowflake`] can contain.
-pub const MAX_GROUP_ID: u64 = (1 << (GROUP_ID_BITS + 1)) - 1;
+/// The highest possible group ID a [`Snowflake`] can contain.
+pub const MAX_GROUP_ID: u64 = (1 << GROUP_ID_BITS) - 1;
 
-const SEQUENCE_MASK: u64 = (1 << (SEQUENCE_BITS + 1)) - 1;
+const SEQUENCE_MASK: u64 = (1 << SEQUENCE_BITS) - 1;
 
-const WORKER_ID_SHIFT: usize = SEQUENCE_BITS;
-const GROUP_ID_SHIFT: usize = SEQUENCE_BITS + WORKER_ID_BITS;
-const TIMESTAMP_SHIFT: usize = SEQUENCE_BITS + WORKER_ID_BITS + GROUP_ID_BITS;
+const WORKER_ID_SHIFT: u32 = SEQUENCE_BITS;
+const GROUP_ID_SHIFT: u32 = SEQUENCE_BITS + WORKER_ID_BITS;
+const TIMESTAMP_SHIFT: u32 = SEQUENCE_BITS + WORKER_ID_BITS + GROUP_ID_BITS;
 
 /// This type is used to represent unique IDs across Akashi.
 ///
 /// Snowflake instances encode a timestamp, application-specific
 /// "group" and "worker" IDs, as well as a sequence number to disambiguate
 /// objects made in the same millisecond.
+///
+/// The accessors on `Snowflake` decode IDs using the default
+/// [`SnowflakeLayout`]. IDs generated under a custom layout should be
+/// decoded with [`SnowflakeLayout::decode`] instead.
 #[derive(Debug, Copy, Clone, Hash, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
 #[serde(from = "u64", into = "u64")]
 pub struct Snowflake(u64);
 
 impl Snowflake {
+    /// Gets the smallest `Snowflake` that could have been generated at
+    /// the given time.
+    ///
+    /// Since `Snowflakes` sort by the time they were generated, this can
+    /// be used as a bound when looking for IDs created within a range of
+    /// time. Times before the [`EPOCH_SECONDS`] epoch all map to
+    /// `Snowflake(0)`, and times too late to represent map to the largest
+    /// possible timestamp. This is the same as calling
+    /// [`SnowflakeLayout::first_at`] on the default layout.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use akashi::{Snowflake, SnowflakeGenerator};
+    /// use std::time::{Duration, SystemTime};
+    ///
+    /// let before = Snowflake::first_at(SystemTime::now() - Duration::from_millis(1));
+    /// let id = SnowflakeGenerator::new(0, 0).generate();
+    /// let after = Snowflake::first_at(SystemTime::now() + Duration::from_millis(1));
+    ///
+    /// assert!(before <= id && id < after);
+    /// ```
+    pub fn first_at(time: SystemTime) -> Snowflake {
+        SnowflakeLayout::default().first_at(time)
+    }
+
     /// Get the time at which this `Snowflake` was generated.
     pub fn timestamp(&self) -> SystemTime {
         let epoch: SystemTime = SystemTime::UNIX_EPOCH + Duration::from_secs(EPOCH_SECONDS);
@@ -59,7 +109,7 @@ impl Snowflake {
 
     /// Get the group ID that generated this `Snowflake`.
     pub fn group_id(&self) -> u64 {
-        (self.0 >> GROUP_ID_SHIFT) & MAX_WORKER_ID
+        (self.0 >> GROUP_ID_SHIFT) & MAX_GROUP_ID
     }
 }
 
@@ -96,13 +146,43 @@ impl fmt::Display for Snowflake {
 impl<E: Entity + 'static> Component<E> for Snowflake {}
 
 /// Generates [`Snowflake`] IDs.
+///
+/// Generators can be shared between threads (for example, by wrapping
+/// them in an [`Arc`](std::sync::Arc)); [`generate`](SnowflakeGenerator::generate)
+/// only needs a shared reference, and doesn't take any locks.
+///
+/// # Example
+///
+/// ```
+/// use akashi::SnowflakeGenerator;
+/// use std::sync::Arc;
+/// use std::thread;
+///
+/// let snowflake_gen = Arc::new(SnowflakeGenerator::new(0, 0));
+/// let threads: Vec<_> = (0..4)
+///     .map(|_| {
+///         let snowflake_gen = snowflake_gen.clone();
+///         thread::spawn(move || snowflake_gen.generate())
+///     })
+///     .collect();
+///
+/// let mut ids: Vec<_> = threads.into_iter().map(|t| t.join().unwrap()).collect();
+/// ids.sort();
+/// ids.dedup();
+/// assert_eq!(ids.len(), 4);
+/// ```
 #[derive(Debug)]
 pub struct SnowflakeGenerator {
-    epoch: SystemTime,
-    last_timestamp: u64,
-    sequence: u64,
+    layout: SnowflakeLayout,
+    // The timestamp and sequence number of the last generated ID, packed
+    // as `timestamp << sequence_bits | sequence`.
+    state: AtomicU64,
     group_id: u64,
     worker_id: u64,
+    clock: Arc<dyn Clock>,
+    // For generators created from a lease, the time at which the lease
+    // expires, in milliseconds since the UNIX epoch.
+    lease_expires: Option<Arc<AtomicU64>>,
 }
 
 impl SnowflakeGenerator {
@@ -116,53 +196,337 @@ impl SnowflakeGenerator {
         assert!(worker_id <= MAX_WORKER_ID, "Invalid worker ID");
 
         SnowflakeGenerator {
-            epoch: SystemTime::UNIX_EPOCH + Duration::from_secs(EPOCH_SECONDS),
-            last_timestamp: 0,
-            sequence: 0,
+            layout: SnowflakeLayout::default(),
+            state: AtomicU64::new(0),
             group_id,
             worker_id,
+            clock: Arc::new(SystemClock),
+            lease_expires: None,
         }
     }
 
-    fn get_current_timestamp(&self) -> u64 {
-        match SystemTime::now().duration_since(self.epoch) {
-            Ok(dt) => dt.as_millis() as u64,
-            Err(_e) => panic!("System clock is set before snowflake epoch?"),
+    /// Creates a new `SnowflakeGenerator` that generates IDs using a
+    /// custom [`SnowflakeLayout`].
+    ///
+    /// # Errors
+    ///
+    /// Returns an [`InvalidSnowflake`] error if the group or worker ID
+    /// doesn't fit in the layout.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use akashi::snowflake::{SnowflakeGenerator, SnowflakeLayout};
+    ///
+    /// let layout = SnowflakeLayout::new(0, 10, 12).unwrap();
+    /// let generator = SnowflakeGenerator::with_layout(layout, 0, 1000).unwrap();
+    ///
+    /// let id = generator.generate();
+    /// assert_eq!(layout.decode(id).worker_id(), 1000);
+    /// ```
+    pub fn with_layout(
+        layout: SnowflakeLayout,
+        group_id: u64,
+        worker_id: u64,
+    ) -> Result<SnowflakeGenerator> {
+        if group_id > layout.max_group_id() {
+            return Err(InvalidSnowflake(format!(
+                "group ID {} is greater than {}",
+                group_id,
+                layout.max_group_id()
+            ))
+            .into());
+        }
+
+        if worker_id > layout.max_worker_id() {
+            return Err(InvalidSnowflake(format!(
+                "worker ID {} is greater than {}",
+                worker_id,
+                layout.max_worker_id()
+            ))
+            .into());
         }
+
+        Ok(SnowflakeGenerator {
+            layout,
+            state: AtomicU64::new(0),
+            group_id,
+            worker_id,
+            clock: Arc::new(SystemClock),
+            lease_expires: None,
+        })
+    }
+
+    /// Gets the [`SnowflakeLayout`] this generator uses.
+    pub fn layout(&self) -> &SnowflakeLayout {
+        &self.layout
+    }
+
+    /// Sets the [`Clock`] this generator reads the time from.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use akashi::clock::ManualClock;
+    /// use akashi::snowflake::{SnowflakeGenerator, EPOCH_SECONDS};
+    /// use std::sync::Arc;
+    /// use std::time::{Duration, SystemTime};
+    ///
+    /// let now = SystemTime::UNIX_EPOCH + Duration::from_secs(EPOCH_SECONDS + 3600);
+    /// let clock = Arc::new(ManualClock::new(now));
+    /// let generator = SnowflakeGenerator::new(0, 0).with_clock(clock.clone());
+    ///
+    /// assert_eq!(generator.generate().timestamp(), now);
+    ///
+    /// clock.advance(Duration::from_secs(60));
+    /// assert_eq!(generator.generate().timestamp(), now + Duration::from_secs(60));
+    /// ```
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> SnowflakeGenerator {
+        self.clock = clock;
+        self
+    }
+
+    /// Gets the [`Clock`] this generator reads the time from.
+    pub fn clock(&self) -> &Arc<dyn Clock> {
+        &self.clock
+    }
+
+    /// Makes this generator stop generating IDs once the time in
+    /// `expires` (in milliseconds since the UNIX epoch) has passed.
+    pub(crate) fn with_lease_expiry(mut self, expires: Arc<AtomicU64>) -> SnowflakeGenerator {
+        self.lease_expires = Some(expires);
+        self
     }
 
     /// Generates a new [`Snowflake`] ID.
     ///
-    /// This might cause the current thread to sleep in the rare event
-    /// that the system clock goes backwards.
-    pub fn generate(&mut self) -> Snowflake {
-        let cur_timestamp = self.get_current_timestamp();
-        if self.last_timestamp > cur_timestamp {
-            // Time is moving backwards-- sleep until last_timestamp and attempt to generate again
-            thread::sleep(Duration::from_millis(self.last_timestamp - cur_timestamp));
-            return self.generate();
+    /// IDs from the same generator are strictly increasing, even if the
+    /// clock goes backwards. This will wait on the generator's [`Clock`]
+    /// (which normally means putting the current thread to sleep) if the
+    /// sequence numbers for the current millisecond run out; use
+    /// [`try_generate`](SnowflakeGenerator::try_generate) to avoid this.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the clock is set before the generator's epoch, or past
+    /// the last time its layout can represent, or if the generator was
+    /// created from a [`WorkerLease`](lease::WorkerLease) that has expired.
+    pub fn generate(&self) -> Snowflake {
+        loop {
+            match self.try_generate() {
+                Ok(id) => return id,
+                Err(e) => match e.downcast::<SequenceOverrun>() {
+                    Ok(overrun) => self.clock.sleep(overrun.retry_after()),
+                    Err(e) => panic!("{}", e),
+                },
+            }
+        }
+    }
+
+    /// Generates a new [`Snowflake`] ID without waiting.
+    ///
+    /// # Errors
+    ///
+    /// Returns a [`SequenceOverrun`] error if the sequence numbers for the
+    /// current millisecond have run out. The error says how long to wait
+    /// before trying again.
+    ///
+    /// Returns a [`ClockOutOfRange`] error if the clock is set before the
+    /// generator's epoch, or past the last time its layout can represent.
+    ///
+    /// Returns a [`LeaseExpired`] error if the generator was created from a
+    /// [`WorkerLease`](lease::WorkerLease) that has expired or been lost,
+    /// since its IDs might now be in use elsewhere.
+    pub fn try_generate(&self) -> Result<Snowflake> {
+        let sequence_bits = self.layout.sequence_bits();
+        let max_sequence = self.layout.max_sequence();
+
+        let now = self.clock.now();
+        if let Some(expires) = &self.lease_expires {
+            if lease::unix_millis(now) >= expires.load(Ordering::Acquire) {
+                return Err(LeaseExpired(self.group_id, self.worker_id).into());
+            }
         }
+        let cur_timestamp = match self.layout.millis_since_epoch(now) {
+            Some(millis) if millis <= self.layout.max_timestamp() => millis,
+            _ => return Err(ClockOutOfRange(now).into()),
+        };
 
-        if self.last_timestamp == cur_timestamp {
-            self.sequence = (self.sequence + 1) & SEQUENCE_MASK;
+        let mut cur = self.state.load(Ordering::Acquire);
+        loop {
+            let last_timestamp = cur >> sequence_bits;
 
-            if self.sequence == 0 {
-                // Sequence overrun
-                self.sequence = (1 << (SEQUENCE_BITS + 1)) - 1;
-                thread::sleep(Duration::from_millis(1));
-                return self.generate();
+            let next = if cur_timestamp > last_timestamp {
+                cur_timestamp << sequence_bits
+            } else if (cur & max_sequence) < max_sequence {
+                // Either this is the same millisecond as the last ID, or
+                // time is moving backwards-- keep counting from the last
+                // timestamp so that IDs stay in order.
+                cur + 1
+            } else {
+                return Err(SequenceOverrun(Duration::from_millis(
+                    last_timestamp - cur_timestamp + 1,
+                ))
+                .into());
+            };
+
+            match self
+                .state
+                .compare_exchange_weak(cur, next, Ordering::AcqRel, Ordering::Acquire)
+            {
+                Ok(_) => {
+                    return Ok(Snowflake(self.layout.pack(
+                        next >> sequence_bits,
+                        self.group_id,
+                        self.worker_id,
+                        next & max_sequence,
+                    )))
+                }
+                Err(actual) => cur = actual,
             }
-        } else {
-            self.sequence = 0;
         }
+    }
+}
+
+/// Returned by [`SnowflakeGenerator::try_generate`] when the sequence
+/// numbers for the current millisecond have run out.
+#[derive(Fail, Debug)]
+#[fail(display = "Snowflake sequence overrun; retry after {:?}", _0)]
+pub struct SequenceOverrun(Duration);
+
+impl SequenceOverrun {
+    /// Gets how long to wait before generating another ID.
+    pub fn retry_after(&self) -> Duration {
+        self.0
+    }
+}
+
+/// Returned by [`SnowflakeGenerator::try_generate`] when the clock is set
+/// to a time that a [`SnowflakeLayout`] can't represent.
+#[derive(Fail, Debug)]
+#[fail(
+    display = "Clock time {:?} is outside of the snowflake layout's range",
+    _0
+)]
+pub struct ClockOutOfRange(SystemTime);
+
+impl ClockOutOfRange {
+    /// Gets the time the clock was set to.
+    pub fn time(&self) -> SystemTime {
+        self.0
+    }
+}
 
-        self.last_timestamp = cur_timestamp;
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::ManualClock;
+    use std::thread;
+
+    #[test]
+    fn test_accessors() {
+        let generator = SnowflakeGenerator::new(MAX_GROUP_ID, 0);
+        let id = generator.generate();
+        assert_eq!(id.group_id(), MAX_GROUP_ID);
+        assert_eq!(id.worker_id(), 0);
+
+        let generator = SnowflakeGenerator::new(0, MAX_WORKER_ID);
+        let id = generator.generate();
+        assert_eq!(id.group_id(), 0);
+        assert_eq!(id.worker_id(), MAX_WORKER_ID);
+    }
+
+    #[test]
+    fn test_first_at_range() {
+        let epoch = SystemTime::UNIX_EPOCH + Duration::from_secs(EPOCH_SECONDS);
+        assert_eq!(Snowflake::first_at(SystemTime::UNIX_EPOCH), Snowflake(0));
+
+        // Times past the end of the range don't wrap around.
+        let far = epoch + Duration::from_millis(1 << TIMESTAMP_BITS) * 2;
+        let last = Snowflake::first_at(far);
+        assert!(last > Snowflake::first_at(SystemTime::now()));
+        assert_eq!(last, SnowflakeLayout::default().first_at(far));
+    }
+
+    #[test]
+    fn test_sequence_overrun() {
+        let generator = SnowflakeGenerator::new(1, 2);
+        let mut last = generator.generate();
+
+        for _ in 0..(3 * (SEQUENCE_MASK + 1)) {
+            let id = generator.generate();
+            assert!(id > last);
+            assert!(id.sequence() <= SEQUENCE_MASK);
+            assert_eq!(id.group_id(), 1);
+            assert_eq!(id.worker_id(), 2);
+            last = id;
+        }
+    }
+
+    #[test]
+    fn test_shared_generator() {
+        let generator = Arc::new(SnowflakeGenerator::new(3, 4));
+        let threads: Vec<_> = (0..8)
+            .map(|_| {
+                let generator = generator.clone();
+                thread::spawn(move || {
+                    let ids: Vec<Snowflake> = (0..10_000).map(|_| generator.generate()).collect();
+                    assert!(ids.windows(2).all(|w| w[0] < w[1]));
+                    ids
+                })
+            })
+            .collect();
+
+        let mut ids: Vec<Snowflake> = threads
+            .into_iter()
+            .flat_map(|t| t.join().unwrap())
+            .collect();
+        let total = ids.len();
+        ids.sort();
+        ids.dedup();
+        assert_eq!(ids.len(), total);
+    }
+
+    #[test]
+    fn test_manual_clock() {
+        let start = SystemTime::UNIX_EPOCH + Duration::from_secs(EPOCH_SECONDS + 60);
+        let clock = Arc::new(ManualClock::new(start));
+        let generator = SnowflakeGenerator::new(0, 0).with_clock(clock.clone());
+
+        // Exhaust the sequence numbers without letting time pass.
+        let mut last = generator.try_generate().unwrap();
+        for _ in 0..SEQUENCE_MASK {
+            let id = generator.try_generate().unwrap();
+            assert!(id > last);
+            assert_eq!(id.timestamp(), start);
+            last = id;
+        }
+
+        let err = generator.try_generate().unwrap_err();
+        let overrun = err.downcast_ref::<SequenceOverrun>().unwrap();
+        assert_eq!(overrun.retry_after(), Duration::from_millis(1));
+
+        // generate() waits on the manual clock, which moves it forward.
+        let id = generator.generate();
+        assert!(id > last);
+        assert_eq!(clock.now(), start + Duration::from_millis(1));
+        assert_eq!(id.timestamp(), clock.now());
+        assert_eq!(id.sequence(), 0);
+
+        // Going backwards keeps counting from the latest timestamp.
+        clock.set(start - Duration::from_secs(10));
+        let next = generator.try_generate().unwrap();
+        assert!(next > id);
+        assert_eq!(next.timestamp(), id.timestamp());
+
+        clock.set(SystemTime::UNIX_EPOCH);
+        let err = generator.try_generate().unwrap_err();
+        assert!(err.downcast_ref::<ClockOutOfRange>().is_some());
+    }
 
-        Snowflake(
-            (cur_timestamp << TIMESTAMP_SHIFT)
-                | (self.group_id << GROUP_ID_SHIFT)
-                | (self.worker_id << WORKER_ID_SHIFT)
-                | self.sequence,
-        )
+    #[test]
+    #[should_panic]
+    fn test_invalid_worker_id() {
+        SnowflakeGenerator::new(0, MAX_WORKER_ID + 1);
     }
 }
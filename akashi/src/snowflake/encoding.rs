@@ -0,0 +1,409 @@
+//! String encodings for [`Snowflakes`](Snowflake).
+//!
+//! Besides plain decimal, `Snowflakes` can be written in two more compact
+//! forms, which are better suited to IDs that players see and type in
+//! (such as friend codes or links to shared cards):
+//!
+//! - [`Base62`](Encoding::Base62) uses digits and upper- and lower-case
+//!   letters, and is the shortest encoding (at most 11 characters), but
+//!   is case-sensitive.
+//! - [Crockford's base32](https://www.crockford.com/base32.html)
+//!   ([`Crockford`](Encoding::Crockford)) is at most 13 characters long,
+//!   and is case-insensitive. It leaves out letters that are easily
+//!   confused with digits, and reads `I` and `L` as `1` and `O` as `0` on
+//!   input. [`CrockfordChecked`](Encoding::CrockfordChecked) adds a check
+//!   symbol to the end, which catches any single mistyped character and
+//!   any pair of swapped adjacent characters.
+//!
+//! The [`serde_string`] module can be used to serialize `Snowflakes` as
+//! decimal strings, for clients (such as JavaScript) that can't represent
+//! every 64-bit integer exactly.
+//!
+//! # Example
+//!
+//! ```
+//! use akashi::snowflake::{Encoding, Snowflake};
+//!
+//! let id = Snowflake::from(123_456_789u64);
+//! let code = id.encode(Encoding::CrockfordChecked);
+//! assert_eq!(code, "3NQK8NU");
+//!
+//! assert_eq!(Snowflake::decode("3nqk-8nu", Encoding::CrockfordChecked).unwrap(), id);
+//! assert!(Snowflake::decode("3NQK9NU", Encoding::CrockfordChecked).is_err());
+//!
+//! assert_eq!(id.encode(Encoding::Base62), "8M0kX");
+//! assert_eq!("123456789".parse::<Snowflake>().unwrap(), id);
+//! ```
+
+use std::str::FromStr;
+
+use failure::Fail;
+
+use super::Snowflake;
+use crate::util::Result;
+
+const BASE62_ALPHABET: &[u8; 62] =
+    b"0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz";
+
+/// The Crockford base32 digits, followed by the extra check symbols.
+const CROCKFORD_ALPHABET: &[u8; 37] = b"0123456789ABCDEFGHJKMNPQRSTVWXYZ*~$=U";
+
+/// Ways of writing a [`Snowflake`] as a string.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Encoding {
+    /// Plain decimal digits.
+    Decimal,
+
+    /// Case-sensitive base62, using the digits `0-9`, then `A-Z`, then
+    /// `a-z`.
+    Base62,
+
+    /// Crockford's base32, without a check symbol.
+    Crockford,
+
+    /// Crockford's base32, followed by a check symbol.
+    CrockfordChecked,
+}
+
+/// Returned when a string can't be decoded as a [`Snowflake`].
+#[derive(Fail, Debug, Clone, PartialEq, Eq)]
+#[fail(display = "Invalid snowflake string: {}", _0)]
+pub struct ParseSnowflakeError(String);
+
+impl Snowflake {
+    /// Writes this `Snowflake` as a string, using the given encoding.
+    pub fn encode(&self, encoding: Encoding) -> String {
+        match encoding {
+            Encoding::Decimal => self.0.to_string(),
+            Encoding::Base62 => encode_digits(self.0, BASE62_ALPHABET),
+            Encoding::Crockford => encode_digits(self.0, &CROCKFORD_ALPHABET[..32]),
+            Encoding::CrockfordChecked => {
+                let mut encoded = encode_digits(self.0, &CROCKFORD_ALPHABET[..32]);
+                encoded.push(CROCKFORD_ALPHABET[(self.0 % 37) as usize] as char);
+                encoded
+            }
+        }
+    }
+
+    /// Reads a `Snowflake` from a string written with the given encoding.
+    ///
+    /// Crockford-encoded strings can contain hyphens, which are ignored.
+    ///
+    /// # Errors
+    ///
+    /// Returns a [`ParseSnowflakeError`] if the string contains invalid
+    /// characters, is out of range, or (for
+    /// [`CrockfordChecked`](Encoding::CrockfordChecked)) doesn't match
+    /// its check symbol.
+    pub fn decode(s: &str, encoding: Encoding) -> Result<Snowflake> {
+        Ok(decode(s, encoding)?)
+    }
+}
+
+/// Parses a `Snowflake` from a decimal string.
+///
+/// For convenience, the bracketed form written by `Snowflake`'s `Display`
+/// implementation (such as `[1234]`) is accepted too.
+impl FromStr for Snowflake {
+    type Err = ParseSnowflakeError;
+
+    fn from_str(s: &str) -> std::result::Result<Snowflake, ParseSnowflakeError> {
+        let inner = s
+            .strip_prefix('[')
+            .and_then(|rest| rest.strip_suffix(']'))
+            .unwrap_or(s);
+
+        decode(inner, Encoding::Decimal)
+    }
+}
+
+fn encode_digits(mut val: u64, alphabet: &[u8]) -> String {
+    let base = alphabet.len() as u64;
+    let mut digits = Vec::new();
+
+    loop {
+        digits.push(alphabet[(val % base) as usize]);
+        val /= base;
+        if val == 0 {
+            break;
+        }
+    }
+
+    digits.iter().rev().map(|&c| c as char).collect()
+}
+
+fn base62_value(c: char) -> Option<u64> {
+    match c {
+        '0'..='9' => Some(c as u64 - '0' as u64),
+        'A'..='Z' => Some(c as u64 - 'A' as u64 + 10),
+        'a'..='z' => Some(c as u64 - 'a' as u64 + 36),
+        _ => None,
+    }
+}
+
+/// Gets the value of a Crockford base32 symbol, including the check
+/// symbols and the aliases for commonly-confused letters.
+fn crockford_value(c: char) -> Option<u64> {
+    let c = match c.to_ascii_uppercase() {
+        'O' => '0',
+        'I' | 'L' => '1',
+        c => c,
+    };
+
+    CROCKFORD_ALPHABET
+        .iter()
+        .position(|&sym| sym as char == c)
+        .map(|pos| pos as u64)
+}
+
+fn decode_digits<I>(s: &str, digits: I, base: u64) -> std::result::Result<u64, ParseSnowflakeError>
+where
+    I: Iterator<Item = Option<u64>>,
+{
+    let mut val: u64 = 0;
+    let mut empty = true;
+
+    for digit in digits {
+        let digit = match digit {
+            Some(d) if d < base => d,
+            _ => return Err(ParseSnowflakeError(format!("invalid character in {:?}", s))),
+        };
+
+        val = val
+            .checked_mul(base)
+            .and_then(|val| val.checked_add(digit))
+            .ok_or_else(|| ParseSnowflakeError(format!("{:?} is out of range", s)))?;
+        empty = false;
+    }
+
+    if empty {
+        return Err(ParseSnowflakeError(String::from("empty string")));
+    }
+
+    Ok(val)
+}
+
+fn decode(s: &str, encoding: Encoding) -> std::result::Result<Snowflake, ParseSnowflakeError> {
+    let val = match encoding {
+        Encoding::Decimal => {
+            decode_digits(s, s.chars().map(|c| c.to_digit(10).map(u64::from)), 10)?
+        }
+        Encoding::Base62 => decode_digits(s, s.chars().map(base62_value), 62)?,
+        Encoding::Crockford => {
+            decode_digits(s, s.chars().filter(|&c| c != '-').map(crockford_value), 32)?
+        }
+        Encoding::CrockfordChecked => {
+            let trimmed = s.trim_end_matches('-');
+            let mut chars = trimmed.chars();
+            let check = chars
+                .next_back()
+                .and_then(crockford_value)
+                .ok_or_else(|| ParseSnowflakeError(format!("missing check symbol in {:?}", s)))?;
+
+            let val = decode_digits(s, chars.filter(|&c| c != '-').map(crockford_value), 32)?;
+
+            if val % 37 != check {
+                return Err(ParseSnowflakeError(format!(
+                    "check symbol doesn't match in {:?}",
+                    s
+                )));
+            }
+
+            val
+        }
+    };
+
+    Ok(Snowflake(val))
+}
+
+/// Serializes [`Snowflakes`](Snowflake) as decimal strings.
+///
+/// Use this with serde's `with` attribute. When deserializing, plain
+/// integers are accepted as well, so existing data doesn't need to be
+/// converted.
+///
+/// # Example
+///
+/// ```
+/// use akashi::Snowflake;
+/// use serde::{Deserialize, Serialize};
+///
+/// #[derive(Serialize, Deserialize, Debug, PartialEq)]
+/// struct Friend {
+///     #[serde(with = "akashi::snowflake::serde_string")]
+///     id: Snowflake,
+/// }
+///
+/// let friend = Friend { id: Snowflake::from(u64::MAX) };
+/// let json = serde_json::to_string(&friend).unwrap();
+/// assert_eq!(json, r#"{"id":"18446744073709551615"}"#);
+///
+/// let parsed: Friend = serde_json::from_str(&json).unwrap();
+/// assert_eq!(parsed, friend);
+/// let parsed: Friend = serde_json::from_str(r#"{"id":18446744073709551615}"#).unwrap();
+/// assert_eq!(parsed, friend);
+/// ```
+pub mod serde_string {
+    use std::fmt;
+
+    use serde::de::{self, Visitor};
+    use serde::{Deserializer, Serializer};
+
+    use super::{decode, Encoding, Snowflake};
+
+    /// Serializes a [`Snowflake`] as a decimal string.
+    pub fn serialize<S>(id: &Snowflake, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.collect_str(&id.0)
+    }
+
+    /// Deserializes a [`Snowflake`] from a decimal string or an integer.
+    pub fn deserialize<'de, D>(deserializer: D) -> Result<Snowflake, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserializer.deserialize_any(SnowflakeVisitor)
+    }
+
+    struct SnowflakeVisitor;
+
+    impl<'de> Visitor<'de> for SnowflakeVisitor {
+        type Value = Snowflake;
+
+        fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            f.write_str("a snowflake as a decimal string or an integer")
+        }
+
+        fn visit_str<E: de::Error>(self, v: &str) -> Result<Snowflake, E> {
+            decode(v, Encoding::Decimal).map_err(E::custom)
+        }
+
+        fn visit_u64<E: de::Error>(self, v: u64) -> Result<Snowflake, E> {
+            Ok(Snowflake(v))
+        }
+
+        fn visit_i64<E: de::Error>(self, v: i64) -> Result<Snowflake, E> {
+            Ok(Snowflake::from(v))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ENCODINGS: [Encoding; 4] = [
+        Encoding::Decimal,
+        Encoding::Base62,
+        Encoding::Crockford,
+        Encoding::CrockfordChecked,
+    ];
+
+    #[test]
+    fn test_round_trip() {
+        let values = [0, 1, 36, 37, 61, 62, 1 << 40, u64::MAX - 1, u64::MAX];
+
+        for &encoding in ENCODINGS.iter() {
+            for &val in values.iter() {
+                let id = Snowflake(val);
+                let encoded = id.encode(encoding);
+                assert_eq!(Snowflake::decode(&encoded, encoding).unwrap(), id);
+            }
+        }
+
+        assert_eq!(Snowflake(u64::MAX).encode(Encoding::Base62).len(), 11);
+        assert_eq!(Snowflake(u64::MAX).encode(Encoding::Crockford).len(), 13);
+        assert_eq!(Snowflake(0).encode(Encoding::Crockford), "0");
+    }
+
+    #[test]
+    fn test_crockford_input() {
+        let id = Snowflake(0x1234_5678_9abc);
+        let encoded = id.encode(Encoding::CrockfordChecked);
+
+        let lower = encoded.to_lowercase();
+        assert_eq!(
+            Snowflake::decode(&lower, Encoding::CrockfordChecked).unwrap(),
+            id
+        );
+
+        let (head, tail) = encoded.split_at(4);
+        let hyphenated = format!("{}-{}", head, tail);
+        assert_eq!(
+            Snowflake::decode(&hyphenated, Encoding::CrockfordChecked).unwrap(),
+            id
+        );
+
+        assert_eq!(
+            Snowflake::decode("1O1", Encoding::Crockford).unwrap(),
+            Snowflake::decode("lol", Encoding::Crockford).unwrap()
+        );
+        assert_eq!(
+            Snowflake::decode("IO", Encoding::Crockford).unwrap(),
+            Snowflake(32)
+        );
+    }
+
+    #[test]
+    fn test_check_symbol() {
+        let id = Snowflake(987_654_321_012_345);
+        let encoded: Vec<char> = id.encode(Encoding::CrockfordChecked).chars().collect();
+        let digits = &CROCKFORD_ALPHABET[..32];
+
+        // Every single-character typo should be caught.
+        for i in 0..encoded.len() {
+            for &sym in CROCKFORD_ALPHABET.iter() {
+                let sym = sym as char;
+                if sym == encoded[i] || (i + 1 < encoded.len() && !digits.contains(&(sym as u8))) {
+                    continue;
+                }
+
+                let mut typo = encoded.clone();
+                typo[i] = sym;
+                let typo: String = typo.into_iter().collect();
+                assert!(
+                    Snowflake::decode(&typo, Encoding::CrockfordChecked).is_err(),
+                    "{} was accepted",
+                    typo
+                );
+            }
+        }
+
+        // As should swapping adjacent characters.
+        for i in 0..(encoded.len() - 1) {
+            if encoded[i] == encoded[i + 1] {
+                continue;
+            }
+
+            let mut swapped = encoded.clone();
+            swapped.swap(i, i + 1);
+            let swapped: String = swapped.into_iter().collect();
+            assert!(Snowflake::decode(&swapped, Encoding::CrockfordChecked).is_err());
+        }
+    }
+
+    #[test]
+    fn test_invalid() {
+        for &encoding in ENCODINGS.iter() {
+            assert!(Snowflake::decode("", encoding).is_err());
+            assert!(Snowflake::decode("!", encoding).is_err());
+        }
+
+        assert!(Snowflake::decode("18446744073709551616", Encoding::Decimal).is_err());
+        assert!(Snowflake::decode("LygHa16AHYG", Encoding::Base62).is_err());
+        assert!(Snowflake::decode("G000000000000", Encoding::Crockford).is_err());
+        assert!(Snowflake::decode("U", Encoding::Crockford).is_err());
+        assert!(Snowflake::decode("-", Encoding::CrockfordChecked).is_err());
+    }
+
+    #[test]
+    fn test_from_str() {
+        let id = Snowflake(1234);
+        assert_eq!("1234".parse::<Snowflake>().unwrap(), id);
+        assert_eq!(id.to_string().parse::<Snowflake>().unwrap(), id);
+        assert!("[1234".parse::<Snowflake>().is_err());
+        assert!("-1".parse::<Snowflake>().is_err());
+    }
+}
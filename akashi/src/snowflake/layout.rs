@@ -0,0 +1,489 @@
+//! Configurable bit layouts for [`Snowflakes`](Snowflake).
+
+use std::fmt;
+use std::time::{Duration, SystemTime};
+
+use failure::Fail;
+
+use super::{
+    Snowflake, EPOCH_SECONDS, GROUP_ID_BITS, SEQUENCE_BITS, TIMESTAMP_BITS, WORKER_ID_BITS,
+};
+use crate::clock::{Clock, SystemClock};
+use crate::util::Result;
+
+/// The number of bits in a [`Snowflake`] that layouts can use.
+///
+/// The highest bit is always left clear, so that IDs stay positive when
+/// stored as signed 64-bit integers (as in SQLite, for example).
+pub const USABLE_BITS: u32 = 63;
+
+/// The fewest bits a [`SnowflakeLayout`] can use for its timestamp.
+///
+/// 32 bits of milliseconds covers a little under 50 days.
+pub const MIN_TIMESTAMP_BITS: u32 = 32;
+
+/// Describes how the fields of a [`Snowflake`] are packed into 64 bits.
+///
+/// From most to least significant, a `Snowflake` contains a timestamp
+/// (in milliseconds since the layout's epoch), a group ID, a worker ID,
+/// and a sequence number. The default layout, which
+/// [`SnowflakeGenerator::new`](super::SnowflakeGenerator::new) and the
+/// accessors on [`Snowflake`] use, has a 41-bit timestamp, 5-bit group and
+/// worker IDs, and a 12-bit sequence number, counted from
+/// [`EPOCH_SECONDS`].
+///
+/// # Example
+///
+/// ```
+/// use akashi::snowflake::{SnowflakeLayout, SnowflakeParts};
+/// use std::time::{Duration, SystemTime};
+///
+/// // Trade some sequence bits for more worker IDs.
+/// let layout = SnowflakeLayout::new(3, 10, 9)
+///     .unwrap()
+///     .with_epoch(SystemTime::UNIX_EPOCH + Duration::from_secs(1_600_000_000));
+/// assert_eq!(layout.max_worker_id(), 1023);
+///
+/// let time = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+/// let id = layout.compose(&SnowflakeParts::new(time, 2, 700, 5)).unwrap();
+///
+/// let parts = layout.decode(id);
+/// assert_eq!(parts.timestamp(), time);
+/// assert_eq!(parts.group_id(), 2);
+/// assert_eq!(parts.worker_id(), 700);
+/// assert_eq!(parts.sequence(), 5);
+/// ```
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct SnowflakeLayout {
+    epoch: SystemTime,
+    group_id_bits: u32,
+    worker_id_bits: u32,
+    sequence_bits: u32,
+}
+
+impl SnowflakeLayout {
+    /// Creates a layout with the given field widths, using the default
+    /// epoch.
+    ///
+    /// Whatever bits are left over (out of [`USABLE_BITS`]) are used for
+    /// the timestamp.
+    ///
+    /// # Errors
+    ///
+    /// Returns an [`InvalidLayout`] error if the fields would leave fewer
+    /// than [`MIN_TIMESTAMP_BITS`] bits for the timestamp.
+    pub fn new(group_id_bits: u32, worker_id_bits: u32, sequence_bits: u32) -> Result<Self> {
+        let used = group_id_bits
+            .checked_add(worker_id_bits)
+            .and_then(|bits| bits.checked_add(sequence_bits));
+
+        match used {
+            Some(used) if used + MIN_TIMESTAMP_BITS <= USABLE_BITS => Ok(SnowflakeLayout {
+                epoch: default_epoch(),
+                group_id_bits,
+                worker_id_bits,
+                sequence_bits,
+            }),
+            _ => Err(InvalidLayout(format!(
+                "{} group ID bits, {} worker ID bits, and {} sequence bits leave fewer than {} bits for timestamps",
+                group_id_bits, worker_id_bits, sequence_bits, MIN_TIMESTAMP_BITS
+            ))
+            .into()),
+        }
+    }
+
+    /// Sets the time that timestamps in this layout are counted from.
+    pub fn with_epoch(mut self, epoch: SystemTime) -> Self {
+        self.epoch = epoch;
+        self
+    }
+
+    /// Gets the time that timestamps in this layout are counted from.
+    pub fn epoch(&self) -> SystemTime {
+        self.epoch
+    }
+
+    /// Gets the number of bits used for timestamps.
+    pub fn timestamp_bits(&self) -> u32 {
+        USABLE_BITS - self.group_id_bits - self.worker_id_bits - self.sequence_bits
+    }
+
+    /// Gets the number of bits used for group IDs.
+    pub fn group_id_bits(&self) -> u32 {
+        self.group_id_bits
+    }
+
+    /// Gets the number of bits used for worker IDs.
+    pub fn worker_id_bits(&self) -> u32 {
+        self.worker_id_bits
+    }
+
+    /// Gets the number of bits used for sequence numbers.
+    pub fn sequence_bits(&self) -> u32 {
+        self.sequence_bits
+    }
+
+    /// Gets the highest timestamp, in milliseconds since the epoch, that
+    /// this layout can hold.
+    pub fn max_timestamp(&self) -> u64 {
+        mask(self.timestamp_bits())
+    }
+
+    /// Gets the highest group ID that this layout can hold.
+    pub fn max_group_id(&self) -> u64 {
+        mask(self.group_id_bits)
+    }
+
+    /// Gets the highest worker ID that this layout can hold.
+    pub fn max_worker_id(&self) -> u64 {
+        mask(self.worker_id_bits)
+    }
+
+    /// Gets the highest sequence number that this layout can hold.
+    pub fn max_sequence(&self) -> u64 {
+        mask(self.sequence_bits)
+    }
+
+    fn worker_id_shift(&self) -> u32 {
+        self.sequence_bits
+    }
+
+    fn group_id_shift(&self) -> u32 {
+        self.sequence_bits + self.worker_id_bits
+    }
+
+    fn timestamp_shift(&self) -> u32 {
+        self.sequence_bits + self.worker_id_bits + self.group_id_bits
+    }
+
+    /// Packs raw field values into a [`Snowflake`], without checking that
+    /// they fit.
+    pub(crate) fn pack(&self, millis: u64, group_id: u64, worker_id: u64, sequence: u64) -> u64 {
+        (millis << self.timestamp_shift())
+            | (group_id << self.group_id_shift())
+            | (worker_id << self.worker_id_shift())
+            | sequence
+    }
+
+    /// Gets the number of milliseconds between this layout's epoch and
+    /// `time`, or `None` if `time` is before the epoch.
+    pub(crate) fn millis_since_epoch(&self, time: SystemTime) -> Option<u64> {
+        time.duration_since(self.epoch)
+            .ok()
+            .map(|dt| dt.as_millis() as u64)
+    }
+
+    /// Gets the smallest [`Snowflake`] that could have been generated
+    /// under this layout at the given time.
+    ///
+    /// Times before the epoch map to `Snowflake(0)`, and times past the
+    /// end of the layout's range map to the largest timestamp it can hold.
+    pub fn first_at(&self, time: SystemTime) -> Snowflake {
+        let millis = self
+            .millis_since_epoch(time)
+            .unwrap_or(0)
+            .min(self.max_timestamp());
+
+        Snowflake(self.pack(millis, 0, 0, 0))
+    }
+
+    /// Splits a [`Snowflake`] into its fields, according to this layout.
+    ///
+    /// Any bits above the ones this layout uses are ignored; use
+    /// [`validate`](SnowflakeLayout::validate) to check for them.
+    pub fn decode(&self, id: Snowflake) -> SnowflakeParts {
+        let raw = id.0;
+        let millis = (raw >> self.timestamp_shift()) & self.max_timestamp();
+
+        SnowflakeParts {
+            timestamp: self.epoch + Duration::from_millis(millis),
+            group_id: (raw >> self.group_id_shift()) & self.max_group_id(),
+            worker_id: (raw >> self.worker_id_shift()) & self.max_worker_id(),
+            sequence: raw & self.max_sequence(),
+        }
+    }
+
+    /// Packs a set of fields into a [`Snowflake`], according to this
+    /// layout.
+    ///
+    /// The timestamp is truncated to the millisecond.
+    ///
+    /// # Errors
+    ///
+    /// Returns an [`InvalidSnowflake`] error if any of the fields don't
+    /// fit in this layout, or if the timestamp is before the epoch.
+    pub fn compose(&self, parts: &SnowflakeParts) -> Result<Snowflake> {
+        let millis = self.millis_since_epoch(parts.timestamp).ok_or_else(|| {
+            InvalidSnowflake(String::from("timestamp is before the layout's epoch"))
+        })?;
+
+        check_field("timestamp", millis, self.max_timestamp())?;
+        check_field("group ID", parts.group_id, self.max_group_id())?;
+        check_field("worker ID", parts.worker_id, self.max_worker_id())?;
+        check_field("sequence number", parts.sequence, self.max_sequence())?;
+
+        Ok(Snowflake(self.pack(
+            millis,
+            parts.group_id,
+            parts.worker_id,
+            parts.sequence,
+        )))
+    }
+
+    /// Checks that a [`Snowflake`] could have been generated under this
+    /// layout, and returns its fields if so.
+    ///
+    /// # Errors
+    ///
+    /// Returns an [`InvalidSnowflake`] error if any bits above the ones
+    /// this layout uses are set, or if the ID's timestamp is in the future.
+    pub fn validate(&self, id: Snowflake) -> Result<SnowflakeParts> {
+        self.validate_at(id, SystemClock.now())
+    }
+
+    /// Checks that a [`Snowflake`] could have been generated under this
+    /// layout by `now`, and returns its fields if so.
+    ///
+    /// This works like [`validate`](SnowflakeLayout::validate), but
+    /// takes the current time from the caller, such as from a
+    /// [`Clock`].
+    ///
+    /// # Errors
+    ///
+    /// Returns an [`InvalidSnowflake`] error if any bits above the ones
+    /// this layout uses are set, or if the ID's timestamp is after `now`.
+    pub fn validate_at(&self, id: Snowflake, now: SystemTime) -> Result<SnowflakeParts> {
+        if id.0 >> USABLE_BITS != 0 {
+            return Err(InvalidSnowflake(format!("{} has reserved bits set", id)).into());
+        }
+
+        let parts = self.decode(id);
+        if parts.timestamp > now {
+            return Err(InvalidSnowflake(format!("{} has a timestamp in the future", id)).into());
+        }
+
+        Ok(parts)
+    }
+
+    /// Converts a [`Snowflake`] generated under another layout to this
+    /// one, keeping the same timestamp, group ID, worker ID, and sequence
+    /// number.
+    ///
+    /// # Errors
+    ///
+    /// Returns an [`InvalidSnowflake`] error if any of the ID's fields
+    /// don't fit in this layout.
+    pub fn migrate(&self, id: Snowflake, from: &SnowflakeLayout) -> Result<Snowflake> {
+        self.compose(&from.decode(id))
+    }
+
+    /// Converts a [`Snowflake`] generated by an older version of
+    /// [`SnowflakeGenerator`](super::SnowflakeGenerator) to this layout.
+    ///
+    /// Older generators used the same field positions as the default
+    /// layout, but accepted group and worker IDs up to 63 and let sequence
+    /// numbers run up to 8191, so those fields could spill into their
+    /// neighbours. IDs from generators with group and worker IDs of 31 or
+    /// less that never made more than 4096 IDs in a millisecond decode
+    /// the same way under both layouts; for other IDs, the overlapping
+    /// bits can't be told apart, and are read as if they belonged to the
+    /// default layout.
+    ///
+    /// # Errors
+    ///
+    /// Returns an [`InvalidSnowflake`] error if any of the ID's fields
+    /// don't fit in this layout.
+    pub fn migrate_legacy(&self, id: Snowflake) -> Result<Snowflake> {
+        self.migrate(id, &SnowflakeLayout::default())
+    }
+}
+
+impl Default for SnowflakeLayout {
+    fn default() -> Self {
+        SnowflakeLayout {
+            epoch: default_epoch(),
+            group_id_bits: GROUP_ID_BITS,
+            worker_id_bits: WORKER_ID_BITS,
+            sequence_bits: SEQUENCE_BITS,
+        }
+    }
+}
+
+fn default_epoch() -> SystemTime {
+    SystemTime::UNIX_EPOCH + Duration::from_secs(EPOCH_SECONDS)
+}
+
+fn mask(bits: u32) -> u64 {
+    if bits >= 64 {
+        u64::MAX
+    } else {
+        (1 << bits) - 1
+    }
+}
+
+fn check_field(name: &str, value: u64, max: u64) -> Result<()> {
+    if value > max {
+        Err(InvalidSnowflake(format!("{} {} is greater than {}", name, value, max)).into())
+    } else {
+        Ok(())
+    }
+}
+
+// The default layout should agree with the constants in the parent
+// module.
+const _: () =
+    assert!(TIMESTAMP_BITS + GROUP_ID_BITS + WORKER_ID_BITS + SEQUENCE_BITS == USABLE_BITS);
+
+/// The fields of a [`Snowflake`], as decoded by a [`SnowflakeLayout`].
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct SnowflakeParts {
+    timestamp: SystemTime,
+    group_id: u64,
+    worker_id: u64,
+    sequence: u64,
+}
+
+impl SnowflakeParts {
+    /// Creates a new set of fields, for use with
+    /// [`SnowflakeLayout::compose`].
+    pub fn new(timestamp: SystemTime, group_id: u64, worker_id: u64, sequence: u64) -> Self {
+        SnowflakeParts {
+            timestamp,
+            group_id,
+            worker_id,
+            sequence,
+        }
+    }
+
+    /// Gets the time at which the [`Snowflake`] was generated.
+    pub fn timestamp(&self) -> SystemTime {
+        self.timestamp
+    }
+
+    /// Gets the group ID that generated the [`Snowflake`].
+    pub fn group_id(&self) -> u64 {
+        self.group_id
+    }
+
+    /// Gets the worker ID that generated the [`Snowflake`].
+    pub fn worker_id(&self) -> u64 {
+        self.worker_id
+    }
+
+    /// Gets the sequence number of the [`Snowflake`].
+    pub fn sequence(&self) -> u64 {
+        self.sequence
+    }
+}
+
+impl fmt::Display for SnowflakeParts {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "group {}, worker {}, sequence {} at {:?}",
+            self.group_id, self.worker_id, self.sequence, self.timestamp
+        )
+    }
+}
+
+/// Returned when a [`SnowflakeLayout`] would be invalid.
+#[derive(Fail, Debug)]
+#[fail(display = "Invalid snowflake layout: {}", _0)]
+pub struct InvalidLayout(String);
+
+/// Returned when a [`Snowflake`] or its fields don't fit a
+/// [`SnowflakeLayout`].
+#[derive(Fail, Debug)]
+#[fail(display = "Invalid snowflake: {}", _0)]
+pub struct InvalidSnowflake(pub(crate) String);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::snowflake::SnowflakeGenerator;
+
+    #[test]
+    fn test_default_layout() {
+        let layout = SnowflakeLayout::default();
+        assert_eq!(layout.timestamp_bits(), 41);
+        assert_eq!(layout.max_group_id(), 31);
+        assert_eq!(layout.max_worker_id(), 31);
+        assert_eq!(layout.max_sequence(), 4095);
+
+        // Fields at their maximum values shouldn't overlap.
+        let time = layout.epoch() + Duration::from_millis(12345);
+        let id = layout
+            .compose(&SnowflakeParts::new(time, 31, 31, 4095))
+            .unwrap();
+        assert_eq!(id.group_id(), 31);
+        assert_eq!(id.worker_id(), 31);
+        assert_eq!(id.sequence(), 4095);
+        assert_eq!(id.timestamp(), time);
+
+        let id = layout.compose(&SnowflakeParts::new(time, 0, 0, 0)).unwrap();
+        assert_eq!(id.group_id(), 0);
+        assert_eq!(id.worker_id(), 0);
+        assert_eq!(id.sequence(), 0);
+    }
+
+    #[test]
+    fn test_generated_ids_decode() {
+        let generator = SnowflakeGenerator::new(17, 29);
+        let id = generator.generate();
+
+        let parts = SnowflakeLayout::default().validate(id).unwrap();
+        assert_eq!(parts.group_id(), 17);
+        assert_eq!(parts.worker_id(), 29);
+        assert_eq!(parts, SnowflakeLayout::default().decode(id));
+    }
+
+    #[test]
+    fn test_invalid() {
+        assert!(SnowflakeLayout::new(10, 10, 12).is_err());
+        assert!(SnowflakeLayout::new(u32::MAX, 1, 1).is_err());
+        assert!(SnowflakeLayout::new(10, 10, 11).is_ok());
+
+        let layout = SnowflakeLayout::default();
+        let time = layout.epoch() + Duration::from_secs(1);
+        assert!(layout
+            .compose(&SnowflakeParts::new(time, 32, 0, 0))
+            .is_err());
+        assert!(layout
+            .compose(&SnowflakeParts::new(time, 0, 0, 4096))
+            .is_err());
+        assert!(layout
+            .compose(&SnowflakeParts::new(SystemTime::UNIX_EPOCH, 0, 0, 0))
+            .is_err());
+
+        assert!(layout.validate(Snowflake(1 << 63)).is_err());
+        let future = SystemTime::now() + Duration::from_secs(3600);
+        let id = layout
+            .compose(&SnowflakeParts::new(future, 0, 0, 0))
+            .unwrap();
+        assert!(layout.validate(id).is_err());
+        assert!(layout.validate_at(id, future).is_ok());
+        assert!(layout
+            .validate_at(id, future - Duration::from_millis(1))
+            .is_err());
+    }
+
+    #[test]
+    fn test_migrate() {
+        let old = SnowflakeLayout::default();
+        let new = SnowflakeLayout::new(2, 12, 8)
+            .unwrap()
+            .with_epoch(SystemTime::UNIX_EPOCH + Duration::from_secs(EPOCH_SECONDS + 86400));
+
+        let generator = SnowflakeGenerator::new(3, 30);
+        let id = generator.generate();
+
+        let migrated = new.migrate_legacy(id).unwrap();
+        let parts = new.decode(migrated);
+        assert_eq!(parts, old.decode(id));
+        assert_eq!(old.migrate(migrated, &new).unwrap(), id);
+
+        // Group ID 3 doesn't fit in a single bit.
+        let narrow = SnowflakeLayout::new(1, 12, 8).unwrap();
+        assert!(narrow.migrate_legacy(id).is_err());
+    }
+}
@@ -0,0 +1,788 @@
+//! Leasing group and worker IDs for [`SnowflakeGenerators`](SnowflakeGenerator).
+//!
+//! Generators only produce unique IDs as long as no two of them use the
+//! same group and worker ID at the same time. A [`LeaseBackend`] hands out
+//! (group, worker) pairs as [`Leases`](Lease), which expire unless they're
+//! renewed, so that IDs held by crashed processes eventually become
+//! available again.
+//!
+//! Most code will want to use a [`WorkerLease`], which acquires a lease,
+//! renews it on request (or from a background [`Heartbeat`] thread), and
+//! releases it when dropped.
+//!
+//! # Example
+//!
+//! ```
+//! use akashi::snowflake::lease::{LocalLeaseBackend, WorkerLease};
+//! use akashi::snowflake::SnowflakeLayout;
+//! use std::sync::Arc;
+//! use std::time::Duration;
+//!
+//! let backend = Arc::new(LocalLeaseBackend::new(SnowflakeLayout::default()));
+//! let ttl = Duration::from_secs(30);
+//!
+//! let first = WorkerLease::acquire(backend.clone(), ttl).unwrap();
+//! let second = WorkerLease::acquire(backend.clone(), ttl).unwrap();
+//! assert_ne!(
+//!     (first.group_id(), first.worker_id()),
+//!     (second.group_id(), second.worker_id())
+//! );
+//!
+//! let generator = first.generator().unwrap();
+//! assert_eq!(generator.generate().worker_id(), first.worker_id());
+//! ```
+
+use std::collections::HashSet;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use failure::{format_err, Fail};
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+
+use super::{SnowflakeGenerator, SnowflakeLayout};
+use crate::clock::{Clock, SystemClock};
+use crate::file_storage::{sync_parent_dir, temp_path};
+use crate::util::Result;
+
+/// A group and worker ID, leased until a given time.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Lease {
+    group_id: u64,
+    worker_id: u64,
+    token: u64,
+    expires: SystemTime,
+}
+
+impl Lease {
+    /// Creates a new `Lease`.
+    ///
+    /// `token` is a value that identifies this particular lease, so that
+    /// backends can tell it apart from later leases on the same IDs.
+    pub fn new(group_id: u64, worker_id: u64, token: u64, expires: SystemTime) -> Lease {
+        Lease {
+            group_id,
+            worker_id,
+            token,
+            expires,
+        }
+    }
+
+    /// Gets the leased group ID.
+    pub fn group_id(&self) -> u64 {
+        self.group_id
+    }
+
+    /// Gets the leased worker ID.
+    pub fn worker_id(&self) -> u64 {
+        self.worker_id
+    }
+
+    /// Gets the token identifying this lease.
+    pub fn token(&self) -> u64 {
+        self.token
+    }
+
+    /// Gets the time at which this lease expires, unless it's renewed.
+    pub fn expires(&self) -> SystemTime {
+        self.expires
+    }
+
+    fn same_ids(&self, other: &Lease) -> bool {
+        self.group_id == other.group_id && self.worker_id == other.worker_id
+    }
+}
+
+/// A coordination service that hands out group and worker IDs.
+pub trait LeaseBackend: Send + Sync {
+    /// Gets the layout that leased IDs have to fit in.
+    fn layout(&self) -> &SnowflakeLayout;
+
+    /// Leases a group and worker ID that isn't currently leased to
+    /// anyone else, for the given length of time.
+    ///
+    /// # Errors
+    ///
+    /// Returns a [`NoWorkerIds`] error if every ID is already leased.
+    fn acquire(&self, ttl: Duration) -> Result<Lease>;
+
+    /// Extends a lease, so that it expires `ttl` from now.
+    ///
+    /// # Errors
+    ///
+    /// Returns a [`LeaseLost`] error if the lease has already expired or
+    /// been released. Any IDs generated with it since it expired might
+    /// not be unique.
+    fn renew(&self, lease: &Lease, ttl: Duration) -> Result<Lease>;
+
+    /// Gives up a lease early.
+    ///
+    /// Releasing a lease that has already expired or been released does
+    /// nothing.
+    fn release(&self, lease: &Lease) -> Result<()>;
+}
+
+/// Returned when every group and worker ID is already leased.
+#[derive(Fail, Debug)]
+#[fail(display = "All group and worker IDs are leased")]
+pub struct NoWorkerIds;
+
+/// Returned when renewing a [`Lease`] that has expired or been released.
+#[derive(Fail, Debug)]
+#[fail(display = "Lease on group {} worker {} was lost", _0, _1)]
+pub struct LeaseLost(u64, u64);
+
+/// Returned by [`SnowflakeGenerator::try_generate`] when the generator
+/// was created from a [`WorkerLease`] that has expired or been lost.
+#[derive(Fail, Debug)]
+#[fail(display = "Lease on group {} worker {} has expired", _0, _1)]
+pub struct LeaseExpired(pub(crate) u64, pub(crate) u64);
+
+/// Converts a time to milliseconds since the UNIX epoch, for comparing
+/// against lease expiry times.
+pub(crate) fn unix_millis(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+/// Removes expired leases, then leases the first free pair of IDs.
+fn acquire_in(
+    leases: &mut Vec<Lease>,
+    layout: &SnowflakeLayout,
+    now: SystemTime,
+    ttl: Duration,
+) -> Result<Lease> {
+    leases.retain(|lease| lease.expires > now);
+
+    let taken: HashSet<(u64, u64)> = leases
+        .iter()
+        .map(|lease| (lease.group_id, lease.worker_id))
+        .collect();
+
+    for group_id in 0..=layout.max_group_id() {
+        for worker_id in 0..=layout.max_worker_id() {
+            if !taken.contains(&(group_id, worker_id)) {
+                let lease = Lease::new(group_id, worker_id, rand::random(), now + ttl);
+                leases.push(lease.clone());
+                return Ok(lease);
+            }
+        }
+    }
+
+    Err(NoWorkerIds.into())
+}
+
+fn renew_in(leases: &mut [Lease], lease: &Lease, now: SystemTime, ttl: Duration) -> Result<Lease> {
+    let current = leases
+        .iter_mut()
+        .find(|cur| cur.same_ids(lease) && cur.token == lease.token && cur.expires > now);
+
+    match current {
+        Some(cur) => {
+            cur.expires = now + ttl;
+            Ok(cur.clone())
+        }
+        None => Err(LeaseLost(lease.group_id, lease.worker_id).into()),
+    }
+}
+
+fn release_in(leases: &mut Vec<Lease>, lease: &Lease) {
+    leases.retain(|cur| !(cur.same_ids(lease) && cur.token == lease.token));
+}
+
+/// In-memory [`LeaseBackend`].
+///
+/// This only coordinates generators within a single process, so it's
+/// mainly meant for use in testing.
+#[derive(Debug)]
+pub struct LocalLeaseBackend {
+    layout: SnowflakeLayout,
+    clock: Arc<dyn Clock>,
+    leases: Mutex<Vec<Lease>>,
+}
+
+impl LocalLeaseBackend {
+    /// Creates a new `LocalLeaseBackend` handing out IDs that fit in the
+    /// given layout.
+    pub fn new(layout: SnowflakeLayout) -> LocalLeaseBackend {
+        LocalLeaseBackend {
+            layout,
+            clock: Arc::new(SystemClock),
+            leases: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Sets the [`Clock`] used to check when leases expire.
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> LocalLeaseBackend {
+        self.clock = clock;
+        self
+    }
+}
+
+impl LeaseBackend for LocalLeaseBackend {
+    fn layout(&self) -> &SnowflakeLayout {
+        &self.layout
+    }
+
+    fn acquire(&self, ttl: Duration) -> Result<Lease> {
+        acquire_in(&mut self.leases.lock(), &self.layout, self.clock.now(), ttl)
+    }
+
+    fn renew(&self, lease: &Lease, ttl: Duration) -> Result<Lease> {
+        renew_in(&mut self.leases.lock(), lease, self.clock.now(), ttl)
+    }
+
+    fn release(&self, lease: &Lease) -> Result<()> {
+        release_in(&mut self.leases.lock(), lease);
+        Ok(())
+    }
+}
+
+/// File-based [`LeaseBackend`], for coordinating processes on a single
+/// host.
+///
+/// Leases are kept in a JSON file within a given directory. Every
+/// operation takes an exclusive lock on that file, reads it, and writes
+/// out an updated copy, so any number of processes can share the same
+/// directory.
+///
+/// The lock is an operating system advisory lock (`flock` or
+/// `LockFileEx`), so it's released if a process crashes while holding it.
+/// Since updated copies of the lease file replace it, the lock is taken
+/// on a separate `leases.lock` file alongside it, which is left in place.
+#[derive(Debug)]
+pub struct FileLeaseBackend {
+    layout: SnowflakeLayout,
+    clock: Arc<dyn Clock>,
+    path: PathBuf,
+    lock_path: PathBuf,
+}
+
+impl FileLeaseBackend {
+    /// Creates a new `FileLeaseBackend` that keeps leases in the given
+    /// directory, handing out IDs that fit in the given layout.
+    ///
+    /// The directory is created if it doesn't exist.
+    pub fn new<P: AsRef<Path>>(dir: P, layout: SnowflakeLayout) -> Result<FileLeaseBackend> {
+        let dir = dir.as_ref();
+        fs::create_dir_all(dir)?;
+
+        Ok(FileLeaseBackend {
+            layout,
+            clock: Arc::new(SystemClock),
+            path: dir.join("leases.json"),
+            lock_path: dir.join("leases.lock"),
+        })
+    }
+
+    /// Sets the [`Clock`] used to check when leases expire.
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> FileLeaseBackend {
+        self.clock = clock;
+        self
+    }
+
+    /// Locks the lease file, then runs `f` on its contents and writes
+    /// them back if it succeeds.
+    fn with_leases<F, R>(&self, f: F) -> Result<R>
+    where
+        F: FnOnce(&mut Vec<Lease>) -> Result<R>,
+    {
+        let _lock = FileLock::acquire(&self.lock_path)?;
+
+        let mut leases: Vec<Lease> = match File::open(&self.path) {
+            Ok(f) => serde_json::from_reader(BufReader::new(f))?,
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => return Err(e.into()),
+        };
+
+        let ret = f(&mut leases)?;
+
+        let tmp_path = temp_path(&self.path);
+        {
+            let mut writer = BufWriter::new(File::create(&tmp_path)?);
+            serde_json::to_writer(&mut writer, &leases)?;
+            writer.flush()?;
+            writer.get_ref().sync_all()?;
+        }
+
+        fs::rename(&tmp_path, &self.path)?;
+        sync_parent_dir(&self.path)?;
+
+        Ok(ret)
+    }
+}
+
+impl LeaseBackend for FileLeaseBackend {
+    fn layout(&self) -> &SnowflakeLayout {
+        &self.layout
+    }
+
+    fn acquire(&self, ttl: Duration) -> Result<Lease> {
+        let now = self.clock.now();
+        self.with_leases(|leases| acquire_in(leases, &self.layout, now, ttl))
+    }
+
+    fn renew(&self, lease: &Lease, ttl: Duration) -> Result<Lease> {
+        let now = self.clock.now();
+        self.with_leases(|leases| renew_in(leases, lease, now, ttl))
+    }
+
+    fn release(&self, lease: &Lease) -> Result<()> {
+        self.with_leases(|leases| {
+            release_in(leases, lease);
+            Ok(())
+        })
+    }
+}
+
+/// An exclusive advisory lock on a file, held until this is dropped.
+///
+/// The operating system releases the lock if the process holding it
+/// exits, so a crash can't leave it locked.
+///
+/// This uses [`File::lock`], which is why the crate needs Rust 1.89.
+struct FileLock {
+    file: File,
+}
+
+impl FileLock {
+    fn acquire(path: &Path) -> Result<FileLock> {
+        let file = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)?;
+        file.lock()?;
+        Ok(FileLock { file })
+    }
+}
+
+impl Drop for FileLock {
+    fn drop(&mut self) {
+        let _ = self.file.unlock();
+    }
+}
+
+/// A [`Lease`] held by this process.
+///
+/// The lease is released when this is dropped.
+pub struct WorkerLease {
+    backend: Arc<dyn LeaseBackend>,
+    ttl: Duration,
+    lease: Mutex<Lease>,
+    // When the lease expires, in milliseconds since the UNIX epoch; shared
+    // with generators so that they stop once it does.
+    expires: Arc<AtomicU64>,
+    released: bool,
+}
+
+impl WorkerLease {
+    /// Acquires a new lease from a backend.
+    ///
+    /// The lease lasts for `ttl`, and each call to
+    /// [`heartbeat`](WorkerLease::heartbeat) extends it to `ttl` from then.
+    pub fn acquire(backend: Arc<dyn LeaseBackend>, ttl: Duration) -> Result<WorkerLease> {
+        let lease = backend.acquire(ttl)?;
+        let expires = Arc::new(AtomicU64::new(unix_millis(lease.expires)));
+
+        Ok(WorkerLease {
+            backend,
+            ttl,
+            lease: Mutex::new(lease),
+            expires,
+            released: false,
+        })
+    }
+
+    /// Gets the leased group ID.
+    pub fn group_id(&self) -> u64 {
+        self.lease.lock().group_id
+    }
+
+    /// Gets the leased worker ID.
+    pub fn worker_id(&self) -> u64 {
+        self.lease.lock().worker_id
+    }
+
+    /// Gets the time at which the lease expires, as of the last
+    /// heartbeat.
+    ///
+    /// IDs generated with this lease after this time might not be unique.
+    pub fn expires(&self) -> SystemTime {
+        self.lease.lock().expires
+    }
+
+    /// Gets a copy of the underlying [`Lease`].
+    pub fn lease(&self) -> Lease {
+        self.lease.lock().clone()
+    }
+
+    /// Creates a [`SnowflakeGenerator`] using the leased IDs.
+    ///
+    /// The generator keeps track of the lease as it's renewed. Once the
+    /// lease expires or is lost, it stops generating IDs:
+    /// [`try_generate`](SnowflakeGenerator::try_generate) returns a
+    /// [`LeaseExpired`] error, and
+    /// [`generate`](SnowflakeGenerator::generate) panics.
+    pub fn generator(&self) -> Result<SnowflakeGenerator> {
+        let lease = self.lease.lock();
+        let generator = SnowflakeGenerator::with_layout(
+            *self.backend.layout(),
+            lease.group_id,
+            lease.worker_id,
+        )?;
+
+        Ok(generator.with_lease_expiry(self.expires.clone()))
+    }
+
+    /// Renews the lease.
+    ///
+    /// # Errors
+    ///
+    /// Returns a [`LeaseLost`] error if the lease has already expired. In
+    /// that case, generators created from this lease stop generating IDs
+    /// immediately. Errors from the backend are passed through as usual.
+    pub fn heartbeat(&self) -> Result<()> {
+        let mut lease = self.lease.lock();
+        match self.backend.renew(&lease, self.ttl) {
+            Ok(renewed) => {
+                self.expires
+                    .store(unix_millis(renewed.expires), Ordering::Release);
+                *lease = renewed;
+                Ok(())
+            }
+            Err(e) => {
+                if e.downcast_ref::<LeaseLost>().is_some() {
+                    self.expires.store(0, Ordering::Release);
+                }
+
+                Err(e)
+            }
+        }
+    }
+
+    /// Starts a thread that renews the lease every `interval`, until the
+    /// returned [`Heartbeat`] is stopped or dropped.
+    ///
+    /// Failed renewals are retried at the next interval. `interval` should
+    /// be a good deal shorter than the lease's time-to-live, so that a few
+    /// failures in a row don't cause it to expire.
+    ///
+    /// If the lease is lost, the thread exits; [`Heartbeat::is_lost`]
+    /// reports this, and [`Heartbeat::stop`] returns the [`LeaseLost`]
+    /// error.
+    pub fn spawn_heartbeat(self: &Arc<Self>, interval: Duration) -> Heartbeat {
+        let (stop, rx) = mpsc::channel::<()>();
+        let lease = self.clone();
+
+        let thread = thread::spawn(move || {
+            while let Err(RecvTimeoutError::Timeout) = rx.recv_timeout(interval) {
+                if let Err(e) = lease.heartbeat() {
+                    if e.downcast_ref::<LeaseLost>().is_some() {
+                        return Err(e);
+                    }
+                }
+            }
+
+            Ok(())
+        });
+
+        Heartbeat {
+            lease: self.clone(),
+            stop: Some(stop),
+            thread: Some(thread),
+        }
+    }
+
+    /// Checks whether the lease has been found to be lost, either while
+    /// renewing it or because it was released.
+    ///
+    /// Generators created from a lost lease don't generate any more IDs.
+    pub fn is_lost(&self) -> bool {
+        self.expires.load(Ordering::Acquire) == 0
+    }
+
+    /// Releases the lease.
+    ///
+    /// This is also done when the `WorkerLease` is dropped, but this method
+    /// lets callers check for errors.
+    pub fn release(mut self) -> Result<()> {
+        self.released = true;
+        self.expires.store(0, Ordering::Release);
+        self.backend.release(&self.lease.lock())
+    }
+}
+
+impl Drop for WorkerLease {
+    fn drop(&mut self) {
+        if !self.released {
+            self.expires.store(0, Ordering::Release);
+            let _ = self.backend.release(self.lease.get_mut());
+        }
+    }
+}
+
+/// A background thread renewing a [`WorkerLease`].
+///
+/// The thread is stopped when this is dropped.
+pub struct Heartbeat {
+    lease: Arc<WorkerLease>,
+    stop: Option<Sender<()>>,
+    thread: Option<JoinHandle<Result<()>>>,
+}
+
+impl Heartbeat {
+    /// Checks whether the thread has stopped because the lease was lost.
+    pub fn is_lost(&self) -> bool {
+        self.lease.is_lost()
+    }
+
+    /// Stops the thread.
+    ///
+    /// # Errors
+    ///
+    /// Returns a [`LeaseLost`] error if the thread had already stopped
+    /// because the lease was lost.
+    pub fn stop(mut self) -> Result<()> {
+        self.join()
+    }
+
+    fn join(&mut self) -> Result<()> {
+        // Dropping the sender wakes the thread up and tells it to exit.
+        self.stop.take();
+
+        match self.thread.take() {
+            Some(thread) => thread
+                .join()
+                .unwrap_or_else(|_| Err(format_err!("heartbeat thread panicked"))),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Drop for Heartbeat {
+    fn drop(&mut self) {
+        let _ = self.join();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::ManualClock;
+    use crate::snowflake::EPOCH_SECONDS;
+
+    fn small_layout() -> SnowflakeLayout {
+        // 2 groups with 2 workers each.
+        SnowflakeLayout::new(1, 1, 12).unwrap()
+    }
+
+    fn start() -> SystemTime {
+        SystemTime::UNIX_EPOCH + Duration::from_secs(EPOCH_SECONDS + 60)
+    }
+
+    fn check_backend(backend: &dyn LeaseBackend, clock: &ManualClock) {
+        let ttl = Duration::from_secs(10);
+
+        let leases: Vec<Lease> = (0..4).map(|_| backend.acquire(ttl).unwrap()).collect();
+        let ids: HashSet<(u64, u64)> = leases
+            .iter()
+            .map(|lease| (lease.group_id(), lease.worker_id()))
+            .collect();
+        assert_eq!(ids.len(), 4);
+
+        let err = backend.acquire(ttl).unwrap_err();
+        assert!(err.downcast_ref::<NoWorkerIds>().is_some());
+
+        // Releasing a lease frees up its IDs.
+        backend.release(&leases[1]).unwrap();
+        let replacement = backend.acquire(ttl).unwrap();
+        assert!(replacement.same_ids(&leases[1]));
+        assert!(backend.renew(&leases[1], ttl).is_err());
+
+        // Renewed leases outlive the others.
+        clock.advance(Duration::from_secs(5));
+        let renewed = backend.renew(&leases[0], ttl).unwrap();
+        assert_eq!(renewed.expires(), clock.now() + ttl);
+
+        clock.advance(Duration::from_secs(6));
+        let err = backend.renew(&leases[2], ttl).unwrap_err();
+        assert!(err.downcast_ref::<LeaseLost>().is_some());
+
+        let reacquired: Vec<Lease> = (0..3).map(|_| backend.acquire(ttl).unwrap()).collect();
+        assert!(reacquired.iter().all(|lease| !lease.same_ids(&renewed)));
+        assert!(backend.acquire(ttl).is_err());
+    }
+
+    #[test]
+    fn test_local_backend() {
+        let clock = Arc::new(ManualClock::new(start()));
+        let backend = LocalLeaseBackend::new(small_layout()).with_clock(clock.clone());
+        check_backend(&backend, &clock);
+    }
+
+    #[test]
+    fn test_file_backend() {
+        let dir = tempfile::tempdir().unwrap();
+        let clock = Arc::new(ManualClock::new(start()));
+        let backend = FileLeaseBackend::new(dir.path(), small_layout())
+            .unwrap()
+            .with_clock(clock.clone());
+        check_backend(&backend, &clock);
+
+        // Other processes sharing the directory see the same leases.
+        let other = FileLeaseBackend::new(dir.path(), small_layout())
+            .unwrap()
+            .with_clock(clock.clone());
+        assert!(other.acquire(Duration::from_secs(10)).is_err());
+
+        // The lock isn't held between operations.
+        let lock_file = File::open(dir.path().join("leases.lock")).unwrap();
+        lock_file.try_lock().unwrap();
+    }
+
+    #[test]
+    fn test_file_backend_concurrent() {
+        let dir = tempfile::tempdir().unwrap();
+        let threads: Vec<_> = (0..8)
+            .map(|_| {
+                let path = dir.path().to_owned();
+                thread::spawn(move || {
+                    let backend = FileLeaseBackend::new(path, SnowflakeLayout::default()).unwrap();
+                    let lease = backend.acquire(Duration::from_secs(60)).unwrap();
+                    (lease.group_id(), lease.worker_id())
+                })
+            })
+            .collect();
+
+        let ids: HashSet<(u64, u64)> = threads.into_iter().map(|t| t.join().unwrap()).collect();
+        assert_eq!(ids.len(), 8);
+    }
+
+    #[test]
+    fn test_worker_lease() {
+        let clock = Arc::new(ManualClock::new(start()));
+        let backend: Arc<dyn LeaseBackend> =
+            Arc::new(LocalLeaseBackend::new(small_layout()).with_clock(clock.clone()));
+        let ttl = Duration::from_secs(10);
+
+        let lease = WorkerLease::acquire(backend.clone(), ttl).unwrap();
+        let generator = lease.generator().unwrap().with_clock(clock.clone());
+        let parts = small_layout().decode(generator.generate());
+        assert_eq!(parts.group_id(), lease.group_id());
+        assert_eq!(parts.worker_id(), lease.worker_id());
+
+        clock.advance(Duration::from_secs(5));
+        lease.heartbeat().unwrap();
+        assert_eq!(lease.expires(), clock.now() + ttl);
+
+        // Dropping the lease releases it.
+        let ids = (lease.group_id(), lease.worker_id());
+        let others: Vec<WorkerLease> = (0..3)
+            .map(|_| WorkerLease::acquire(backend.clone(), ttl).unwrap())
+            .collect();
+        drop(lease);
+        let next = WorkerLease::acquire(backend.clone(), ttl).unwrap();
+        assert_eq!((next.group_id(), next.worker_id()), ids);
+
+        // As does releasing it explicitly.
+        next.release().unwrap();
+        let lease = backend.acquire(ttl).unwrap();
+        assert_eq!((lease.group_id(), lease.worker_id()), ids);
+        assert!(backend.acquire(ttl).is_err());
+        drop(others);
+    }
+
+    #[test]
+    fn test_generator_expiry() {
+        let clock = Arc::new(ManualClock::new(start()));
+        let backend: Arc<dyn LeaseBackend> =
+            Arc::new(LocalLeaseBackend::new(small_layout()).with_clock(clock.clone()));
+        let ttl = Duration::from_secs(10);
+
+        let lease = WorkerLease::acquire(backend.clone(), ttl).unwrap();
+        let generator = lease.generator().unwrap().with_clock(clock.clone());
+        assert!(generator.try_generate().is_ok());
+
+        // Renewing the lease keeps the generator going...
+        clock.advance(Duration::from_secs(9));
+        lease.heartbeat().unwrap();
+        clock.advance(Duration::from_secs(9));
+        assert!(generator.try_generate().is_ok());
+
+        // ...but once it expires, the generator stops.
+        clock.advance(Duration::from_secs(1));
+        let err = generator.try_generate().unwrap_err();
+        assert!(err.downcast_ref::<LeaseExpired>().is_some());
+
+        // Even if the clock goes back, a lost lease stays lost.
+        assert!(lease.heartbeat().is_err());
+        clock.set(start());
+        assert!(generator.try_generate().is_err());
+
+        // Released leases stop generators too.
+        let lease = WorkerLease::acquire(backend, ttl).unwrap();
+        let generator = lease.generator().unwrap().with_clock(clock.clone());
+        assert!(generator.try_generate().is_ok());
+        lease.release().unwrap();
+        assert!(generator.try_generate().is_err());
+    }
+
+    #[test]
+    fn test_heartbeat_thread() {
+        let clock = Arc::new(ManualClock::new(start()));
+        let backend = Arc::new(LocalLeaseBackend::new(small_layout()).with_clock(clock.clone()));
+        let ttl = Duration::from_secs(10);
+
+        let lease = Arc::new(WorkerLease::acquire(backend, ttl).unwrap());
+        let heartbeat = lease.spawn_heartbeat(Duration::from_millis(1));
+
+        clock.advance(Duration::from_secs(5));
+        let expected = clock.now() + ttl;
+        for _ in 0..1000 {
+            if lease.expires() == expected {
+                break;
+            }
+            thread::sleep(Duration::from_millis(1));
+        }
+        assert_eq!(lease.expires(), expected);
+
+        assert!(!heartbeat.is_lost());
+        heartbeat.stop().unwrap();
+        assert_eq!(Arc::strong_count(&lease), 1);
+    }
+
+    #[test]
+    fn test_heartbeat_lost() {
+        let clock = Arc::new(ManualClock::new(start()));
+        let backend = Arc::new(LocalLeaseBackend::new(small_layout()).with_clock(clock.clone()));
+        let ttl = Duration::from_secs(10);
+
+        let lease = Arc::new(WorkerLease::acquire(backend, ttl).unwrap());
+        let generator = lease.generator().unwrap().with_clock(clock.clone());
+
+        // Let the lease expire before the thread gets a chance to renew it.
+        clock.advance(ttl);
+        let heartbeat = lease.spawn_heartbeat(Duration::from_millis(1));
+
+        for _ in 0..1000 {
+            if heartbeat.is_lost() {
+                break;
+            }
+            thread::sleep(Duration::from_millis(1));
+        }
+
+        assert!(heartbeat.is_lost());
+        assert!(generator.try_generate().is_err());
+
+        let err = heartbeat.stop().unwrap_err();
+        assert!(err.downcast_ref::<LeaseLost>().is_some());
+    }
+}
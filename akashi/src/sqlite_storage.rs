@@ -0,0 +1,837 @@
+//! Storage systems backed by an embedded SQLite database.
+//!
+//! This module is only available when the `sqlite` feature is enabled.
+//!
+//! Each storage object uses its own table within a shared
+//! [`SqliteDatabase`]. Entity IDs are used as the tables' integer primary
+//! keys, and [`Component`] data is stored as JSON text.
+//!
+//! # Example
+//!
+//! ```
+//! use akashi::{Card, Component, Entity, EntityManager};
+//! use akashi::sqlite_storage::SqliteDatabase;
+//! use serde::{Deserialize, Serialize};
+//!
+//! #[derive(Serialize, Deserialize)]
+//! struct Level(u64);
+//! impl Component<Card> for Level {}
+//!
+//! let db = SqliteDatabase::open_in_memory().unwrap();
+//!
+//! let mut manager = EntityManager::new();
+//! manager
+//!     .register_entity(db.entity_storage::<Card>("Card").unwrap())
+//!     .unwrap();
+//!
+//! // Using the registered component name as the table name keeps things
+//! // easy to follow.
+//! manager
+//!     .register_component("Level", db.component_storage::<Card, Level>("Level").unwrap())
+//!     .unwrap();
+//!
+//! let mut card: Card = manager.create(123456789u64.into()).unwrap();
+//! card.set_component(Level(5)).unwrap();
+//! manager.store(card).unwrap();
+//!
+//! let handle = manager.load::<Card>(123456789u64.into()).unwrap();
+//! let level: Level = handle.get().unwrap().get_component().unwrap().unwrap();
+//! assert_eq!(level.0, 5);
+//! ```
+
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::marker::PhantomData;
+use std::path::Path;
+use std::sync::Arc;
+use std::thread::{self, ThreadId};
+
+use failure::format_err;
+use parking_lot::{Condvar, MappedMutexGuard, Mutex, MutexGuard};
+use rusqlite::{params, params_from_iter, Connection, OptionalExtension};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use crate::ecs::{
+    store_attachment_changes, ChangeSet, Component, ComponentBackend, ComponentManager, Entity,
+    EntityBackend, TransactionalBackend,
+};
+use crate::snowflake::Snowflake;
+use crate::util::Result;
+
+/// A shared connection to an SQLite database.
+///
+/// Cloning a `SqliteDatabase` is cheap, and clones share the same
+/// underlying connection.
+///
+/// All storage objects created from the same database (or its clones)
+/// share a single [`TransactionalBackend`], so changes made to them by a
+/// [`Transaction`](crate::ecs::Transaction) are committed atomically.
+#[derive(Clone)]
+pub struct SqliteDatabase {
+    inner: Arc<DatabaseInner>,
+}
+
+struct ConnectionState {
+    conn: Connection,
+    txn_owner: Option<ThreadId>,
+}
+
+struct DatabaseInner {
+    state: Mutex<ConnectionState>,
+    txn_done: Condvar,
+}
+
+impl DatabaseInner {
+    /// Locks the connection state.
+    ///
+    /// If another thread has a transaction open, this waits for it to
+    /// finish first, so that other threads' changes don't get mixed into
+    /// the transaction.
+    fn lock(&self) -> MutexGuard<'_, ConnectionState> {
+        let current = thread::current().id();
+        let mut state = self.state.lock();
+        while matches!(state.txn_owner, Some(owner) if owner != current) {
+            self.txn_done.wait(&mut state);
+        }
+
+        state
+    }
+
+    fn finish(&self, mut state: MutexGuard<'_, ConnectionState>) {
+        state.txn_owner = None;
+        drop(state);
+        self.txn_done.notify_all();
+    }
+}
+
+impl TransactionalBackend for DatabaseInner {
+    fn begin(&self) -> Result<()> {
+        let mut state = self.lock();
+        if state.txn_owner.is_some() {
+            return Err(format_err!("transaction already in progress"));
+        }
+
+        state.conn.execute_batch("BEGIN IMMEDIATE")?;
+        state.txn_owner = Some(thread::current().id());
+        Ok(())
+    }
+
+    fn commit(&self) -> Result<()> {
+        let state = self.lock();
+        if state.txn_owner.is_none() {
+            return Err(format_err!("no transaction in progress"));
+        }
+
+        // If this fails, the transaction is left open so that it can
+        // still be rolled back.
+        state.conn.execute_batch("COMMIT")?;
+        self.finish(state);
+        Ok(())
+    }
+
+    fn rollback(&self) -> Result<()> {
+        let state = self.lock();
+        if state.txn_owner.is_none() {
+            return Err(format_err!("no transaction in progress"));
+        }
+
+        // SQLite may have already rolled back the transaction by itself
+        // after some errors.
+        let res = if state.conn.is_autocommit() {
+            Ok(())
+        } else {
+            state.conn.execute_batch("ROLLBACK")
+        };
+
+        self.finish(state);
+        Ok(res?)
+    }
+}
+
+impl SqliteDatabase {
+    /// Opens (or creates) an SQLite database file at the given path.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<SqliteDatabase> {
+        let conn = Connection::open(path)?;
+        conn.pragma_update(None, "journal_mode", "WAL")?;
+        conn.pragma_update(None, "synchronous", "FULL")?;
+
+        Ok(SqliteDatabase::from_connection(conn))
+    }
+
+    /// Opens a new database held entirely in memory.
+    ///
+    /// This is mainly useful for testing.
+    pub fn open_in_memory() -> Result<SqliteDatabase> {
+        Ok(SqliteDatabase::from_connection(
+            Connection::open_in_memory()?
+        ))
+    }
+
+    /// Wraps an already-open `rusqlite` connection.
+    pub fn from_connection(conn: Connection) -> SqliteDatabase {
+        SqliteDatabase {
+            inner: Arc::new(DatabaseInner {
+                state: Mutex::new(ConnectionState {
+                    conn,
+                    txn_owner: None,
+                }),
+                txn_done: Condvar::new(),
+            }),
+        }
+    }
+
+    fn lock(&self) -> MappedMutexGuard<'_, Connection> {
+        MutexGuard::map(self.inner.lock(), |state| &mut state.conn)
+    }
+
+    fn transactional(&self) -> Option<Arc<dyn TransactionalBackend>> {
+        Some(self.inner.clone())
+    }
+
+    /// Creates an [`Entity`] storage backend that uses the given table,
+    /// creating the table if it doesn't already exist.
+    pub fn entity_storage<T>(&self, table: &str) -> Result<SqliteEntityStorage<T>>
+    where
+        T: Entity + 'static,
+    {
+        SqliteEntityStorage::new(self.clone(), table)
+    }
+
+    /// Creates a [`Component`] storage backend that uses the given table,
+    /// creating the table if it doesn't already exist.
+    pub fn component_storage<T, U>(&self, table: &str) -> Result<SqliteComponentStorage<T, U>>
+    where
+        T: Entity + 'static,
+        U: Component<T> + Serialize + DeserializeOwned + 'static,
+    {
+        SqliteComponentStorage::new(self.clone(), table)
+    }
+
+    fn create_table(&self, table: &str, data_column: &str) -> Result<()> {
+        let conn = self.lock();
+        conn.execute(
+            &format!(
+                "CREATE TABLE IF NOT EXISTS {} (id INTEGER PRIMARY KEY NOT NULL, {} TEXT NOT NULL)",
+                quote_identifier(table),
+                data_column
+            ),
+            params![],
+        )?;
+
+        Ok(())
+    }
+}
+
+impl fmt::Debug for SqliteDatabase {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "<SqliteDatabase>")
+    }
+}
+
+/// Quotes an SQL identifier, such as a table name.
+fn quote_identifier(name: &str) -> String {
+    format!("\"{}\"", name.replace('"', "\"\""))
+}
+
+/// Converts a [`Snowflake`] to an SQLite integer key.
+fn to_key(id: Snowflake) -> i64 {
+    id.into()
+}
+
+/// The most IDs to put in a single `IN (...)` list, to stay within
+/// SQLite's limit on the number of parameters in a statement.
+const BATCH_SIZE: usize = 500;
+
+/// Makes a list of `n` parameter placeholders, for use in `IN (...)`.
+fn placeholders(n: usize) -> String {
+    vec!["?"; n].join(", ")
+}
+
+/// Runs `f` within a savepoint, so that its changes are all undone if it
+/// fails.
+///
+/// Unlike a transaction, this also works if a [`Transaction`](crate::ecs::Transaction)
+/// is already open.
+fn with_savepoint<F>(conn: &Connection, f: F) -> Result<()>
+where
+    F: FnOnce() -> Result<()>,
+{
+    conn.execute_batch("SAVEPOINT akashi_batch")?;
+
+    match f() {
+        Ok(()) => {
+            conn.execute_batch("RELEASE akashi_batch")?;
+            Ok(())
+        }
+        Err(e) => {
+            conn.execute_batch("ROLLBACK TO akashi_batch; RELEASE akashi_batch")?;
+            Err(e)
+        }
+    }
+}
+
+/// SQLite-based [`Entity`] storage backend.
+///
+/// Entities are stored as a list of the names of their attached
+/// [`Components`](Component), as given to
+/// [`ComponentManager::register_component`]. The component data itself
+/// needs to be stored separately (such as with [`SqliteComponentStorage`]).
+pub struct SqliteEntityStorage<T: Entity + 'static> {
+    db: SqliteDatabase,
+    table: String,
+    pd: PhantomData<T>,
+}
+
+impl<T> SqliteEntityStorage<T>
+where
+    T: Entity + 'static,
+{
+    /// Creates a new storage backend that uses the given table, creating
+    /// the table if it doesn't already exist.
+    pub fn new(db: SqliteDatabase, table: &str) -> Result<SqliteEntityStorage<T>> {
+        db.create_table(table, "components")?;
+
+        Ok(SqliteEntityStorage {
+            db,
+            table: quote_identifier(table),
+            pd: PhantomData,
+        })
+    }
+
+    fn to_row(obj: &T) -> Result<String> {
+        let cm = obj.component_manager();
+        let mut names: Vec<&str> = obj
+            .components_attached()
+            .iter()
+            .filter_map(|type_id| cm.component_name(type_id))
+            .collect();
+        names.sort_unstable();
+
+        Ok(serde_json::to_string(&names)?)
+    }
+
+    fn from_row(id: Snowflake, components: &str, cm: Arc<ComponentManager<T>>) -> Result<T> {
+        let names: Vec<String> = serde_json::from_str(components)?;
+        let components: HashSet<_> = names
+            .iter()
+            .filter_map(|name| cm.component_type_id(name).copied())
+            .collect();
+
+        Ok(T::new(id, cm, components))
+    }
+
+    /// Looks up the `components` column for each of the given IDs that
+    /// has a row.
+    fn select_many(&self, ids: &[Snowflake]) -> Result<HashMap<Snowflake, String>> {
+        let conn = self.db.lock();
+        let mut rows: HashMap<Snowflake, String> = HashMap::with_capacity(ids.len());
+
+        for batch in ids.chunks(BATCH_SIZE) {
+            let mut stmt = conn.prepare_cached(&format!(
+                "SELECT id, components FROM {} WHERE id IN ({})",
+                self.table,
+                placeholders(batch.len())
+            ))?;
+
+            let found = stmt.query_map(
+                params_from_iter(batch.iter().map(|id| to_key(*id))),
+                |row| Ok((row.get::<_, i64>(0)?, row.get::<_, String>(1)?)),
+            )?;
+
+            for row in found {
+                let (id, components) = row?;
+                rows.insert(id.into(), components);
+            }
+        }
+
+        Ok(rows)
+    }
+}
+
+impl<T> EntityBackend<T> for SqliteEntityStorage<T>
+where
+    T: Entity + 'static,
+{
+    fn exists(&self, id: Snowflake) -> Result<bool> {
+        let conn = self.db.lock();
+        let found: Option<i64> = conn
+            .query_row(
+                &format!("SELECT 1 FROM {} WHERE id = ?1", self.table),
+                params![to_key(id)],
+                |row| row.get(0),
+            )
+            .optional()?;
+
+        Ok(found.is_some())
+    }
+
+    fn load(&self, id: Snowflake, cm: Arc<ComponentManager<T>>) -> Result<Option<T>> {
+        let conn = self.db.lock();
+        let components: Option<String> = conn
+            .query_row(
+                &format!("SELECT components FROM {} WHERE id = ?1", self.table),
+                params![to_key(id)],
+                |row| row.get(0),
+            )
+            .optional()?;
+        drop(conn);
+
+        components
+            .map(|components| Self::from_row(id, &components, cm))
+            .transpose()
+    }
+
+    fn store(&self, id: Snowflake, obj: &T) -> Result<()> {
+        let components = Self::to_row(obj)?;
+        let conn = self.db.lock();
+        conn.execute(
+            &format!(
+                "INSERT OR REPLACE INTO {} (id, components) VALUES (?1, ?2)",
+                self.table
+            ),
+            params![to_key(id), components],
+        )?;
+
+        Ok(())
+    }
+
+    fn store_changes(&self, id: Snowflake, obj: &T, changes: &ChangeSet) -> Result<()> {
+        // Rows only list attached components.
+        store_attachment_changes(self, id, obj, changes)
+    }
+
+    fn delete(&self, id: Snowflake) -> Result<()> {
+        let conn = self.db.lock();
+        conn.execute(
+            &format!("DELETE FROM {} WHERE id = ?1", self.table),
+            params![to_key(id)],
+        )?;
+
+        Ok(())
+    }
+
+    fn keys(&self, page: u64, limit: u64) -> Result<Vec<Snowflake>> {
+        let conn = self.db.lock();
+        let mut stmt = conn.prepare_cached(&format!(
+            "SELECT id FROM {} ORDER BY id LIMIT ?1 OFFSET ?2",
+            self.table
+        ))?;
+
+        let rows = stmt.query_map(params![limit as i64, (page * limit) as i64], |row| {
+            row.get::<_, i64>(0)
+        })?;
+
+        let mut ids = Vec::new();
+        for row in rows {
+            ids.push(row?.into());
+        }
+
+        Ok(ids)
+    }
+
+    fn keys_after(&self, after: Option<Snowflake>, limit: u64) -> Result<Vec<Snowflake>> {
+        let conn = self.db.lock();
+        let mut stmt = conn.prepare_cached(&format!(
+            "SELECT id FROM {} WHERE ?1 IS NULL OR id > ?1 ORDER BY id LIMIT ?2",
+            self.table
+        ))?;
+
+        let after: Option<i64> = after.map(|id| id.into());
+        let rows = stmt.query_map(params![after, limit as i64], |row| row.get::<_, i64>(0))?;
+
+        let mut ids = Vec::new();
+        for row in rows {
+            ids.push(row?.into());
+        }
+
+        Ok(ids)
+    }
+
+    fn load_many(&self, ids: &[Snowflake], cm: Arc<ComponentManager<T>>) -> Result<Vec<Option<T>>> {
+        let rows = self.select_many(ids)?;
+        ids.iter()
+            .map(|id| {
+                rows.get(id)
+                    .map(|components| Self::from_row(*id, components, cm.clone()))
+                    .transpose()
+            })
+            .collect()
+    }
+
+    fn exists_many(&self, ids: &[Snowflake]) -> Result<Vec<bool>> {
+        let conn = self.db.lock();
+        let mut found: HashSet<Snowflake> = HashSet::with_capacity(ids.len());
+
+        for batch in ids.chunks(BATCH_SIZE) {
+            let mut stmt = conn.prepare_cached(&format!(
+                "SELECT id FROM {} WHERE id IN ({})",
+                self.table,
+                placeholders(batch.len())
+            ))?;
+
+            let rows = stmt.query_map(
+                params_from_iter(batch.iter().map(|id| to_key(*id))),
+                |row| row.get::<_, i64>(0),
+            )?;
+
+            for row in rows {
+                found.insert(row?.into());
+            }
+        }
+
+        Ok(ids.iter().map(|id| found.contains(id)).collect())
+    }
+
+    fn store_many(&self, objects: &[&T]) -> Result<()> {
+        let rows = objects
+            .iter()
+            .map(|obj| Ok((to_key(obj.id()), Self::to_row(obj)?)))
+            .collect::<Result<Vec<(i64, String)>>>()?;
+
+        let conn = self.db.lock();
+        with_savepoint(&conn, || {
+            let mut stmt = conn.prepare_cached(&format!(
+                "INSERT OR REPLACE INTO {} (id, components) VALUES (?1, ?2)",
+                self.table
+            ))?;
+
+            for (id, components) in rows.iter() {
+                stmt.execute(params![id, components])?;
+            }
+
+            Ok(())
+        })
+    }
+
+    fn delete_many(&self, ids: &[Snowflake]) -> Result<()> {
+        let conn = self.db.lock();
+        with_savepoint(&conn, || {
+            for batch in ids.chunks(BATCH_SIZE) {
+                conn.execute(
+                    &format!(
+                        "DELETE FROM {} WHERE id IN ({})",
+                        self.table,
+                        placeholders(batch.len())
+                    ),
+                    params_from_iter(batch.iter().map(|id| to_key(*id))),
+                )?;
+            }
+
+            Ok(())
+        })
+    }
+
+    fn transactional(&self) -> Option<Arc<dyn TransactionalBackend>> {
+        self.db.transactional()
+    }
+}
+
+/// SQLite-based [`Component`] storage backend.
+///
+/// This can store any [`Component`] type that can be serialized and
+/// deserialized using `serde`.
+pub struct SqliteComponentStorage<T, U>
+where
+    T: Entity + 'static,
+    U: Component<T> + Serialize + DeserializeOwned + 'static,
+{
+    db: SqliteDatabase,
+    table: String,
+    pd: PhantomData<(T, fn() -> U)>,
+}
+
+impl<T, U> SqliteComponentStorage<T, U>
+where
+    T: Entity + 'static,
+    U: Component<T> + Serialize + DeserializeOwned + 'static,
+{
+    /// Creates a new storage backend that uses the given table, creating
+    /// the table if it doesn't already exist.
+    pub fn new(db: SqliteDatabase, table: &str) -> Result<SqliteComponentStorage<T, U>> {
+        db.create_table(table, "data")?;
+
+        Ok(SqliteComponentStorage {
+            db,
+            table: quote_identifier(table),
+            pd: PhantomData,
+        })
+    }
+}
+
+impl<T, U> ComponentBackend<T, U> for SqliteComponentStorage<T, U>
+where
+    T: Entity + 'static,
+    U: Component<T> + Serialize + DeserializeOwned + 'static,
+{
+    fn load(&self, entity: &T) -> Result<Option<U>> {
+        let conn = self.db.lock();
+        let data: Option<String> = conn
+            .query_row(
+                &format!("SELECT data FROM {} WHERE id = ?1", self.table),
+                params![to_key(entity.id())],
+                |row| row.get(0),
+            )
+            .optional()?;
+        drop(conn);
+
+        match data {
+            Some(data) => Ok(Some(serde_json::from_str(&data)?)),
+            None => Ok(None),
+        }
+    }
+
+    fn store(&self, entity: &T, component: U) -> Result<()> {
+        let data = serde_json::to_string(&component)?;
+        let conn = self.db.lock();
+        conn.execute(
+            &format!(
+                "INSERT OR REPLACE INTO {} (id, data) VALUES (?1, ?2)",
+                self.table
+            ),
+            params![to_key(entity.id()), data],
+        )?;
+
+        Ok(())
+    }
+
+    fn exists(&self, entity: &T) -> Result<bool> {
+        let conn = self.db.lock();
+        let found: Option<i64> = conn
+            .query_row(
+                &format!("SELECT 1 FROM {} WHERE id = ?1", self.table),
+                params![to_key(entity.id())],
+                |row| row.get(0),
+            )
+            .optional()?;
+
+        Ok(found.is_some())
+    }
+
+    fn delete(&self, entity: &T) -> Result<()> {
+        let conn = self.db.lock();
+        conn.execute(
+            &format!("DELETE FROM {} WHERE id = ?1", self.table),
+            params![to_key(entity.id())],
+        )?;
+
+        Ok(())
+    }
+
+    fn keys(&self) -> Result<Option<Vec<Snowflake>>> {
+        let conn = self.db.lock();
+        let mut stmt = conn.prepare_cached(&format!("SELECT id FROM {}", self.table))?;
+        let rows = stmt.query_map(params![], |row| row.get::<_, i64>(0))?;
+
+        let mut ids = Vec::new();
+        for row in rows {
+            ids.push(row?.into());
+        }
+
+        Ok(Some(ids))
+    }
+
+    fn transactional(&self) -> Option<Arc<dyn TransactionalBackend>> {
+        self.db.transactional()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::card::Card;
+    use crate::ecs::EntityManager;
+    use crate::snowflake::SnowflakeGenerator;
+
+    use serde::Deserialize;
+
+    #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+    struct TestComponent {
+        name: String,
+        value: u64,
+    }
+
+    impl Component<Card> for TestComponent {}
+
+    fn new_manager(db: &SqliteDatabase) -> EntityManager {
+        let mut manager = EntityManager::new();
+        manager
+            .register_entity(db.entity_storage::<Card>("Card").unwrap())
+            .unwrap();
+        manager
+            .register_component(
+                "TestComponent",
+                db.component_storage::<Card, TestComponent>("TestComponent")
+                    .unwrap(),
+            )
+            .unwrap();
+        manager
+    }
+
+    #[test]
+    fn test_persistence() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("test.db");
+        let snowflake_gen = SnowflakeGenerator::new(0, 0);
+        let id = snowflake_gen.generate();
+        let component = TestComponent {
+            name: "foo".to_owned(),
+            value: 50,
+        };
+
+        {
+            let manager = new_manager(&SqliteDatabase::open(&path).unwrap());
+            let mut card: Card = manager.create(id).unwrap();
+            card.set_component(component.clone()).unwrap();
+            manager.store(card).unwrap();
+        }
+
+        let manager = new_manager(&SqliteDatabase::open(&path).unwrap());
+        let handle = manager.load::<Card>(id).unwrap();
+        let card = handle.get().unwrap();
+        assert!(card.has_component::<TestComponent>());
+
+        let loaded: TestComponent = card.get_component().unwrap().unwrap();
+        assert_eq!(loaded, component);
+    }
+
+    #[test]
+    fn test_keys_after() {
+        let db = SqliteDatabase::open_in_memory().unwrap();
+        let manager = new_manager(&db);
+
+        for id in [5u64, 1, 4, 2, 3].iter() {
+            manager
+                .store(manager.create::<Card>((*id).into()).unwrap())
+                .unwrap();
+        }
+
+        let ids: Vec<Snowflake> = (1..=5u64).map(Snowflake::from).collect();
+        let store = db.entity_storage::<Card>("Card").unwrap();
+        assert_eq!(store.keys_after(None, 2).unwrap(), ids[..2].to_vec());
+        assert_eq!(
+            store.keys_after(Some(ids[1]), 10).unwrap(),
+            ids[2..].to_vec()
+        );
+        assert!(store.keys_after(Some(ids[4]), 10).unwrap().is_empty());
+
+        let all = manager
+            .iter_keys::<Card>()
+            .unwrap()
+            .with_batch_size(2)
+            .collect::<Result<Vec<_>>>()
+            .unwrap();
+        assert_eq!(all, ids);
+    }
+
+    #[test]
+    fn test_delete() {
+        let db = SqliteDatabase::open_in_memory().unwrap();
+        let manager = new_manager(&db);
+        let snowflake_gen = SnowflakeGenerator::new(0, 0);
+
+        let id = snowflake_gen.generate();
+        let mut card: Card = manager.create(id).unwrap();
+        card.set_component(TestComponent {
+            name: "foo".to_owned(),
+            value: 50,
+        })
+        .unwrap();
+        manager.store(card).unwrap();
+        assert!(manager.exists::<Card>(id).unwrap());
+
+        manager.delete::<Card>(id).unwrap();
+        assert!(!manager.exists::<Card>(id).unwrap());
+
+        let storage = db
+            .component_storage::<Card, TestComponent>("TestComponent")
+            .unwrap();
+        assert_eq!(storage.keys().unwrap(), Some(vec![]));
+    }
+
+    #[test]
+    fn test_transaction_rollback() {
+        let db = SqliteDatabase::open_in_memory().unwrap();
+        let manager = new_manager(&db);
+        let snowflake_gen = SnowflakeGenerator::new(0, 0);
+        let id = snowflake_gen.generate();
+
+        let mut txn = manager.transaction();
+        txn.store(manager.create::<Card>(id).unwrap())
+            .set_component::<Card, _>(
+                id,
+                TestComponent {
+                    name: "foo".to_owned(),
+                    value: 50,
+                },
+            )
+            .set_component::<Card, _>(
+                snowflake_gen.generate(),
+                TestComponent {
+                    name: "bar".to_owned(),
+                    value: 10,
+                },
+            );
+        assert!(txn.commit().is_err());
+
+        assert!(!manager.exists::<Card>(id).unwrap());
+        assert!(db.lock().is_autocommit());
+
+        let storage = db
+            .component_storage::<Card, TestComponent>("TestComponent")
+            .unwrap();
+        assert_eq!(storage.keys().unwrap(), Some(vec![]));
+    }
+
+    #[test]
+    fn test_keys() {
+        let db = SqliteDatabase::open_in_memory().unwrap();
+        let manager = new_manager(&db);
+        let snowflake_gen = SnowflakeGenerator::new(0, 0);
+
+        let ids: Vec<Snowflake> = (0..25).map(|_| snowflake_gen.generate()).collect();
+        for id in ids.iter() {
+            let card: Card = manager.create(*id).unwrap();
+            manager.store(card).unwrap();
+        }
+
+        assert_eq!(manager.keys::<Card>(0, 10).unwrap(), ids[..10].to_vec());
+        assert_eq!(manager.keys::<Card>(1, 10).unwrap(), ids[10..20].to_vec());
+        assert_eq!(manager.keys::<Card>(2, 10).unwrap(), ids[20..].to_vec());
+        assert!(manager.keys::<Card>(3, 10).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_batches() {
+        let db = SqliteDatabase::open_in_memory().unwrap();
+        let manager = new_manager(&db);
+        let storage = db.entity_storage::<Card>("Card").unwrap();
+        let snowflake_gen = SnowflakeGenerator::new(0, 0);
+
+        // Enough IDs to need more than one `IN (...)` list.
+        let ids: Vec<Snowflake> = (0..(BATCH_SIZE + 10))
+            .map(|_| snowflake_gen.generate())
+            .collect();
+        let cards: Vec<Card> = ids.iter().map(|id| manager.create(*id).unwrap()).collect();
+        storage
+            .store_many(&cards.iter().collect::<Vec<_>>())
+            .unwrap();
+
+        let missing = snowflake_gen.generate();
+        let query = vec![ids[BATCH_SIZE + 5], missing, ids[0]];
+        assert_eq!(
+            storage.exists_many(&query).unwrap(),
+            vec![true, false, true]
+        );
+
+        let cm = Arc::new(ComponentManager::new());
+        let loaded = storage.load_many(&query, cm).unwrap();
+        assert_eq!(loaded[0].as_ref().unwrap().id(), query[0]);
+        assert!(loaded[1].is_none());
+        assert_eq!(loaded[2].as_ref().unwrap().id(), query[2]);
+
+        storage.delete_many(&ids[1..]).unwrap();
+        assert_eq!(manager.keys::<Card>(0, 10).unwrap(), vec![ids[0]]);
+    }
+}
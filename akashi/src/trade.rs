@@ -0,0 +1,847 @@
+//! Player-to-player trading of cards and resources.
+//!
+//! A [`Trade`] is proposed by one [`Player`] to another, and can then be
+//! countered with different terms, accepted, or cancelled. Each side of a
+//! trade is described by a [`TradeOffer`]: a set of [`Card`] IDs from the
+//! player's [`Inventory`], plus an amount of their [`Resource`].
+//!
+//! While a trade is pending, whatever the player who made the latest offer
+//! is giving is held in escrow: their cards are removed from their
+//! `Inventory` and their resources are debited, and both are recorded in
+//! their [`Escrow`] component. This means the same cards or resources
+//! can't be offered in more than one trade at once. Countering or
+//! cancelling a trade returns escrowed items to their owner.
+//!
+//! Accepting a trade moves everything from both sides at once, within a
+//! single [`Transaction`](crate::ecs::Transaction).
+//!
+//! # Example
+//!
+//! ```
+//! use akashi::{Card, Entity, EntityManager, Player};
+//! use akashi::components::{Inventory, InventoryBackendWrapper, Resource};
+//! use akashi::local_storage::{LocalComponentStorage, LocalEntityStorage};
+//! use akashi::snowflake::Snowflake;
+//! use akashi::trade::{Escrow, Trade, TradeOffer};
+//!
+//! let mut manager = EntityManager::new();
+//! manager.register_entity(LocalEntityStorage::<Player>::new()).unwrap();
+//! manager.register_entity(LocalEntityStorage::<Card>::new()).unwrap();
+//! manager
+//!     .register_component("Resource", LocalComponentStorage::<Player, Resource>::new())
+//!     .unwrap();
+//! manager
+//!     .register_component("Escrow", LocalComponentStorage::<Player, Escrow>::new())
+//!     .unwrap();
+//! manager
+//!     .register_component(
+//!         "Inventory",
+//!         InventoryBackendWrapper::new(LocalComponentStorage::<Player, Vec<Snowflake>>::new()),
+//!     )
+//!     .unwrap();
+//!
+//! let card: Card = manager.create(10u64.into()).unwrap();
+//! manager.store(card).unwrap();
+//!
+//! let mut alice: Player = manager.create(1u64.into()).unwrap();
+//! alice.set_component(Inventory::from(vec![10u64.into()])).unwrap();
+//! alice.set_component(Resource::new(0, Some(0), None)).unwrap();
+//! manager.store(alice).unwrap();
+//!
+//! let mut bob: Player = manager.create(2u64.into()).unwrap();
+//! bob.set_component(Resource::new(500, Some(0), None)).unwrap();
+//! manager.store(bob).unwrap();
+//!
+//! // Alice offers her card to Bob for 300 of his currency.
+//! let mut trade = Trade::propose(
+//!     &manager,
+//!     100u64.into(),
+//!     1u64.into(),
+//!     2u64.into(),
+//!     TradeOffer::new(vec![10u64.into()], 0),
+//!     TradeOffer::new(Vec::new(), 300),
+//! )
+//! .unwrap();
+//!
+//! trade.accept(&manager, 2u64.into()).unwrap();
+//!
+//! let handle = manager.load::<Player>(2u64.into()).unwrap();
+//! let bob = handle.get().unwrap();
+//! let inv: Inventory = bob.get_component().unwrap().unwrap();
+//! let rsc: Resource = bob.get_component().unwrap().unwrap();
+//! assert!(inv.contains(10u64.into()));
+//! assert_eq!(rsc.val(), 200);
+//! ```
+
+use std::collections::{BTreeMap, HashSet};
+
+use failure::format_err;
+use serde::{Deserialize, Serialize};
+
+use crate::card::Card;
+use crate::components::{Inventory, Resource};
+use crate::ecs::{Component, EntityManager, Transaction};
+use crate::player::Player;
+use crate::snowflake::Snowflake;
+use crate::util::Result;
+
+/// One side of a [`Trade`]: the cards and resources a player will give.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TradeOffer {
+    cards: Vec<Snowflake>,
+    resource: i64,
+}
+
+impl TradeOffer {
+    /// Creates a new `TradeOffer` for the given [`Card`] IDs and
+    /// [`Resource`] amount.
+    pub fn new(cards: Vec<Snowflake>, resource: i64) -> TradeOffer {
+        TradeOffer { cards, resource }
+    }
+
+    /// Creates a new `TradeOffer` that gives nothing.
+    pub fn empty() -> TradeOffer {
+        TradeOffer::default()
+    }
+
+    /// Gets the IDs of the [`Cards`](Card) being offered.
+    pub fn cards(&self) -> &[Snowflake] {
+        &self.cards
+    }
+
+    /// Gets the amount of [`Resource`] being offered.
+    pub fn resource(&self) -> i64 {
+        self.resource
+    }
+
+    fn validate(&self) -> Result<()> {
+        if self.resource < 0 {
+            return Err(InvalidTrade(format!(
+                "resource amounts must not be negative (got {})",
+                self.resource
+            ))
+            .into());
+        }
+
+        let mut seen = HashSet::with_capacity(self.cards.len());
+        for id in self.cards.iter() {
+            if !seen.insert(*id) {
+                return Err(InvalidTrade(format!("card {} is offered more than once", id)).into());
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Holds the items that a [`Player`] has put into escrow for pending
+/// [`Trades`](Trade), keyed by trade ID.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Escrow {
+    held: BTreeMap<Snowflake, TradeOffer>,
+}
+
+impl Escrow {
+    /// Creates a new, empty `Escrow`.
+    pub fn new() -> Escrow {
+        Escrow {
+            held: BTreeMap::new(),
+        }
+    }
+
+    /// Gets the items held in escrow for a trade, if any.
+    pub fn get(&self, trade_id: Snowflake) -> Option<&TradeOffer> {
+        self.held.get(&trade_id)
+    }
+
+    /// Checks whether nothing is held in escrow.
+    pub fn is_empty(&self) -> bool {
+        self.held.is_empty()
+    }
+
+    /// Iterates over the IDs of every trade with items held in escrow.
+    pub fn iter_trades(&self) -> impl Iterator<Item = &Snowflake> + '_ {
+        self.held.keys()
+    }
+}
+
+impl Component<Player> for Escrow {}
+
+/// The state of a [`Trade`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TradeStatus {
+    /// Waiting for a response from [`Trade::awaiting`].
+    Pending,
+
+    /// The trade was accepted and completed.
+    Accepted,
+
+    /// The trade was cancelled or rejected.
+    Cancelled,
+}
+
+/// A trade of cards and resources between two [`Players`](Player).
+///
+/// `Trade` values are serializable, so they can be stored wherever is
+/// convenient while they are pending. The items involved are tracked
+/// separately by each player's [`Escrow`] component, and are always
+/// checked against the trade's terms before being released.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Trade {
+    id: Snowflake,
+    proposer: Snowflake,
+    recipient: Snowflake,
+    proposer_offer: TradeOffer,
+    recipient_offer: TradeOffer,
+    awaiting: Snowflake,
+    status: TradeStatus,
+}
+
+impl Trade {
+    /// Proposes a new trade from `proposer` to `recipient`, where the
+    /// proposer gives `give` in exchange for `want`.
+    ///
+    /// Everything in `give` is put into escrow straight away.
+    ///
+    /// # Errors
+    ///
+    /// Fails with an [`InvalidTrade`] error if the offer itself is
+    /// invalid, or with a [`CardNotInInventory`] or
+    /// [`InvalidSubtraction`](crate::components::InvalidSubtraction) error
+    /// if the proposer doesn't have what they're offering.
+    pub fn propose(
+        manager: &EntityManager,
+        id: Snowflake,
+        proposer: Snowflake,
+        recipient: Snowflake,
+        give: TradeOffer,
+        want: TradeOffer,
+    ) -> Result<Trade> {
+        if proposer == recipient {
+            return Err(InvalidTrade(String::from("players can't trade with themselves")).into());
+        }
+
+        give.validate()?;
+        want.validate()?;
+
+        let mut txn = manager.transaction();
+        escrow(&mut txn, manager, id, proposer, give.clone());
+        txn.commit()?;
+
+        Ok(Trade {
+            id,
+            proposer,
+            recipient,
+            proposer_offer: give,
+            recipient_offer: want,
+            awaiting: recipient,
+            status: TradeStatus::Pending,
+        })
+    }
+
+    /// Gets this trade's ID.
+    pub fn id(&self) -> Snowflake {
+        self.id
+    }
+
+    /// Gets the ID of the player who proposed this trade.
+    pub fn proposer(&self) -> Snowflake {
+        self.proposer
+    }
+
+    /// Gets the ID of the player this trade was proposed to.
+    pub fn recipient(&self) -> Snowflake {
+        self.recipient
+    }
+
+    /// Gets what the proposer will give, under the current terms.
+    pub fn proposer_offer(&self) -> &TradeOffer {
+        &self.proposer_offer
+    }
+
+    /// Gets what the recipient will give, under the current terms.
+    pub fn recipient_offer(&self) -> &TradeOffer {
+        &self.recipient_offer
+    }
+
+    /// Gets the ID of the player who needs to respond to the current terms.
+    pub fn awaiting(&self) -> Snowflake {
+        self.awaiting
+    }
+
+    /// Gets the status of this trade.
+    pub fn status(&self) -> TradeStatus {
+        self.status
+    }
+
+    fn other(&self, player: Snowflake) -> Snowflake {
+        if player == self.proposer {
+            self.recipient
+        } else {
+            self.proposer
+        }
+    }
+
+    fn offer_of(&self, player: Snowflake) -> &TradeOffer {
+        if player == self.proposer {
+            &self.proposer_offer
+        } else {
+            &self.recipient_offer
+        }
+    }
+
+    fn check_pending(&self) -> Result<()> {
+        if self.status != TradeStatus::Pending {
+            return Err(InvalidTrade(format!("trade {} is no longer pending", self.id)).into());
+        }
+
+        Ok(())
+    }
+
+    fn check_turn(&self, player: Snowflake) -> Result<()> {
+        self.check_pending()?;
+        if player != self.awaiting {
+            return Err(InvalidTrade(format!(
+                "trade {} is waiting on player {}, not {}",
+                self.id, self.awaiting, player
+            ))
+            .into());
+        }
+
+        Ok(())
+    }
+
+    /// Responds to this trade with new terms, where `player` gives `give`
+    /// in exchange for `want`.
+    ///
+    /// The other player's escrowed items are returned to them, and
+    /// everything in `give` is put into escrow instead. The other player
+    /// then needs to respond to the new terms.
+    ///
+    /// # Errors
+    ///
+    /// Fails with an [`InvalidTrade`] error if it isn't `player`'s turn to
+    /// respond, or any of the errors from [`propose`](Trade::propose).
+    pub fn counter(
+        &mut self,
+        manager: &EntityManager,
+        player: Snowflake,
+        give: TradeOffer,
+        want: TradeOffer,
+    ) -> Result<()> {
+        self.check_turn(player)?;
+        give.validate()?;
+        want.validate()?;
+
+        let other = self.other(player);
+        let mut txn = manager.transaction();
+        release(
+            &mut txn,
+            manager,
+            self.id,
+            other,
+            self.offer_of(other).clone(),
+        );
+        escrow(&mut txn, manager, self.id, player, give.clone());
+        txn.commit()?;
+
+        if player == self.proposer {
+            self.proposer_offer = give;
+            self.recipient_offer = want;
+        } else {
+            self.recipient_offer = give;
+            self.proposer_offer = want;
+        }
+
+        self.awaiting = other;
+        Ok(())
+    }
+
+    /// Accepts the current terms of this trade on behalf of `player`.
+    ///
+    /// The other player's escrowed items are given to `player`, and
+    /// `player`'s side of the trade is taken from their
+    /// [`Inventory`] and [`Resource`] and given to the other player, all
+    /// within a single transaction.
+    ///
+    /// # Errors
+    ///
+    /// Fails with an [`InvalidTrade`] error if it isn't `player`'s turn to
+    /// respond or the escrowed items don't match the trade's terms, with a
+    /// [`CardNotInInventory`] or
+    /// [`InvalidSubtraction`](crate::components::InvalidSubtraction) error
+    /// if `player` doesn't have what they're giving, or with an
+    /// [`InvalidAddition`](crate::components::InvalidAddition) error if
+    /// either player's [`Resource`] would go over its cap.
+    /// Nothing is changed if this fails.
+    pub fn accept(&mut self, manager: &EntityManager, player: Snowflake) -> Result<()> {
+        self.check_turn(player)?;
+
+        let id = self.id;
+        let other = self.other(player);
+        let give = self.offer_of(player).clone();
+        let receive = self.offer_of(other).clone();
+
+        let mut txn = manager.transaction();
+
+        let expected = receive.clone();
+        txn.update_component::<Player, Escrow, _>(other, move |escrow| {
+            let mut escrow = escrow.cloned().unwrap_or_default();
+            if escrow.held.remove(&id).as_ref() != Some(&expected) {
+                return Err(InvalidTrade(format!(
+                    "escrow for trade {} doesn't match its terms",
+                    id
+                ))
+                .into());
+            }
+
+            Ok(escrow)
+        });
+
+        let (taken, given) = (give.cards.clone(), receive.cards.clone());
+        txn.update_component::<Player, Inventory, _>(player, move |inv| {
+            let mut inv = owned_inventory(inv);
+            remove_cards(&mut inv, player, &taken, manager)?;
+            insert_cards(&mut inv, &given, manager)?;
+            Ok(inv)
+        });
+
+        adjust_resource(&mut txn, player, give.resource, receive.resource);
+
+        let given = give.cards.clone();
+        txn.update_component::<Player, Inventory, _>(other, move |inv| {
+            let mut inv = owned_inventory(inv);
+            insert_cards(&mut inv, &given, manager)?;
+            Ok(inv)
+        });
+
+        adjust_resource(&mut txn, other, 0, give.resource);
+
+        txn.commit()?;
+        self.status = TradeStatus::Accepted;
+        Ok(())
+    }
+
+    /// Cancels this trade on behalf of `player`, who may be either party.
+    ///
+    /// Cancelling a trade that's waiting on `player` rejects it. Any
+    /// escrowed items are returned to their owner.
+    pub fn cancel(&mut self, manager: &EntityManager, player: Snowflake) -> Result<()> {
+        self.check_pending()?;
+        if player != self.proposer && player != self.recipient {
+            return Err(InvalidTrade(format!(
+                "player {} is not part of trade {}",
+                player, self.id
+            ))
+            .into());
+        }
+
+        let offerer = self.other(self.awaiting);
+        let mut txn = manager.transaction();
+        release(
+            &mut txn,
+            manager,
+            self.id,
+            offerer,
+            self.offer_of(offerer).clone(),
+        );
+        txn.commit()?;
+
+        self.status = TradeStatus::Cancelled;
+        Ok(())
+    }
+}
+
+/// Copies an [`Inventory`] being updated in a transaction, so that cards
+/// can be moved in and out of it.
+fn owned_inventory(inv: Option<&Inventory>) -> Inventory {
+    match inv {
+        Some(inv) => Inventory::from(inv.iter_ids().copied().collect::<Vec<Snowflake>>()),
+        None => Inventory::empty(),
+    }
+}
+
+fn remove_cards(
+    inv: &mut Inventory,
+    player: Snowflake,
+    cards: &[Snowflake],
+    manager: &EntityManager,
+) -> Result<()> {
+    for id in cards.iter() {
+        if !inv.contains(*id) {
+            return Err(CardNotInInventory(*id, player).into());
+        }
+
+        inv.remove(*id, manager);
+    }
+
+    Ok(())
+}
+
+fn insert_cards(inv: &mut Inventory, cards: &[Snowflake], manager: &EntityManager) -> Result<()> {
+    for id in cards.iter() {
+        let handle = manager.load_mut::<Card>(*id)?;
+        if !handle.exists() {
+            return Err(format_err!("card not found: {}", id));
+        }
+
+        inv.insert_handle(handle);
+    }
+
+    Ok(())
+}
+
+/// Adds an operation to a transaction that debits and credits a player's
+/// [`Resource`], if either amount is nonzero.
+fn adjust_resource(txn: &mut Transaction, player: Snowflake, debit: i64, credit: i64) {
+    if debit == 0 && credit == 0 {
+        return;
+    }
+
+    txn.update_component::<Player, Resource, _>(player, move |rsc| {
+        let mut rsc = rsc
+            .cloned()
+            .ok_or_else(|| format_err!("player {} has no Resource", player))?;
+        rsc.checked_sub(debit.into())?;
+        rsc.checked_add(credit.into())?;
+        Ok(rsc)
+    });
+}
+
+/// Adds operations to a transaction that put a player's side of a trade
+/// into escrow.
+fn escrow<'a>(
+    txn: &mut Transaction<'a>,
+    manager: &'a EntityManager,
+    trade_id: Snowflake,
+    player: Snowflake,
+    offer: TradeOffer,
+) {
+    let cards = offer.cards.clone();
+    txn.update_component::<Player, Inventory, _>(player, move |inv| {
+        let mut inv = owned_inventory(inv);
+        remove_cards(&mut inv, player, &cards, manager)?;
+        Ok(inv)
+    });
+
+    adjust_resource(txn, player, offer.resource, 0);
+
+    txn.update_component::<Player, Escrow, _>(player, move |escrow| {
+        let mut escrow = escrow.cloned().unwrap_or_default();
+        if escrow.held.contains_key(&trade_id) {
+            return Err(InvalidTrade(format!("trade {} is already in escrow", trade_id)).into());
+        }
+
+        escrow.held.insert(trade_id, offer);
+        Ok(escrow)
+    });
+}
+
+/// Adds operations to a transaction that return a player's escrowed side
+/// of a trade to them.
+fn release<'a>(
+    txn: &mut Transaction<'a>,
+    manager: &'a EntityManager,
+    trade_id: Snowflake,
+    player: Snowflake,
+    offer: TradeOffer,
+) {
+    let expected = offer.clone();
+    txn.update_component::<Player, Escrow, _>(player, move |escrow| {
+        let mut escrow = escrow.cloned().unwrap_or_default();
+        if escrow.held.remove(&trade_id).as_ref() != Some(&expected) {
+            return Err(InvalidTrade(format!(
+                "escrow for trade {} doesn't match its terms",
+                trade_id
+            ))
+            .into());
+        }
+
+        Ok(escrow)
+    });
+
+    adjust_resource(txn, player, 0, offer.resource);
+
+    txn.update_component::<Player, Inventory, _>(player, move |inv| {
+        let mut inv = owned_inventory(inv);
+        insert_cards(&mut inv, &offer.cards, manager)?;
+        Ok(inv)
+    });
+}
+
+/// Returned when a [`Trade`] operation isn't valid, such as when a
+/// player responds out of turn.
+#[derive(Fail, Debug)]
+#[fail(display = "Invalid trade: {}", _0)]
+pub struct InvalidTrade(String);
+
+/// Returned when a player tries to trade a [`Card`] that isn't in their
+/// [`Inventory`].
+#[derive(Fail, Debug)]
+#[fail(display = "Card {} is not in the inventory of player {}", _0, _1)]
+pub struct CardNotInInventory(Snowflake, Snowflake);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::components::{InvalidSubtraction, InventoryBackendWrapper};
+    use crate::ecs::{Entity, TransactionError};
+    use crate::local_storage::{LocalComponentStorage, LocalEntityStorage};
+
+    fn setup() -> EntityManager {
+        let mut manager = EntityManager::new();
+        manager
+            .register_entity(LocalEntityStorage::<Player>::new())
+            .unwrap();
+        manager
+            .register_entity(LocalEntityStorage::<Card>::new())
+            .unwrap();
+        manager
+            .register_component("Resource", LocalComponentStorage::<Player, Resource>::new())
+            .unwrap();
+        manager
+            .register_component("Escrow", LocalComponentStorage::<Player, Escrow>::new())
+            .unwrap();
+        manager
+            .register_component(
+                "Inventory",
+                InventoryBackendWrapper::new(LocalComponentStorage::<Player, Vec<Snowflake>>::new()),
+            )
+            .unwrap();
+
+        for id in 10..16u64 {
+            let card: Card = manager.create(id.into()).unwrap();
+            manager.store(card).unwrap();
+        }
+
+        add_player(&manager, 1, vec![10, 11, 12], 100);
+        add_player(&manager, 2, vec![13, 14, 15], 100);
+        manager
+    }
+
+    fn add_player(manager: &EntityManager, id: u64, cards: Vec<u64>, resource: i64) {
+        let cards: Vec<Snowflake> = cards.into_iter().map(|id| id.into()).collect();
+        let mut player: Player = manager.create(id.into()).unwrap();
+        player.set_component(Inventory::from(cards)).unwrap();
+        player
+            .set_component(Resource::new(resource, Some(0), Some(1000)))
+            .unwrap();
+        manager.store(player).unwrap();
+    }
+
+    fn ids(ids: &[u64]) -> Vec<Snowflake> {
+        ids.iter().map(|id| (*id).into()).collect()
+    }
+
+    fn state(manager: &EntityManager, id: u64) -> (Vec<u64>, i64, Escrow) {
+        let handle = manager.load::<Player>(id.into()).unwrap();
+        let player = handle.get().unwrap();
+
+        let inv: Inventory = player.get_component().unwrap().unwrap_or_default();
+        let rsc: Resource = player.get_component().unwrap().unwrap();
+        let escrow: Escrow = player.get_component().unwrap().unwrap_or_default();
+
+        let mut cards: Vec<u64> = inv.iter_ids().map(|id| (*id).into()).collect();
+        cards.sort();
+        (cards, rsc.val(), escrow)
+    }
+
+    #[test]
+    fn test_propose_and_accept() {
+        let manager = setup();
+        let mut trade = Trade::propose(
+            &manager,
+            100u64.into(),
+            1u64.into(),
+            2u64.into(),
+            TradeOffer::new(ids(&[10, 11]), 30),
+            TradeOffer::new(ids(&[13]), 5),
+        )
+        .unwrap();
+
+        let (cards, rsc, escrow) = state(&manager, 1);
+        assert_eq!(cards, vec![12]);
+        assert_eq!(rsc, 70);
+        assert_eq!(escrow.get(100u64.into()), Some(trade.proposer_offer()));
+
+        // The proposer can't accept their own offer.
+        assert!(trade.accept(&manager, 1u64.into()).is_err());
+
+        trade.accept(&manager, 2u64.into()).unwrap();
+        assert_eq!(trade.status(), TradeStatus::Accepted);
+
+        let (cards, rsc, escrow) = state(&manager, 1);
+        assert_eq!(cards, vec![12, 13]);
+        assert_eq!(rsc, 75);
+        assert!(escrow.is_empty());
+
+        let (cards, rsc, _escrow) = state(&manager, 2);
+        assert_eq!(cards, vec![10, 11, 14, 15]);
+        assert_eq!(rsc, 125);
+
+        assert!(trade.accept(&manager, 2u64.into()).is_err());
+    }
+
+    #[test]
+    fn test_escrow_prevents_double_spending() {
+        let manager = setup();
+        Trade::propose(
+            &manager,
+            100u64.into(),
+            1u64.into(),
+            2u64.into(),
+            TradeOffer::new(ids(&[10]), 80),
+            TradeOffer::empty(),
+        )
+        .unwrap();
+
+        let err = Trade::propose(
+            &manager,
+            101u64.into(),
+            1u64.into(),
+            2u64.into(),
+            TradeOffer::new(ids(&[10]), 0),
+            TradeOffer::empty(),
+        )
+        .unwrap_err();
+
+        let err = err.downcast::<TransactionError>().unwrap();
+        assert!(err.error().downcast_ref::<CardNotInInventory>().is_some());
+
+        let err = Trade::propose(
+            &manager,
+            102u64.into(),
+            1u64.into(),
+            2u64.into(),
+            TradeOffer::new(Vec::new(), 30),
+            TradeOffer::empty(),
+        )
+        .unwrap_err();
+
+        let err = err.downcast::<TransactionError>().unwrap();
+        assert!(err.error().downcast_ref::<InvalidSubtraction>().is_some());
+
+        // Failed proposals don't leave anything in escrow.
+        let (cards, rsc, escrow) = state(&manager, 1);
+        assert_eq!(cards, vec![11, 12]);
+        assert_eq!(rsc, 20);
+        assert_eq!(escrow.iter_trades().count(), 1);
+    }
+
+    #[test]
+    fn test_counter_and_cancel() {
+        let manager = setup();
+        let mut trade = Trade::propose(
+            &manager,
+            100u64.into(),
+            1u64.into(),
+            2u64.into(),
+            TradeOffer::new(ids(&[10]), 0),
+            TradeOffer::new(ids(&[13, 14]), 0),
+        )
+        .unwrap();
+
+        // Only the recipient can respond.
+        assert!(trade
+            .counter(
+                &manager,
+                1u64.into(),
+                TradeOffer::empty(),
+                TradeOffer::empty()
+            )
+            .is_err());
+
+        trade
+            .counter(
+                &manager,
+                2u64.into(),
+                TradeOffer::new(ids(&[13]), 10),
+                TradeOffer::new(ids(&[10]), 0),
+            )
+            .unwrap();
+        assert_eq!(trade.awaiting(), 1u64.into());
+
+        // The proposer's card is returned, and the recipient's side is
+        // now in escrow.
+        let (cards, _rsc, escrow) = state(&manager, 1);
+        assert_eq!(cards, vec![10, 11, 12]);
+        assert!(escrow.is_empty());
+
+        let (cards, rsc, escrow) = state(&manager, 2);
+        assert_eq!(cards, vec![14, 15]);
+        assert_eq!(rsc, 90);
+        assert!(!escrow.is_empty());
+
+        trade.cancel(&manager, 1u64.into()).unwrap();
+        assert_eq!(trade.status(), TradeStatus::Cancelled);
+
+        let (cards, rsc, escrow) = state(&manager, 2);
+        assert_eq!(cards, vec![13, 14, 15]);
+        assert_eq!(rsc, 100);
+        assert!(escrow.is_empty());
+
+        assert!(trade.cancel(&manager, 1u64.into()).is_err());
+    }
+
+    #[test]
+    fn test_failed_accept_changes_nothing() {
+        let manager = setup();
+        let mut trade = Trade::propose(
+            &manager,
+            100u64.into(),
+            1u64.into(),
+            2u64.into(),
+            TradeOffer::new(ids(&[10]), 0),
+            TradeOffer::new(ids(&[13]), 0),
+        )
+        .unwrap();
+
+        // Move the recipient's card elsewhere before they accept.
+        Trade::propose(
+            &manager,
+            101u64.into(),
+            2u64.into(),
+            1u64.into(),
+            TradeOffer::new(ids(&[13]), 0),
+            TradeOffer::empty(),
+        )
+        .unwrap();
+
+        let err = trade.accept(&manager, 2u64.into()).unwrap_err();
+        let err = err.downcast::<TransactionError>().unwrap();
+        assert!(err.error().downcast_ref::<CardNotInInventory>().is_some());
+        assert_eq!(trade.status(), TradeStatus::Pending);
+
+        let (cards, _rsc, escrow) = state(&manager, 1);
+        assert_eq!(cards, vec![11, 12]);
+        assert!(escrow.get(100u64.into()).is_some());
+
+        let (cards, _rsc, _escrow) = state(&manager, 2);
+        assert_eq!(cards, vec![14, 15]);
+    }
+
+    #[test]
+    fn test_invalid_offers() {
+        let manager = setup();
+        let propose = |proposer: u64, give: TradeOffer| {
+            Trade::propose(
+                &manager,
+                100u64.into(),
+                proposer.into(),
+                2u64.into(),
+                give,
+                TradeOffer::empty(),
+            )
+        };
+
+        let err = propose(2, TradeOffer::empty()).unwrap_err();
+        assert!(err.downcast_ref::<InvalidTrade>().is_some());
+
+        let err = propose(1, TradeOffer::new(Vec::new(), -5)).unwrap_err();
+        assert!(err.downcast_ref::<InvalidTrade>().is_some());
+
+        let err = propose(1, TradeOffer::new(ids(&[10, 10]), 0)).unwrap_err();
+        assert!(err.downcast_ref::<InvalidTrade>().is_some());
+    }
+}